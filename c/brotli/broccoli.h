@@ -10,6 +10,7 @@ typedef enum BroccoliResult_ {
   BroccoliSuccess = 0,
   BroccoliNeedsMoreInput = 1,
   BroccoliNeedsMoreOutput = 2,
+  BroccoliBrotliFileCorrupt = 123,
   BroccoliBrotliFileNotCraftedForAppend = 124,
   BroccoliInvalidWindowSize = 125,
   BroccoliWindowSizeLargerThanPreviousFile = 126,
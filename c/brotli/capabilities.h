@@ -0,0 +1,65 @@
+/**
+ * @file
+ * Build and ABI information for dynamic loaders of libbrotli_ffi.
+ */
+
+#ifndef BROTLI_CAPABILITIES_H
+#define BROTLI_CAPABILITIES_H
+#include <stddef.h>
+#include <stdint.h>
+#include "broccoli.h"
+
+#if defined(__cplusplus) || defined(c_plusplus)
+extern "C" {
+#endif
+
+/** Version of the structs shared with C and of the exported signatures. */
+#define BROTLI_FFI_ABI_VERSION 1
+
+/** Work pools and multithreaded compression spawn threads. */
+#define BROTLI_FFI_CAPABILITY_MULTITHREADING 1
+/** Built with the simd feature. */
+#define BROTLI_FFI_CAPABILITY_SIMD 2
+/** Broccoli* concatenation functions are available. */
+#define BROTLI_FFI_CAPABILITY_BROCCOLI 4
+/** Metablock IR can be observed from C while compressing. */
+#define BROTLI_FFI_CAPABILITY_IR_CALLBACK 8
+/** BROTLI_PARAM_LARGE_WINDOW streams can be produced and decoded. */
+#define BROTLI_FFI_CAPABILITY_LARGE_WINDOW 16
+/** Null alloc_func and free_func are accepted; otherwise allocators must be supplied. */
+#define BROTLI_FFI_CAPABILITY_DEFAULT_ALLOCATOR 32
+
+/** Returns the ::BROTLI_FFI_ABI_VERSION the library was built with. */
+uint32_t BrotliFFIAbiVersion(void);
+
+/** Returns the BROTLI_FFI_CAPABILITY_* bits of the features compiled into the library. */
+uint64_t BrotliFFICapabilities(void);
+
+/** Returns sizeof(BroccoliState) as the library sees it. */
+size_t BroccoliStateSize(void);
+
+/**
+ * Return the number of bytes a custom alloc_func is asked for when creating each state.
+ * These differ between builds with different features.
+ */
+size_t BrotliEncoderStateSize(void);
+size_t CBrotliDecoderStateSize(void);
+size_t BrotliEncoderWorkPoolSize(void);
+
+/**
+ * Returns 1 if a caller built against @p abi_version, @p broccoli_state_size and
+ * @p encoder_state_size can use the library, 0 otherwise. Callers whose alloc_func
+ * is sized for a particular BrotliEncoderStateSize() pass that size, others pass 0.
+ * Use ::BROTLI_FFI_CHECK_ABI.
+ */
+int32_t BrotliFFICheckAbi(uint32_t abi_version, size_t broccoli_state_size,
+                          size_t encoder_state_size);
+
+#define BROTLI_FFI_CHECK_ABI(encoder_state_size) \
+  BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, sizeof(BroccoliState), (encoder_state_size))
+
+#if defined(__cplusplus) || defined(c_plusplus)
+}  /* extern "C" */
+#endif
+
+#endif
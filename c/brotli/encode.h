@@ -445,6 +445,73 @@ BROTLI_ENC_API void BrotliEncoderSetCustomDictionary(
   size_t size,
   const uint8_t *dict);
     
+/** Kinds of ::BrotliEncoderIRCommand delivered to a ::brotli_encoder_metablock_callback. */
+typedef enum BrotliEncoderIRCommandType {
+  BROTLI_IR_LITERAL = 0,
+  BROTLI_IR_COPY = 1,
+  BROTLI_IR_DICT = 2,
+  BROTLI_IR_BLOCK_SWITCH_COMMAND = 3,
+  BROTLI_IR_BLOCK_SWITCH_LITERAL = 4,
+  BROTLI_IR_BLOCK_SWITCH_DISTANCE = 5
+} BrotliEncoderIRCommandType;
+
+/**
+ * One command of a metablock. Which fields are meaningful depends on
+ * @p command_type; @p data points to @p num_bytes literals and is only valid
+ * during the callback.
+ */
+typedef struct BrotliEncoderIRCommand {
+  BrotliEncoderIRCommandType command_type;
+  uint32_t num_bytes;  /* literal length, copy length or transformed word length */
+  uint32_t distance;  /* copy */
+  uint32_t word_id;  /* dict */
+  uint8_t word_size;  /* dict */
+  uint8_t transform;  /* dict */
+  uint8_t block_type;  /* block switches */
+  uint8_t stride;  /* literal block switch */
+  uint8_t high_entropy;  /* literal */
+  const uint8_t* data;  /* literal */
+} BrotliEncoderIRCommand;
+
+/**
+ * Context maps a metablock was coded with. The arrays are only valid during
+ * the callback.
+ */
+typedef struct BrotliEncoderIRPredictionMode {
+  uint8_t literal_prediction_mode;
+  const uint8_t* literal_context_map;
+  size_t literal_context_map_size;
+  const uint8_t* distance_context_map;
+  size_t distance_context_map_size;
+  const uint8_t* predmode_speed_and_distance_context_map;
+  size_t predmode_speed_and_distance_context_map_size;
+} BrotliEncoderIRPredictionMode;
+
+typedef void (*brotli_encoder_metablock_callback)(
+    void* opaque, const BrotliEncoderIRPredictionMode* prediction_mode,
+    const BrotliEncoderIRCommand* commands, size_t num_commands);
+
+/**
+ * Calls @p callback with the commands of every metablock written by
+ * ::BrotliEncoderCompressStream on @p state, passing @p opaque along.
+ * A null @p callback stops the calls.
+ */
+BROTLI_ENC_API void BrotliEncoderSetMetablockCallback(
+    BrotliEncoderState* state, brotli_encoder_metablock_callback callback,
+    void* opaque);
+
+/**
+ * Applies a profile to the parameters of @p state: @p size bytes of
+ * key=value lines starting with version=1, as printed by brotli
+ * --print-params. Keys the profile leaves out keep their current value.
+ *
+ * @returns ::BROTLI_FALSE if the profile is invalid or encoding has started,
+ *          leaving the parameters untouched
+ * @returns ::BROTLI_TRUE if the profile was applied
+ */
+BROTLI_ENC_API BROTLI_BOOL BrotliEncoderSetProfile(
+    BrotliEncoderState* state, size_t size, const char* profile);
+
 /**
  * Gets an encoder library version.
  *
@@ -9,6 +9,14 @@
  */
 typedef struct BrotliEncoderWorkPoolStruct BrotliEncoderWorkPool;
 
+/**
+ * Opaque structure that holds the state of a streaming multithreaded encoder
+ *
+ * Allocated and initialized with ::BrotliEncoderCreateMultiStream.
+ * Cleaned up and deallocated with ::BrotliEncoderDestroyMultiStream.
+ */
+typedef struct BrotliEncoderMultiStreamStruct BrotliEncoderMultiStream;
+
 /**
  * Performs one-shot memory-to-memory compression.
  *
@@ -50,6 +58,29 @@ BROTLI_ENC_API int32_t BrotliEncoderCompressMulti(
     brotli_alloc_func alloc_func, brotli_free_func free_func,
     void** alloc_opaque_per_thread);
 
+/**
+ * Same as ::BrotliEncoderCompressMulti, but every thread may reference a custom dictionary.
+ *
+ * The same @p dictionary_buffer must be supplied to the decoder.
+ * With ::BROTLI_PARAM_FAVOR_EFFICIENCY set, the dictionary is hashed once and shared by all threads.
+ *
+ * @param dictionary_size size of @p dictionary_buffer, or @c 0 for no dictionary
+ * @param dictionary_buffer custom dictionary with at least @p dictionary_size addressable bytes
+ */
+BROTLI_ENC_API int32_t BrotliEncoderCompressMultiWithDictionary(
+    size_t num_params,
+    const BrotliEncoderParameter* param_keys,
+    const uint32_t* param_values,
+    size_t dictionary_size,
+    const uint8_t *dictionary_buffer,
+    size_t input_size,
+    const uint8_t *input_buffer,
+    size_t *encoded_size,
+    uint8_t *encoded,
+    size_t desired_num_threads,
+    brotli_alloc_func alloc_func, brotli_free_func free_func,
+    void** alloc_opaque_per_thread);
+
 
 
 BROTLI_ENC_API size_t BrotliEncoderMaxCompressedSizeMulti(size_t input_size, size_t num_threads);
@@ -126,4 +157,100 @@ BROTLI_ENC_API int32_t BrotliEncoderCompressWorkPool(
     brotli_alloc_func alloc_func, brotli_free_func free_func,
     void** alloc_opaque_per_thread);
 
+/**
+ * Same as ::BrotliEncoderCompressWorkPool, but every job may reference a custom dictionary.
+ *
+ * The same @p dictionary_buffer must be supplied to the decoder.
+ * With ::BROTLI_PARAM_FAVOR_EFFICIENCY set, the dictionary is hashed once and shared by all jobs.
+ *
+ * @param dictionary_size size of @p dictionary_buffer, or @c 0 for no dictionary
+ * @param dictionary_buffer custom dictionary with at least @p dictionary_size addressable bytes
+ */
+BROTLI_ENC_API int32_t BrotliEncoderCompressWorkPoolWithDictionary(
+    BrotliEncoderWorkPool *work_pool,
+    size_t num_params,
+    const BrotliEncoderParameter* param_keys,
+    const uint32_t* param_values,
+    size_t dictionary_size,
+    const uint8_t *dictionary_buffer,
+    size_t input_size,
+    const uint8_t *input_buffer,
+    size_t *encoded_size,
+    uint8_t *encoded,
+    size_t desired_num_threads,
+    brotli_alloc_func alloc_func, brotli_free_func free_func,
+    void** alloc_opaque_per_thread);
+
+/**
+ * Creates a streaming encoder that compresses its input in parallel.
+ *
+ * Input is gathered until there are @p desired_num_threads chunks of @p chunk_size bytes; that batch
+ * is then split between the threads, using everything before it in the window as a dictionary,
+ * and the compressed batches are joined into a single brotli stream.
+ *
+ * @param work_pool an optional work pool that must outlive the stream; if null, threads are spawned per batch
+ * @param num_params indicates how long both the param_keys and param_values arrays will be
+ * @param param_keys is an array of BrotliEncoderParameters that must be the same length as param_values
+ * @param param_values is an array of uint32_t and for each one of these, the matching param_keys will be changed to this value
+ * @param desired_num_threads is the number of chunks compressed together
+ * @param chunk_size is the number of input bytes per chunk, or @c 0 for 1 MiB
+ * @param alloc_func is an optional allocator that will be called for any allocations. If null, builtin malloc will be used
+ * @param free_func is an optional allocator that will be called for any frees. If null, builtin free will be used
+ * @param alloc_opaque_per_thread is an opaque function that will be passed into both alloc and free.
+ *                                each thread will have an independent allocator and will pass its own opaque. So this array must
+ *                                either be entirely null (and the above functions also be null) or sized to the desired_num_threads
+ * @returns @c 0 if the parameters are invalid or the instance can not be allocated
+ * @returns pointer to initialized ::BrotliEncoderMultiStream otherwise
+ */
+BROTLI_ENC_API BrotliEncoderMultiStream* BrotliEncoderCreateMultiStream(
+    BrotliEncoderWorkPool *work_pool,
+    size_t num_params,
+    const BrotliEncoderParameter* param_keys,
+    const uint32_t* param_values,
+    size_t desired_num_threads,
+    size_t chunk_size,
+    brotli_alloc_func alloc_func, brotli_free_func free_func,
+    void** alloc_opaque_per_thread);
+
+/**
+ * Deinitializes and frees ::BrotliEncoderMultiStream instance.
+ *
+ * @param stream stream instance to be cleaned up and deallocated
+ */
+BROTLI_ENC_API void BrotliEncoderDestroyMultiStream(BrotliEncoderMultiStream* stream);
+
+/**
+ * Compresses input stream to output stream, following the contract of ::BrotliEncoderCompressStream.
+ *
+ * Input is copied until a batch is full; the batch is then compressed before this call returns,
+ * and no more input is accepted until its output has been written or taken.
+ * ::BROTLI_OPERATION_FLUSH compresses the input gathered so far, though up to two bytes of output
+ * are held back until the next batch or the end of the stream.
+ *
+ * @returns ::BROTLI_FALSE if there was an error
+ * @returns ::BROTLI_TRUE otherwise
+ */
+BROTLI_ENC_API int32_t BrotliEncoderMultiStreamCompress(
+    BrotliEncoderMultiStream* stream, BrotliEncoderOperation op, size_t* available_in,
+    const uint8_t** next_in, size_t* available_out, uint8_t** next_out,
+    size_t* total_out);
+
+/**
+ * Acquires pointer to internal output buffer, like ::BrotliEncoderTakeOutput.
+ *
+ * The returned data is valid until the next call on @p stream.
+ *
+ * @param[out] size number of bytes available at the returned pointer
+ */
+BROTLI_ENC_API const uint8_t* BrotliEncoderMultiStreamTakeOutput(
+    BrotliEncoderMultiStream* stream, size_t* size);
 
+/**
+ * Checks if the stream has compressed output that has not been written or taken yet.
+ */
+BROTLI_ENC_API int32_t BrotliEncoderMultiStreamHasMoreOutput(BrotliEncoderMultiStream* stream);
+
+/**
+ * Checks if the stream has been finished and all of its output has been written or taken.
+ */
+BROTLI_ENC_API int32_t BrotliEncoderMultiStreamIsFinished(BrotliEncoderMultiStream* stream);
@@ -3,6 +3,7 @@
 #include <string.h>
 #include <assert.h>
 #include "brotli/broccoli.h"
+#include "brotli/capabilities.h"
 
 void usage() {
     fprintf(stderr,
@@ -16,6 +17,10 @@ int main(int argc, char**argv) {
     unsigned char double_dash = 0;
     size_t buffer_size = 4096;
     int i;
+    if (!BROTLI_FFI_CHECK_ABI(0)) {
+        fprintf(stderr, "libbrotli_ffi was built for a different ABI\n");
+        return 1;
+    }
     if (argc == 1) {
         usage();
         return 1;
@@ -23,6 +23,14 @@ static BroccoliResult BrConcatStream(BroccoliState *s,
                                      uint8_t *output_buf) {
     return BroccoliConcatStream(s, available_in, &input_buf, available_out, &output_buf);
 }
+static int32_t BrMultiStreamCompress(BrotliEncoderMultiStream *s,
+                                     BrotliEncoderOperation op,
+                                     size_t *available_in,
+                                     const uint8_t *input_buf) {
+    size_t available_out = 0;
+    uint8_t *output_buf = NULL;
+    return BrotliEncoderMultiStreamCompress(s, op, available_in, &input_buf, &available_out, &output_buf, NULL);
+}
 static BroccoliResult BrConcatFinish(BroccoliState *s,
                                      size_t *available_out,
                                      uint8_t *output_buf) {
@@ -200,7 +208,8 @@ func makeCompressionOptionsStreams(options CompressionOptions,
 
 type MultiCompressionWriter struct {
 	options    CompressionOptions
-	buffer     []byte
+	workPool   *C.BrotliEncoderWorkPool
+	stream     *C.BrotliEncoderMultiStream
 	downstream io.Writer
 }
 
@@ -208,39 +217,77 @@ func NewMultiCompressionWriter(
 	downstream io.Writer,
 	options CompressionOptions,
 ) *MultiCompressionWriter {
-	return &MultiCompressionWriter{options: options, downstream: downstream}
+	parameters, values, numParams := makeCompressionOptionsStreams(
+		options,
+	)
+	workPool := C.BrotliEncoderCreateWorkPool(C.size_t(options.NumThreads), nil, nil, nil)
+	stream := C.BrotliEncoderCreateMultiStream(
+		workPool,
+		numParams,
+		parameters,
+		values,
+		C.size_t(options.NumThreads),
+		0,
+		nil, nil, nil)
+	return &MultiCompressionWriter{
+		options:    options,
+		workPool:   workPool,
+		stream:     stream,
+		downstream: downstream,
+	}
+}
+
+// compress hands data to the stream and writes out whatever compressed output is ready
+func (mself *MultiCompressionWriter) compress(data []byte, op C.BrotliEncoderOperation) error {
+	if mself.stream == nil {
+		return errors.New("Compression failed")
+	}
+	for {
+		availIn := C.size_t(len(data))
+		var nextIn *C.uint8_t
+		if len(data) != 0 {
+			nextIn = (*C.uint8_t)(&data[0])
+		}
+		ret := C.BrMultiStreamCompress(mself.stream, op, &availIn, nextIn)
+		data = data[len(data)-int(availIn):]
+		for C.BrotliEncoderMultiStreamHasMoreOutput(mself.stream) != 0 {
+			var size C.size_t
+			output := C.BrotliEncoderMultiStreamTakeOutput(mself.stream, &size)
+			if size != 0 {
+				_, err := mself.downstream.Write(C.GoBytes(unsafe.Pointer(output), C.int(size)))
+				if err != nil {
+					return err
+				}
+			}
+		}
+		if ret == 0 {
+			return errors.New("Compression failed")
+		}
+		if len(data) == 0 && (op != C.BROTLI_OPERATION_FINISH ||
+			C.BrotliEncoderMultiStreamIsFinished(mself.stream) != 0) {
+			return nil
+		}
+	}
 }
 
 func (mself *MultiCompressionWriter) Write(data []byte) (int, error) {
-	mself.buffer = append(mself.buffer, data...)
+	err := mself.compress(data, C.BROTLI_OPERATION_PROCESS)
+	if err != nil {
+		return 0, err
+	}
 	return len(data), nil
 }
 
 func (mself *MultiCompressionWriter) Close() error {
-	output := make([]byte, int(C.BrotliEncoderMaxCompressedSizeMulti(
-		C.size_t(len(mself.buffer)), C.size_t(mself.options.NumThreads))))
-	var bufferAddr *C.uint8_t
-	if len(mself.buffer) != 0 {
-		bufferAddr = (*C.uint8_t)(&mself.buffer[0])
+	err := mself.compress(nil, C.BROTLI_OPERATION_FINISH)
+	if mself.stream != nil {
+		C.BrotliEncoderDestroyMultiStream(mself.stream)
+		mself.stream = nil
 	}
-	parameters, values, numParams := makeCompressionOptionsStreams(
-		mself.options,
-	)
-	outputLen := C.size_t(len(output))
-	ret := C.BrotliEncoderCompressMulti(
-		numParams,
-		parameters,
-		values,
-		C.size_t(len(mself.buffer)),
-		bufferAddr,
-		&outputLen,
-		(*C.uint8_t)(&output[0]),
-		C.size_t(mself.options.NumThreads),
-		nil, nil, nil)
-	if ret == 0 {
-		return errors.New("Compression failed")
+	if mself.workPool != nil {
+		C.BrotliEncoderDestroyWorkPool(mself.workPool)
+		mself.workPool = nil
 	}
-	_, err := mself.downstream.Write(output[:outputLen])
 	if err != nil {
 		return err
 	}
@@ -0,0 +1,104 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include "brotli/encode.h"
+#include "brotli/capabilities.h"
+
+/* Prints the commands of every metablock the encoder writes for a file, in the
+   style of the -log output of the rust brotli tool. */
+
+static const char *prediction_mode_name(uint8_t mode) {
+    switch (mode) {
+      case 0: return "lsb6";
+      case 1: return "msb6";
+      case 2: return "utf8";
+      case 3: return "sign";
+      default: return "unknown";
+    }
+}
+
+static void print_metablock(void *opaque,
+                            const BrotliEncoderIRPredictionMode *prediction_mode,
+                            const BrotliEncoderIRCommand *commands,
+                            size_t num_commands) {
+    size_t *num_metablocks = (size_t*)opaque;
+    size_t i, j;
+    printf("metablock %u prediction %s lcontextmap %u dcontextmap %u\n",
+           (unsigned)(*num_metablocks)++,
+           prediction_mode_name(prediction_mode->literal_prediction_mode),
+           (unsigned)prediction_mode->literal_context_map_size,
+           (unsigned)prediction_mode->distance_context_map_size);
+    for (i = 0; i < num_commands; ++i) {
+        const BrotliEncoderIRCommand *cmd = &commands[i];
+        switch (cmd->command_type) {
+          case BROTLI_IR_LITERAL:
+            printf("insert %u ", (unsigned)cmd->num_bytes);
+            for (j = 0; j < cmd->num_bytes; ++j) {
+                printf("%02x", cmd->data[j]);
+            }
+            printf("\n");
+            break;
+          case BROTLI_IR_COPY:
+            printf("copy %u from %u\n", (unsigned)cmd->num_bytes, (unsigned)cmd->distance);
+            break;
+          case BROTLI_IR_DICT:
+            printf("dict %u word %u,%u func %u\n", (unsigned)cmd->num_bytes,
+                   (unsigned)cmd->word_size, (unsigned)cmd->word_id, (unsigned)cmd->transform);
+            break;
+          case BROTLI_IR_BLOCK_SWITCH_COMMAND:
+            printf("ctype %u\n", (unsigned)cmd->block_type);
+            break;
+          case BROTLI_IR_BLOCK_SWITCH_LITERAL:
+            printf("ltype %u %u\n", (unsigned)cmd->block_type, (unsigned)cmd->stride);
+            break;
+          case BROTLI_IR_BLOCK_SWITCH_DISTANCE:
+            printf("dtype %u\n", (unsigned)cmd->block_type);
+            break;
+          default:
+            break;
+        }
+    }
+}
+
+int main(int argc, char **argv) {
+    unsigned char buffer[65536];
+    unsigned char output[65536];
+    size_t num_metablocks = 0;
+    BrotliEncoderState *state;
+    FILE *input;
+    int done = 0;
+    if (argc != 2) {
+        fprintf(stderr, "Usage: %s filename\n", argv[0]);
+        return 1;
+    }
+    if (!BROTLI_FFI_CHECK_ABI(0)
+        || !(BrotliFFICapabilities() & BROTLI_FFI_CAPABILITY_IR_CALLBACK)) {
+        fprintf(stderr, "libbrotli_ffi does not support metablock callbacks\n");
+        return 1;
+    }
+    input = fopen(argv[1], "rb");
+    if (!input) {
+        fprintf(stderr, "Unable to open %s\n", argv[1]);
+        return 1;
+    }
+    state = BrotliEncoderCreateInstance(NULL, NULL, NULL);
+    BrotliEncoderSetParameter(state, BROTLI_PARAM_QUALITY, 9);
+    BrotliEncoderSetMetablockCallback(state, print_metablock, &num_metablocks);
+    while (!done) {
+        size_t available_in = fread(buffer, 1, sizeof(buffer), input);
+        const uint8_t *next_in = buffer;
+        BrotliEncoderOperation op = feof(input) ? BROTLI_OPERATION_FINISH : BROTLI_OPERATION_PROCESS;
+        do {
+            size_t available_out = sizeof(output);
+            uint8_t *next_out = output;
+            if (!BrotliEncoderCompressStream(state, op, &available_in, &next_in,
+                                             &available_out, &next_out, NULL)) {
+                fprintf(stderr, "Compression failed\n");
+                return 1;
+            }
+        } while (available_in || BrotliEncoderHasMoreOutput(state));
+        done = op == BROTLI_OPERATION_FINISH && BrotliEncoderIsFinished(state);
+    }
+    BrotliEncoderDestroyInstance(state);
+    fclose(input);
+    return 0;
+}
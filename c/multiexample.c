@@ -128,6 +128,59 @@ int32_t compress_immediate_thread_spawn(const unsigned char *data, size_t len, s
     return ret;
 }
 
+int32_t compress_stream(const unsigned char *data, size_t len, struct VecU8 *ret_buffer,
+                        int argc, char** argv, int quality_override) {
+    BrotliEncoderParameter param_keys[MAX_ARGS + 2];
+    uint32_t param_values[MAX_ARGS + 2];
+    size_t num_threads = 1;
+    size_t num_params = set_options(param_keys, param_values, argc > MAX_ARGS ? MAX_ARGS : argc, argv, len, &num_threads);
+    if (quality_override != 0) {
+        param_keys[num_params] = BROTLI_PARAM_QUALITY;
+        param_values[num_params] = quality_override;
+        num_params += 1;
+        num_threads = quality_override > 16 ? 16 : quality_override;
+    }
+    void * opaque_per_thread[MAX_THREADS];
+    {
+        size_t i;
+        for (i = 0; i < MAX_THREADS; i+=1) {
+            opaque_per_thread[i] = custom_alloc_opaque;
+        }
+    }
+    BrotliEncoderWorkPool *work_pool = BrotliEncoderCreateWorkPool(num_threads, custom_malloc, custom_free, custom_alloc_opaque);
+    if (!work_pool) {
+      return 0;
+    }
+    /* small chunks so that several batches are joined even for modest inputs */
+    BrotliEncoderMultiStream *stream = BrotliEncoderCreateMultiStream(
+        work_pool, num_params, param_keys, param_values, num_threads, len / (num_threads * 4) + 1,
+        custom_malloc, custom_free, custom_malloc ? opaque_per_thread : NULL);
+    if (!stream) {
+      BrotliEncoderDestroyWorkPool(work_pool);
+      return 0;
+    }
+    int32_t ret = 1;
+    size_t offset = 0;
+    reinit_vec_u8(ret_buffer, 0);
+    while (ret && !BrotliEncoderMultiStreamIsFinished(stream)) {
+        size_t avail_in = len - offset < 4096 ? len - offset : 4096;
+        const uint8_t *next_in = data + offset;
+        size_t avail_out = 0;
+        uint8_t *next_out = NULL;
+        size_t taken = 0;
+        const uint8_t *output;
+        ret = BrotliEncoderMultiStreamCompress(stream,
+                                               offset + avail_in == len ? BROTLI_OPERATION_FINISH : BROTLI_OPERATION_PROCESS,
+                                               &avail_in, &next_in, &avail_out, &next_out, NULL);
+        offset = next_in - data;
+        output = BrotliEncoderMultiStreamTakeOutput(stream, &taken);
+        push_vec_u8(ret_buffer, output, taken);
+    }
+    BrotliEncoderDestroyMultiStream(stream);
+    BrotliEncoderDestroyWorkPool(work_pool);
+    return ret;
+}
+
 BrotliDecoderResult decompress(const unsigned char *data, size_t len, struct VecU8 *ret_buffer) {
     unsigned char buf[BUF_SIZE];
     size_t total_out = 0;
@@ -191,6 +244,8 @@ int main(int argc, char**argv) {
         int32_t res;
         if (getenv("NO_WORK_POOL")) {
             res = compress_immediate_thread_spawn(data, len, &brotli_file, argc, argv, i);
+        } else if (getenv("STREAM")) {
+            res = compress_stream(data, len, &brotli_file, argc, argv, i);
         } else {
             res = compress(data, len, &brotli_file, argc, argv, i);
         }
@@ -38,6 +38,8 @@ _BrotliDecoderDestroyInstance = brotli_library.BrotliDecoderDestroyInstance
 _BrotliDecoderDestroyInstance.restype = None
 BrotliEncoderMaxCompressedSizeMulti = brotli_library.BrotliEncoderMaxCompressedSizeMulti
 BrotliEncoderMaxCompressedSizeMulti.restype = c_size_t
+BrotliEncoderMaxCompressedSize = brotli_library.BrotliEncoderMaxCompressedSize
+BrotliEncoderMaxCompressedSize.restype = c_size_t
 _BrotliDecoderDecompressStream = brotli_library.BrotliDecoderDecompressStream
 _BrotliDecoderDecompressStream.restype = int
 
@@ -194,6 +196,116 @@ def BrotliCompress(
                                         + " threads")
     return bytearray(encoded[:encoded_size.value])
 
+class BrotliEncoderState(ctypes.Structure):
+    pass
+class BrotliEncoderIRPredictionMode(ctypes.Structure):
+    _fields_ = [('literal_prediction_mode', c_ubyte),
+                ('literal_context_map', POINTER(c_ubyte)),
+                ('literal_context_map_size', c_size_t),
+                ('distance_context_map', POINTER(c_ubyte)),
+                ('distance_context_map_size', c_size_t),
+                ('predmode_speed_and_distance_context_map', POINTER(c_ubyte)),
+                ('predmode_speed_and_distance_context_map_size', c_size_t),
+    ]
+class BrotliEncoderIRCommand(ctypes.Structure):
+    _fields_ = [('command_type', c_int),
+                ('num_bytes', c_uint32),
+                ('distance', c_uint32),
+                ('word_id', c_uint32),
+                ('word_size', c_ubyte),
+                ('transform', c_ubyte),
+                ('block_type', c_ubyte),
+                ('stride', c_ubyte),
+                ('high_entropy', c_ubyte),
+                ('data', POINTER(c_ubyte)),
+    ]
+BROTLI_IR_LITERAL = 0
+BROTLI_IR_COPY = 1
+BROTLI_IR_DICT = 2
+BROTLI_IR_BLOCK_SWITCH_COMMAND = 3
+BROTLI_IR_BLOCK_SWITCH_LITERAL = 4
+BROTLI_IR_BLOCK_SWITCH_DISTANCE = 5
+brotli_encoder_metablock_callback = ctypes.CFUNCTYPE(None,
+                                                     c_void_p,
+                                                     POINTER(BrotliEncoderIRPredictionMode),
+                                                     POINTER(BrotliEncoderIRCommand),
+                                                     c_size_t)
+_BrotliEncoderCreateInstance = brotli_library.BrotliEncoderCreateInstance
+_BrotliEncoderCreateInstance.restype = POINTER(BrotliEncoderState)
+_BrotliEncoderSetParameter = brotli_library.BrotliEncoderSetParameter
+_BrotliEncoderSetParameter.restype = c_int
+_BrotliEncoderSetMetablockCallback = brotli_library.BrotliEncoderSetMetablockCallback
+_BrotliEncoderSetMetablockCallback.restype = None
+_BrotliEncoderCompressStream = brotli_library.BrotliEncoderCompressStream
+_BrotliEncoderCompressStream.restype = c_int
+_BrotliEncoderIsFinished = brotli_library.BrotliEncoderIsFinished
+_BrotliEncoderIsFinished.restype = c_int
+_BrotliEncoderDestroyInstance = brotli_library.BrotliEncoderDestroyInstance
+_BrotliEncoderDestroyInstance.restype = None
+BROTLI_OPERATION_FINISH = 2
+
+def _string_at(ptr, size):
+    if size == 0:
+        return b''
+    return ctypes.string_at(ptr, size)
+
+def _ir_command(cmd):
+    if cmd.command_type == BROTLI_IR_LITERAL:
+        return ('insert', _string_at(cmd.data, cmd.num_bytes))
+    if cmd.command_type == BROTLI_IR_COPY:
+        return ('copy', cmd.num_bytes, cmd.distance)
+    if cmd.command_type == BROTLI_IR_DICT:
+        return ('dict', cmd.num_bytes, cmd.word_size, cmd.word_id, cmd.transform)
+    if cmd.command_type == BROTLI_IR_BLOCK_SWITCH_COMMAND:
+        return ('ctype', cmd.block_type)
+    if cmd.command_type == BROTLI_IR_BLOCK_SWITCH_LITERAL:
+        return ('ltype', cmd.block_type, cmd.stride)
+    return ('dtype', cmd.block_type)
+
+def BrotliCompressWithMetablockCallback(
+        any_input,
+        compression_options_map,
+        metablock_callback,
+        ):
+    """ compresses on a single thread, calling metablock_callback(prediction_mode, commands)
+    for every metablock, where prediction_mode is a dict of the context maps and
+    commands is a list of tuples such as ('insert', literals) or ('copy', length, distance) """
+    input = _fix_ctype_input_arrays(any_input)
+    def deliver(_opaque, prediction_mode, commands, num_commands):
+        pm = prediction_mode.contents
+        metablock_callback({
+            'literal_prediction_mode': pm.literal_prediction_mode,
+            'literal_context_map': _string_at(pm.literal_context_map, pm.literal_context_map_size),
+            'distance_context_map': _string_at(pm.distance_context_map, pm.distance_context_map_size),
+        }, [_ir_command(commands[i]) for i in range(num_commands)])
+    callback = brotli_encoder_metablock_callback(deliver)
+    state = _BrotliEncoderCreateInstance(c_void_p(), c_void_p(), c_void_p())
+    try:
+        for k, v in compression_options_map.items():
+            _BrotliEncoderSetParameter(state, c_uint(k), c_uint32(v))
+        _BrotliEncoderSetMetablockCallback(state, callback, c_void_p())
+        max_size = BrotliEncoderMaxCompressedSize(c_size_t(len(input)))
+        encoded = (c_ubyte * max_size)()
+        available_in = c_size_t(len(input))
+        input_buffer = (c_ubyte * len(input)).from_buffer_copy(input)
+        next_in = ctypes.cast(input_buffer, POINTER(c_ubyte))
+        available_out = c_size_t(max_size)
+        next_out = ctypes.cast(encoded, POINTER(c_ubyte))
+        ret_code = _BrotliEncoderCompressStream(state,
+                                                c_int(BROTLI_OPERATION_FINISH),
+                                                byref(available_in),
+                                                byref(next_in),
+                                                byref(available_out),
+                                                byref(next_out),
+                                                c_void_p())
+        if ret_code == 0 or not _BrotliEncoderIsFinished(state):
+            raise BrotliCompressorException("Unable to compress "
+                                            + str(len(input))
+                                            + " bytes")
+        return bytearray(encoded[:max_size - available_out.value])
+    finally:
+        _BrotliEncoderDestroyInstance(state)
+
 def BrotliParseHeader(raw_data):
     """ returns None or the version and size of the file """
     data = [ord(x) for x in raw_data[:min(len(raw_data), 16)]]
@@ -319,6 +431,11 @@ def main(args):
     work_pool = False
     decompress = False
     raw = False
+    ir = False
+    for (index, arg) in enumerate(args):
+        if arg == '-ir':
+            ir = True
+            args = args[:index] + args[index + 1:]
     for (index, arg) in enumerate(args):
         if arg == '-workpool':
             work_pool = True
@@ -339,7 +456,19 @@ def main(args):
                 data = f.read()
     else:
         data = sys.stdin.read()
-    if decompress and work_pool:
+    if ir:
+        def print_metablock(prediction_mode, commands):
+            sys.stderr.write('prediction %d lcontextmap %d dcontextmap %d\n' % (
+                prediction_mode['literal_prediction_mode'],
+                len(prediction_mode['literal_context_map']),
+                len(prediction_mode['distance_context_map'])))
+            for command in commands:
+                sys.stderr.write(' '.join(str(item) for item in command) + '\n')
+        processed = BrotliCompressWithMetablockCallback(data, {
+            BROTLI_PARAM_QUALITY:9,
+            BROTLI_PARAM_LGWIN: 16,
+        }, print_metablock)
+    elif decompress and work_pool:
         processed = BrotliDecode(data,2)
     elif decompress:
         processed = BrotliDecode(data)
@@ -24,6 +24,25 @@ class TestBrotliLibrary(unittest.TestCase):
         assert rt == self.test_data
         assert len(output) < 1024 * 1024
 
+    def test_metablock_callback(self):
+        metablocks = []
+        data = self.test_data[:256 * 1024]
+        output = BrotliCompressWithMetablockCallback(data,
+                                                     {BROTLI_PARAM_QUALITY:9,
+                                                      BROTLI_PARAM_LGWIN:18},
+                                                     lambda pm, cmds: metablocks.append((pm, cmds)))
+        assert BrotliDecode(output) == data
+        assert len(metablocks) != 0
+        total = 0
+        for prediction_mode, commands in metablocks:
+            assert prediction_mode['literal_prediction_mode'] < 4
+            for command in commands:
+                if command[0] == 'insert':
+                    total += len(command[1])
+                elif command[0] in ('copy', 'dict'):
+                    total += command[1]
+        assert total == len(data)
+
     def test_header(self):
         data = ''.join(chr(x) for x in [
             0x6b, 0x1d, 0x00, 0xe1, 0x97, 0x81, 0x01, 0xe8, 0x99, 0xf4, 0x01, 0x08,
@@ -0,0 +1,172 @@
+"""Brotli compression backed by rust-brotli.
+
+compress/decompress work on whole buffers, Compressor/Decompressor stream,
+WorkPool keeps threads around for multithreaded compression, concat and
+Concatenator join brotli streams without recompressing them and open()
+reads or writes brotli files.
+"""
+import builtins
+import io
+import os
+
+from ._rust_brotli import (
+    BrotliError,
+    Compressor,
+    Concatenator,
+    Decompressor,
+    MODE_FONT,
+    MODE_GENERIC,
+    MODE_TEXT,
+    WorkPool,
+    compress,
+    concat,
+    decompress,
+)
+
+__all__ = [
+    "BrotliError", "BrotliFile", "Compressor", "Concatenator", "Decompressor",
+    "MODE_FONT", "MODE_GENERIC", "MODE_TEXT", "WorkPool",
+    "compress", "concat", "decompress", "open",
+]
+
+READ_BUFFER_SIZE = 65536
+
+
+class _DecompressReader(io.RawIOBase):
+    def __init__(self, fp, dictionary):
+        self._fp = fp
+        self._decompressor = Decompressor(dictionary)
+        self._pending = b""
+
+    def readable(self):
+        return True
+
+    def readinto(self, b):
+        with memoryview(b) as view, view.cast("B") as byte_view:
+            while not self._pending:
+                if self._decompressor.is_finished():
+                    return 0
+                chunk = self._fp.read(READ_BUFFER_SIZE)
+                if not chunk:
+                    raise EOFError("Compressed file ended before the end-of-stream marker was reached")
+                self._pending = self._decompressor.process(chunk)
+            size = min(len(byte_view), len(self._pending))
+            byte_view[:size] = self._pending[:size]
+            self._pending = self._pending[size:]
+            return size
+
+
+class BrotliFile(io.BufferedIOBase):
+    """A file object that decompresses on read or compresses on write.
+
+    Extra keyword arguments are encoder parameters such as quality=9 or lgwin=22.
+    """
+
+    def __init__(self, filename, mode="rb", dictionary=None, **params):
+        mode = mode.replace("b", "")
+        if mode not in ("r", "w", "x"):
+            raise ValueError("Invalid mode: {!r}".format(mode))
+        if mode == "r" and params:
+            raise ValueError("Encoder parameters are only valid when writing")
+        if isinstance(filename, (str, bytes, os.PathLike)):
+            self._fp = builtins.open(filename, mode + "b")
+            self._close_fp = True
+        elif hasattr(filename, "read") or hasattr(filename, "write"):
+            self._fp = filename
+            self._close_fp = False
+        else:
+            raise TypeError("filename must be a str, bytes, PathLike or file object")
+        self._reading = mode == "r"
+        if self._reading:
+            self._buffer = io.BufferedReader(_DecompressReader(self._fp, dictionary))
+        else:
+            self._compressor = Compressor(dictionary, **params)
+
+    def _check_open(self):
+        if self.closed:
+            raise ValueError("I/O operation on closed file")
+
+    def _check_reading(self):
+        self._check_open()
+        if not self._reading:
+            raise io.UnsupportedOperation("File not open for reading")
+
+    def _check_writing(self):
+        self._check_open()
+        if self._reading:
+            raise io.UnsupportedOperation("File not open for writing")
+
+    def readable(self):
+        self._check_open()
+        return self._reading
+
+    def writable(self):
+        self._check_open()
+        return not self._reading
+
+    def seekable(self):
+        return False
+
+    def fileno(self):
+        self._check_open()
+        return self._fp.fileno()
+
+    def read(self, size=-1):
+        self._check_reading()
+        return self._buffer.read(size)
+
+    def read1(self, size=-1):
+        self._check_reading()
+        return self._buffer.read1(size)
+
+    def readinto(self, b):
+        self._check_reading()
+        return self._buffer.readinto(b)
+
+    def readline(self, size=-1):
+        self._check_reading()
+        return self._buffer.readline(size)
+
+    def peek(self, size=0):
+        self._check_reading()
+        return self._buffer.peek(size)
+
+    def write(self, data):
+        self._check_writing()
+        with memoryview(data) as view:
+            self._fp.write(self._compressor.process(view))
+            return view.nbytes
+
+    def flush(self):
+        self._check_open()
+        if not self._reading and self._compressor is not None:
+            self._fp.write(self._compressor.flush())
+            self._fp.flush()
+
+    def close(self):
+        if self.closed:
+            return
+        try:
+            if self._reading:
+                self._buffer.close()
+            else:
+                self._fp.write(self._compressor.finish())
+                self._compressor = None
+        finally:
+            try:
+                if self._close_fp:
+                    self._fp.close()
+            finally:
+                super().close()
+
+
+def open(filename, mode="rb", dictionary=None, encoding=None, errors=None, newline=None, **params):
+    """Opens a brotli file in binary ('rb', 'wb', 'xb') or text ('rt', 'wt', ...) mode."""
+    if "t" in mode:
+        if "b" in mode:
+            raise ValueError("Invalid mode: {!r}".format(mode))
+        binary_file = BrotliFile(filename, mode.replace("t", ""), dictionary, **params)
+        return io.TextIOWrapper(binary_file, encoding, errors, newline)
+    if encoding is not None or errors is not None or newline is not None:
+        raise ValueError("encoding, errors and newline are only valid in text mode")
+    return BrotliFile(filename, mode, dictionary, **params)
@@ -0,0 +1,162 @@
+import io
+import os
+import tempfile
+import unittest
+import rust_brotli
+from .testdata import *
+class TestRustBrotli(unittest.TestCase):
+    def setUp(self):
+        self.test_data = make_test_data(4096 * 1024)
+
+    def test_rt(self):
+        output = rust_brotli.compress(self.test_data, quality=5)
+        assert rust_brotli.decompress(output) == self.test_data
+        assert len(output) < 1024 * 1024
+
+    def test_memory_view(self):
+        output = rust_brotli.compress(memoryview(self.test_data), quality=5)
+        assert rust_brotli.decompress(bytearray(output)) == self.test_data
+
+    def test_streaming(self):
+        compressor = rust_brotli.Compressor(quality=9, lgwin=22)
+        pieces = []
+        for index in range(0, len(self.test_data), 100000):
+            pieces.append(compressor.process(self.test_data[index:index + 100000]))
+            if index == 500000:
+                pieces.append(compressor.flush())
+        pieces.append(compressor.finish())
+        assert compressor.is_finished()
+        output = b''.join(pieces)
+        decompressor = rust_brotli.Decompressor()
+        decoded = []
+        for index in range(0, len(output), 1000):
+            assert not decompressor.is_finished()
+            decoded.append(decompressor.process(output[index:index + 1000]))
+        assert decompressor.is_finished()
+        assert b''.join(decoded) == self.test_data
+
+    def test_flush_output_decodes(self):
+        compressor = rust_brotli.Compressor(quality=5)
+        output = compressor.process(self.test_data[:65536]) + compressor.flush()
+        decompressor = rust_brotli.Decompressor()
+        assert decompressor.process(output) == self.test_data[:65536]
+        assert not decompressor.is_finished()
+
+    def test_work_pool(self):
+        work_pool = rust_brotli.WorkPool(8)
+        assert work_pool.num_threads == 8
+        for _ in range(2):
+            output = rust_brotli.compress(self.test_data, work_pool=work_pool, quality=5)
+            assert rust_brotli.decompress(output) == self.test_data
+            assert len(output) < 1024 * 1024
+
+    def test_multithreaded_without_pool(self):
+        output = rust_brotli.compress(self.test_data, num_threads=4, quality=5)
+        assert rust_brotli.decompress(output) == self.test_data
+
+    def test_rnd(self):
+        random_data = os.urandom(131072)
+        output = rust_brotli.compress(random_data, work_pool=rust_brotli.WorkPool(8), quality=7)
+        assert rust_brotli.decompress(output) == random_data
+        assert len(output) > 130000
+
+    def test_dictionary(self):
+        dictionary = self.test_data[:65536]
+        data = self.test_data[65536:131072]
+        plain = rust_brotli.compress(data, quality=9, lgwin=22)
+        output = rust_brotli.compress(data, dictionary=dictionary, quality=9, lgwin=22)
+        assert len(output) < len(plain)
+        assert rust_brotli.decompress(output, dictionary=dictionary) == data
+        try:
+            assert rust_brotli.decompress(output) != data
+        except rust_brotli.BrotliError:
+            pass
+        decompressor = rust_brotli.Decompressor(dictionary)
+        assert decompressor.process(output) == data
+
+    def test_dictionary_work_pool(self):
+        dictionary = self.test_data[:65536]
+        data = self.test_data[65536:1024 * 1024]
+        output = rust_brotli.compress(data, dictionary=dictionary,
+                                      work_pool=rust_brotli.WorkPool(4), quality=5)
+        assert rust_brotli.decompress(output, dictionary=dictionary) == data
+
+    def test_concat(self):
+        half = len(self.test_data) // 2
+        first = rust_brotli.compress(self.test_data[:half], quality=5, appendable=True)
+        second = rust_brotli.compress(self.test_data[half:], quality=5, catable=True)
+        output = rust_brotli.concat([first, second])
+        assert rust_brotli.decompress(output) == self.test_data
+        concatenator = rust_brotli.Concatenator()
+        pieces = []
+        for stream in (first, second):
+            concatenator.new_file()
+            for index in range(0, len(stream), 4096):
+                pieces.append(concatenator.process(stream[index:index + 4096]))
+        pieces.append(concatenator.finish())
+        assert b''.join(pieces) == output
+
+    def test_concat_corrupt(self):
+        first = rust_brotli.compress(self.test_data[:65536], quality=5, appendable=True)
+        try:
+            rust_brotli.concat([first, b'\xff' * 64])
+        except rust_brotli.BrotliError:
+            pass
+        else:
+            assert False, "Should have errored"
+
+    def test_open(self):
+        with tempfile.TemporaryDirectory() as directory:
+            filename = os.path.join(directory, 'data.br')
+            with rust_brotli.open(filename, 'wb', quality=5) as f:
+                for index in range(0, len(self.test_data), 300000):
+                    f.write(self.test_data[index:index + 300000])
+            with open(filename, 'rb') as f:
+                assert rust_brotli.decompress(f.read()) == self.test_data
+            with rust_brotli.open(filename) as f:
+                assert f.read(10) == self.test_data[:10]
+                assert f.read() == self.test_data[10:]
+            text = self.test_data[:65536].decode('latin-1')
+            with rust_brotli.open(filename, 'wt', encoding='latin-1') as f:
+                f.write(text)
+            with rust_brotli.open(filename, 'rt', encoding='latin-1', newline='') as f:
+                assert f.read() == text
+
+    def test_open_file_object(self):
+        dictionary = self.test_data[:4096]
+        buf = io.BytesIO()
+        with rust_brotli.open(buf, 'wb', dictionary=dictionary) as f:
+            f.write(self.test_data[4096:65536])
+        assert not buf.closed
+        buf.seek(0)
+        with rust_brotli.open(buf, dictionary=dictionary) as f:
+            assert f.read() == self.test_data[4096:65536]
+
+    def test_unknown_parameter(self):
+        try:
+            rust_brotli.Compressor(qualty=5)
+        except TypeError:
+            pass
+        else:
+            assert False, "Should have errored"
+
+    def test_truncation(self):
+        output = rust_brotli.compress(self.test_data[:65536], quality=6)
+        try:
+            rust_brotli.decompress(output[:len(output) - 1])
+        except rust_brotli.BrotliError:
+            pass
+        else:
+            assert False, "Should have errored"
+
+    def test_corruption(self):
+        output = rust_brotli.compress(self.test_data[:65536], quality=6)
+        corrupt = output[:len(output) // 2] + output[len(output) // 2 + 1:]
+        try:
+            rust_brotli.decompress(corrupt)
+        except rust_brotli.BrotliError:
+            pass
+        else:
+            assert False, "Should have errored"
+if __name__ == '__main__':
+    unittest.main()
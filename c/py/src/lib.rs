@@ -0,0 +1,446 @@
+// Python bindings for rust-brotli: streaming Compressor/Decompressor objects, multithreaded
+// compression on a WorkPool, broccoli concatenation and custom dictionaries.
+use brotli::concat::{BroCatli, BroCatliResult};
+use brotli::enc::encode::{set_parameter, BrotliEncoderCompressStream, BrotliEncoderCreateInstance,
+                          BrotliEncoderHasMoreOutput, BrotliEncoderIsFinished,
+                          BrotliEncoderOperation, BrotliEncoderParameter,
+                          BrotliEncoderSetCustomDictionary, BrotliEncoderSetParameter,
+                          BrotliEncoderStateStruct};
+use brotli::enc::threading::{BrotliEncoderThreadError, CompressionThreadResult, Owned, SendAlloc};
+use brotli::enc::{compress_multi_with_dictionary, compress_worker_pool_with_dictionary, new_work_pool, Allocator,
+                  BrotliEncoderInitParams, BrotliEncoderMaxCompressedSizeMulti,
+                  BrotliEncoderParams, SliceWrapperMut, StandardAlloc, UnionHasher, WorkerPool};
+use brotli::{BrotliDecompressStream, BrotliResult, BrotliState};
+use pyo3::buffer::PyBuffer;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+create_exception!(rust_brotli, BrotliError, PyException);
+
+const BUFFER_SIZE: usize = 65536;
+// matches the number of per-thread allocators the C interface offers
+const MAX_THREADS: usize = 16;
+
+type Memory = <StandardAlloc as Allocator<u8>>::AllocatedMemory;
+type Pool = WorkerPool<CompressionThreadResult<StandardAlloc>,
+                       UnionHasher<StandardAlloc>,
+                       StandardAlloc,
+                       (Memory, BrotliEncoderParams, Memory)>;
+
+fn copy_to_memory(data: &[u8]) -> Memory {
+  let mut memory = <StandardAlloc as Allocator<u8>>::alloc_cell(&mut StandardAlloc::default(),
+                                                                data.len());
+  memory.slice_mut().copy_from_slice(data);
+  memory
+}
+
+fn parameter_from_name(name: &str) -> Option<BrotliEncoderParameter> {
+  use brotli::enc::encode::BrotliEncoderParameter::*;
+  Some(match name {
+    "mode" => BROTLI_PARAM_MODE,
+    "quality" => BROTLI_PARAM_QUALITY,
+    "lgwin" => BROTLI_PARAM_LGWIN,
+    "lgblock" => BROTLI_PARAM_LGBLOCK,
+    "disable_literal_context_modeling" => BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING,
+    "size_hint" => BROTLI_PARAM_SIZE_HINT,
+    "large_window" => BROTLI_PARAM_LARGE_WINDOW,
+    "q9_5" => BROTLI_PARAM_Q9_5,
+    "stride_detection_quality" => BROTLI_PARAM_STRIDE_DETECTION_QUALITY,
+    "high_entropy_detection_quality" => BROTLI_PARAM_HIGH_ENTROPY_DETECTION_QUALITY,
+    "literal_byte_score" => BROTLI_PARAM_LITERAL_BYTE_SCORE,
+    "cdf_adaptation_detection" => BROTLI_PARAM_CDF_ADAPTATION_DETECTION,
+    "prior_bitmask_detection" => BROTLI_PARAM_PRIOR_BITMASK_DETECTION,
+    "avoid_distance_prefix_search" => BROTLI_PARAM_AVOID_DISTANCE_PREFIX_SEARCH,
+    "catable" => BROTLI_PARAM_CATABLE,
+    "appendable" => BROTLI_PARAM_APPENDABLE,
+    "magic_number" => BROTLI_PARAM_MAGIC_NUMBER,
+    "no_dictionary" => BROTLI_PARAM_NO_DICTIONARY,
+    "favor_efficiency" => BROTLI_PARAM_FAVOR_EFFICIENCY,
+    "adaptive_quality" => BROTLI_PARAM_ADAPTIVE_QUALITY,
+    "auto_detect_content" => BROTLI_PARAM_AUTO_DETECT_CONTENT,
+    "skip_incompressible" => BROTLI_PARAM_SKIP_INCOMPRESSIBLE,
+    "deterministic" => BROTLI_PARAM_DETERMINISTIC,
+    "long_distance_matching" => BROTLI_PARAM_LONG_DISTANCE_MATCHING,
+    _ => return None,
+  })
+}
+
+// turns keyword arguments such as quality=9, catable=True into encoder parameters
+fn parse_params(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Vec<(BrotliEncoderParameter, u32)>> {
+  let mut ret = Vec::new();
+  if let Some(kwargs) = kwargs {
+    for (key, value) in kwargs.iter() {
+      let name: String = key.extract()?;
+      match parameter_from_name(&name) {
+        Some(param) => ret.push((param, value.extract::<u32>()?)),
+        None => return Err(PyTypeError::new_err(format!("unknown brotli parameter '{}'", name))),
+      }
+    }
+  }
+  Ok(ret)
+}
+
+fn invalid_parameter(param: BrotliEncoderParameter, value: u32) -> PyErr {
+  PyValueError::new_err(format!("invalid value {} for {:?}", value, param))
+}
+
+#[pyclass(module = "rust_brotli")]
+pub struct Compressor {
+  state: BrotliEncoderStateStruct<StandardAlloc>,
+  buffer: Vec<u8>,
+}
+
+impl Compressor {
+  fn compress(&mut self, op: BrotliEncoderOperation, input: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut available_in = input.len();
+    let mut input_offset = 0usize;
+    loop {
+      let mut available_out = self.buffer.len();
+      let mut output_offset = 0usize;
+      let ret = BrotliEncoderCompressStream(&mut self.state,
+                                            op,
+                                            &mut available_in,
+                                            input,
+                                            &mut input_offset,
+                                            &mut available_out,
+                                            &mut self.buffer[..],
+                                            &mut output_offset,
+                                            &mut None,
+                                            &mut |_a, _b, _c, _d| ());
+      output.extend_from_slice(&self.buffer[..output_offset]);
+      if ret <= 0 {
+        return None;
+      }
+      if available_in == 0 && BrotliEncoderHasMoreOutput(&self.state) == 0 &&
+        (op != BrotliEncoderOperation::BROTLI_OPERATION_FINISH ||
+         BrotliEncoderIsFinished(&self.state) != 0) {
+        return Some(output);
+      }
+    }
+  }
+  fn run<'py>(&mut self, py: Python<'py>, op: BrotliEncoderOperation, input: &[u8])
+              -> PyResult<Bound<'py, PyBytes>> {
+    match py.allow_threads(|| self.compress(op, input)) {
+      Some(output) => Ok(PyBytes::new_bound(py, &output)),
+      None => Err(BrotliError::new_err("brotli compression failed")),
+    }
+  }
+}
+
+#[pymethods]
+impl Compressor {
+  #[new]
+  #[pyo3(signature = (dictionary=None, **params))]
+  fn new(py: Python<'_>,
+         dictionary: Option<PyBuffer<u8>>,
+         params: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+    let mut state = BrotliEncoderCreateInstance(StandardAlloc::default());
+    for (param, value) in parse_params(params)? {
+      if BrotliEncoderSetParameter(&mut state, param, value) == 0 {
+        return Err(invalid_parameter(param, value));
+      }
+    }
+    if let Some(dictionary) = dictionary {
+      let dictionary = dictionary.to_vec(py)?;
+      BrotliEncoderSetCustomDictionary(&mut state, dictionary.len(), &dictionary);
+    }
+    Ok(Compressor {
+      state: state,
+      buffer: vec![0u8; BUFFER_SIZE],
+    })
+  }
+  // compresses data, returning whatever output is ready so far
+  fn process<'py>(&mut self, py: Python<'py>, data: PyBuffer<u8>) -> PyResult<Bound<'py, PyBytes>> {
+    let input = data.to_vec(py)?;
+    self.run(py, BrotliEncoderOperation::BROTLI_OPERATION_PROCESS, &input)
+  }
+  // returns all output for the data processed so far
+  fn flush<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+    self.run(py, BrotliEncoderOperation::BROTLI_OPERATION_FLUSH, &[])
+  }
+  // ends the stream and returns the remaining output
+  fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+    self.run(py, BrotliEncoderOperation::BROTLI_OPERATION_FINISH, &[])
+  }
+  fn is_finished(&self) -> bool {
+    BrotliEncoderIsFinished(&self.state) != 0
+  }
+}
+
+#[pyclass(module = "rust_brotli")]
+pub struct Decompressor {
+  state: BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>,
+  buffer: Vec<u8>,
+  finished: bool,
+}
+
+impl Decompressor {
+  fn decompress(&mut self, input: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::new();
+    let mut available_in = input.len();
+    let mut input_offset = 0usize;
+    while !self.finished {
+      let mut available_out = self.buffer.len();
+      let mut output_offset = 0usize;
+      let mut written = 0usize;
+      let result = BrotliDecompressStream(&mut available_in,
+                                          &mut input_offset,
+                                          input,
+                                          &mut available_out,
+                                          &mut output_offset,
+                                          &mut self.buffer[..],
+                                          &mut written,
+                                          &mut self.state);
+      output.extend_from_slice(&self.buffer[..output_offset]);
+      match result {
+        BrotliResult::ResultSuccess => self.finished = true,
+        BrotliResult::NeedsMoreOutput => {},
+        BrotliResult::NeedsMoreInput => return Ok(output),
+        BrotliResult::ResultFailure => return Err("corrupt brotli stream"),
+      }
+    }
+    if available_in != 0 {
+      return Err("data found after the end of the brotli stream");
+    }
+    Ok(output)
+  }
+}
+
+#[pymethods]
+impl Decompressor {
+  #[new]
+  #[pyo3(signature = (dictionary=None))]
+  fn new(py: Python<'_>, dictionary: Option<PyBuffer<u8>>) -> PyResult<Self> {
+    let state = match dictionary {
+      Some(dictionary) => BrotliState::new_with_custom_dictionary(
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+        copy_to_memory(&dictionary.to_vec(py)?)),
+      None => BrotliState::new(StandardAlloc::default(),
+                               StandardAlloc::default(),
+                               StandardAlloc::default()),
+    };
+    Ok(Decompressor {
+      state: state,
+      buffer: vec![0u8; BUFFER_SIZE],
+      finished: false,
+    })
+  }
+  // decompresses data, returning all the output it produces
+  fn process<'py>(&mut self, py: Python<'py>, data: PyBuffer<u8>) -> PyResult<Bound<'py, PyBytes>> {
+    let input = data.to_vec(py)?;
+    match py.allow_threads(|| self.decompress(&input)) {
+      Ok(output) => Ok(PyBytes::new_bound(py, &output)),
+      Err(msg) => Err(BrotliError::new_err(msg)),
+    }
+  }
+  fn is_finished(&self) -> bool {
+    self.finished
+  }
+}
+
+// a set of threads that multithreaded compression can reuse between calls
+#[pyclass(module = "rust_brotli")]
+pub struct WorkPool {
+  pool: Pool,
+  num_threads: usize,
+}
+
+#[pymethods]
+impl WorkPool {
+  #[new]
+  fn new(num_threads: usize) -> PyResult<Self> {
+    if num_threads == 0 || num_threads > MAX_THREADS {
+      return Err(PyValueError::new_err(format!("num_threads must be between 1 and {}", MAX_THREADS)));
+    }
+    Ok(WorkPool {
+      pool: new_work_pool(num_threads),
+      num_threads: num_threads,
+    })
+  }
+  #[getter]
+  fn num_threads(&self) -> usize {
+    self.num_threads
+  }
+}
+
+fn compress_parallel(params: &BrotliEncoderParams,
+                     input: &[u8],
+                     dictionary: &[u8],
+                     num_threads: usize,
+                     work_pool: Option<&mut Pool>) -> Result<Vec<u8>, BrotliEncoderThreadError> {
+  let mut output = vec![0u8; BrotliEncoderMaxCompressedSizeMulti(input.len(), num_threads)];
+  let mut owned_input = Owned::new(copy_to_memory(input));
+  let size = if let Some(pool) = work_pool {
+    let mut alloc_per_thread: Vec<_> = (0..num_threads).map(
+      |_| SendAlloc::new(StandardAlloc::default(), UnionHasher::Uninit)).collect();
+    compress_worker_pool_with_dictionary(params, &mut owned_input, copy_to_memory(dictionary), &mut output[..],
+                                         &mut alloc_per_thread[..], pool)?
+  } else {
+    let mut alloc_per_thread: Vec<_> = (0..num_threads).map(
+      |_| SendAlloc::new(StandardAlloc::default(), UnionHasher::Uninit)).collect();
+    compress_multi_with_dictionary(params, &mut owned_input, copy_to_memory(dictionary), &mut output[..],
+                                   &mut alloc_per_thread[..])?
+  };
+  output.truncate(size);
+  Ok(output)
+}
+
+// One-shot compression. With num_threads > 1 or a work_pool the input is split between threads
+// and the pieces are concatenated, costing a little density for speed.
+#[pyfunction]
+#[pyo3(signature = (data, dictionary=None, num_threads=None, work_pool=None, **params))]
+fn compress<'py>(py: Python<'py>,
+                 data: PyBuffer<u8>,
+                 dictionary: Option<PyBuffer<u8>>,
+                 num_threads: Option<usize>,
+                 work_pool: Option<PyRefMut<'py, WorkPool>>,
+                 params: Option<&Bound<'py, PyDict>>) -> PyResult<Bound<'py, PyBytes>> {
+  let num_threads = match (num_threads, work_pool.as_ref()) {
+    (Some(num_threads), _) => num_threads,
+    (None, Some(work_pool)) => work_pool.num_threads,
+    (None, None) => 1,
+  };
+  if num_threads == 0 || num_threads > MAX_THREADS {
+    return Err(PyValueError::new_err(format!("num_threads must be between 1 and {}", MAX_THREADS)));
+  }
+  if num_threads == 1 && work_pool.is_none() {
+    let mut compressor = Compressor::new(py, dictionary, params)?;
+    let input = data.to_vec(py)?;
+    return compressor.run(py, BrotliEncoderOperation::BROTLI_OPERATION_FINISH, &input);
+  }
+  let mut encoder_params = BrotliEncoderInitParams();
+  for (param, value) in parse_params(params)? {
+    if set_parameter(&mut encoder_params, param, value) == 0 {
+      return Err(invalid_parameter(param, value));
+    }
+  }
+  let dictionary = match dictionary {
+    Some(dictionary) => dictionary.to_vec(py)?,
+    None => Vec::new(),
+  };
+  let input = data.to_vec(py)?;
+  let mut work_pool = work_pool;
+  let pool = work_pool.as_mut().map(|work_pool| &mut work_pool.pool);
+  match py.allow_threads(|| compress_parallel(&encoder_params, &input, &dictionary, num_threads, pool)) {
+    Ok(output) => Ok(PyBytes::new_bound(py, &output)),
+    Err(err) => Err(BrotliError::new_err(format!("brotli compression failed: {:?}", err))),
+  }
+}
+
+#[pyfunction]
+#[pyo3(signature = (data, dictionary=None))]
+fn decompress<'py>(py: Python<'py>,
+                   data: PyBuffer<u8>,
+                   dictionary: Option<PyBuffer<u8>>) -> PyResult<Bound<'py, PyBytes>> {
+  let mut decompressor = Decompressor::new(py, dictionary)?;
+  let output = decompressor.process(py, data)?;
+  if !decompressor.finished {
+    return Err(BrotliError::new_err("brotli stream is truncated"));
+  }
+  Ok(output)
+}
+
+// Joins brotli streams into one without recompressing them. The first stream must be
+// compressed with appendable=True and the others with catable=True.
+#[pyclass(module = "rust_brotli")]
+pub struct Concatenator {
+  state: BroCatli,
+  buffer: Vec<u8>,
+}
+
+impl Concatenator {
+  fn concatenate(&mut self, input: &[u8]) -> PyResult<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut input_offset = 0usize;
+    loop {
+      let mut output_offset = 0usize;
+      let result = self.state.stream(input, &mut input_offset, &mut self.buffer[..], &mut output_offset);
+      output.extend_from_slice(&self.buffer[..output_offset]);
+      match result {
+        BroCatliResult::NeedsMoreOutput => {},
+        BroCatliResult::NeedsMoreInput => return Ok(output),
+        err => return Err(concat_error(err)),
+      }
+    }
+  }
+  fn finish_stream(&mut self) -> PyResult<Vec<u8>> {
+    let mut output = Vec::new();
+    loop {
+      let mut output_offset = 0usize;
+      let result = self.state.finish(&mut self.buffer[..], &mut output_offset);
+      output.extend_from_slice(&self.buffer[..output_offset]);
+      match result {
+        BroCatliResult::Success => return Ok(output),
+        BroCatliResult::NeedsMoreOutput => {},
+        err => return Err(concat_error(err)),
+      }
+    }
+  }
+}
+
+fn concat_error(err: BroCatliResult) -> PyErr {
+  BrotliError::new_err(format!("brotli concatenation failed: {:?}", err))
+}
+
+#[pymethods]
+impl Concatenator {
+  #[new]
+  #[pyo3(signature = (window_size=None))]
+  fn new(window_size: Option<u8>) -> Self {
+    Concatenator {
+      state: match window_size {
+        Some(window_size) => BroCatli::new_with_window_size(window_size),
+        None => BroCatli::new(),
+      },
+      buffer: vec![0u8; BUFFER_SIZE],
+    }
+  }
+  // marks the start of the next input stream
+  fn new_file(&mut self) {
+    self.state.new_brotli_file();
+  }
+  fn process<'py>(&mut self, py: Python<'py>, data: PyBuffer<u8>) -> PyResult<Bound<'py, PyBytes>> {
+    let output = self.concatenate(&data.to_vec(py)?)?;
+    Ok(PyBytes::new_bound(py, &output))
+  }
+  fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+    let output = self.finish_stream()?;
+    Ok(PyBytes::new_bound(py, &output))
+  }
+}
+
+#[pyfunction]
+#[pyo3(signature = (streams, window_size=None))]
+fn concat<'py>(py: Python<'py>,
+               streams: Vec<PyBuffer<u8>>,
+               window_size: Option<u8>) -> PyResult<Bound<'py, PyBytes>> {
+  let mut concatenator = Concatenator::new(window_size);
+  let mut output = Vec::new();
+  for stream in streams {
+    concatenator.new_file();
+    output.extend(concatenator.concatenate(&stream.to_vec(py)?)?);
+  }
+  output.extend(concatenator.finish_stream()?);
+  Ok(PyBytes::new_bound(py, &output))
+}
+
+#[pymodule]
+fn _rust_brotli(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add("BrotliError", m.py().get_type_bound::<BrotliError>())?;
+  m.add("MODE_GENERIC", 0)?;
+  m.add("MODE_TEXT", 1)?;
+  m.add("MODE_FONT", 2)?;
+  m.add_class::<Compressor>()?;
+  m.add_class::<Decompressor>()?;
+  m.add_class::<WorkPool>()?;
+  m.add_class::<Concatenator>()?;
+  m.add_function(wrap_pyfunction!(compress, m)?)?;
+  m.add_function(wrap_pyfunction!(decompress, m)?)?;
+  m.add_function(wrap_pyfunction!(concat, m)?)?;
+  Ok(())
+}
@@ -124,8 +124,7 @@ On the pallid bust of Pallas just above my chamber door;
     And the lamp-light o'er him streaming throws his shadow on the floor;
 And my soul from out that shadow that lies floating on the floor
             Shall be lifted-nevermore!"""
-	ret = []
-        for index in range(0, bufsize, len(poem)):
-            ret.append(''.join(chr((ord(x) + index)&255) for x in poem))
-	return ''.join(ret)[:bufsize]
-
+	ret = bytearray()
+	for index in range(0, bufsize, len(poem)):
+		ret.extend((x + index) & 255 for x in bytearray(poem))
+	return bytes(ret[:bufsize])
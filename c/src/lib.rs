@@ -8,10 +8,12 @@ pub use brotli::*;
 pub use brotli::ffi::compressor::*;
 pub use brotli::ffi::multicompress::*;
 pub use brotli::ffi::decompressor::*;
+pub use brotli::ffi::capabilities::*;
 use core::ptr::null_mut;
 #[cfg(feature="std")]
 unsafe fn std_only_functions() {
     let _ = brotli::ffi::decompressor::CBrotliDecoderDecompress(0, null_mut(), null_mut(), null_mut());
+    let _ = brotli::ffi::decompressor::CBrotliDecoderDecompressWithLimits(0, null_mut(), null_mut(), null_mut(), 0, 0, 0);
 }
 #[cfg(not(feature="std"))]
 unsafe fn std_only_functions() {
@@ -21,9 +23,18 @@ unsafe fn std_only_functions() {
 pub unsafe extern fn instantiate_functions(must_be_null: *const u8) {
     if !must_be_null.is_null() {
         let _ = brotli::ffi::compressor::BrotliEncoderVersion();
+        let _ = BrotliFFIAbiVersion();
+        let _ = BrotliFFICapabilities();
+        let _ = BroccoliStateSize();
+        let _ = BrotliEncoderStateSize();
+        let _ = CBrotliDecoderStateSize();
+        let _ = BrotliEncoderWorkPoolSize();
+        let _ = BrotliFFICheckAbi(0, 0, 0);
         let _ = brotli::ffi::decompressor::CBrotliDecoderCreateInstance(None, None, null_mut());
-        let _ = brotli::ffi::decompressor::CBrotliDecoderSetParameter(null_mut(), brotli::ffi::decompressor::ffi::interface::BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION, 0);
+        let _ = brotli::ffi::decompressor::CBrotliDecoderSetParameter(null_mut(), brotli::ffi::decompressor::CBrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION, 0);
         let _ = brotli::ffi::decompressor::CBrotliDecoderDecompressStream(null_mut(), null_mut(), null_mut(), null_mut(), null_mut(), null_mut());
+        let _ = brotli::ffi::decompressor::CBrotliDecoderGetLimitExceeded(null_mut());
+        let _ = brotli::ffi::decompressor::CBrotliDecoderInspectHeader(core::ptr::null(), 0, null_mut(), null_mut());
         std_only_functions();
         let _ = brotli::ffi::decompressor::CBrotliDecoderMallocU8(null_mut(), 0);
         let _ = brotli::ffi::decompressor::CBrotliDecoderMallocUsize(null_mut(), 0);
@@ -46,6 +57,7 @@ pub unsafe extern fn instantiate_functions(must_be_null: *const u8) {
         let _ = BrotliEncoderTakeOutput(null_mut(), null_mut());
         let _ = BrotliEncoderMaxCompressedSize(0);
         let _ = BrotliEncoderSetCustomDictionary(null_mut(), 0, null_mut());
+        let _ = BrotliEncoderSetProfile(null_mut(), 0, null_mut());
         let _ = BrotliEncoderCompress(0,0,BrotliEncoderMode::BROTLI_MODE_GENERIC, 0, null_mut(), null_mut(), null_mut());
         let _ = BrotliEncoderCompressStream(null_mut(), BrotliEncoderOperation::BROTLI_OPERATION_FINISH, null_mut(), null_mut(), null_mut(), null_mut(), null_mut());
         let _ = BrotliEncoderMallocU8(null_mut(), 0);
@@ -54,9 +66,17 @@ pub unsafe extern fn instantiate_functions(must_be_null: *const u8) {
         let _ = BrotliEncoderFreeUsize(null_mut(), null_mut(), 0);
         let _ = BrotliEncoderMaxCompressedSizeMulti(0,0);
         let _ = BrotliEncoderCompressMulti(0,null_mut(), null_mut(), 0, null_mut(), null_mut(), null_mut(), 0, None, None, null_mut());
+        let _ = BrotliEncoderCompressMultiWithDictionary(0,null_mut(), null_mut(), 0, null_mut(), 0, null_mut(), null_mut(), null_mut(), 0, None, None, null_mut());
         let _ = BrotliEncoderCreateWorkPool(0, None, None, null_mut());
         let _ = BrotliEncoderDestroyWorkPool(null_mut());
         let _ = BrotliEncoderCompressWorkPool(null_mut(), 0, null_mut(), null_mut(), 0, null_mut(), null_mut(), null_mut(), 0 ,None, None, null_mut());
+        let _ = BrotliEncoderCompressWorkPoolWithDictionary(null_mut(), 0, null_mut(), null_mut(), 0, null_mut(), 0, null_mut(), null_mut(), null_mut(), 0 ,None, None, null_mut());
+        let _ = BrotliEncoderCreateMultiStream(null_mut(), 0, null_mut(), null_mut(), 0, 0, None, None, null_mut());
+        let _ = BrotliEncoderDestroyMultiStream(null_mut());
+        let _ = BrotliEncoderMultiStreamCompress(null_mut(), BrotliEncoderOperation::BROTLI_OPERATION_FINISH, null_mut(), null_mut(), null_mut(), null_mut(), null_mut());
+        let _ = BrotliEncoderMultiStreamTakeOutput(null_mut(), null_mut());
+        let _ = BrotliEncoderMultiStreamHasMoreOutput(null_mut());
+        let _ = BrotliEncoderMultiStreamIsFinished(null_mut());
     }
 }
 
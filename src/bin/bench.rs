@@ -0,0 +1,448 @@
+// brotli bench: sweeps qualities, windows and thread counts over a corpus and reports the ratio
+// and the compression and decompression throughput of every combination as a table, CSV or JSON
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use brotli::enc::{BrotliAnalyzeContent, BrotliEncoderParams, UnionHasher, WorkerPool, new_work_pool};
+use brotli::enc::threading::CompressionThreadResult;
+use super::{compress, compress_multi, decompress, fail, has_stdlib, parse_quality, parse_value, HeapAllocator, Rebox};
+
+pub const ALL_QUALITIES: &'static [&'static str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9",
+                                                     "9.5", "9.5x", "9.5y", "10", "11"];
+// name used for the row summing every file of a setting
+pub const TOTAL: &'static str = "TOTAL";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+  Table,
+  Csv,
+  Json,
+}
+
+pub struct BenchOptions {
+  pub qualities: Vec<String>,
+  pub windows: Vec<i32>,
+  pub threads: Vec<usize>,
+  // untimed runs before the measured ones, to warm caches and the allocator
+  pub warmup: usize,
+  pub repeat: usize,
+  pub buffer_size: usize,
+  pub format: Format,
+  pub output: Option<String>,
+}
+
+impl Default for BenchOptions {
+  fn default() -> Self {
+    BenchOptions {
+      qualities: ALL_QUALITIES.iter().map(|q| q.to_string()).collect(),
+      windows: vec![22],
+      threads: vec![1],
+      warmup: 1,
+      repeat: 3,
+      buffer_size: 65536,
+      format: Format::Table,
+      output: None,
+    }
+  }
+}
+
+// timing of the repeated runs, in seconds
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+  pub min: f64,
+  pub median: f64,
+  pub mean: f64,
+  pub stddev: f64,
+}
+
+impl Stats {
+  pub fn new(samples: &[f64]) -> Stats {
+    if samples.len() == 0 {
+      return Stats::default();
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = sorted.len() / 2;
+    let median = if sorted.len() % 2 == 0 {
+      (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+      sorted[mid]
+    };
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    let variance = sorted.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / sorted.len() as f64;
+    Stats {
+      min: sorted[0],
+      median: median,
+      mean: mean,
+      stddev: variance.sqrt(),
+    }
+  }
+  fn add(&mut self, other: &Stats) {
+    self.min += other.min;
+    self.median += other.median;
+    self.mean += other.mean;
+    // the runs of different files are independent so their variances add up
+    self.stddev = (self.stddev * self.stddev + other.stddev * other.stddev).sqrt();
+  }
+}
+
+// megabytes of uncompressed data per second of the median run
+fn throughput(size: usize, stats: &Stats) -> f64 {
+  if stats.median <= 0.0 {
+    return 0.0;
+  }
+  size as f64 / 1000000.0 / stats.median
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+  pub file: String,
+  pub content_type: String,
+  pub quality: String,
+  pub lgwin: i32,
+  pub threads: usize,
+  pub input_size: usize,
+  pub compressed_size: usize,
+  pub compress: Stats,
+  pub decompress: Stats,
+}
+
+impl Record {
+  pub fn ratio(&self) -> f64 {
+    if self.compressed_size == 0 {
+      return 0.0;
+    }
+    self.input_size as f64 / self.compressed_size as f64
+  }
+  pub fn compress_speed(&self) -> f64 {
+    throughput(self.input_size, &self.compress)
+  }
+  pub fn decompress_speed(&self) -> f64 {
+    throughput(self.input_size, &self.decompress)
+  }
+}
+
+fn run_compress(data: &[u8], params: &BrotliEncoderParams, num_threads: usize, buffer_size: usize,
+                pool: &mut Option<WorkerPool<CompressionThreadResult<HeapAllocator>,
+                                             UnionHasher<HeapAllocator>,
+                                             HeapAllocator,
+                                             (Rebox<u8>, BrotliEncoderParams, Rebox<u8>)>>) -> io::Result<Vec<u8>> {
+  let mut output = Vec::with_capacity(data.len() / 2 + 1024);
+  if num_threads > 1 {
+    compress_multi(&mut &data[..], &mut output, params, &[], num_threads, pool.as_mut())?;
+  } else {
+    compress(&mut &data[..], &mut output, buffer_size, params, &[], 1)?;
+  }
+  Ok(output)
+}
+
+fn run_decompress(compressed: &[u8], size_hint: usize, buffer_size: usize) -> io::Result<Vec<u8>> {
+  let mut output = Vec::with_capacity(size_hint);
+  decompress(&mut &compressed[..], &mut output, buffer_size, Rebox::from(Vec::new()))?;
+  Ok(output)
+}
+
+fn time<T, F: FnMut() -> io::Result<T>>(warmup: usize, repeat: usize, mut f: F) -> io::Result<(T, Stats)> {
+  for _ in 0..warmup {
+    f()?;
+  }
+  let mut samples = Vec::with_capacity(repeat);
+  let mut result = None;
+  for _ in 0..core::cmp::max(repeat, 1) {
+    let start = Instant::now();
+    let ret = f()?;
+    samples.push(start.elapsed().as_secs_f64());
+    result = Some(ret);
+  }
+  Ok((result.unwrap(), Stats::new(&samples)))
+}
+
+pub fn content_type(data: &[u8]) -> String {
+  format!("{:?}", BrotliAnalyzeContent(&mut HeapAllocator::default(), data, 0, usize::MAX, data.len(), true).content_type)
+}
+
+// measures one file under every combination of the options, checking each round trip
+pub fn bench_data(name: &str, data: &[u8], options: &BenchOptions) -> io::Result<Vec<Record>> {
+  let content_type = content_type(data);
+  let mut records = Vec::new();
+  for &num_threads in options.threads.iter() {
+    let mut pool = if num_threads > 1 && has_stdlib() {
+      Some(new_work_pool(num_threads - 1))
+    } else {
+      None
+    };
+    for &lgwin in options.windows.iter() {
+      for quality in options.qualities.iter() {
+        let mut params = BrotliEncoderParams::default();
+        parse_quality(&mut params, "--quality", quality);
+        params.lgwin = lgwin;
+        params.large_window = lgwin > 24;
+        params.size_hint = data.len();
+        let (compressed, compress_stats) = time(options.warmup, options.repeat, || {
+          run_compress(data, &params, num_threads, options.buffer_size, &mut pool)
+        })?;
+        let (decompressed, decompress_stats) = time(options.warmup, options.repeat, || {
+          run_decompress(&compressed, data.len(), options.buffer_size)
+        })?;
+        if &decompressed[..] != data {
+          return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                    format!("{}: round trip mismatch at quality {} lgwin {} threads {}",
+                                            name, quality, lgwin, num_threads)));
+        }
+        records.push(Record {
+          file: name.to_string(),
+          content_type: content_type.clone(),
+          quality: quality.clone(),
+          lgwin: lgwin,
+          threads: num_threads,
+          input_size: data.len(),
+          compressed_size: compressed.len(),
+          compress: compress_stats,
+          decompress: decompress_stats,
+        });
+      }
+    }
+  }
+  Ok(records)
+}
+
+// appends a row per setting that sums the sizes and times of every file
+pub fn add_totals(records: &mut Vec<Record>) {
+  let mut totals: Vec<Record> = Vec::new();
+  for record in records.iter() {
+    let found = totals.iter().position(|t| t.quality == record.quality && t.lgwin == record.lgwin &&
+                                       t.threads == record.threads);
+    match found {
+      Some(index) => {
+        let total = &mut totals[index];
+        total.input_size += record.input_size;
+        total.compressed_size += record.compressed_size;
+        total.compress.add(&record.compress);
+        total.decompress.add(&record.decompress);
+        if total.content_type != record.content_type {
+          total.content_type = "Mixed".to_string();
+        }
+      },
+      None => {
+        let mut total = record.clone();
+        total.file = TOTAL.to_string();
+        totals.push(total);
+      },
+    }
+  }
+  records.extend(totals);
+}
+
+pub fn collect_files(path: &Path, files: &mut Vec<String>) -> io::Result<()> {
+  if fs::metadata(path)?.is_dir() {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+      entries.push(entry?.path());
+    }
+    entries.sort();
+    for entry in entries.iter() {
+      collect_files(entry, files)?;
+    }
+  } else {
+    files.push(path.to_string_lossy().into_owned());
+  }
+  Ok(())
+}
+
+const CSV_HEADER: &'static str = "file,content_type,quality,lgwin,threads,input_size,compressed_size,ratio,\
+compress_mb_per_s,compress_min_s,compress_median_s,compress_mean_s,compress_stddev_s,\
+decompress_mb_per_s,decompress_min_s,decompress_median_s,decompress_mean_s,decompress_stddev_s";
+
+fn csv_field(field: &str) -> String {
+  if field.contains(',') || field.contains('"') || field.contains('\n') {
+    format!("\"{}\"", field.replace("\"", "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+fn json_string(field: &str) -> String {
+  let mut ret = String::from("\"");
+  for c in field.chars() {
+    match c {
+      '"' => ret.push_str("\\\""),
+      '\\' => ret.push_str("\\\\"),
+      '\n' => ret.push_str("\\n"),
+      c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+      c => ret.push(c),
+    }
+  }
+  ret.push('"');
+  ret
+}
+
+fn json_stats(stats: &Stats) -> String {
+  format!("{{\"min_s\": {:.9}, \"median_s\": {:.9}, \"mean_s\": {:.9}, \"stddev_s\": {:.9}}}",
+          stats.min, stats.median, stats.mean, stats.stddev)
+}
+
+pub fn write_records<W: Write>(w: &mut W, records: &[Record], format: Format) -> io::Result<()> {
+  match format {
+    Format::Csv => {
+      writeln!(w, "{}", CSV_HEADER)?;
+      for r in records.iter() {
+        writeln!(w, "{},{},{},{},{},{},{},{:.4},{:.3},{:.9},{:.9},{:.9},{:.9},{:.3},{:.9},{:.9},{:.9},{:.9}",
+                      csv_field(&r.file), r.content_type, r.quality, r.lgwin, r.threads,
+                      r.input_size, r.compressed_size, r.ratio(),
+                      r.compress_speed(), r.compress.min, r.compress.median, r.compress.mean, r.compress.stddev,
+                      r.decompress_speed(), r.decompress.min, r.decompress.median, r.decompress.mean,
+                      r.decompress.stddev)?;
+      }
+    },
+    Format::Json => {
+      writeln!(w, "[")?;
+      for (index, r) in records.iter().enumerate() {
+        writeln!(w, "  {{\"file\": {}, \"content_type\": {}, \"quality\": {}, \"lgwin\": {}, \"threads\": {}, \
+\"input_size\": {}, \"compressed_size\": {}, \"ratio\": {:.4}, \"compress_mb_per_s\": {:.3}, \"compress\": {}, \
+\"decompress_mb_per_s\": {:.3}, \"decompress\": {}}}{}",
+                      json_string(&r.file), json_string(&r.content_type), json_string(&r.quality),
+                      r.lgwin, r.threads, r.input_size, r.compressed_size, r.ratio(),
+                      r.compress_speed(), json_stats(&r.compress),
+                      r.decompress_speed(), json_stats(&r.decompress),
+                      if index + 1 == records.len() { "" } else { "," })?;
+      }
+      writeln!(w, "]")?;
+    },
+    Format::Table => {
+      writeln!(w, "{:<32} {:<12} {:>5} {:>5} {:>7} {:>12} {:>12} {:>8} {:>10} {:>10} {:>9}",
+                    "file", "type", "q", "lgwin", "threads", "input", "compressed", "ratio",
+                    "comp MB/s", "dec MB/s", "stddev%")?;
+      for r in records.iter() {
+        let stddev_percent = if r.compress.mean > 0.0 { 100.0 * r.compress.stddev / r.compress.mean } else { 0.0 };
+        writeln!(w, "{:<32} {:<12} {:>5} {:>5} {:>7} {:>12} {:>12} {:>8.3} {:>10.2} {:>10.2} {:>9.1}",
+                      r.file, r.content_type, r.quality, r.lgwin, r.threads, r.input_size,
+                      r.compressed_size, r.ratio(), r.compress_speed(), r.decompress_speed(), stddev_percent)?;
+      }
+    },
+  }
+  Ok(())
+}
+
+fn parse_list<T: std::str::FromStr>(argument: &str, value: &str) -> Vec<T> {
+  value.split(',').filter(|v| v.len() != 0).map(|v| parse_value::<T>(argument, v)).collect()
+}
+
+pub fn parse_args(args: Vec<String>) -> (BenchOptions, Vec<String>) {
+  let mut options = BenchOptions::default();
+  let mut paths = Vec::new();
+  let mut args = args.into_iter();
+  let mut double_dash = false;
+  while let Some(argument) = args.next() {
+    if double_dash || !argument.starts_with("-") || argument == "-" {
+      paths.push(argument);
+      continue;
+    }
+    if argument == "--" {
+      double_dash = true;
+      continue;
+    }
+    // --flag=value, -xvalue or a separate value
+    let (flag, inline_value) = match argument.find('=') {
+      Some(index) if argument.starts_with("--") => (argument[..index].to_string(), Some(argument[index + 1..].to_string())),
+      _ if !argument.starts_with("--") && argument.len() > 2 => (argument[..2].to_string(), Some(argument[2..].to_string())),
+      _ => (argument.clone(), None),
+    };
+    let value = match inline_value.or_else(|| args.next()) {
+      Some(value) => value,
+      None => fail(format!("option requires an argument -- {}", flag)),
+    };
+    match &flag[..] {
+      "-q" | "--quality" => {
+        options.qualities = if value == "all" {
+          ALL_QUALITIES.iter().map(|q| q.to_string()).collect()
+        } else {
+          parse_list::<String>(&flag, &value)
+        };
+        // reject bad qualities before spending any time measuring
+        for quality in options.qualities.iter() {
+          parse_quality(&mut BrotliEncoderParams::default(), &flag, quality);
+        }
+      },
+      "-w" | "--lgwin" => options.windows = parse_list::<i32>(&flag, &value),
+      "-j" | "--threads" => options.threads = parse_list::<usize>(&flag, &value),
+      "--warmup" => options.warmup = parse_value::<usize>(&flag, &value),
+      "-n" | "--repeat" => options.repeat = parse_value::<usize>(&flag, &value),
+      "--format" => options.format = match &value[..] {
+        "table" => Format::Table,
+        "csv" => Format::Csv,
+        "json" => Format::Json,
+        _ => fail(format!("unknown format '{}': expected table, csv or json", value)),
+      },
+      "-o" | "--output" => options.output = Some(value),
+      _ => fail(format!("unknown bench option {}", flag)),
+    }
+  }
+  if options.qualities.len() == 0 || options.windows.len() == 0 || options.threads.len() == 0 {
+    fail("bench needs at least one quality, window and thread count");
+  }
+  if options.windows.iter().any(|&w| w < 10 || w > 30) {
+    fail("lgwin must be between 10 and 30");
+  }
+  if options.threads.iter().any(|&t| t == 0) {
+    fail("thread counts must be positive");
+  }
+  if options.repeat == 0 {
+    fail("--repeat must be at least 1");
+  }
+  (options, paths)
+}
+
+pub const USAGE: &'static str = "brotli bench [options] file|dir...\n\
+-q --quality LIST   qualities to sweep, e.g. 1,5,9,9.5,11 (default all: 0-11, 9.5, 9.5x, 9.5y)\n\
+-w --lgwin LIST     window sizes to sweep (default 22)\n\
+-j --threads LIST   thread counts to sweep (default 1)\n\
+--warmup N          untimed runs before measuring (default 1)\n\
+-n --repeat N       timed runs per measurement (default 3)\n\
+--format FORMAT     table, csv or json (default table)\n\
+-o --output FILE    write the report to FILE instead of stdout";
+
+// entry point for brotli bench, returns the exit status
+pub fn main(args: Vec<String>) -> i32 {
+  if args.iter().any(|a| a == "-h" || a == "--help") {
+    println!("{}", USAGE);
+    return super::cli::EXIT_OK;
+  }
+  let (options, paths) = parse_args(args);
+  if paths.len() == 0 {
+    fail(format!("bench needs at least one file or directory\n{}", USAGE));
+  }
+  let mut files = Vec::new();
+  for path in paths.iter() {
+    if let Err(e) = collect_files(Path::new(path), &mut files) {
+      fail(format!("{}: {}", path, e));
+    }
+  }
+  let mut records = Vec::new();
+  for file in files.iter() {
+    let data = match fs::read(file) {
+      Ok(data) => data,
+      Err(e) => fail(format!("{}: {}", file, e)),
+    };
+    match bench_data(file, &data, &options) {
+      Ok(r) => records.extend(r),
+      Err(e) => fail(e),
+    }
+  }
+  if files.len() > 1 {
+    add_totals(&mut records);
+  }
+  let result = match options.output {
+    Some(ref output) => File::create(output).and_then(|mut f| write_records(&mut f, &records, options.format)),
+    None => {
+      let stdout = io::stdout();
+      let mut lock = stdout.lock();
+      write_records(&mut lock, &records, options.format)
+    },
+  };
+  if let Err(e) = result {
+    fail(e);
+  }
+  super::cli::EXIT_OK
+}
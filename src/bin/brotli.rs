@@ -3,9 +3,18 @@
 mod test_broccoli;
 mod test_custom_dict;
 mod test_threading;
+mod test_cli;
+mod test_bench;
+#[cfg(feature="std")]
+mod test_tune;
+mod test_deterministic;
 pub mod integration_tests;
 mod tests;
 mod util;
+mod cli;
+mod bench;
+#[cfg(feature="std")]
+mod tune;
 
 extern crate brotli;
 extern crate brotli_decompressor;
@@ -15,8 +24,8 @@ extern crate sha2;
 #[allow(unused_imports)]
 #[macro_use]
 extern crate alloc_no_stdlib;
-use brotli::enc::{UnionHasher, BrotliEncoderParams, BrotliEncoderMaxCompressedSizeMulti, WorkerPool, compress_worker_pool, new_work_pool};
-use brotli::enc::threading::{SendAlloc,Owned, CompressionThreadResult, CompressMulti, BrotliEncoderThreadError};
+use brotli::enc::{UnionHasher, BrotliEncoderParams, BrotliEncoderParseProfile, ParamsProfile, BrotliEncoderMaxCompressedSizeMulti, WorkerPool, compress_worker_pool_with_dictionary, new_work_pool};
+use brotli::enc::threading::{SendAlloc,Owned, CompressionThreadResult, CompressMultiWithDictionary, BrotliEncoderThreadError, BrotliEncoderNumChunks};
 #[allow(unused_imports)]
 use brotli::{HuffmanCode};
 use brotli::CustomRead;
@@ -284,6 +293,7 @@ impl brotli::enc::BrotliAlloc for HeapAllocator {
 }
 pub fn compress_multi_nostd(
   input: Vec<u8>,
+  custom_dictionary: &[u8],
   output: &mut [u8],
   params:&BrotliEncoderParams,
   mut num_threads: usize,
@@ -309,24 +319,27 @@ pub fn compress_multi_nostd(
       if num_threads > alloc_array.len() {
         num_threads = alloc_array.len();
       }
-      CompressMulti(params, &mut Owned::new(Rebox::from(input)), output, &mut alloc_array[..num_threads], &mut util::MTSpawner::default())
+      CompressMultiWithDictionary(params, &mut Owned::new(Rebox::from(input)), Rebox::from(custom_dictionary.to_vec()), output,
+                                  &mut alloc_array[..num_threads], &mut util::MTSpawner::default())
 }
 pub fn compress_multi<InputType:Read,
                       OutputType:Write>(
   r: &mut InputType,
   w: &mut OutputType,
   params:&BrotliEncoderParams,
+  custom_dictionary: &[u8],
   mut num_threads: usize,
   work_pool: Option<&mut WorkerPool<CompressionThreadResult<HeapAllocator>,
                                     UnionHasher<HeapAllocator>,
                                     HeapAllocator,
-                                    (Rebox<u8>, BrotliEncoderParams)>>,
+                                    (Rebox<u8>, BrotliEncoderParams, Rebox<u8>)>>,
 ) -> Result<usize, io::Error> {
   let mut input: Vec<u8> = Vec::<u8>::new();
   if let Err(err) = r.read_to_end(&mut input) {
     return Err(err);
   }
-  let mut output = Rebox::from(vec![0u8;BrotliEncoderMaxCompressedSizeMulti(input.len(), num_threads)]);
+  let num_chunks = BrotliEncoderNumChunks(params, input.len(), num_threads);
+  let mut output = Rebox::from(vec![0u8;BrotliEncoderMaxCompressedSizeMulti(input.len(), num_chunks)]);
   let res = if let Some(worker_pool) = work_pool {
       let mut alloc_array = [
         SendAlloc::new(HeapAllocator::default(), UnionHasher::Uninit),
@@ -349,15 +362,16 @@ pub fn compress_multi<InputType:Read,
       if num_threads > alloc_array.len() {
         num_threads = alloc_array.len();
       }
-      compress_worker_pool(
+      compress_worker_pool_with_dictionary(
         params,
         &mut Owned::new(Rebox::from(input)),
+        Rebox::from(custom_dictionary.to_vec()),
         output.slice_mut(),
         &mut alloc_array[..num_threads],
         worker_pool,
       )
   } else {
-      compress_multi_nostd(input, output.slice_mut(), params, num_threads)  
+      compress_multi_nostd(input, custom_dictionary, output.slice_mut(), params, num_threads)
   };
   
   match res {
@@ -381,11 +395,12 @@ pub fn compress<InputType, OutputType>(r: &mut InputType,
                                        num_threads: usize) -> Result<usize, io::Error>
     where InputType: Read,
           OutputType: Write {
-    if num_threads > 1 && custom_dictionary.len() ==0 && !params.log_meta_block {
+    // deterministic output comes from the chunked compressor whatever the number of threads
+    if (num_threads > 1 || params.deterministic) && !params.log_meta_block {
       if has_stdlib() {
-        return compress_multi(r, w, params, num_threads, Some(&mut new_work_pool(num_threads - 1)));
+        return compress_multi(r, w, params, custom_dictionary, num_threads, Some(&mut new_work_pool(num_threads - 1)));
       } else {
-        return compress_multi(r, w, params, num_threads, None);
+        return compress_multi(r, w, params, custom_dictionary, num_threads, None);
       }
     }
     let mut alloc_u8 = HeapAllocator::default();
@@ -472,16 +487,93 @@ fn writeln_time<OutputType: Write>(strm: &mut OutputType,
 }
 
 
+// prints the message and exits with the error status instead of panicking
+fn fail<T: core::fmt::Display>(message: T) -> ! {
+  println_stderr!("brotli: {}", message);
+  std::process::exit(cli::EXIT_ERROR);
+}
+
+// removes the unfinished output of the legacy form before failing
+fn fail_legacy<T: core::fmt::Display>(output: cli::LegacyOutput, message: T) -> ! {
+  output.discard();
+  fail(message)
+}
+
+fn parse_value<T: std::str::FromStr>(argument: &str, value: &str) -> T {
+  match value.parse::<T>() {
+    Ok(v) => v,
+    Err(_) => fail(format!("invalid value '{}' for {}", value, argument)),
+  }
+}
+
+fn parse_quality(params: &mut BrotliEncoderParams, argument: &str, value: &str) {
+  match value {
+    "9.5" => { params.quality = 10; params.q9_5 = true; },
+    "9.5x" => { params.quality = 11; params.q9_5 = true; },
+    "9.5y" => { params.quality = 12; params.q9_5 = true; },
+    _ => {
+      params.q9_5 = false;
+      params.quality = parse_value::<i32>(argument, value);
+      if params.quality < 0 || params.quality > 11 {
+        fail(format!("quality must be between 0 and 11, not {}", value));
+      }
+    },
+  }
+}
+
+// splits bundled front end flags such as -dc or -kvf into separate arguments
+fn expand_short_flags(args: Vec<String>) -> Vec<String> {
+  let mut ret = Vec::new();
+  let mut double_dash = false;
+  for argument in args {
+    if argument == "--" {
+      double_dash = true;
+    }
+    if !double_dash && argument.len() > 2 && argument.starts_with("-") &&
+      argument[1..].chars().all(|c| "cdfknrtvzZ".contains(c)) {
+      for c in argument[1..].chars() {
+        ret.push(format!("-{}", c));
+      }
+    } else {
+      ret.push(argument);
+    }
+  }
+  ret
+}
+
+// applies a key=value profile as written by --print-params
+fn read_params_file(filename: &str, params: &mut BrotliEncoderParams) {
+  let profile = match std::fs::read_to_string(filename) {
+    Ok(profile) => profile,
+    Err(e) => fail(format!("{}: {}", filename, e)),
+  };
+  if let Err(e) = BrotliEncoderParseProfile(&profile, params) {
+    fail(format!("{}: {}", filename, e));
+  }
+}
+
 fn read_custom_dictionary(filename :&str) -> Vec<u8> {
   let mut dict = match File::open(&Path::new(&filename)) {
-    Err(why) => panic!("couldn't open custom dictionary {:}\n{:}", filename, why),
+    Err(why) => fail(format!("couldn't open custom dictionary {}: {}", filename, why)),
     Ok(file) => file,
   };
   let mut ret = Vec::<u8>::new();
-  dict.read_to_end(&mut ret).unwrap();
+  if let Err(e) = dict.read_to_end(&mut ret) {
+    fail(format!("couldn't read custom dictionary {}: {}", filename, e));
+  }
   ret
 }
 
+// the tune subcommand builds on the std-only tuner
+#[cfg(feature="std")]
+fn tune_usage() -> &'static str {
+  tune::USAGE
+}
+#[cfg(not(feature="std"))]
+fn tune_usage() -> &'static str {
+  "brotli tune requires the std feature"
+}
+
 #[cfg(feature="std")]
 fn has_stdlib() -> bool {
     true
@@ -491,29 +583,157 @@ fn has_stdlib() -> bool {
     false
 }
 
+// prints the window a stream declares without decoding it
+fn inspect_header<InputType: Read>(r: &mut InputType) {
+  // BrotliReadHeader needs std, so feed the no_std inspector one byte at a time
+  let mut header = [0u8; 2];
+  let mut header_len = 0;
+  loop {
+    match brotli::BrotliInspectHeader(&header[..header_len]) {
+      Ok(parsed) => {
+        println!("window_bits {} large_window {} window_memory {}",
+                 parsed.window_bits, parsed.large_window, parsed.window_memory());
+        return;
+      },
+      Err(brotli::BrotliHeaderError::Corrupt) => fail("Invalid brotli stream header"),
+      Err(brotli::BrotliHeaderError::NeedsMoreInput) => {},
+    }
+    match r.read(&mut header[header_len..header_len + 1]) {
+      Ok(0) => fail("Truncated brotli stream header"),
+      Ok(size) => header_len += size,
+      Err(e) => if e.kind() != io::ErrorKind::Interrupted {
+        fail(format!("{} reading brotli header", e));
+      },
+    }
+  }
+}
+
+// compresses newdata (or stdin) onto the end of an appendable .br file in place
+#[cfg(feature="std")]
+fn append_to_file(existing: &str, newdata: &str, params: &brotli::enc::BrotliEncoderParams) {
+  let mut data = Vec::<u8>::new();
+  let read_result = if newdata != "" {
+    match File::open(&Path::new(newdata)) {
+      Err(why) => fail(format!("{}: {}", newdata, why)),
+      Ok(mut file) => file.read_to_end(&mut data),
+    }
+  } else {
+    io::stdin().read_to_end(&mut data)
+  };
+  if let Err(e) = read_result {
+    fail(e);
+  }
+  let mut file = match std::fs::OpenOptions::new().read(true).write(true).create(true).open(&Path::new(existing)) {
+    Err(why) => fail(format!("{}: {}", existing, why)),
+    Ok(file) => file,
+  };
+  if let Err(e) = brotli::concat::BrotliAppendToStream(&mut file, &data[..], params) {
+    fail(format!("{:?} appending to {}", e, existing));
+  }
+}
+#[cfg(not(feature="std"))]
+fn append_to_file(_existing: &str, _newdata: &str, _params: &brotli::enc::BrotliEncoderParams) {
+  fail("--append requires the std feature");
+}
+
 fn main() {
   let mut buffer_size = 65536;
   let mut do_compress = false;
   let mut do_validate = false;
+  let mut do_inspect = false;
+  let mut do_append = false;
   let mut params = brotli::enc::BrotliEncoderInitParams();
   let mut custom_dictionary = Vec::<u8>::new();
   let mut use_work_pool = has_stdlib();
   params.quality = 11; // default
-  let mut filenames = [std::string::String::new(), std::string::String::new()];
+  let mut filenames = Vec::<String>::new();
   let mut num_benchmarks = 1;
   let mut double_dash = false;
   let mut num_threads = 1;
+  // any gzip-style option switches to the multi-file front end, where -c means --stdout
+  let mut options = cli::Options::default();
+  let mut gzip_style = false;
+  let mut stdout_flag = false;
+  let mut print_params = false;
+  if env::args().nth(1).map_or(false, |a| a == "bench") {
+    std::process::exit(bench::main(env::args().skip(2).collect()));
+  }
+  #[cfg(feature="std")]
+  {
+    if env::args().nth(1).map_or(false, |a| a == "tune") {
+      std::process::exit(tune::main(env::args().skip(2).collect()));
+    }
+  }
   if env::args_os().len() > 1 {
-    let mut first = true;
-    for argument in env::args() {
-      if first {
-        first = false;
-        continue;
-      }
+    let mut args = expand_short_flags(env::args().skip(1).collect()).into_iter();
+    while let Some(argument) = args.next() {
       if argument == "--" {
         double_dash = true;
         continue;
       }
+      if !double_dash {
+        let (flag, inline_value) = match argument.find('=') {
+          Some(index) if argument.starts_with("--") => (argument[..index].to_string(), Some(argument[index + 1..].to_string())),
+          _ => (argument.clone(), None),
+        };
+        match &flag[..] {
+          "-o" | "--output" | "-S" | "--suffix" | "-q" | "--quality" | "-w" | "--lgwin" | "-D" | "--dictionary" |
+          "--params-file" => {
+            let value = match inline_value.or_else(|| args.next()) {
+              Some(value) => value,
+              None => fail(format!("option requires an argument -- {}", flag)),
+            };
+            match &flag[..] {
+              "-o" | "--output" => {
+                options.output = Some(value);
+                gzip_style = true;
+              },
+              "-S" | "--suffix" => {
+                if value.len() == 0 {
+                  fail("the suffix must not be empty");
+                }
+                options.suffix = value;
+                gzip_style = true;
+              },
+              "-q" | "--quality" => parse_quality(&mut params, &flag, &value),
+              "-w" | "--lgwin" => params.lgwin = parse_value::<i32>(&flag, &value),
+              "--params-file" => read_params_file(&value, &mut params),
+              _ => custom_dictionary = read_custom_dictionary(&value),
+            }
+            continue;
+          },
+          _ => {},
+        }
+        let front_end_flag = match &flag[..] {
+          "-d" | "--decompress" | "--uncompress" => { options.operation = cli::Operation::Decompress; true },
+          "-z" | "--compress" => { options.operation = cli::Operation::Compress; true },
+          "-t" | "--test" => { options.operation = cli::Operation::Test; true },
+          "-k" | "--keep" => { options.keep = true; true },
+          "--rm" => { options.keep = false; true },
+          "-f" | "--force" => { options.force = true; true },
+          "--stdout" | "--to-stdout" => { stdout_flag = true; true },
+          "-r" | "--recursive" => { options.recursive = true; true },
+          "-v" | "--verbose" => { options.verbose = true; true },
+          "-n" | "--no-copy-stat" => { options.copy_stat = false; true },
+          "-Z" | "--best" => {
+            params.quality = 11;
+            continue;
+          },
+          "--print-params" => {
+            print_params = true;
+            continue;
+          },
+          "-V" | "--version" => {
+            println!("brotli {}", env!("CARGO_PKG_VERSION"));
+            return;
+          },
+          _ => false,
+        };
+        if front_end_flag {
+          gzip_style = true;
+          continue;
+        }
+      }
       if (argument == "-catable" || argument == "--catable") && !double_dash {
           params.catable = true;
           params.use_dictionary = false;
@@ -559,6 +779,26 @@ fn main() {
           params.mode = BrotliEncoderMode::BROTLI_FORCE_SIGNED_PRIOR;
           continue;
       }
+      if (argument == "-autodetect" || argument == "--autodetect") && !double_dash {
+          params.auto_detect_content = true;
+          continue;
+      }
+      if (argument == "-skipincompressible" || argument == "--skipincompressible") && !double_dash {
+          params.skip_incompressible = true;
+          continue;
+      }
+      if (argument == "-long" || argument == "--long") && !double_dash {
+          params.long_distance_matching = true;
+          continue;
+      }
+      if (argument == "-append" || argument == "--append") && !double_dash {
+          do_append = true;
+          continue;
+      }
+      if (argument == "-inspect" || argument == "--inspect") && !double_dash {
+          do_inspect = true;
+          continue;
+      }
       if argument == "-efficient" && !double_dash {
           params.favor_cpu_efficiency = true;
           continue;
@@ -636,23 +876,44 @@ fn main() {
         continue;
       }
       if argument.starts_with("-l") && !double_dash {
-        params.lgblock = argument.trim_matches('-').trim_matches('l').parse::<i32>().unwrap();
+        params.lgblock = parse_value::<i32>(&argument, argument.trim_matches('-').trim_matches('l'));
         continue;
       }
       if argument.starts_with("-j") && !double_dash {
         num_threads = core::cmp::min(
           core::cmp::max(
             1,
-            argument.trim_matches('-').trim_matches('j').parse::<i32>().unwrap() as usize),
+            parse_value::<i32>(&argument, argument.trim_matches('-').trim_matches('j')) as usize),
           MAX_THREADS);
         continue;
       }
+      if (argument == "-deterministic" || argument == "--deterministic") && !double_dash {
+        params.deterministic = true;
+        continue;
+      }
+      if argument.starts_with("-minchunk=") && !double_dash {
+        params.min_chunk_size = parse_value::<usize>(&argument, &argument["-minchunk=".len()..]);
+        continue;
+      }
+      if argument.starts_with("-maxchunk=") && !double_dash {
+        params.max_chunk_size = parse_value::<usize>(&argument, &argument["-maxchunk=".len()..]);
+        continue;
+      }
+      if argument.starts_with("-splitat=") && !double_dash {
+        // align the boundaries between thread chunks to just after this byte
+        params.chunk_delimiter = match &argument["-splitat=".len()..] {
+          "newline" => Some(b'\n'),
+          "nul" => Some(0),
+          value => Some(parse_value::<u8>(&argument, value)),
+        };
+        continue;
+      }
       if argument.starts_with("-bytescore=") && !double_dash {
-        params.hasher.literal_byte_score = argument.trim_matches('-').trim_matches('b').trim_matches('y').trim_matches('t').trim_matches('e').trim_matches('s').trim_matches('c').trim_matches('o').trim_matches('r').trim_matches('e').trim_matches('=').parse::<i32>().unwrap();
+        params.hasher.literal_byte_score = parse_value::<i32>(&argument, argument.trim_matches('-').trim_matches('b').trim_matches('y').trim_matches('t').trim_matches('e').trim_matches('s').trim_matches('c').trim_matches('o').trim_matches('r').trim_matches('e').trim_matches('='));
         continue;
       }
       if argument.starts_with("-w") && !double_dash {
-          params.lgwin = argument.trim_matches('-').trim_matches('w').parse::<i32>().unwrap();
+          params.lgwin = parse_value::<i32>(&argument, argument.trim_matches('-').trim_matches('w'));
           continue;
       }
       if (argument == "-validate" || argument == "--validate") && !double_dash {
@@ -660,11 +921,11 @@ fn main() {
           continue;
       }
       if argument.starts_with("-bs") && !double_dash {
-          buffer_size = argument.trim_matches('-').trim_matches('b').trim_matches('s').trim_matches('=').parse::<usize>().unwrap();
+          buffer_size = parse_value::<usize>(&argument, argument.trim_matches('-').trim_matches('b').trim_matches('s').trim_matches('='));
           continue;
       }
       if argument.starts_with("-l") && !double_dash {
-          params.lgblock = argument.trim_matches('-').trim_matches('l').parse::<i32>().unwrap();
+          params.lgblock = parse_value::<i32>(&argument, argument.trim_matches('-').trim_matches('l'));
           continue;
       }
       if argument.starts_with("-findprior") && !double_dash {
@@ -672,7 +933,7 @@ fn main() {
           continue;
       }
       if argument.starts_with("-findspeed=") && !double_dash {
-          params.cdf_adaptation_detection = argument.trim_matches('-').trim_matches('f').trim_matches('i').trim_matches('n').trim_matches('d').trim_matches('r').trim_matches('a').trim_matches('n').trim_matches('d').trim_matches('o').trim_matches('m').trim_matches('=').parse::<u32>().unwrap() as u8;
+          params.cdf_adaptation_detection = parse_value::<u32>(&argument, argument.trim_matches('-').trim_matches('f').trim_matches('i').trim_matches('n').trim_matches('d').trim_matches('r').trim_matches('a').trim_matches('n').trim_matches('d').trim_matches('o').trim_matches('m').trim_matches('=')) as u8;
           continue;
       } else if argument == "-findspeed" && !double_dash {
           params.cdf_adaptation_detection = 1;
@@ -690,7 +951,7 @@ fn main() {
               continue;
           } else {
               if (argument.starts_with("-s") && !argument.starts_with("-speed=")) && !double_dash {
-                  params.size_hint = argument.trim_matches('-').trim_matches('s').parse::<usize>().unwrap();
+                  params.size_hint = parse_value::<usize>(&argument, argument.trim_matches('-').trim_matches('s'));
                   continue;
               }
           }
@@ -699,7 +960,7 @@ fn main() {
           let comma_string = argument.trim_matches('-').trim_matches('s').trim_matches('p').trim_matches('e').trim_matches('e').trim_matches('d').trim_matches('=');
           let split = comma_string.split(",");
           for (index, s) in split.enumerate() {
-              let data = s.parse::<u16>().unwrap();
+              let data = parse_value::<u16>(&argument, s);
               if data > 16384 {
                   println_stderr!("Speed must be <= 16384, not {}", data);
               }
@@ -723,38 +984,60 @@ fn main() {
       }
       if argument == "-avoiddistanceprefixsearch" && !double_dash {
           params.avoid_distance_prefix_search = true;
+          continue;
       }
       if argument.starts_with("-b") && !double_dash {
-          num_benchmarks = argument.trim_matches('-').trim_matches('b').parse::<usize>().unwrap();
+          num_benchmarks = parse_value::<usize>(&argument, argument.trim_matches('-').trim_matches('b'));
           continue;
       }
       if argument == "-c" && !double_dash {
         do_compress = true;
+        stdout_flag = true;
         continue;
       }
       if argument == "-h" || argument == "-help" || argument == "--help" && !double_dash {
-        println_stderr!("Decompression:\nbrotli [input_file] [output_file]\nCompression:brotli -c -q9.5 -w22 [input_file] [output_file]\nQuality may be one of -q9.5 -q9.5x -q9.5y or -q[0-11] for standard brotli settings.\nOptional size hint -s<size> to direct better compression\n\nThe -i parameter produces a cross human readdable IR representation of the file.\nThis can be ingested by other compressors.\nIR-specific options include:\n-findprior\n-speed=<inc,max,inc,max,inc,max,inc,max>");
+        println_stderr!("Decompression:\nbrotli [input_file] [output_file]\nCompression:brotli -c -q9.5 -w22 [input_file] [output_file]\nQuality may be one of -q9.5 -q9.5x -q9.5y or -q[0-11] for standard brotli settings.\nOptional size hint -s<size> to direct better compression\nThis legacy form only replaces output_file once input_file was processed successfully\n\nMultithreaded (-j) options: -minchunk=<bytes> -maxchunk=<bytes> bound the size of each thread's chunk,\n-splitat=<newline|nul|byte> moves chunk boundaries to just after that byte,\n-deterministic writes the same bytes for any -j and platform\n\n-long also searches for repeats far back in the window, which the regular match finders forget\n\nThe -i parameter produces a cross human readdable IR representation of the file.\nThis can be ingested by other compressors.\nIR-specific options include:\n-findprior\n-speed=<inc,max,inc,max,inc,max,inc,max>\n\n-inspect prints the window size declared by a compressed file without decoding it\n--append existing.br [newdata] compresses newdata (or stdin) onto the end of an appendable file in place\n\nMulti-file usage like gzip, selected by any of the options below or by more than two files (-c then means --stdout):\nbrotli [-z|-d|-t] [-k|--rm] [-f] [-c] [-o file] [-S suffix] [-r] [-v] [-n] [-q quality] [-w lgwin] [-D dictionary] [file...]\n-z --compress, -d --decompress, -t --test integrity\n-k --keep input files (default), --rm remove them\n-f --force overwrite existing files and write compressed data to a terminal\n-c --stdout write to standard output, -o --output write to the given file\n-S --suffix use the given suffix instead of .br\n-r --recursive operate on directories, -v --verbose print ratios\n-n --no-copy-stat do not copy timestamps and permissions to the output\n--params-file FILE applies an encoder profile, --print-params prints the profile of the options given before it and exits\nExit status is 0 on success, 1 on errors and 2 when files were skipped with a warning\n\n{}\n\n{}", bench::USAGE, tune_usage());
         return;
       }
-      if filenames[0] == "" {
-         filenames[0] = argument.clone();
-         continue;
+      if argument.starts_with("-") && argument != "-" && !double_dash {
+        fail(format!("unknown option {}\nTry 'brotli --help' for more information.", argument));
       }
-      if filenames[1] == "" {
-         filenames[1] = argument.clone();
-         continue;
+      filenames.push(argument.clone());
+   }
+   if print_params {
+      print!("{}", ParamsProfile(&params));
+      return;
+   }
+   // only `brotli [input [output]]` keeps the legacy meaning; longer file lists are multi-file
+   if gzip_style || filenames.len() > 2 {
+      if do_validate || do_append || do_inspect || params.log_meta_block || num_benchmarks != 1 {
+        fail("-validate, --append, --inspect, -i and -b cannot be combined with the multi-file options or more than two files");
+      }
+      options.to_stdout = stdout_flag;
+      let status = cli::FrontEnd::new(&options, &params, &custom_dictionary[..], buffer_size, num_threads).run(&filenames);
+      std::process::exit(status);
+   }
+   let filenames = [filenames.get(0).cloned().unwrap_or_default(), filenames.get(1).cloned().unwrap_or_default()];
+   if do_append {
+      if filenames[0] == "" {
+        fail("--append requires the file to append to");
       }
-      panic!("Unknown Argument {:}", argument);
+      append_to_file(&filenames[0], &filenames[1], &params);
+      return;
    }
    if filenames[0] != "" {
       let mut input = match File::open(&Path::new(&filenames[0])) {
-        Err(why) => panic!("couldn't open {:}\n{:}", filenames[0], why),
+        Err(why) => fail(format!("{}: {}", filenames[0], why)),
         Ok(file) => file,
       };
+      if do_inspect {
+        inspect_header(&mut input);
+        return;
+      }
       if filenames[1] != "" {
-        let mut output = match File::create(&Path::new(&filenames[1])) {
-          Err(why) => panic!("couldn't open file for writing: {:}\n{:}", filenames[1], why),
-          Ok(file) => file,
+        let mut output = match cli::LegacyOutput::create(&Path::new(&filenames[1])) {
+          Err(why) => fail(format!("{}: {}", filenames[1], why)),
+          Ok(output) => output,
         };
         let mut worker_pool = if num_threads != 1 && do_compress && use_work_pool && !do_validate {
           Some(new_work_pool(num_threads - 1))
@@ -767,26 +1050,26 @@ fn main() {
             if num_benchmarks > 0 {
               custom_dictionary = dict.clone();
             }
-            match validate::compress_validate(&mut input, &mut output, buffer_size, &params, dict.into(), num_threads) {
+            match validate::compress_validate(&mut input, &mut output.file, buffer_size, &params, dict.into(), num_threads) {
               Ok(_) => {}
-              Err(e) => panic!("Error {:?}", e),
+              Err(e) => fail_legacy(output, e),
             }
           } else if do_compress {
             if let Some(ref mut work_pool) = worker_pool {
-              match compress_multi(&mut input, &mut output, &params, num_threads, Some(work_pool)) {
+              match compress_multi(&mut input, &mut output.file, &params, &custom_dictionary[..], num_threads, Some(work_pool)) {
                 Ok(_) => {}
-                Err(e) => panic!("Error {:?}", e),
+                Err(e) => fail_legacy(output, e),
               }
             } else {
               if num_threads != 1 {
-                match compress_multi(&mut input, &mut output, &params, num_threads, None) {
+                match compress_multi(&mut input, &mut output.file, &params, &custom_dictionary[..], num_threads, None) {
                   Ok(_) => {}
-                  Err(e) => panic!("Error {:?}", e),
+                  Err(e) => fail_legacy(output, e),
                 }
               } else {
-                match compress(&mut input, &mut output, buffer_size, &params, &custom_dictionary[..], num_threads) {
+                match compress(&mut input, &mut output.file, buffer_size, &params, &custom_dictionary[..], num_threads) {
                   Ok(_) => {}
-                  Err(e) => panic!("Error {:?}", e),
+                  Err(e) => fail_legacy(output, e),
                 }
               }
             }
@@ -795,75 +1078,88 @@ fn main() {
             if num_benchmarks > 0 {
               custom_dictionary = dict.clone();
             }
-            match decompress(&mut input, &mut output, buffer_size, dict.into()) {
+            match decompress(&mut input, &mut output.file, buffer_size, dict.into()) {
               Ok(_) => {}
-              Err(e) => panic!("Error: {:} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e),
+              Err(e) => fail_legacy(output, format!("{} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e)),
             }
           }
           if i + 1 != num_benchmarks {
-              input.seek(SeekFrom::Start(0)).unwrap();
-              output.seek(SeekFrom::Start(0)).unwrap();
+              if let Err(e) = input.seek(SeekFrom::Start(0)).and_then(|_| output.file.seek(SeekFrom::Start(0))) {
+                fail_legacy(output, e);
+              }
           }
         }
-        drop(output);
+        if let Err(e) = output.commit() {
+          fail(format!("{}: {}", filenames[1], e));
+        }
       } else {
-        assert_eq!(num_benchmarks, 1);
+        if num_benchmarks != 1 {
+      fail("-b needs an input and an output file");
+    }
         if do_validate {
           if do_compress {
             match validate::compress_validate(&mut input, &mut io::stdout(), buffer_size, &params, custom_dictionary.into(), num_threads) {
               Ok(_) => {}
-              Err(e) => panic!("Error {:?}", e),
+              Err(e) => fail(e),
             }
           } else {
             match validate::compress_validate(&mut input, &mut io::sink(), buffer_size, &params, custom_dictionary.into(), num_threads) {
               Ok(_) => {}
-              Err(e) => panic!("Error {:?}", e),
+              Err(e) => fail(e),
             }
           }
         } else if do_compress {
           match compress(&mut input, &mut io::stdout(), buffer_size, &params, &custom_dictionary[..], num_threads) {
             Ok(_) => {}
-            Err(e) => panic!("Error {:?}", e),
+            Err(e) => fail(e),
           }
         } else {
           match decompress(&mut input, &mut io::stdout(), buffer_size, custom_dictionary.into()) {
             Ok(_) => {}
-            Err(e) => panic!("Error: {:} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e),
+            Err(e) => fail(format!("{} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e)),
           }
         }
       }
       drop(input);
    } else {
-     assert_eq!(num_benchmarks, 1);
+     if num_benchmarks != 1 {
+      fail("-b needs an input and an output file");
+    }
+     if do_inspect {
+       inspect_header(&mut io::stdin());
+       return;
+     }
      if do_validate {
         if do_compress {
             match validate::compress_validate(&mut io::stdin(), &mut io::stdout(), buffer_size, &params, custom_dictionary.into(), num_threads) {
                 Ok(_) => {}
-                Err(e) => panic!("Error {:?}", e),
+                Err(e) => fail(e),
             }
         } else {
             match validate::compress_validate(&mut io::stdin(), &mut io::sink(), buffer_size, &params, custom_dictionary.into(), num_threads) {
                 Ok(_) => {}
-                Err(e) => panic!("Error {:?}", e),
+                Err(e) => fail(e),
             }
         }
       } else if do_compress {
         match compress(&mut io::stdin(), &mut io::stdout(), buffer_size, &params, &custom_dictionary[..], num_threads) {
           Ok(_) => return,
-          Err(e) => panic!("Error {:?}", e),
+          Err(e) => fail(e),
         }
       } else {
         match decompress(&mut io::stdin(), &mut io::stdout(), buffer_size, custom_dictionary.into()) {
           Ok(_) => return,
-          Err(e) => panic!("Error: {:} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e),
+          Err(e) => fail(format!("{} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e)),
         }
       }
     }
   } else {
-    assert_eq!(num_benchmarks, 1);
+    if num_benchmarks != 1 {
+      fail("-b needs an input and an output file");
+    }
     match decompress(&mut io::stdin(), &mut io::stdout(), buffer_size, custom_dictionary.into()) {
       Ok(_) => return,
-      Err(e) => panic!("Error: {:} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e),
+      Err(e) => fail(format!("{} during brotli decompress\nTo compress with Brotli, specify the -c flag.", e)),
     }
   }
 }
@@ -7,8 +7,14 @@ use std::path::Path;
 use std::fs::File;
 
 use brotli::concat::{BroCatli, BroCatliResult};
+#[cfg(feature="std")]
+use brotli::concat::BrotliTranscodeForConcatenation;
+#[cfg(feature="std")]
+use brotli::concat::{BrotliDescribeStream, BrotliCheckConcatenation};
 fn usage() {
-    writeln!(&mut ::std::io::stderr(), "Usage: [-w<window_size>] filename0 filename1 filename2...").unwrap();
+    writeln!(&mut ::std::io::stderr(), "Usage: [-w<window_size>] [-transcode] [--check] filename0 filename1 filename2...").unwrap();
+    writeln!(&mut ::std::io::stderr(), "  -transcode re-encodes the start of files that were not compressed to be concatenated").unwrap();
+    writeln!(&mut ::std::io::stderr(), "  --check describes each file and reports whether and how they can be concatenated").unwrap();
 }
 fn read_no_interrupt<R:Read>(r: &mut R, buf: &mut [u8]) -> Result<usize, io::Error> {
     loop {
@@ -46,9 +52,101 @@ fn write_no_interrupt<W:Write>(w: &mut W, mut buf: &[u8]) -> Result<usize, io::E
 }
 
 
+fn stream_file<R:Read, W:Write>(bro_cat_li: &mut BroCatli, input_file: &mut R, filename: &str,
+                                ibuffer: &mut [u8], obuffer: &mut [u8], ooffset: &mut usize, ostream: &mut W) {
+    loop {
+        let mut ioffset = 0;
+        match read_no_interrupt(input_file, &mut ibuffer[..]) {
+            Err(e) => panic!(e),
+            Ok(cur_read) => {
+                if cur_read == 0 {
+                    break;
+                }
+                loop {
+                    match bro_cat_li.stream(&ibuffer[..cur_read], &mut ioffset,
+                                            &mut obuffer[..], ooffset) {
+                        BroCatliResult::NeedsMoreOutput => {
+                            match write_no_interrupt(ostream, &obuffer[..*ooffset]) {
+                                Err(why) => panic!("couldn't write: {:}", why),
+                                Ok(count) => {assert_eq!(count, *ooffset);},
+                            }
+                            *ooffset = 0;
+                        },
+                        BroCatliResult::NeedsMoreInput => {
+                            break;
+                        },
+                        BroCatliResult::Success => {
+                            panic!("Unexpected state: Success when streaming before finish");
+                        }
+                        failure => {
+                            panic!("Failed to concatenate files on {:} {:?}", filename, failure);
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature="std")]
+fn transcode_file(data: &[u8], window_size: u8, filename: &str) -> Vec<u8> {
+    match BrotliTranscodeForConcatenation(data, window_size) {
+        Ok(transcoded) => transcoded.data,
+        Err(failure) => panic!("Failed to transcode {:} {:?}", filename, failure),
+    }
+}
+#[cfg(not(feature="std"))]
+fn transcode_file(_data: &[u8], _window_size: u8, _filename: &str) -> Vec<u8> {
+    panic!("-transcode requires the std feature");
+}
+
+// prints how each file was crafted and the window BroCatli needs; returns false if it would fail
+#[cfg(feature="std")]
+fn check_files(filenames: &[String], window_size: Option<u8>) -> bool {
+    let mut descriptions = Vec::new();
+    for filename in filenames.iter() {
+        let mut data = Vec::<u8>::new();
+        if let Err(why) = File::open(&Path::new(filename)).and_then(|mut f| f.read_to_end(&mut data)) {
+            panic!("couldn't read {:}\n{:}", filename, why);
+        }
+        match BrotliDescribeStream(&data[..]) {
+            Ok(d) => {
+                println!("{}: window_bits {} large_window {} catable {} appendable {} magic_number {} uses_dictionary {}",
+                         filename, d.window_bits, d.large_window, d.catable, d.appendable, d.magic_number, d.uses_dictionary);
+                descriptions.push(d);
+            },
+            Err(failure) => {
+                println!("{}: {:?}", filename, failure);
+                return false;
+            },
+        }
+    }
+    match BrotliCheckConcatenation(&descriptions[..], window_size) {
+        Ok(plan) => {
+            if plan.needs_window_override {
+                println!("can concatenate with -w{}", plan.window_size);
+            } else {
+                println!("can concatenate with window {}", plan.window_size);
+            }
+            true
+        },
+        Err((index, failure)) => {
+            println!("cannot concatenate: {} {:?}", filenames[index], failure);
+            false
+        },
+    }
+}
+
+#[cfg(not(feature="std"))]
+fn check_files(_filenames: &[String], _window_size: Option<u8>) -> bool {
+    panic!("--check requires the std feature");
+}
+
 fn main() {
     let mut window_size: Option<u8> = None;
     let mut double_dash = false;
+    let mut transcode = false;
+    let mut check = false;
     let mut buffer_size = 4096usize;
     let mut filenames = Vec::<String>::new();
     let mut ostream = io::stdout();
@@ -62,6 +160,14 @@ fn main() {
                 buffer_size = argument.trim_matches('-').trim_matches('b').trim_matches('s').parse::<usize>().unwrap();
                 continue;
             }
+            if (argument == "-check" || argument == "--check") && !double_dash {
+                check = true;
+                continue;
+            }
+            if argument == "-transcode" && !double_dash {
+                transcode = true;
+                continue;
+            }
             if argument == "--" {
                 double_dash = true;
                 continue;
@@ -72,10 +178,15 @@ fn main() {
         usage();
         return;
     }
+    if check {
+        if !check_files(&filenames[..], window_size) {
+            ::std::process::exit(1);
+        }
+        return;
+    }
     let mut ibuffer = vec![0u8; buffer_size];
     let mut obuffer = vec![0u8; buffer_size];
     let mut ooffset = 0;
-    let mut ioffset;
     let mut bro_cat_li = match window_size {
         Some(ws) => BroCatli::new_with_window_size(ws),
         None => BroCatli::new(),
@@ -86,38 +197,21 @@ fn main() {
             Err(why) => panic!("couldn't open {:}\n{:}", filename, why),
             Ok(file) => file,
         };
-        loop {
-            ioffset = 0;
-            match read_no_interrupt(&mut input_file, &mut ibuffer[..]) {
-                Err(e) => panic!(e),
-                Ok(cur_read) => {
-                    if cur_read == 0 {
-                        break;
-                    }
-                    loop {
-                        match bro_cat_li.stream(&ibuffer[..cur_read], &mut ioffset,
-                                                &mut obuffer[..], &mut ooffset) {
-                            BroCatliResult::NeedsMoreOutput => {
-                                match write_no_interrupt(&mut ostream, &obuffer[..ooffset]) {
-                                    Err(why) => panic!("couldn't write: {:}", why),
-                                    Ok(count) => {assert_eq!(count, ooffset);},
-                                }
-                                ooffset = 0;
-                            },
-                            BroCatliResult::NeedsMoreInput => {
-                                break;
-                            },
-                            BroCatliResult::Success => {
-                                panic!("Unexpected state: Success when streaming before finish");
-                            }
-                            failure => {
-                                panic!("Failed to concatenate files on {:} {:?}", filename, failure);
-                            },
-                        }
-                        
-                    }
-                }
+        if transcode {
+            let mut data = Vec::<u8>::new();
+            if let Err(e) = input_file.read_to_end(&mut data) {
+                panic!(e);
+            }
+            let transcoded = transcode_file(&data[..], window_size.unwrap_or(0), &filename);
+            if window_size.is_none() {
+                // later files must fit the window of the first one
+                window_size = Some(brotli::BrotliInspectHeader(&transcoded[..]).unwrap().window_bits);
             }
+            stream_file(&mut bro_cat_li, &mut io::Cursor::new(transcoded), &filename,
+                        &mut ibuffer[..], &mut obuffer[..], &mut ooffset, &mut ostream);
+        } else {
+            stream_file(&mut bro_cat_li, &mut input_file, &filename,
+                        &mut ibuffer[..], &mut obuffer[..], &mut ooffset, &mut ostream);
         }
     }
     loop {
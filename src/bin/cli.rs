@@ -0,0 +1,352 @@
+// gzip/brotli compatible front end: many input files, -d/-z/-t, -k/--rm, -f, -c, -o, -S, -r, -v
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+use brotli::enc::BrotliEncoderParams;
+use super::{compress, decompress, Rebox};
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+// like gzip: something was skipped but nothing failed
+pub const EXIT_WARNING: i32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operation {
+  Compress,
+  Decompress,
+  Test,
+}
+
+pub struct Options {
+  pub operation: Operation,
+  pub keep: bool,
+  pub force: bool,
+  pub to_stdout: bool,
+  pub output: Option<String>,
+  pub suffix: String,
+  pub recursive: bool,
+  pub verbose: bool,
+  pub copy_stat: bool,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      operation: Operation::Compress,
+      keep: true,
+      force: false,
+      to_stdout: false,
+      output: None,
+      suffix: ".br".to_string(),
+      recursive: false,
+      verbose: false,
+      copy_stat: true,
+    }
+  }
+}
+
+enum Destination {
+  Stdout,
+  Sink,
+  File(PathBuf),
+}
+
+pub struct FrontEnd<'a> {
+  options: &'a Options,
+  params: &'a BrotliEncoderParams,
+  custom_dictionary: &'a [u8],
+  buffer_size: usize,
+  num_threads: usize,
+  status: i32,
+}
+
+impl<'a> FrontEnd<'a> {
+  pub fn new(options: &'a Options,
+             params: &'a BrotliEncoderParams,
+             custom_dictionary: &'a [u8],
+             buffer_size: usize,
+             num_threads: usize) -> Self {
+    FrontEnd {
+      options: options,
+      params: params,
+      custom_dictionary: custom_dictionary,
+      buffer_size: buffer_size,
+      num_threads: num_threads,
+      status: EXIT_OK,
+    }
+  }
+  fn error(&mut self, message: String) {
+    eprintln!("brotli: {}", message);
+    self.status = EXIT_ERROR;
+  }
+  fn warn(&mut self, message: String) {
+    eprintln!("brotli: {}", message);
+    if self.status == EXIT_OK {
+      self.status = EXIT_WARNING;
+    }
+  }
+  // processes every file (or stdin for none or "-") and returns the exit status
+  pub fn run(&mut self, filenames: &[String]) -> i32 {
+    if self.options.output.is_some() && (filenames.len() > 1 || self.options.recursive) {
+      self.error("-o can only be used with a single input file".to_string());
+      return self.status;
+    }
+    if filenames.len() == 0 {
+      self.process_stdin();
+    }
+    for filename in filenames.iter() {
+      if filename == "-" {
+        self.process_stdin();
+      } else {
+        self.process_path(Path::new(filename));
+      }
+    }
+    self.status
+  }
+  fn transform<R: Read, W: Write>(&self, r: &mut R, w: &mut W) -> io::Result<()> {
+    match self.options.operation {
+      Operation::Compress => compress(r, w, self.buffer_size, self.params,
+                                      self.custom_dictionary, self.num_threads).map(|_| ()),
+      Operation::Decompress | Operation::Test => decompress(r, w, self.buffer_size,
+                                                            Rebox::from(self.custom_dictionary.to_vec())),
+    }
+  }
+  fn check_terminal(&mut self) -> bool {
+    if self.options.operation == Operation::Compress && !self.options.force && io::stdout().is_terminal() {
+      self.error("compressed data not written to a terminal. Use -f to force compression.".to_string());
+      return false;
+    }
+    true
+  }
+  fn process_stdin(&mut self) {
+    let mut input = io::stdin();
+    let result = if self.options.operation == Operation::Test {
+      self.transform(&mut input, &mut io::sink())
+    } else if let Some(ref output) = self.options.output {
+      let output_path = PathBuf::from(output);
+      match self.create_output(&output_path) {
+        Some(mut file) => {
+          let result = self.transform(&mut input, &mut file);
+          if result.is_err() {
+            let _ = fs::remove_file(&output_path);
+          }
+          result
+        },
+        None => return,
+      }
+    } else {
+      if !self.check_terminal() {
+        return;
+      }
+      let stdout = io::stdout();
+      let mut lock = stdout.lock();
+      self.transform(&mut input, &mut lock).and_then(|_| lock.flush())
+    };
+    match result {
+      Ok(_) => if self.options.verbose && self.options.operation == Operation::Test {
+        eprintln!("stdin:\tOK");
+      },
+      Err(e) => self.error(format!("stdin: {}", e)),
+    }
+  }
+  fn destination(&mut self, path: &Path) -> Option<Destination> {
+    if self.options.operation == Operation::Test {
+      return Some(Destination::Sink);
+    }
+    if self.options.to_stdout {
+      return Some(Destination::Stdout);
+    }
+    if let Some(ref output) = self.options.output {
+      return Some(Destination::File(PathBuf::from(output)));
+    }
+    let name = path.to_string_lossy().into_owned();
+    let suffix = &self.options.suffix[..];
+    if self.options.operation == Operation::Compress {
+      if name.ends_with(suffix) {
+        self.warn(format!("{}: already has {} suffix -- unchanged", name, suffix));
+        return None;
+      }
+      let mut output = path.as_os_str().to_os_string();
+      output.push(suffix);
+      Some(Destination::File(PathBuf::from(output)))
+    } else {
+      if !name.ends_with(suffix) || name.len() == suffix.len() {
+        self.warn(format!("{}: unknown suffix -- ignored", name));
+        return None;
+      }
+      Some(Destination::File(PathBuf::from(&name[..name.len() - suffix.len()])))
+    }
+  }
+  fn create_output(&mut self, output_path: &Path) -> Option<File> {
+    if !self.options.force && fs::symlink_metadata(output_path).is_ok() {
+      self.error(format!("{}: already exists; not overwritten", output_path.display()));
+      return None;
+    }
+    match File::create(output_path) {
+      Ok(file) => Some(file),
+      Err(e) => {
+        self.error(format!("{}: {}", output_path.display(), e));
+        None
+      },
+    }
+  }
+  fn process_path(&mut self, path: &Path) {
+    let metadata = match fs::metadata(path) {
+      Ok(metadata) => metadata,
+      Err(e) => return self.error(format!("{}: {}", path.display(), e)),
+    };
+    if metadata.is_dir() {
+      if !self.options.recursive {
+        return self.warn(format!("{}: is a directory -- ignored", path.display()));
+      }
+      let mut entries = Vec::new();
+      match fs::read_dir(path) {
+        Ok(dir) => for entry in dir {
+          match entry {
+            Ok(entry) => entries.push(entry.path()),
+            Err(e) => self.error(format!("{}: {}", path.display(), e)),
+          }
+        },
+        Err(e) => return self.error(format!("{}: {}", path.display(), e)),
+      }
+      entries.sort();
+      for entry in entries.iter() {
+        self.process_path(entry);
+      }
+      return;
+    }
+    if !metadata.is_file() {
+      return self.warn(format!("{}: not a regular file -- ignored", path.display()));
+    }
+    let destination = match self.destination(path) {
+      Some(destination) => destination,
+      None => return,
+    };
+    let mut input = match File::open(path) {
+      Ok(file) => file,
+      Err(e) => return self.error(format!("{}: {}", path.display(), e)),
+    };
+    match destination {
+      Destination::Sink => match self.transform(&mut input, &mut io::sink()) {
+        Ok(_) => if self.options.verbose {
+          eprintln!("{}:\tOK", path.display());
+        },
+        Err(e) => self.error(format!("{}: {}", path.display(), e)),
+      },
+      Destination::Stdout => {
+        if !self.check_terminal() {
+          return;
+        }
+        let stdout = io::stdout();
+        let mut lock = stdout.lock();
+        if let Err(e) = self.transform(&mut input, &mut lock).and_then(|_| lock.flush()) {
+          self.error(format!("{}: {}", path.display(), e));
+        }
+      },
+      Destination::File(output_path) => self.process_file(path, &metadata, &mut input, &output_path),
+    }
+  }
+  fn process_file(&mut self, path: &Path, metadata: &Metadata, input: &mut File, output_path: &Path) {
+    if let (Ok(a), Ok(b)) = (fs::canonicalize(path), fs::canonicalize(output_path)) {
+      if a == b {
+        return self.error(format!("{}: input and output are the same file", path.display()));
+      }
+    }
+    let mut output = match self.create_output(output_path) {
+      Some(file) => file,
+      None => return,
+    };
+    if let Err(e) = self.transform(input, &mut output) {
+      drop(output);
+      let _ = fs::remove_file(output_path);
+      return self.error(format!("{}: {}", path.display(), e));
+    }
+    if self.options.copy_stat {
+      if let Err(e) = copy_stat(metadata, &output) {
+        self.warn(format!("{}: could not copy timestamps and permissions: {}", output_path.display(), e));
+      }
+    }
+    let output_len = output.metadata().map(|m| m.len()).unwrap_or(0);
+    drop(output);
+    if !self.options.keep {
+      if let Err(e) = fs::remove_file(path) {
+        self.error(format!("{}: {}", path.display(), e));
+      }
+    }
+    if self.options.verbose {
+      let (compressed, uncompressed) = if self.options.operation == Operation::Compress {
+        (output_len, metadata.len())
+      } else {
+        (metadata.len(), output_len)
+      };
+      let saved = if uncompressed == 0 {
+        0.0
+      } else {
+        100.0 * (1.0 - compressed as f64 / uncompressed as f64)
+      };
+      eprintln!("{}:\t{:.1}% -- {} {}", path.display(), saved,
+                if self.options.keep { "created" } else { "replaced with" }, output_path.display());
+    }
+  }
+}
+
+fn copy_stat(metadata: &Metadata, output: &File) -> io::Result<()> {
+  let times = FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?);
+  output.set_times(times)?;
+  output.set_permissions(metadata.permissions())
+}
+
+// Output of the legacy `brotli input output` form. Regular files are written under a temporary
+// name next to the output and only renamed over it by commit, so a failed run (e.g. decompressing
+// a file that is not brotli) leaves an existing output untouched. Devices and pipes are written
+// directly.
+pub struct LegacyOutput {
+  pub file: File,
+  path: PathBuf,
+  temp_path: Option<PathBuf>,
+}
+
+impl LegacyOutput {
+  pub fn create(path: &Path) -> io::Result<LegacyOutput> {
+    let existing = fs::metadata(path).ok();
+    let temp_path = match (path.file_name(), &existing) {
+      (_, &Some(ref metadata)) if !metadata.is_file() => None,
+      (Some(name), _) => {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        Some(path.with_file_name(temp_name))
+      },
+      (None, _) => None,
+    };
+    let file = File::create(temp_path.as_ref().map_or(path, |p| p.as_path()))?;
+    if let (Some(metadata), true) = (existing, temp_path.is_some()) {
+      // keep the permissions an overwritten output had
+      let _ = file.set_permissions(metadata.permissions());
+    }
+    Ok(LegacyOutput {
+      file: file,
+      path: path.to_path_buf(),
+      temp_path: temp_path,
+    })
+  }
+  pub fn commit(self) -> io::Result<()> {
+    match self.temp_path {
+      Some(temp_path) => {
+        drop(self.file);
+        fs::rename(&temp_path, &self.path).map_err(|e| {
+          let _ = fs::remove_file(&temp_path);
+          e
+        })
+      },
+      None => Ok(()),
+    }
+  }
+  pub fn discard(self) {
+    if let Some(temp_path) = self.temp_path {
+      drop(self.file);
+      let _ = fs::remove_file(&temp_path);
+    }
+  }
+}
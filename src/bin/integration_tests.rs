@@ -302,30 +302,65 @@ fn test_roundtrip_64x() {
   assert_eq!(input.read_offset, in_buf.len());
 }
 
+// compresses the segments as one stream, flushing after each but the last so that every segment is
+// its own metablock, checks that it decompresses to their concatenation and returns the compressed size.
+// on_segment sees the encoder before each segment with its index and once more with segments.len() when it is finished
+fn params_roundtrip_helper<F>(segments: &[&[u8]], params: &super::brotli::enc::BrotliEncoderParams, mut on_segment: F) -> usize
+  where F: FnMut(&mut super::brotli::enc::encode::BrotliEncoderStateStruct<HeapAllocator>, usize) {
+  use super::brotli::enc::encode::{BrotliEncoderCreateInstance, BrotliEncoderDestroyInstance,
+                                   BrotliEncoderCompressStream, BrotliEncoderOperation,
+                                   BrotliEncoderIsFinished, BrotliEncoderHasMoreOutput};
+  let in_buf: Vec<u8> = segments.concat();
+  let mut s = BrotliEncoderCreateInstance(HeapAllocator::default());
+  s.params = params.clone();
+  let mut compressed = vec![0u8; super::brotli::enc::BrotliEncoderMaxCompressedSize(in_buf.len()) + 65536];
+  let mut next_out_offset = 0usize;
+  let mut total_out = Some(0usize);
+  for (index, segment) in segments.iter().enumerate() {
+    on_segment(&mut s, index);
+    if index + 1 == segments.len() {
+      break;
+    }
+    let mut available_in = segment.len();
+    let mut next_in_offset = 0usize;
+    while available_in != 0 || BrotliEncoderHasMoreOutput(&s) != 0 {
+      let mut available_out = compressed.len() - next_out_offset;
+      assert!(BrotliEncoderCompressStream(&mut s, BrotliEncoderOperation::BROTLI_OPERATION_FLUSH,
+                                          &mut available_in, segment, &mut next_in_offset,
+                                          &mut available_out, &mut compressed[..], &mut next_out_offset,
+                                          &mut total_out, &mut |_a,_b,_c,_d|()) != 0);
+    }
+  }
+  let last: &[u8] = segments.last().map_or(&[], |segment| *segment);
+  let mut available_in = last.len();
+  let mut next_in_offset = 0usize;
+  while BrotliEncoderIsFinished(&s) == 0 {
+    let mut available_out = compressed.len() - next_out_offset;
+    assert!(BrotliEncoderCompressStream(&mut s, BrotliEncoderOperation::BROTLI_OPERATION_FINISH,
+                                        &mut available_in, last, &mut next_in_offset,
+                                        &mut available_out, &mut compressed[..], &mut next_out_offset,
+                                        &mut total_out, &mut |_a,_b,_c,_d|()) != 0);
+  }
+  on_segment(&mut s, segments.len());
+  BrotliEncoderDestroyInstance(&mut s);
+  let mut compressed_in = UnlimitedBuffer::new(&compressed[..next_out_offset]);
+  let mut output = UnlimitedBuffer::new(&[]);
+  match super::decompress(&mut compressed_in, &mut output, 4096, Rebox::default()) {
+    Ok(_) => {}
+    Err(e) => panic!("Error {:?}", e),
+  }
+  assert_eq!(output.data.len(), in_buf.len());
+  assert!(output.data[..] == in_buf[..]);
+  next_out_offset
+}
+
 fn roundtrip_helper(in_buf: &[u8], q: i32, lgwin: i32, q9_5: bool) -> usize {
   let mut params = super::brotli::enc::BrotliEncoderInitParams();
   params.quality = q;
   params.q9_5 = q9_5;
   params.lgwin = lgwin;
   params.size_hint = if in_buf.len() > 100000 { 2048 * 1024} else {in_buf.len()};
-  let mut input = UnlimitedBuffer::new(&in_buf);
-  let mut compressed = UnlimitedBuffer::new(&[]);
-  let mut output = UnlimitedBuffer::new(&[]);
-  match super::compress(&mut input, &mut compressed, 4096, &params, &[], 1) {
-    Ok(_) => {}
-    Err(e) => panic!("Error {:?}", e),
-  }
-  let mut compressed_in = UnlimitedBuffer::new(&compressed.data[..]);
-  match super::decompress(&mut compressed_in, &mut output, 4096, Rebox::default()) {
-    Ok(_) => {}
-    Err(e) => panic!("Error {:?}", e),
-  }
-  for i in 0..input.data.len() {
-    assert_eq!(output.data[i], input.data[i]);
-  }
-  assert_eq!(output.data.len(), input.data.len());
-  assert_eq!(input.read_offset, in_buf.len());
-  return compressed.data[..].len()
+  params_roundtrip_helper(&[in_buf], &params, |_s, _index| ())
 }
 
 fn total_roundtrip_helper(data: &[u8]) {
@@ -384,38 +419,20 @@ fn test_random_then_unicode_8() {
 fn test_random_then_unicode_9() {
     roundtrip_helper(RANDOM_THEN_UNICODE, 9, 22, false);
 }
-#[cfg(feature="std")]
+// the cost models use a portable log2, so builds without std compress identically
 const random_then_unicode_compressed_size_9_5 : usize = 136563;
-#[cfg(feature="std")]
 const random_then_unicode_compressed_size_9_5x : usize = 136045;
 
-
-#[cfg(not(feature="std"))]
-const alice_compressed_size_10 : usize = 47490;
-#[cfg(not(feature="std"))]
-const alice_compressed_size_11 : usize = 46496;
-
-#[cfg(feature="std")]
 #[cfg(not(feature="float64"))]
 const alice_compressed_size_10 : usize = 47488;
-#[cfg(feature="std")]
 #[cfg(not(feature="float64"))]
 const alice_compressed_size_11 : usize = 46493;
 
-#[cfg(feature="std")]
 #[cfg(feature="float64")]
 const alice_compressed_size_10 : usize = 47515;
-#[cfg(feature="std")]
 #[cfg(feature="float64")]
 const alice_compressed_size_11 : usize = 46510;
 
-
-
-#[cfg(not(feature="std"))] // approx log
-const random_then_unicode_compressed_size_9_5 : usize = 136719;
-#[cfg(not(feature="std"))] // approx log
-const random_then_unicode_compressed_size_9_5x : usize = 136095;
-
 #[test]
 fn test_random_then_unicode_9_5() {
     let c_size = roundtrip_helper(RANDOM_THEN_UNICODE, 10, 28, true);
@@ -1315,3 +1332,487 @@ fn test_error_returned() {
     assert_eq!(output.data, &onetwothreefourfive[..]);
 }
 
+
+#[cfg(feature="std")]
+fn adaptive_quality_helper(in_buf: &[u8], qualities: &[i32], catable: bool) {
+  let mut params = super::brotli::enc::BrotliEncoderParams::default();
+  params.adaptive_quality = true;
+  params.catable = catable;
+  params.use_dictionary = !catable;
+  params.lgwin = 18;
+  params.quality = qualities[0];
+  let chunk_size = in_buf.len() / qualities.len() + 1;
+  let chunks: Vec<&[u8]> = in_buf.chunks(chunk_size).collect();
+  let num_chunks = chunks.len();
+  params_roundtrip_helper(&chunks, &params, |s, index| if index < num_chunks {
+    assert_eq!(super::brotli::enc::encode::BrotliEncoderChangeQuality(s, qualities[index]), 1);
+  });
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_adaptive_quality_switches() {
+  adaptive_quality_helper(include_bytes!("../../testdata/alice29.txt"), &[11, 0, 5, 1, 10, 2, 9, 0, 4], false);
+  adaptive_quality_helper(include_bytes!("../../testdata/alice29.txt"), &[0, 1, 0, 6, 6, 11, 3], false);
+  adaptive_quality_helper(include_bytes!("../../testdata/asyoulik.txt"), &[9, 0, 9, 1, 11], true);
+}
+
+#[test]
+fn test_adaptive_quality_controller() {
+  use super::brotli::enc::{AdaptiveQualityController, AdaptiveTarget};
+  let mut controller = AdaptiveQualityController::new(AdaptiveTarget::Throughput(1000), 1, 9);
+  assert_eq!(controller.quality(), 9);
+  // 1000 bytes in 10 seconds is far too slow
+  assert_eq!(controller.record(1000, 10_000_000_000), 7);
+  // slightly too slow
+  assert_eq!(controller.record(1000, 1_500_000_000), 6);
+  // on target: stay put
+  assert_eq!(controller.record(1000, 900_000_000), 6);
+  // plenty of headroom: speed back up
+  assert_eq!(controller.record(1000, 100_000_000), 7);
+  for _ in 0..10 {
+    controller.record(1000, 100_000_000_000);
+  }
+  assert_eq!(controller.quality(), 1);
+  assert_eq!(controller.total_bytes(), 14000);
+  let mut budget = AdaptiveQualityController::new(AdaptiveTarget::TimeBudget{nanos: 1_000_000_000, expected_size: 2000}, 0, 11);
+  // the whole budget is spent on the first half of the input
+  assert_eq!(budget.record(1000, 1_000_000_000), 9);
+  assert_eq!(budget.bytes_per_quality[11], 1000);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_adaptive_compress_roundtrip() {
+  use super::brotli::enc::{AdaptiveQualityController, AdaptiveTarget, BrotliCompressAdaptive};
+  let in_buf = include_bytes!("../../testdata/alice29.txt");
+  let mut params = super::brotli::enc::BrotliEncoderParams::default();
+  params.lgwin = 16;
+  params.lgblock = 16;
+  let mut controller = AdaptiveQualityController::new(AdaptiveTarget::megabytes_per_second(1 << 20), 0, 11);
+  let mut input = UnlimitedBuffer::new(&in_buf[..]);
+  let mut compressed = UnlimitedBuffer::new(&[]);
+  match BrotliCompressAdaptive(&mut input, &mut compressed, &params, &mut controller) {
+    Ok(_) => {}
+    Err(e) => panic!("Error {:?}", e),
+  }
+  // an unreachable target keeps walking down the quality ladder
+  assert!(controller.num_quality_changes > 0);
+  assert!(controller.bytes_per_quality[11] > 0);
+  assert!(controller.quality() < 11);
+  let mut compressed_in = UnlimitedBuffer::new(&compressed.data[..]);
+  let mut output = UnlimitedBuffer::new(&[]);
+  match super::decompress(&mut compressed_in, &mut output, 65536, Rebox::default()) {
+    Ok(_) => {}
+    Err(e) => panic!("Error {:?}", e),
+  }
+  assert!(output.data[..] == in_buf[..]);
+}
+
+#[cfg(feature="std")]
+fn content_detect_roundtrip(segments: &[&[u8]], quality: i32) -> super::brotli::enc::ContentReport {
+  let mut params = super::brotli::enc::BrotliEncoderParams::default();
+  params.auto_detect_content = true;
+  params.quality = quality;
+  params.lgwin = 18;
+  let mut report = super::brotli::enc::ContentReport::default();
+  params_roundtrip_helper(segments, &params, |s, _index| report = s.content_report);
+  report
+}
+
+fn pseudo_random_bytes(len: usize) -> Vec<u8> {
+  let mut state = 0x2545f4914f6cdd1du64;
+  (0..len).map(|_| {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    (state >> 24) as u8
+  }).collect()
+}
+
+#[test]
+fn test_content_detection() {
+  use super::brotli::enc::{BrotliAnalyzeContent, ContentType};
+  let mut m32 = HeapAllocator::default();
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let analysis = BrotliAnalyzeContent(&mut m32, text, 0, usize::max_value(), text.len(), true);
+  assert_eq!(analysis.content_type, ContentType::Text);
+  let random = pseudo_random_bytes(1 << 17);
+  assert_eq!(BrotliAnalyzeContent(&mut m32, &random, 0, usize::max_value(), random.len(), false).content_type,
+             ContentType::Compressed);
+  let mut table = Vec::new();
+  for i in 0..32768u32 {
+    let value = 100000 + i * 7 + (i % 13);
+    table.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+  }
+  let analysis = BrotliAnalyzeContent(&mut m32, &table, 0, usize::max_value(), table.len(), false);
+  assert_eq!(analysis.content_type, ContentType::BinaryTable);
+  assert_eq!(analysis.stride, 4);
+  let mut image = Vec::new();
+  for y in 0..256u32 {
+    for x in 0..256u32 {
+      image.extend_from_slice(&[(x + 1) as u8 | 1, ((x + y) / 2) as u8 | 1, (255 - y) as u8 | 1]);
+    }
+  }
+  let analysis = BrotliAnalyzeContent(&mut m32, &image, 0, usize::max_value(), image.len(), false);
+  assert_eq!(analysis.content_type, ContentType::Image);
+  assert_eq!(analysis.stride, 3);
+  let mut font = b"wOF2\x00\x01\x00\x00".to_vec();
+  font.extend_from_slice(&table[..]);
+  assert_eq!(BrotliAnalyzeContent(&mut m32, &font, 0, usize::max_value(), font.len(), true).content_type,
+             ContentType::Font);
+  assert_eq!(BrotliAnalyzeContent(&mut m32, &font, 0, usize::max_value(), font.len(), false).content_type,
+             ContentType::BinaryTable);
+  // a TrueType signature only counts with a table directory behind it
+  let mut truetype = b"true\x00\x0b\x00\x80\x00\x03\x00\x30".to_vec();
+  truetype.extend_from_slice(&table[..]);
+  assert_eq!(BrotliAnalyzeContent(&mut m32, &truetype, 0, usize::max_value(), truetype.len(), true).content_type,
+             ContentType::Font);
+  let json = b"true\n".repeat(1000);
+  assert_eq!(BrotliAnalyzeContent(&mut m32, &json, 0, usize::max_value(), json.len(), true).content_type,
+             ContentType::Text);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_content_detection_roundtrip() {
+  use super::brotli::enc::ContentType;
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let random = pseudo_random_bytes(65536);
+  let mut table = Vec::new();
+  for i in 0..16384u32 {
+    let value = 100000 + i * 7 + (i % 13);
+    table.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+  }
+  for quality in [2, 5, 9, 11].iter() {
+    let report = content_detect_roundtrip(&[&text[..65536], &random[..], &table[..]], *quality);
+    assert!(report.num_metablocks >= 3);
+    assert_eq!(report.bytes_per_type[ContentType::Text as usize], 65536);
+    assert_eq!(report.bytes_per_type[ContentType::Compressed as usize], random.len() as u64);
+    assert_eq!(report.bytes_per_type[ContentType::BinaryTable as usize], table.len() as u64);
+    assert!(report.bytes_per_stride[3] >= table.len() as u64);
+  }
+  let mut font = b"wOFF\x00\x01\x00\x00".to_vec();
+  font.extend_from_slice(&text[..4096]);
+  let report = content_detect_roundtrip(&[&font[..]], 10);
+  assert_eq!(report.last.content_type, ContentType::Font);
+}
+
+#[cfg(feature="std")]
+fn skip_incompressible_roundtrip(in_buf: &[u8], quality: i32, catable: bool) -> (usize, super::brotli::enc::IncompressibleStats) {
+  let mut params = super::brotli::enc::BrotliEncoderParams::default();
+  params.quality = quality;
+  params.lgwin = 20;
+  params.skip_incompressible = true;
+  params.catable = catable;
+  params.use_dictionary = !catable;
+  let mut stats = super::brotli::enc::IncompressibleStats::default();
+  let size = params_roundtrip_helper(&[in_buf], &params, |s, _index| stats = s.incompressible_stats);
+  (size, stats)
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_skip_incompressible() {
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let random = pseudo_random_bytes(1 << 18);
+  let mut mixed = random.clone();
+  mixed.extend_from_slice(&text[..]);
+  mixed.extend_from_slice(&random[..100000]);
+  mixed.extend_from_slice(&text[..50000]);
+  for quality in [2, 5, 9, 10, 11].iter() {
+    let (size, stats) = skip_incompressible_roundtrip(&mixed, *quality, false);
+    assert!(stats.num_blocks_skipped > 0);
+    assert!(stats.num_blocks_sampled >= stats.num_blocks_skipped);
+    assert!(stats.bytes_skipped >= random.len() as u64 / 2);
+    assert!(stats.bytes_skipped <= (random.len() + 100000) as u64);
+    assert!(size < mixed.len() - text.len() / 2);
+  }
+  let (_, stats) = skip_incompressible_roundtrip(&mixed, 9, true);
+  assert!(stats.num_blocks_skipped > 0);
+  let (_, stats) = skip_incompressible_roundtrip(&text[..], 9, false);
+  assert_eq!(stats.num_blocks_skipped, 0);
+}
+
+#[test]
+fn test_is_incompressible() {
+  use super::brotli::enc::BrotliIsIncompressible;
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let random = pseudo_random_bytes(1 << 16);
+  // high entropy detection qualities 0 and 1 never call anything random
+  assert!(!BrotliIsIncompressible(&random, 0, usize::max_value(), random.len(), 1));
+  assert!(BrotliIsIncompressible(&random, 0, usize::max_value(), random.len(), 2));
+  assert!(!BrotliIsIncompressible(&random, 0, usize::max_value(), 100, 2));
+  assert!(!BrotliIsIncompressible(&text[..], 0, usize::max_value(), text.len(), 11));
+}
+
+fn long_distance_roundtrip(in_buf: &[u8], quality: i32, long_distance_matching: bool) -> usize {
+  let mut params = super::brotli::enc::BrotliEncoderParams::default();
+  params.quality = quality;
+  params.lgwin = 20;
+  params.long_distance_matching = long_distance_matching;
+  params_roundtrip_helper(&[in_buf], &params, |_s, _index| ())
+}
+
+#[test]
+fn test_long_distance_matching() {
+  // a 128k file stored twice with 768k of text-like data between the copies, which keeps the
+  // regular hash tables busy enough to forget the first copy
+  let random = pseudo_random_bytes((1 << 17) + (3 << 18));
+  let mut repeated = random[..1 << 17].to_vec();
+  repeated.extend(random[1 << 17..].iter().map(|b| b'a' + (b & 15)));
+  repeated.extend_from_slice(&random[..1 << 17]);
+  for quality in [2, 5, 10, 11].iter() {
+    let size = long_distance_roundtrip(&repeated, *quality, true);
+    let regular_size = long_distance_roundtrip(&repeated, *quality, false);
+    assert!(size <= regular_size + 64);
+    if *quality == 2 {
+      assert!(size + (1 << 16) < regular_size);
+    }
+  }
+  let text = include_bytes!("../../testdata/alice29.txt");
+  assert!(long_distance_roundtrip(&text[..], 9, true) <= long_distance_roundtrip(&text[..], 9, false) + 64);
+}
+
+#[cfg(feature="std")]
+fn compress_for_limits(data: &[u8], lgwin: u32) -> Vec<u8> {
+  let mut compressed = Vec::new();
+  {
+    let mut writer = CompressorWriter::new(&mut compressed, 4096, 5, lgwin);
+    writer.write_all(data).unwrap();
+  }
+  compressed
+}
+
+#[cfg(feature="std")]
+fn limit_error(err: &io::Error) -> Option<super::brotli::DecompressionLimitExceeded> {
+  err.get_ref().and_then(|e| e.downcast_ref::<super::brotli::DecompressionLimitExceeded>()).cloned()
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_decompression_limits_reader() {
+  use super::brotli::{DecompressionLimits, DecompressionLimitExceeded, LimitedDecompressor};
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let compressed = compress_for_limits(&text[..], 22);
+  let read_all = |limits: DecompressionLimits| {
+    let mut output = Vec::new();
+    let mut reader = LimitedDecompressor::new(&compressed[..], 4096, limits);
+    let result = reader.read_to_end(&mut output);
+    assert!(output.len() as u64 <= if limits.max_output_size != 0 { limits.max_output_size } else { u64::max_value() });
+    result.map(|_| output)
+  };
+  assert!(read_all(DecompressionLimits::default()).unwrap()[..] == text[..]);
+  let generous = DecompressionLimits{max_output_size: text.len() as u64, max_ratio: 10, max_window_bits: 22};
+  assert!(read_all(generous).unwrap()[..] == text[..]);
+  let small = DecompressionLimits{max_output_size: 100000, ..DecompressionLimits::default()};
+  assert_eq!(limit_error(&read_all(small).unwrap_err()), Some(DecompressionLimitExceeded::OutputSize));
+  let ratio = DecompressionLimits{max_ratio: 2, ..DecompressionLimits::default()};
+  assert_eq!(limit_error(&read_all(ratio).unwrap_err()), Some(DecompressionLimitExceeded::Ratio));
+  let window = DecompressionLimits{max_window_bits: 20, ..DecompressionLimits::default()};
+  assert_eq!(limit_error(&read_all(window).unwrap_err()), Some(DecompressionLimitExceeded::WindowSize));
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_decompression_limits_writer() {
+  use super::brotli::{DecompressionLimits, DecompressionLimitExceeded, LimitedDecompressorWriter};
+  // a tiny stream that expands to 16MB
+  let bomb = compress_for_limits(&vec![0u8; 16 << 20][..], 24);
+  assert!(bomb.len() < 16384);
+  let write_all = |limits: DecompressionLimits| {
+    let mut output = Vec::new();
+    let result = {
+      let mut writer = LimitedDecompressorWriter::new(&mut output, 4096, limits);
+      writer.write_all(&bomb[..]).and_then(|_| writer.close())
+    };
+    result.map(|_| output.len())
+  };
+  assert_eq!(write_all(DecompressionLimits::default()).unwrap(), 16 << 20);
+  let small = DecompressionLimits{max_output_size: 1 << 20, ..DecompressionLimits::default()};
+  assert_eq!(limit_error(&write_all(small).unwrap_err()), Some(DecompressionLimitExceeded::OutputSize));
+  let ratio = DecompressionLimits{max_ratio: 100, ..DecompressionLimits::default()};
+  assert_eq!(limit_error(&write_all(ratio).unwrap_err()), Some(DecompressionLimitExceeded::Ratio));
+  let mut output = Vec::new();
+  let limits = DecompressionLimits{max_output_size: 1 << 20, ..DecompressionLimits::default()};
+  match super::brotli::BrotliDecompressWithLimits(&mut &bomb[..], &mut output, limits) {
+    Ok(_) => panic!("bomb should not decompress"),
+    Err(e) => assert_eq!(limit_error(&e), Some(DecompressionLimitExceeded::OutputSize)),
+  }
+  assert_eq!(output.len(), 1 << 20);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_decompression_limits_ffi() {
+  use super::brotli::ffi::decompressor::{CBrotliDecoderCreateInstance, CBrotliDecoderDestroyInstance,
+                                         CBrotliDecoderSetParameter, CBrotliDecoderParameter,
+                                         CBrotliDecoderDecompressStream, CBrotliDecoderGetLimitExceeded,
+                                         CBrotliDecoderHasMoreOutput, CBrotliDecoderTakeOutput,
+                                         CBrotliDecoderDecompressWithLimits};
+  use super::brotli::ffi::decompressor::ffi::interface::BrotliDecoderResult;
+  use super::brotli::DecompressionLimitExceeded;
+  let bomb = compress_for_limits(&vec![0u8; 16 << 20][..], 24);
+  let decode = |selector: Option<(CBrotliDecoderParameter, u32)>| unsafe {
+    let state = CBrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    if let Some((selector, value)) = selector {
+      CBrotliDecoderSetParameter(state, selector, value);
+    }
+    let mut output = vec![0u8; 65536];
+    let mut total = 0usize;
+    let mut available_in = bomb.len();
+    let mut input_ptr = bomb.as_ptr();
+    let result = loop {
+      let mut available_out = output.len();
+      let mut output_ptr = output.as_mut_ptr();
+      match CBrotliDecoderDecompressStream(state, &mut available_in, &mut input_ptr,
+                                           &mut available_out, &mut output_ptr, core::ptr::null_mut()) {
+        BrotliDecoderResult::BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT => total += output.len() - available_out,
+        other => {
+          total += output.len() - available_out;
+          break other;
+        }
+      }
+    };
+    let exceeded = CBrotliDecoderGetLimitExceeded(state);
+    CBrotliDecoderDestroyInstance(state);
+    (result as i32, total, exceeded)
+  };
+  assert_eq!(decode(None), (BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS as i32, 16 << 20, 0));
+  assert_eq!(decode(Some((CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE, 1 << 20))),
+             (BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32, 1 << 20, DecompressionLimitExceeded::OutputSize as u32));
+  let (result, total, exceeded) = decode(Some((CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_RATIO, 100)));
+  assert_eq!(result, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32);
+  assert!(total as usize <= bomb.len() * 100);
+  assert_eq!(exceeded, DecompressionLimitExceeded::Ratio as u32);
+  assert_eq!(decode(Some((CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_WINDOW_BITS, 22))),
+             (BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32, 0, DecompressionLimitExceeded::WindowSize as u32));
+  // each decoder owns its limits
+  unsafe {
+    let limited = CBrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    let unlimited = CBrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    CBrotliDecoderSetParameter(limited, CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_WINDOW_BITS, 22);
+    let mut output = vec![0u8; 16 << 20];
+    for &(state, expected) in [(limited, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32),
+                               (unlimited, BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS as i32)].iter() {
+      let mut available_in = bomb.len();
+      let mut input_ptr = bomb.as_ptr();
+      let mut available_out = output.len();
+      let mut output_ptr = output.as_mut_ptr();
+      assert_eq!(CBrotliDecoderDecompressStream(state, &mut available_in, &mut input_ptr,
+                                                &mut available_out, &mut output_ptr, core::ptr::null_mut()) as i32,
+                 expected);
+    }
+    assert_eq!(CBrotliDecoderGetLimitExceeded(unlimited), 0);
+    CBrotliDecoderDestroyInstance(limited);
+    CBrotliDecoderDestroyInstance(unlimited);
+  }
+  // output drained with TakeOutput counts against the limits too
+  let take = |selector: CBrotliDecoderParameter, value: u32| unsafe {
+    let state = CBrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    CBrotliDecoderSetParameter(state, selector, value);
+    let mut total = 0usize;
+    let mut available_in = bomb.len();
+    let mut input_ptr = bomb.as_ptr();
+    let result = loop {
+      let mut available_out = 0usize;
+      let mut output_ptr = core::ptr::null_mut();
+      let result = CBrotliDecoderDecompressStream(state, &mut available_in, &mut input_ptr,
+                                                  &mut available_out, &mut output_ptr, core::ptr::null_mut()) as i32;
+      while CBrotliDecoderHasMoreOutput(state) != 0 {
+        let mut size = 0usize;
+        CBrotliDecoderTakeOutput(state, &mut size);
+        total += size;
+      }
+      if result != BrotliDecoderResult::BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT as i32 {
+        break result;
+      }
+    };
+    let exceeded = CBrotliDecoderGetLimitExceeded(state);
+    CBrotliDecoderDestroyInstance(state);
+    (result, total, exceeded)
+  };
+  assert_eq!(take(CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE, 0),
+             (BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS as i32, 16 << 20, 0));
+  assert_eq!(take(CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE, 1 << 20),
+             (BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32, 1 << 20, DecompressionLimitExceeded::OutputSize as u32));
+  let (result, total, exceeded) = take(CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_RATIO, 100);
+  assert_eq!(result, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32);
+  assert!(total <= bomb.len() * 100);
+  assert_eq!(exceeded, DecompressionLimitExceeded::Ratio as u32);
+  // the one-shot decoder takes the limits as arguments
+  let mut output = vec![0u8; 16 << 20];
+  for &(max_output_size, max_ratio, max_window_bits, expected) in
+      [(0, 0, 0, BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS as i32),
+       (1 << 20, 0, 0, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32),
+       (0, 100, 0, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32),
+       (0, 0, 22, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR as i32)].iter() {
+    let mut decoded_size = output.len();
+    let result = unsafe {
+      CBrotliDecoderDecompressWithLimits(bomb.len(), bomb.as_ptr(), &mut decoded_size, output.as_mut_ptr(),
+                                         max_output_size, max_ratio, max_window_bits)
+    };
+    assert_eq!(result as i32, expected);
+    if max_output_size != 0 {
+      assert!(decoded_size as u64 <= max_output_size);
+    } else if max_ratio == 0 && max_window_bits == 0 {
+      assert_eq!(decoded_size, 16 << 20);
+    }
+  }
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_inspect_header() {
+  use super::brotli::{BrotliInspectHeader, BrotliHeaderError, BrotliStreamHeader};
+  let data = include_bytes!("../../testdata/alice29.txt");
+  for &(lgwin, large_window) in [(10, false), (16, false), (17, false), (18, false), (24, false), (26, true), (30, true)].iter() {
+    let mut params = super::brotli::enc::BrotliEncoderParams::default();
+    params.quality = 5;
+    params.lgwin = lgwin;
+    params.large_window = large_window;
+    let mut compressed = Vec::new();
+    super::brotli::BrotliCompress(&mut &data[..], &mut compressed, &params).unwrap();
+    let header = BrotliInspectHeader(&compressed[..]).unwrap();
+    assert_eq!(header, BrotliStreamHeader{window_bits: lgwin as u8, large_window: large_window});
+  }
+  assert_eq!(BrotliInspectHeader(&[]), Err(BrotliHeaderError::NeedsMoreInput));
+  // large window streams declare the window in their second byte
+  assert_eq!(BrotliInspectHeader(&[0x11]), Err(BrotliHeaderError::NeedsMoreInput));
+  assert_eq!(BrotliInspectHeader(&[0x11, 30]).unwrap(), BrotliStreamHeader{window_bits: 30, large_window: true});
+  assert_eq!(BrotliInspectHeader(&[0x11, 40]), Err(BrotliHeaderError::Corrupt));
+  assert_eq!(BrotliInspectHeader(&[0x91, 30]), Err(BrotliHeaderError::Corrupt));
+  assert_eq!(super::brotli::BrotliDecoderWindowMemory(30), (1u64 << 30) + 542);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_read_header_before_allocating() {
+  use super::brotli::{BrotliReadHeader, DecompressionLimits, DecompressionLimitExceeded, LimitedDecompressor};
+  use super::brotli::ffi::decompressor::CBrotliDecoderInspectHeader;
+  let text = include_bytes!("../../testdata/alice29.txt");
+  let compressed = compress_for_limits(&text[..], 22);
+  let header = BrotliReadHeader(&mut &compressed[..]).unwrap();
+  assert_eq!(header.window_bits, 22);
+  assert!(!header.large_window);
+  assert_eq!(header.window_memory(), (1 << 22) + 542);
+  assert_eq!(BrotliReadHeader(&mut &[0x11u8][..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+  let mut window_bits = 0u32;
+  let mut large_window = -1i32;
+  unsafe {
+    assert_eq!(CBrotliDecoderInspectHeader(compressed.as_ptr(), compressed.len(), &mut window_bits, &mut large_window), 1);
+    assert_eq!((window_bits, large_window), (22, 0));
+    assert_eq!(CBrotliDecoderInspectHeader([0x11u8, 28].as_ptr(), 2, &mut window_bits, &mut large_window), 1);
+    assert_eq!((window_bits, large_window), (28, 1));
+    assert_eq!(CBrotliDecoderInspectHeader([0x11u8].as_ptr(), 1, &mut window_bits, &mut large_window), 0);
+    assert_eq!(CBrotliDecoderInspectHeader(core::ptr::null(), 0, &mut window_bits, &mut large_window), 0);
+  }
+  // the oversized window is refused before the decoder sees a single byte
+  let mut reader = LimitedDecompressor::new(&compressed[..], 4096, DecompressionLimits{max_window_bits: 20, ..DecompressionLimits::default()});
+  let mut output = Vec::new();
+  assert_eq!(limit_error(&reader.read_to_end(&mut output).unwrap_err()), Some(DecompressionLimitExceeded::WindowSize));
+  assert!(output.is_empty());
+  assert_eq!(reader.tracker().window_bits(), Some(22));
+  assert_eq!(reader.tracker().window_memory(), Some((1 << 22) + 542));
+  assert_eq!(reader.tracker().input_size(), 0);
+}
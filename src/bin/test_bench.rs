@@ -0,0 +1,73 @@
+#![cfg(test)]
+extern crate brotli;
+use super::bench::{add_totals, bench_data, parse_args, write_records, BenchOptions, Format, Stats, TOTAL};
+
+static ALICE: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+
+fn small_options() -> BenchOptions {
+  let mut options = BenchOptions::default();
+  options.qualities = vec!["1".to_string(), "9.5".to_string()];
+  options.windows = vec![16, 22];
+  options.threads = vec![1, 2];
+  options.warmup = 0;
+  options.repeat = 2;
+  options
+}
+
+#[test]
+fn test_bench_stats() {
+  let stats = Stats::new(&[4.0, 1.0, 3.0, 2.0]);
+  assert_eq!(stats.min, 1.0);
+  assert_eq!(stats.median, 2.5);
+  assert_eq!(stats.mean, 2.5);
+  assert!((stats.stddev - 1.25f64.sqrt()).abs() < 1e-9);
+  assert_eq!(Stats::new(&[3.0, 1.0, 2.0]).median, 2.0);
+}
+
+#[test]
+fn test_bench_parse_args() {
+  let args = vec!["--quality=0,9.5x,11", "-w", "18,24", "-j4", "--format", "json", "a", "--", "-b"];
+  let (options, paths) = parse_args(args.iter().map(|a| a.to_string()).collect());
+  assert_eq!(options.qualities, vec!["0", "9.5x", "11"]);
+  assert_eq!(options.windows, vec![18, 24]);
+  assert_eq!(options.threads, vec![4]);
+  assert_eq!(options.format, Format::Json);
+  assert_eq!(paths, vec!["a", "-b"]);
+}
+
+#[test]
+fn test_bench_sweep_and_report() {
+  let options = small_options();
+  let mut records = bench_data("alice", &ALICE[..32768], &options).unwrap();
+  records.extend(bench_data("alice2", &ALICE[32768..65536], &options).unwrap());
+  assert_eq!(records.len(), 16);
+  for record in records.iter() {
+    assert_eq!(record.content_type, "Text");
+    assert!(record.ratio() > 1.5);
+    assert!(record.compress.min <= record.compress.median && record.compress.median > 0.0);
+  }
+  // 9.5 compresses better than quality 1 on the same window
+  assert!(records[1].compressed_size < records[0].compressed_size);
+  add_totals(&mut records);
+  assert_eq!(records.len(), 24);
+  let total = &records[16];
+  assert_eq!(total.file, TOTAL);
+  assert_eq!(total.input_size, 65536);
+  assert_eq!(total.compressed_size, records[0].compressed_size + records[8].compressed_size);
+
+  let mut csv = Vec::new();
+  write_records(&mut csv, &records, Format::Csv).unwrap();
+  let csv = String::from_utf8(csv).unwrap();
+  let lines: Vec<&str> = csv.lines().collect();
+  assert_eq!(lines.len(), 25);
+  assert!(lines[0].starts_with("file,content_type,quality,lgwin,threads,input_size,compressed_size,ratio"));
+  assert!(lines[2].starts_with("alice,Text,9.5,16,1,32768,"));
+  assert!(lines.iter().all(|l| l.split(',').count() == 18));
+
+  let mut json = Vec::new();
+  write_records(&mut json, &records[..2], Format::Json).unwrap();
+  let json = String::from_utf8(json).unwrap();
+  assert!(json.starts_with("[\n  {\"file\": \"alice\", \"content_type\": \"Text\", \"quality\": \"1\""));
+  assert!(json.ends_with("}\n]\n"));
+  assert_eq!(json.matches("\"median_s\"").count(), 4);
+}
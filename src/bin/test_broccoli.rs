@@ -6,6 +6,11 @@ extern crate brotli_decompressor;
 use brotli_decompressor::{CustomRead, CustomWrite};
 use super::brotli::enc::BrotliEncoderParams;
 use super::brotli::concat::{BroCatli, BroCatliResult};
+#[cfg(feature="std")]
+use super::brotli::concat::{BrotliParseStream, BrotliTranscodeForConcatenation,
+                            BrotliFindSplitPoints, BrotliExtractMetablocks, BrotliSplitStream,
+                            BrotliAppendToStream, BrotliDescribeStream, BrotliCheckConcatenation,
+                            ConcatReader, ConcatWriter};
 use super::integration_tests::UnlimitedBuffer;
 static RANDOM_THEN_UNICODE : &'static [u8] = include_bytes!("../../testdata/random_then_unicode");
 static ALICE: &'static[u8]  = include_bytes!("../../testdata/alice29.txt");
@@ -18,10 +23,9 @@ static RANDOMTHENUNICODE: &'static [u8] = include_bytes!("../../testdata/random_
 static QUICKFOX: &'static [u8] = include_bytes!("../../testdata/quickfox_repeated");
 static EMPTY: &'static [u8] = &[];
 use super::Rebox;
-fn concat(files:&mut [UnlimitedBuffer],
-          brotli_files:&mut [UnlimitedBuffer],
-          window_override:Option<u8>,
-          bs: usize) {
+fn concat_streams(brotli_files:&mut [UnlimitedBuffer],
+                  window_override:Option<u8>,
+                  bs: usize) -> UnlimitedBuffer {
   let mut obuffer = vec![0u8; bs];
   let mut ibuffer = vec![0u8; bs];
   let mut ooffset = 0usize;
@@ -99,6 +103,14 @@ fn concat(files:&mut [UnlimitedBuffer],
       }
     }
   }
+  uboutput
+}
+
+fn concat(files:&mut [UnlimitedBuffer],
+          brotli_files:&mut [UnlimitedBuffer],
+          window_override:Option<u8>,
+          bs: usize) {
+  let mut uboutput = concat_streams(brotli_files, window_override, bs);
   let mut rt = UnlimitedBuffer::new(&[]);
   match super::decompress(&mut uboutput, &mut rt, 65536, Rebox::default()) {
     Ok(_) => {},
@@ -422,3 +434,412 @@ fn test_concat() {
     concat_many_subsets(&mut files[..], &mut ufiles[..], None);
     concat_many_subsets(&mut files[..], &mut ufiles[..], Some(28)); // FIXME: make this 28
 }
+
+#[test]
+fn test_concat_catable_profile() {
+    // a profile that only says catable=true must still produce files BroCatli accepts
+    let mut params = BrotliEncoderParams::default();
+    params.quality = 9;
+    super::brotli::enc::BrotliEncoderParseProfile("version=1\ncatable=true\n", &mut params).unwrap();
+    let mut compressed = Vec::<UnlimitedBuffer>::new();
+    for data in [ALICE, DICTWORD, UKKONOOA].iter() {
+        let mut src = UnlimitedBuffer::new(data);
+        let mut dst = UnlimitedBuffer::new(&[]);
+        super::compress(&mut src, &mut dst, 4096, &params, &[], 1).unwrap();
+        compressed.push(dst);
+    }
+    let joined = concat_streams(&mut compressed[..], None, 4096);
+    let mut expected = ALICE.to_vec();
+    expected.extend(DICTWORD);
+    expected.extend(UKKONOOA);
+    assert_eq!(decompress_all(joined.data()), expected);
+}
+
+#[cfg(feature="std")]
+fn transcode_and_concat(files:&mut [UnlimitedBuffer], options:&mut [BrotliEncoderParams], window_override:Option<u8>) {
+    let mut tfiles = Vec::<UnlimitedBuffer>::new();
+    let mut window = window_override.unwrap_or(0);
+    for (index, src) in files.iter_mut().enumerate() {
+        let mut dst = UnlimitedBuffer::new(&[]);
+        super::compress(src, &mut dst, 4096, &options[index % options.len()], &[], 1).unwrap();
+        src.reset_read();
+        let parsed = BrotliParseStream(dst.data()).unwrap();
+        assert_eq!(&parsed.output[..], src.data());
+        let transcoded = BrotliTranscodeForConcatenation(dst.data(), window).unwrap();
+        assert_eq!(transcoded.reencoded_bytes + transcoded.copied_bytes, src.data().len() as u64);
+        if window == 0 {
+            window = parsed.window_bits;
+        }
+        tfiles.push(UnlimitedBuffer::new(&transcoded.data[..]));
+    }
+    concat(files, &mut tfiles[..], window_override, 4096);
+    concat(files, &mut tfiles[..], window_override, 3);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_transcode_concat() {
+    let mut files = [
+        UnlimitedBuffer::new(ALICE),
+        UnlimitedBuffer::new(RANDOMTHENUNICODE),
+        UnlimitedBuffer::new(UKKONOOA),
+        UnlimitedBuffer::new(ASYOULIKE),
+        UnlimitedBuffer::new(BACKWARD65536),
+        UnlimitedBuffer::new(EMPTY),
+        UnlimitedBuffer::new(DICTWORD),
+        UnlimitedBuffer::new(RANDOM10K),
+        UnlimitedBuffer::new(QUICKFOX),
+    ];
+    let mut options = [BrotliEncoderParams::default(),
+                       BrotliEncoderParams::default(),
+                       BrotliEncoderParams::default(),
+                       BrotliEncoderParams::default()];
+    for (option, quality) in options.iter_mut().zip([11, 9, 5, 2].iter()) {
+        option.quality = *quality;
+        option.lgwin = 22;
+    }
+    transcode_and_concat(&mut files[..], &mut options[..], None);
+    // smaller windows than the concatenated stream re-encode any use of the static dictionary
+    for (option, lgwin) in options.iter_mut().zip([22, 18, 16, 10].iter()) {
+        option.lgwin = *lgwin;
+    }
+    transcode_and_concat(&mut files[..], &mut options[..], None);
+    transcode_and_concat(&mut files[..], &mut options[..], Some(24));
+    for option in options.iter_mut() {
+        option.lgwin = 26;
+        option.large_window = true;
+    }
+    transcode_and_concat(&mut files[..], &mut options[..], None);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_transcode_copies_most_metablocks() {
+    let mut input = Vec::<u8>::new();
+    for _ in 0..3 {
+        input.extend(ALICE);
+        input.extend(ASYOULIKE);
+    }
+    let mut params = BrotliEncoderParams::default();
+    params.quality = 5;
+    params.lgwin = 16;
+    let mut src = UnlimitedBuffer::new(&input[..]);
+    let mut dst = UnlimitedBuffer::new(&[]);
+    super::compress(&mut src, &mut dst, 4096, &params, &[], 1).unwrap();
+    let transcoded = BrotliTranscodeForConcatenation(dst.data(), 0).unwrap();
+    assert!(transcoded.copied_bytes > 4 * transcoded.reencoded_bytes);
+    assert!(transcoded.data.len() < dst.data().len() + dst.data().len() / 50);
+    // the copied metablocks were only walked, so check they still decode to the input
+    assert_eq!(decompress_all(&transcoded.data[..]), input);
+    // corrupt input is rejected rather than copied
+    let truncated = &dst.data()[..dst.data().len() - 8];
+    assert_eq!(BrotliTranscodeForConcatenation(truncated, 0).err(), Some(BroCatliResult::BrotliFileCorrupt));
+}
+
+fn decompress_all(data: &[u8]) -> Vec<u8> {
+    let mut src = UnlimitedBuffer::new(data);
+    let mut rt = UnlimitedBuffer::new(&[]);
+    match super::decompress(&mut src, &mut rt, 65536, Rebox::default()) {
+        Ok(_) => {},
+        Err(e) => panic!("Error {:?}", e),
+    }
+    rt.data().to_vec()
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_extract_catable_files() {
+    let mut files = [
+        UnlimitedBuffer::new(ALICE),
+        UnlimitedBuffer::new(UKKONOOA),
+        UnlimitedBuffer::new(ASYOULIKE),
+        UnlimitedBuffer::new(RANDOM10K),
+        UnlimitedBuffer::new(QUICKFOX),
+    ];
+    let mut ufiles = Vec::<UnlimitedBuffer>::new();
+    for (index, src) in files.iter_mut().enumerate() {
+        let mut params = BrotliEncoderParams::default();
+        params.quality = [9, 5, 11, 2, 6][index];
+        params.lgwin = 22;
+        params.catable = true;
+        params.use_dictionary = false;
+        params.appendable = index == 0;
+        params.magic_number = index == 3;
+        let mut dst = UnlimitedBuffer::new(&[]);
+        super::compress(src, &mut dst, 4096, &params, &[], 1).unwrap();
+        src.reset_read();
+        ufiles.push(dst);
+    }
+    let joined = concat_streams(&mut ufiles[..], None, 4096);
+    let parsed = BrotliParseStream(joined.data()).unwrap();
+    let points = BrotliFindSplitPoints(&parsed);
+    let mut file_starts = Vec::<u64>::new();
+    let mut offset = 0u64;
+    for file in files.iter() {
+        file_starts.push(offset);
+        offset += file.data().len() as u64;
+    }
+    for start in file_starts.iter() {
+        assert!(points.iter().any(|point| point.output_offset == *start));
+    }
+    // each original file comes back out on its own
+    for (index, file) in files.iter().enumerate() {
+        let first = points.iter().find(|point| point.output_offset == file_starts[index]).unwrap().metablock;
+        let end = if index + 1 < files.len() {
+            points.iter().find(|point| point.output_offset == file_starts[index + 1]).unwrap().metablock
+        } else {
+            parsed.metablocks.len()
+        };
+        let extracted = BrotliExtractMetablocks(joined.data(), &parsed, first, end).unwrap();
+        assert_eq!(&decompress_all(&extracted[..])[..], file.data());
+    }
+    for target in [1u64, 100000, 1 << 30].iter() {
+        let chunks = BrotliSplitStream(joined.data(), *target).unwrap();
+        let mut rt = Vec::<u8>::new();
+        for chunk in chunks.iter() {
+            rt.extend(decompress_all(&chunk[..]));
+        }
+        assert_eq!(rt.len() as u64, offset);
+        assert_eq!(&rt[..ALICE.len()], ALICE);
+        if *target == 1 {
+            assert!(chunks.len() >= files.len());
+        }
+    }
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_split_multithreaded_output() {
+    let mut input = Vec::<u8>::new();
+    for _ in 0..4 {
+        input.extend(ALICE);
+        input.extend(ASYOULIKE);
+    }
+    let mut params = BrotliEncoderParams::default();
+    params.quality = 5;
+    params.lgwin = 22;
+    let mut src = UnlimitedBuffer::new(&input[..]);
+    let mut dst = UnlimitedBuffer::new(&[]);
+    super::compress(&mut src, &mut dst, 4096, &params, &[], 4).unwrap();
+    // work items see the input before them as a dictionary, so cuts land only where nothing reaches back
+    let chunks = BrotliSplitStream(dst.data(), 1).unwrap();
+    let mut rt = Vec::<u8>::new();
+    for chunk in chunks.iter() {
+        rt.extend(decompress_all(&chunk[..]));
+    }
+    assert_eq!(rt, input);
+    // a cut that is not a split point is refused
+    let parsed = BrotliParseStream(dst.data()).unwrap();
+    let points = BrotliFindSplitPoints(&parsed);
+    if let Some(index) = (1..parsed.metablocks.len()).find(|i| !points.iter().any(|p| p.metablock == *i)
+                                                           && parsed.metablocks[*i].output_len != 0) {
+        assert_eq!(BrotliExtractMetablocks(dst.data(), &parsed, index, parsed.metablocks.len()).err(),
+                   Some(BroCatliResult::BrotliFileNotCraftedForConcatenation));
+    }
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_append_in_place() {
+    for lgwin in [16, 22, 26].iter() {
+        let mut params = BrotliEncoderParams::default();
+        params.quality = 5;
+        params.lgwin = *lgwin;
+        params.large_window = *lgwin > 24;
+        let mut file = ::std::io::Cursor::new(Vec::<u8>::new());
+        let mut expected = Vec::<u8>::new();
+        for (index, data) in [ALICE, EMPTY, UKKONOOA, QUICKFOX, RANDOM10K].iter().enumerate() {
+            params.quality = [5, 11, 1, 9, 0][index];
+            let before = file.get_ref().len() as u64;
+            let new_len = BrotliAppendToStream(&mut file, data, &params).unwrap();
+            assert_eq!(new_len, file.get_ref().len() as u64);
+            assert!(index == 0 || new_len >= before);
+            expected.extend(*data);
+            assert_eq!(decompress_all(&file.get_ref()[..]), expected);
+        }
+    }
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_append_requires_appendable_file() {
+    let params = BrotliEncoderParams::default();
+    // window 22, then a final byte whose top two bits are not both set
+    for original in [vec![0x0bu8, 0x02], vec![0x0bu8, 0x00, 0x00], vec![0x3bu8, 0x01]].iter() {
+        let mut file = ::std::io::Cursor::new(original.clone());
+        assert!(BrotliAppendToStream(&mut file, UKKONOOA, &params).is_err());
+        assert_eq!(file.get_ref(), original);
+    }
+    // a regular stream whose last bits happen to be set ends in data, not in an empty last metablock
+    for len in 2980..3000 {
+        let original = compress_with(&ALICE[..len], 9, 22, false, false, false);
+        let mut file = ::std::io::Cursor::new(original.clone());
+        assert_eq!(BrotliAppendToStream(&mut file, UKKONOOA, &params).unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+        assert_eq!(file.get_ref(), &original);
+    }
+}
+
+
+fn compress_with(data: &[u8], quality: i32, lgwin: i32, catable: bool, appendable: bool, magic_number: bool) -> Vec<u8> {
+    let mut params = BrotliEncoderParams::default();
+    params.quality = quality;
+    params.lgwin = lgwin;
+    params.catable = catable;
+    params.use_dictionary = !catable;
+    params.appendable = appendable;
+    params.magic_number = magic_number;
+    let mut src = UnlimitedBuffer::new(data);
+    let mut dst = UnlimitedBuffer::new(&[]);
+    super::compress(&mut src, &mut dst, 4096, &params, &[], 1).unwrap();
+    dst.data().to_vec()
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_describe_stream() {
+    let catable = BrotliDescribeStream(&compress_with(ALICE, 9, 18, true, true, true)[..]).unwrap();
+    assert_eq!(catable.window_bits, 18);
+    assert!(catable.catable && catable.appendable && catable.magic_number);
+    assert!(!catable.uses_dictionary && !catable.large_window);
+    assert_eq!(catable.output_len, ALICE.len() as u64);
+    let appendable = BrotliDescribeStream(&compress_with(ASYOULIKE, 5, 22, false, true, false)[..]).unwrap();
+    assert!(appendable.appendable && !appendable.catable && !appendable.magic_number);
+    let plain = BrotliDescribeStream(&compress_with(ALICE, 11, 22, false, false, false)[..]).unwrap();
+    assert!(!plain.appendable && !plain.catable && plain.uses_dictionary);
+    assert_eq!(BrotliDescribeStream(&[0x0b, 0x02, 0x80]).err(), Some(BroCatliResult::BrotliFileCorrupt));
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_check_concatenation() {
+    let first = BrotliDescribeStream(&compress_with(ALICE, 9, 18, true, true, false)[..]).unwrap();
+    let second = BrotliDescribeStream(&compress_with(UKKONOOA, 5, 22, true, true, false)[..]).unwrap();
+    let plain = BrotliDescribeStream(&compress_with(ASYOULIKE, 9, 22, false, false, false)[..]).unwrap();
+    let plan = BrotliCheckConcatenation(&[second, first], None).unwrap();
+    assert_eq!((plan.window_size, plan.needs_window_override), (22, false));
+    assert_eq!(BrotliCheckConcatenation(&[first, second, plain], None).err(),
+               Some((2, BroCatliResult::BrotliFileNotCraftedForConcatenation)));
+    // a later file with a larger window needs the window up front
+    let plan = BrotliCheckConcatenation(&[first, second], None).unwrap();
+    assert_eq!((plan.window_size, plan.needs_window_override), (22, true));
+    assert_eq!(BrotliCheckConcatenation(&[first, second], Some(20)).err(),
+               Some((1, BroCatliResult::WindowSizeLargerThanPreviousFile)));
+    assert_eq!(BrotliCheckConcatenation(&[plain, first], None).err(),
+               Some((0, BroCatliResult::BrotliFileNotCraftedForAppend)));
+    assert_eq!(BrotliCheckConcatenation(&[plain], Some(24)).err(),
+               Some((0, BroCatliResult::BrotliFileNotCraftedForConcatenation)));
+    // the plan is what BroCatli actually needs
+    let mut files = [
+        UnlimitedBuffer::new(&compress_with(ALICE, 9, 18, true, true, false)[..]),
+        UnlimitedBuffer::new(&compress_with(UKKONOOA, 5, 22, true, true, false)[..]),
+    ];
+    let joined = concat_streams(&mut files[..], Some(plan.window_size), 4096);
+    let mut expected = ALICE.to_vec();
+    expected.extend(UKKONOOA);
+    assert_eq!(decompress_all(joined.data()), expected);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_concat_reader_writer() {
+    use std::io::{Read, Write};
+    let files = [
+        compress_with(ALICE, 9, 22, true, true, false),
+        compress_with(EMPTY, 5, 22, true, true, false),
+        compress_with(UKKONOOA, 11, 18, true, true, true),
+        compress_with(RANDOM10K, 2, 20, true, false, false),
+    ];
+    let mut expected = ALICE.to_vec();
+    expected.extend(UKKONOOA);
+    expected.extend(RANDOM10K);
+    for buffer_size in [1usize, 3, 4096].iter() {
+        let mut reader = ConcatReader::new(files.iter().map(|file| &file[..]).collect(), *buffer_size);
+        let mut joined = Vec::<u8>::new();
+        let mut chunk = vec![0u8; *buffer_size];
+        loop {
+            let count = reader.read(&mut chunk[..]).unwrap();
+            if count == 0 {
+                break;
+            }
+            joined.extend(&chunk[..count]);
+        }
+        assert_eq!(decompress_all(&joined[..]), expected);
+
+        let mut writer = ConcatWriter::new(Vec::<u8>::new(), *buffer_size);
+        for file in files.iter() {
+            writer.start_file();
+            for piece in file.chunks(7) {
+                writer.write_all(piece).unwrap();
+            }
+        }
+        assert_eq!(writer.finish().unwrap(), joined);
+    }
+    // the window can be given up front so a larger window may follow
+    let mut reader = ConcatReader::new_with_window_size(vec![&files[2][..], &files[0][..]], 4096, 24);
+    let mut joined = Vec::<u8>::new();
+    reader.read_to_end(&mut joined).unwrap();
+    let mut expected = UKKONOOA.to_vec();
+    expected.extend(ALICE);
+    assert_eq!(decompress_all(&joined[..]), expected);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_concat_reader_writer_errors() {
+    use std::io::{Read, Write};
+    let plain = compress_with(ALICE, 9, 22, false, false, false);
+    let catable = compress_with(UKKONOOA, 5, 22, true, true, false);
+    let mut joined = Vec::<u8>::new();
+    let err = ConcatReader::new(vec![&catable[..], &plain[..]], 4096).read_to_end(&mut joined).unwrap_err();
+    assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+    let mut writer = ConcatWriter::new(Vec::<u8>::new(), 4096);
+    assert_eq!(writer.write(&catable[..]).unwrap_err().kind(), ::std::io::ErrorKind::InvalidInput);
+    writer.start_file();
+    writer.write_all(&catable[..]).unwrap();
+    writer.start_file();
+    assert_eq!(writer.write_all(&plain[..]).unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_concat_with_empty_output_calls() {
+    // a call with no room to write must leave the state as it was, whatever the previous file's last bits are
+    for len in 1..24 {
+        let first = compress_with(&ALICE[..len * 37], 5, 22, false, true, false);
+        let second = compress_with(&UKKONOOA[..len], 5, 22, true, true, false);
+        let mut bro_cat_li = BroCatli::new();
+        let mut joined = Vec::<u8>::new();
+        let mut out = [0u8; 1];
+        for file in [&first[..], &second[..]].iter() {
+            bro_cat_li.new_brotli_file();
+            let mut in_offset = 0usize;
+            loop {
+                let mut out_offset = 0usize;
+                match bro_cat_li.stream(file, &mut in_offset, &mut out[..0], &mut out_offset) {
+                    BroCatliResult::NeedsMoreInput | BroCatliResult::NeedsMoreOutput => {},
+                    err => panic!("{:?}", err),
+                }
+                match bro_cat_li.stream(file, &mut in_offset, &mut out[..], &mut out_offset) {
+                    BroCatliResult::NeedsMoreInput => {
+                        joined.extend(&out[..out_offset]);
+                        break;
+                    },
+                    BroCatliResult::NeedsMoreOutput => joined.extend(&out[..out_offset]),
+                    err => panic!("{:?}", err),
+                }
+            }
+        }
+        loop {
+            let mut out_offset = 0usize;
+            let result = bro_cat_li.finish(&mut out[..], &mut out_offset);
+            joined.extend(&out[..out_offset]);
+            match result {
+                BroCatliResult::Success => break,
+                BroCatliResult::NeedsMoreOutput => {},
+                err => panic!("{:?}", err),
+            }
+        }
+        let mut expected = ALICE[..len * 37].to_vec();
+        expected.extend(&UKKONOOA[..len]);
+        assert_eq!(decompress_all(&joined[..]), expected);
+    }
+}
@@ -0,0 +1,117 @@
+#![cfg(test)]
+extern crate brotli;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use super::cli::{FrontEnd, LegacyOutput, Operation, Options, EXIT_ERROR, EXIT_OK, EXIT_WARNING};
+use super::expand_short_flags;
+
+static ALICE: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+
+fn scratch_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("brotli_cli_{}_{}", name, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+fn run(options: &Options, filenames: &[PathBuf]) -> i32 {
+  let mut params = brotli::enc::BrotliEncoderInitParams();
+  params.quality = 5;
+  let names: Vec<String> = filenames.iter().map(|f| f.to_string_lossy().into_owned()).collect();
+  FrontEnd::new(options, &params, &[], 65536, 1).run(&names)
+}
+
+#[test]
+fn test_expand_short_flags() {
+  let args = vec!["-dkc".to_string(), "-q9".to_string(), "-findprior".to_string(), "--".to_string(), "-dc".to_string()];
+  assert_eq!(expand_short_flags(args),
+             vec!["-d", "-k", "-c", "-q9", "-findprior", "--", "-dc"]);
+}
+
+#[test]
+fn test_cli_round_trip_removes_and_restores() {
+  let dir = scratch_dir("round_trip");
+  let input = dir.join("alice.txt");
+  fs::write(&input, ALICE).unwrap();
+  let mtime = fs::metadata(&input).unwrap().modified().unwrap() - std::time::Duration::from_secs(86400);
+  fs::File::options().write(true).open(&input).unwrap().set_modified(mtime).unwrap();
+  let mut options = Options::default();
+  options.keep = false;
+  assert_eq!(run(&options, &[input.clone()]), EXIT_OK);
+  let compressed = dir.join("alice.txt.br");
+  assert!(!input.exists());
+  assert!(fs::metadata(&compressed).unwrap().len() < ALICE.len() as u64);
+  assert_eq!(fs::metadata(&compressed).unwrap().modified().unwrap(), mtime);
+  options.operation = Operation::Test;
+  assert_eq!(run(&options, &[compressed.clone()]), EXIT_OK);
+  options.operation = Operation::Decompress;
+  assert_eq!(run(&options, &[compressed.clone()]), EXIT_OK);
+  assert!(!compressed.exists());
+  assert_eq!(&fs::read(&input).unwrap()[..], ALICE);
+  assert_eq!(fs::metadata(&input).unwrap().modified().unwrap(), mtime);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cli_refuses_overwrite_and_skips_suffix() {
+  let dir = scratch_dir("overwrite");
+  let input = dir.join("alice.txt");
+  fs::write(&input, ALICE).unwrap();
+  let mut options = Options::default();
+  assert_eq!(run(&options, &[input.clone()]), EXIT_OK);
+  assert!(input.exists());
+  let compressed = dir.join("alice.txt.br");
+  fs::write(&compressed, b"keep me").unwrap();
+  assert_eq!(run(&options, &[input.clone()]), EXIT_ERROR);
+  assert_eq!(&fs::read(&compressed).unwrap()[..], b"keep me");
+  assert_eq!(run(&options, &[compressed.clone()]), EXIT_WARNING);
+  options.force = true;
+  assert_eq!(run(&options, &[input.clone()]), EXIT_OK);
+  options.operation = Operation::Test;
+  assert_eq!(run(&options, &[compressed.clone()]), EXIT_OK);
+  assert_eq!(run(&options, &[input.clone()]), EXIT_ERROR);
+  assert_eq!(run(&options, &[dir.join("missing.br")]), EXIT_ERROR);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cli_recursive_and_output() {
+  let dir = scratch_dir("recursive");
+  fs::create_dir_all(dir.join("sub")).unwrap();
+  fs::write(dir.join("a.txt"), &ALICE[..1000]).unwrap();
+  fs::write(dir.join("sub").join("b.txt"), &ALICE[1000..5000]).unwrap();
+  let mut options = Options::default();
+  assert_eq!(run(&options, &[dir.clone()]), EXIT_WARNING);
+  options.recursive = true;
+  options.suffix = ".bro".to_string();
+  assert_eq!(run(&options, &[dir.clone()]), EXIT_OK);
+  assert!(dir.join("a.txt.bro").exists());
+  assert!(dir.join("sub").join("b.txt.bro").exists());
+  options.recursive = false;
+  options.operation = Operation::Decompress;
+  options.output = Some(dir.join("b.out").to_string_lossy().into_owned());
+  assert_eq!(run(&options, &[dir.join("sub").join("b.txt.bro")]), EXIT_OK);
+  assert_eq!(&fs::read(dir.join("b.out")).unwrap()[..], &ALICE[1000..5000]);
+  assert_eq!(run(&options, &[dir.join("a.txt.bro"), dir.join("sub").join("b.txt.bro")]), EXIT_ERROR);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_legacy_output_replaces_only_on_commit() {
+  let dir = scratch_dir("legacy");
+  let output_path = dir.join("b.txt");
+  fs::write(&output_path, b"keep me").unwrap();
+  let mut output = LegacyOutput::create(&output_path).unwrap();
+  output.file.write_all(&ALICE[..100]).unwrap();
+  assert_eq!(&fs::read(&output_path).unwrap()[..], b"keep me");
+  output.discard();
+  assert_eq!(&fs::read(&output_path).unwrap()[..], b"keep me");
+  let mut output = LegacyOutput::create(&output_path).unwrap();
+  output.file.write_all(&ALICE[..100]).unwrap();
+  output.commit().unwrap();
+  assert_eq!(&fs::read(&output_path).unwrap()[..], &ALICE[..100]);
+  // no temporary files are left behind
+  assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+  let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,77 @@
+#![cfg(test)]
+// Deterministic mode must write the same bytes for the same input and params whatever the number of
+// threads, the spawner, the simd feature or the target. The hashes below are the reference output: run
+// these tests with and without --features simd and on every target, and only update the hashes
+// together with an intentional change to the encoder.
+extern crate brotli;
+use brotli::enc::BrotliEncoderParams;
+use super::integration_tests::UnlimitedBuffer;
+use super::{compress, decompress, Rebox};
+
+static ALICE: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+
+fn deterministic_params(quality: i32, q9_5: bool) -> BrotliEncoderParams {
+  let mut params = BrotliEncoderParams::default();
+  params.quality = quality;
+  params.q9_5 = q9_5;
+  params.deterministic = true;
+  params
+}
+
+fn compress_with_threads(params: &BrotliEncoderParams, input: &[u8], num_threads: usize) -> Vec<u8> {
+  let mut output = UnlimitedBuffer::new(&[]);
+  compress(&mut UnlimitedBuffer::new(input), &mut output, 4096, params, &[], num_threads).unwrap();
+  let mut rt = UnlimitedBuffer::new(&[]);
+  decompress(&mut UnlimitedBuffer::new(output.data()), &mut rt, 4096, Rebox::default()).unwrap();
+  assert_eq!(rt.data(), input);
+  output.data().to_vec()
+}
+
+// 64 bit FNV-1a
+fn fingerprint(data: &[u8]) -> u64 {
+  let mut hash = 0xcbf29ce484222325u64;
+  for byte in data.iter() {
+    hash ^= u64::from(*byte);
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+#[test]
+fn test_deterministic_output_ignores_thread_count() {
+  for &favor_cpu_efficiency in [false, true].iter() {
+    let mut params = deterministic_params(5, false);
+    params.favor_cpu_efficiency = favor_cpu_efficiency;
+    params.max_chunk_size = 20000;
+    let reference = compress_with_threads(&params, ALICE, 1);
+    for &num_threads in [2usize, 3, 4, 16].iter() {
+      assert_eq!(compress_with_threads(&params, ALICE, num_threads), reference);
+    }
+  }
+}
+
+#[test]
+fn test_deterministic_default_chunk_size() {
+  let params = deterministic_params(9, false);
+  let reference = compress_with_threads(&params, ALICE, 1);
+  assert_eq!(compress_with_threads(&params, ALICE, 4), reference);
+  let mut threaded = params.clone();
+  threaded.deterministic = false;
+  assert!(compress_with_threads(&threaded, ALICE, 4) != reference);
+}
+
+#[test]
+fn test_deterministic_reference_output() {
+  let expected = [
+    (deterministic_params(5, false), 0x8f75814c38398a92),
+    (deterministic_params(9, false), 0x670604848ae0c1db),
+    (deterministic_params(10, true), 0xa68eadbf88a7b301),
+    (deterministic_params(11, false), 0x8385dca7ca49622a),
+  ];
+  for &(ref params, hash) in expected.iter() {
+    let mut chunked = params.clone();
+    chunked.max_chunk_size = 32768;
+    let output = compress_with_threads(&chunked, &ALICE[..65536], 2);
+    assert_eq!((params.quality, fingerprint(&output)), (params.quality, hash));
+  }
+}
@@ -5,8 +5,9 @@ extern crate core;
 extern crate brotli_decompressor;
 use super::new_brotli_heap_alloc;
 use brotli_decompressor::{SliceWrapperMut, SliceWrapper};
-use super::brotli::enc::{UnionHasher, BrotliEncoderParams, BrotliEncoderMaxCompressedSizeMulti, compress_multi, compress_multi_no_threadpool};
-use brotli::enc::threading::{SendAlloc,Owned};
+use super::brotli::enc::{UnionHasher, BrotliEncoderParams, BrotliEncoderMaxCompressedSizeMulti, compress_multi, compress_multi_no_threadpool,
+                         compress_multi_with_dictionary, compress_multi_no_threadpool_with_dictionary};
+use brotli::enc::threading::{SendAlloc,Owned, BrotliEncoderNumChunks};
 
 use super::integration_tests::UnlimitedBuffer;
 static RANDOM_THEN_UNICODE : &'static [u8] = include_bytes!("../../testdata/random_then_unicode");
@@ -179,3 +180,167 @@ fn thread_spawn_per_job_split_compression_test_0b1() {
 fn thread_spawn_per_job_split_compression_test_0b3() {
     thread_spawn_per_job_split_compression_test(&[], 3, 5, false, 144325)
 }
+
+fn multi_threaded_dictionary_compression_test(dictionary: &[u8], input_data: &[u8], num_threads: usize, quality: i32, favor_cpu_efficiency: bool, expected_size: usize) {
+    let mut params = BrotliEncoderParams::default();
+    params.quality = quality;
+    params.favor_cpu_efficiency = favor_cpu_efficiency;
+    let mut output = Rebox::from(vec![0u8;BrotliEncoderMaxCompressedSizeMulti(input_data.len(), num_threads)]);
+    let mut alloc_per_thread = [
+        SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+        SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+        SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+        SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+    ];
+    let res = compress_multi_no_threadpool_with_dictionary(
+        &params,
+        &mut Owned::new(Rebox::from(input_data.to_vec())),
+        Rebox::from(dictionary.to_vec()),
+        output.slice_mut(),
+        &mut alloc_per_thread[..num_threads],
+    );
+    let observed_size = res.unwrap();
+    if observed_size > expected_size {
+        assert_eq!(observed_size, expected_size);
+    }
+    let mut compressed_version = UnlimitedBuffer::new(&output.slice()[..observed_size]);
+    let mut rt = UnlimitedBuffer::new(&[]);
+    match super::decompress(&mut compressed_version, &mut rt, 65536, Rebox::from(dictionary.to_vec())) {
+        Ok(_) => {}
+        Err(e) => panic!("Error {:?}", e),
+    }
+    assert_eq!(rt.data(), input_data);
+}
+#[test]
+fn multi_threaded_dictionary_compression_test_1() {
+    multi_threaded_dictionary_compression_test(&ALICE[..], &ALICE[..], 1, 5, false, 32)
+}
+#[test]
+fn multi_threaded_dictionary_compression_test_3() {
+    multi_threaded_dictionary_compression_test(&ALICE[..], &ALICE[..], 3, 5, false, 96)
+}
+#[test]
+fn multi_threaded_dictionary_compression_test_3_favor_cpu() {
+    multi_threaded_dictionary_compression_test(&ALICE[..], &ALICE[..], 3, 5, true, 96)
+}
+#[test]
+fn multi_threaded_dictionary_compression_test_4_favor_cpu_partial() {
+    multi_threaded_dictionary_compression_test(&ALICE[..65536], &ALICE[32768..], 4, 9, true, 28000)
+}
+#[test]
+fn multi_threaded_dictionary_compression_test_0b4_favor_cpu() {
+    multi_threaded_dictionary_compression_test(&ALICE[..4096], &[], 4, 5, true, 16)
+}
+
+// compresses with both the worker pool and the thread-per-chunk spawner, which must agree, and returns the size
+fn chunked_compression_test(params: &BrotliEncoderParams, dictionary: &[u8], input_data: &[u8], num_threads: usize) -> usize {
+    let num_chunks = BrotliEncoderNumChunks(params, input_data.len(), num_threads);
+    let mut outputs = [Vec::new(), Vec::new()];
+    for (pool, output) in outputs.iter_mut().enumerate() {
+        let mut buffer = Rebox::from(vec![0u8;BrotliEncoderMaxCompressedSizeMulti(input_data.len(), num_chunks)]);
+        let res = if pool != 0 {
+            let mut alloc_per_thread = [
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+            ];
+            compress_multi_with_dictionary(params, &mut Owned::new(Rebox::from(input_data.to_vec())),
+                                           Rebox::from(dictionary.to_vec()),
+                                           buffer.slice_mut(), &mut alloc_per_thread[..num_threads])
+        } else {
+            let mut alloc_per_thread = [
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+                SendAlloc::new(new_brotli_heap_alloc(), UnionHasher::Uninit),
+            ];
+            compress_multi_no_threadpool_with_dictionary(params, &mut Owned::new(Rebox::from(input_data.to_vec())),
+                                                         Rebox::from(dictionary.to_vec()),
+                                                         buffer.slice_mut(), &mut alloc_per_thread[..num_threads])
+        };
+        *output = buffer.slice()[..res.unwrap()].to_vec();
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    let mut compressed_version = UnlimitedBuffer::new(&outputs[0][..]);
+    let mut rt = UnlimitedBuffer::new(&[]);
+    match super::decompress(&mut compressed_version, &mut rt, 65536, Rebox::from(dictionary.to_vec())) {
+        Ok(_) => {}
+        Err(e) => panic!("Error {:?}", e),
+    }
+    assert_eq!(rt.data(), input_data);
+    outputs[0].len()
+}
+
+fn chunk_params(favor_cpu_efficiency: bool) -> BrotliEncoderParams {
+    let mut params = BrotliEncoderParams::default();
+    params.quality = 5;
+    params.favor_cpu_efficiency = favor_cpu_efficiency;
+    params
+}
+
+#[test]
+fn chunk_size_max_runs_more_chunks_than_threads() {
+    for &favor_cpu_efficiency in [false, true].iter() {
+        let mut params = chunk_params(favor_cpu_efficiency);
+        params.max_chunk_size = 10000;
+        assert_eq!(BrotliEncoderNumChunks(&params, ALICE.len(), 3), 16);
+        let chunked = chunked_compression_test(&params, &[], ALICE, 3);
+        params.max_chunk_size = 0;
+        assert!(chunked > chunked_compression_test(&params, &[], ALICE, 3));
+    }
+}
+
+#[test]
+fn chunk_size_min_keeps_small_inputs_whole() {
+    let mut params = chunk_params(false);
+    params.min_chunk_size = 65536;
+    assert_eq!(BrotliEncoderNumChunks(&params, 8192, 4), 1);
+    assert_eq!(BrotliEncoderNumChunks(&params, ALICE.len(), 4), 2);
+    assert_eq!(chunked_compression_test(&params, &[], &ALICE[..8192], 4),
+               chunked_compression_test(&params, &[], &ALICE[..8192], 1));
+    chunked_compression_test(&params, &[], ALICE, 4);
+}
+
+#[test]
+fn chunk_delimiter_newline() {
+    let mut params = chunk_params(true);
+    params.chunk_delimiter = Some(b'\n');
+    chunked_compression_test(&params, &[], ALICE, 4);
+    params.max_chunk_size = 20000;
+    chunked_compression_test(&params, &[], ALICE, 4);
+    // no newline at all keeps the equal split
+    params.chunk_delimiter = Some(0);
+    chunked_compression_test(&params, &[], ALICE, 4);
+}
+
+fn split_after_crlf(data: &[u8], pos: usize) -> bool {
+    pos >= 2 && data[pos - 1] == b'\n' && data[pos - 2] == b'\r'
+}
+
+#[test]
+fn chunk_split_hint_with_dictionary() {
+    let mut params = chunk_params(true);
+    params.chunk_split_hint = Some(split_after_crlf);
+    params.max_chunk_size = 30000;
+    chunked_compression_test(&params, &ALICE[..4096], &ALICE[4096..], 2);
+    params.favor_cpu_efficiency = false;
+    chunked_compression_test(&params, &ALICE[..4096], &ALICE[4096..], 1);
+}
+
+#[test]
+fn chunks_reference_earlier_chunks() {
+    // incompressible data repeated once: only a match from the second chunk into the first shrinks it
+    let mut block = vec![0u8; 65536];
+    let mut seed = 0x2545f491u32;
+    for item in block.iter_mut() {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        *item = (seed >> 16) as u8;
+    }
+    let mut input = block.clone();
+    input.extend_from_slice(&block[..]);
+    for &favor_cpu_efficiency in [false, true].iter() {
+        let size = chunked_compression_test(&chunk_params(favor_cpu_efficiency), &[], &input[..], 2);
+        assert!(size < block.len() + block.len() / 16);
+    }
+}
@@ -0,0 +1,83 @@
+#![cfg(test)]
+extern crate brotli;
+use brotli::enc::{BrotliEncoderParams, BrotliTuneParams, TuneKnob, TuneOptions, TuneStrategy};
+use super::tune::parse_args;
+
+static ALICE: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+
+fn base_params() -> BrotliEncoderParams {
+  let mut params = BrotliEncoderParams::default();
+  params.quality = 5;
+  params
+}
+
+#[test]
+fn test_tune_grid_evaluates_every_combination() {
+  let options = TuneOptions {
+    knobs: vec![TuneKnob::AvoidDistancePrefixSearch, TuneKnob::LiteralByteScore],
+    strategy: TuneStrategy::Grid,
+    repeat: 1,
+  };
+  let corpus = [&ALICE[..20000], &ALICE[20000..50000]];
+  let result = BrotliTuneParams(&base_params(), &corpus[..], &options).unwrap();
+  // the base configuration is one of the 2 * 8 combinations
+  assert_eq!(result.evaluated.len(), 16);
+  let best = result.best();
+  assert!(result.evaluated.iter().all(|c| c.compressed_size >= best.compressed_size));
+  assert_eq!(result.frontier[0], result.best);
+  for pair in result.frontier.windows(2) {
+    let (a, b) = (&result.evaluated[pair[0]], &result.evaluated[pair[1]]);
+    assert!(a.compressed_size <= b.compressed_size && a.nanos > b.nanos);
+  }
+  let mut output = Vec::new();
+  brotli::BrotliCompress(&mut &ALICE[..20000], &mut output, &best.params).unwrap();
+  let mut decoded = Vec::new();
+  brotli::BrotliDecompress(&mut &output[..], &mut decoded).unwrap();
+  assert_eq!(&decoded[..], &ALICE[..20000]);
+}
+
+#[test]
+fn test_tune_coordinate_descent_never_worse_than_base() {
+  let options = TuneOptions {
+    knobs: vec![TuneKnob::LiteralByteScore, TuneKnob::AvoidDistancePrefixSearch],
+    strategy: TuneStrategy::CoordinateDescent{max_passes: 2},
+    repeat: 1,
+  };
+  let corpus = [&ALICE[..40000]];
+  let result = BrotliTuneParams(&base_params(), &corpus[..], &options).unwrap();
+  assert!(result.best().compressed_size <= result.evaluated[0].compressed_size);
+  assert!(result.evaluated.len() <= 16);
+}
+
+#[test]
+fn test_tune_cli_arguments() {
+  let mut candidate = brotli::enc::TuneCandidate {
+    params: base_params(),
+    compressed_size: 0,
+    nanos: 0,
+  };
+  assert_eq!(candidate.cli_arguments(), "-q5 -w22");
+  candidate.params.quality = 10;
+  candidate.params.q9_5 = true;
+  TuneKnob::LiteralByteScore.apply(&mut candidate.params, 5);
+  TuneKnob::StrideDetectionQuality.apply(&mut candidate.params, 2);
+  TuneKnob::PriorBitmaskDetection.apply(&mut candidate.params, 1);
+  TuneKnob::LiteralAdaptation.apply(&mut candidate.params, 1);
+  TuneKnob::CdfAdaptationDetection.apply(&mut candidate.params, 1);
+  TuneKnob::AvoidDistancePrefixSearch.apply(&mut candidate.params, 1);
+  assert_eq!(candidate.cli_arguments(),
+             "-q9.5 -w22 -bytescore=700 -stride -findprior -speed=1,16384,1,16384,1,16384,1,16384 \
+-findspeed=1 -avoiddistanceprefixsearch");
+}
+
+#[test]
+fn test_tune_parse_args() {
+  let args = vec!["-q7", "--knobs=speed,prior", "--strategy", "grid", "--sample=0", "corpus"];
+  let (tune, paths) = parse_args(args.iter().map(|a| a.to_string()).collect());
+  assert_eq!(tune.params.quality, 7);
+  assert!(!tune.params.q9_5);
+  assert_eq!(tune.options.knobs, vec![TuneKnob::LiteralAdaptation, TuneKnob::PriorBitmaskDetection]);
+  assert_eq!(tune.options.strategy, TuneStrategy::Grid);
+  assert_eq!(tune.sample_size, 0);
+  assert_eq!(paths, vec!["corpus"]);
+}
@@ -0,0 +1,156 @@
+// brotli tune: searches the expert encoder knobs on a sample corpus, prints the Pareto frontier of
+// size versus compression time and the smallest configuration, optionally saved as a profile
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use brotli::enc::{BrotliEncoderParams, BrotliTuneParams, ParamsProfile, TuneKnob, TuneOptions, TuneResult, TuneStrategy};
+use super::{fail, parse_quality, parse_value};
+use super::bench::collect_files;
+
+pub struct TuneArgs {
+  pub params: BrotliEncoderParams,
+  pub options: TuneOptions,
+  // bytes read from the start of each file, 0 for the whole file
+  pub sample_size: usize,
+  pub output: Option<String>,
+}
+
+pub fn parse_args(args: Vec<String>) -> (TuneArgs, Vec<String>) {
+  let mut params = BrotliEncoderParams::default();
+  parse_quality(&mut params, "--quality", "9.5");
+  let mut tune = TuneArgs {
+    params: params,
+    options: TuneOptions::default(),
+    sample_size: 1 << 20,
+    output: None,
+  };
+  let mut max_passes = 3;
+  let mut grid = false;
+  let mut paths = Vec::new();
+  let mut args = args.into_iter();
+  let mut double_dash = false;
+  while let Some(argument) = args.next() {
+    if double_dash || !argument.starts_with("-") || argument == "-" {
+      paths.push(argument);
+      continue;
+    }
+    if argument == "--" {
+      double_dash = true;
+      continue;
+    }
+    let (flag, inline_value) = match argument.find('=') {
+      Some(index) if argument.starts_with("--") => (argument[..index].to_string(), Some(argument[index + 1..].to_string())),
+      _ if !argument.starts_with("--") && argument.len() > 2 => (argument[..2].to_string(), Some(argument[2..].to_string())),
+      _ => (argument.clone(), None),
+    };
+    let value = match inline_value.or_else(|| args.next()) {
+      Some(value) => value,
+      None => fail(format!("option requires an argument -- {}", flag)),
+    };
+    match &flag[..] {
+      "-q" | "--quality" => parse_quality(&mut tune.params, &flag, &value),
+      "-w" | "--lgwin" => tune.params.lgwin = parse_value::<i32>(&flag, &value),
+      "--strategy" => grid = match &value[..] {
+        "grid" => true,
+        "descent" => false,
+        _ => fail(format!("unknown strategy '{}': expected grid or descent", value)),
+      },
+      "--passes" => max_passes = parse_value::<usize>(&flag, &value),
+      "--knobs" => {
+        tune.options.knobs = value.split(',').filter(|k| k.len() != 0).map(|k| match TuneKnob::from_name(k) {
+          Some(knob) => knob,
+          None => fail(format!("unknown knob '{}': expected bytescore, stride, prior, speed, cdf or avoiddistance", k)),
+        }).collect();
+      },
+      "-n" | "--repeat" => tune.options.repeat = parse_value::<usize>(&flag, &value),
+      "--sample" => tune.sample_size = parse_value::<usize>(&flag, &value),
+      "-o" | "--output" => tune.output = Some(value),
+      _ => fail(format!("unknown tune option {}", flag)),
+    }
+  }
+  tune.params.large_window = tune.params.lgwin > 24;
+  tune.options.strategy = if grid {
+    TuneStrategy::Grid
+  } else {
+    TuneStrategy::CoordinateDescent{max_passes: max_passes}
+  };
+  (tune, paths)
+}
+
+pub fn write_report<W: Write>(w: &mut W, result: &TuneResult, input_size: usize) -> io::Result<()> {
+  writeln!(w, "{} configurations evaluated on {} bytes; Pareto frontier of size versus time:",
+           result.evaluated.len(), input_size)?;
+  writeln!(w, "{:>12} {:>8} {:>10}  {}", "compressed", "ratio", "ms", "arguments")?;
+  for &index in result.frontier.iter() {
+    let candidate = &result.evaluated[index];
+    writeln!(w, "{:>12} {:>8.4} {:>10.2}  {}", candidate.compressed_size,
+             input_size as f64 / core::cmp::max(candidate.compressed_size, 1) as f64,
+             candidate.nanos as f64 / 1000000.0, candidate.cli_arguments())?;
+  }
+  let base = &result.evaluated[0];
+  let best = result.best();
+  writeln!(w, "best: {} ({} bytes, {:.2}% smaller than the base configuration)",
+           best.cli_arguments(), best.compressed_size,
+           100.0 * (1.0 - best.compressed_size as f64 / core::cmp::max(base.compressed_size, 1) as f64))
+}
+
+pub const USAGE: &'static str = "brotli tune [options] file|dir...\n\
+-q --quality Q      quality to tune (default 9.5)\n\
+-w --lgwin W        window size (default 22)\n\
+--knobs LIST        knobs to search: bytescore,stride,prior,speed,cdf,avoiddistance (default all)\n\
+--strategy S        descent (coordinate descent, default) or grid (every combination)\n\
+--passes N          maximum coordinate descent passes (default 3)\n\
+-n --repeat N       timed compressions per configuration (default 1)\n\
+--sample BYTES      bytes read from each file, 0 for whole files (default 1048576)\n\
+-o --output FILE    write the winning configuration to FILE as a --params-file profile";
+
+// entry point for brotli tune, returns the exit status
+pub fn main(args: Vec<String>) -> i32 {
+  if args.iter().any(|a| a == "-h" || a == "--help") {
+    println!("{}", USAGE);
+    return super::cli::EXIT_OK;
+  }
+  let (tune, paths) = parse_args(args);
+  if paths.len() == 0 {
+    fail(format!("tune needs at least one file or directory\n{}", USAGE));
+  }
+  let mut files = Vec::new();
+  for path in paths.iter() {
+    if let Err(e) = collect_files(Path::new(path), &mut files) {
+      fail(format!("{}: {}", path, e));
+    }
+  }
+  let mut corpus = Vec::new();
+  for file in files.iter() {
+    let mut data = Vec::new();
+    let result = File::open(file).and_then(|f| if tune.sample_size == 0 {
+      f.take(u64::max_value()).read_to_end(&mut data)
+    } else {
+      f.take(tune.sample_size as u64).read_to_end(&mut data)
+    });
+    if let Err(e) = result {
+      fail(format!("{}: {}", file, e));
+    }
+    corpus.push(data);
+  }
+  let samples: Vec<&[u8]> = corpus.iter().map(|d| &d[..]).collect();
+  let input_size = samples.iter().map(|d| d.len()).sum();
+  let result = match BrotliTuneParams(&tune.params, &samples[..], &tune.options) {
+    Ok(result) => result,
+    Err(e) => fail(e),
+  };
+  let stdout = io::stdout();
+  if let Err(e) = write_report(&mut stdout.lock(), &result, input_size) {
+    fail(e);
+  }
+  if let Some(ref output) = tune.output {
+    let best = result.best();
+    let profile = format!("# brotli tune: {} bytes with {}\n{}", best.compressed_size, best.cli_arguments(),
+                          ParamsProfile(&best.params));
+    if let Err(e) = fs::write(output, profile) {
+      fail(format!("{}: {}", output, e));
+    }
+  }
+  super::cli::EXIT_OK
+}
@@ -0,0 +1,71 @@
+// Appends newly compressed data to an appendable stream by rewriting only its final bytes.
+use core;
+use std::io;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::vec::Vec;
+use super::parse::{BrotliParseStream, MetablockKind};
+use super::transcode::{BitWriter, copy_metablocks};
+use ::enc::BrotliCompress;
+use ::enc::backward_references::BrotliEncoderParams;
+
+fn invalid_data(message: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Compresses data as catable metablocks and splices them onto the end of an appendable stream,
+// replacing the empty last metablock in place. An empty file receives a fresh appendable stream.
+// Returns the new length of the file.
+pub fn BrotliAppendToStream<F: Read + Write + Seek>(file: &mut F,
+                                                    data: &[u8],
+                                                    params: &BrotliEncoderParams) -> io::Result<u64> {
+  let file_len = file.seek(SeekFrom::End(0))?;
+  let mut params = params.clone();
+  params.appendable = true;
+  if file_len == 0 {
+    BrotliCompress(&mut io::Cursor::new(data), file, &params)?;
+    return file.seek(SeekFrom::End(0));
+  }
+  let mut existing = Vec::<u8>::new();
+  file.seek(SeekFrom::Start(0))?;
+  file.read_to_end(&mut existing)?;
+  // only a stream that ends in an empty last metablock can be continued without losing data:
+  // its ISLAST, ISLASTEMPTY bits are overwritten by the new metablocks
+  let existing_parsed = match BrotliParseStream(&existing[..]) {
+    Ok(parsed) => parsed,
+    Err(()) => return Err(invalid_data("unable to parse existing brotli file")),
+  };
+  let end_bit = match existing_parsed.metablocks.last() {
+    Some(mb) if mb.kind == MetablockKind::LastEmpty && existing_parsed.stream_len as u64 == file_len => mb.start_bit as u64,
+    _ => return Err(invalid_data("brotli file not crafted for append")),
+  };
+  let window_bits = existing_parsed.window_bits;
+
+  // the new metablocks must fit in the window the existing header declares
+  params.catable = true;
+  params.use_dictionary = false;
+  params.magic_number = false;
+  params.lgwin = core::cmp::min(params.lgwin, i32::from(window_bits));
+  params.large_window = params.lgwin > 24;
+  if params.lgwin < 18 && params.quality < 2 {
+    params.quality = 2; // lower qualities always use an 18 bit window
+  }
+  let mut compressed = Vec::<u8>::new();
+  BrotliCompress(&mut io::Cursor::new(data), &mut compressed, &params)?;
+  // the new data is parsed so that uncompressed metablocks are re-padded at their new bit offset
+  let parsed = match BrotliParseStream(&compressed[..]) {
+    Ok(parsed) => parsed,
+    Err(()) => return Err(invalid_data("unable to parse compressed data")),
+  };
+  let first_byte = end_bit >> 3;
+  let mut writer = BitWriter::new();
+  let kept_bits = end_bit & 7;
+  writer.write_bits(kept_bits as usize,
+                    u64::from(existing[first_byte as usize]) & ((1 << kept_bits) - 1));
+  copy_metablocks(&mut writer, &compressed[..], &parsed.metablocks[..]);
+  writer.write_bits(2, 3);
+  writer.align();
+  let rewritten = writer.into_bytes();
+  file.seek(SeekFrom::Start(first_byte))?;
+  file.write_all(&rewritten[..])?;
+  Ok(first_byte + rewritten.len() as u64)
+}
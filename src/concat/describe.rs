@@ -0,0 +1,112 @@
+// Reports how a set of streams were crafted and whether BroCatli can join them, and why not.
+use core;
+use super::BroCatliResult;
+use super::parse::{BrotliParseStream, ParsedStream, MetablockKind, CopyKind};
+use super::transcode::check_distance_cache;
+
+// first bytes of the metadata block written for BROTLI_PARAM_MAGIC_NUMBER
+const MAGIC_NUMBER_PREFIX: [u8; 2] = [0xe1, 0x97];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamDescription {
+  pub window_bits: u8,
+  pub large_window: bool,
+  pub magic_number: bool,
+  pub catable: bool, // decodes the same after other data, so BroCatli accepts it after the first file
+  pub appendable: bool, // ends with an empty last metablock, so other files may follow it
+  pub uses_dictionary: bool,
+  pub output_len: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConcatenationPlan {
+  pub window_size: u8,
+  pub needs_window_override: bool, // BroCatli::new_with_window_size(window_size) is required
+}
+
+fn has_magic_number(input: &[u8], parsed: &ParsedStream) -> bool {
+  match parsed.metablocks.first() {
+    Some(mb) if mb.kind == MetablockKind::Metadata => {
+      let data_start = (mb.header_end_bit + 7) >> 3;
+      mb.end_bit >= (data_start + 3) << 3
+        && input[data_start..data_start + 2] == MAGIC_NUMBER_PREFIX[..]
+        && (input[data_start + 2] & 0xfc) == 0x80
+    },
+    _ => false,
+  }
+}
+
+// BroCatli needs each later file to open with metablocks it can re-align, and the content
+// must not depend on being at the start of the stream.
+fn is_catable(parsed: &ParsedStream) -> bool {
+  if parsed.metablocks[0].kind == MetablockKind::Compressed {
+    return false;
+  }
+  for (index, mb) in parsed.metablocks.iter().enumerate() {
+    if mb.kind != MetablockKind::Compressed {
+      continue;
+    }
+    // literals in the first bytes are coded with the end of the previous file as context
+    if parsed.metablock_commands(index).iter().any(|cmd| {
+      (cmd.insert_len != 0 && cmd.position < 2) || cmd.kind == CopyKind::Dictionary
+    }) {
+      return false;
+    }
+  }
+  check_distance_cache(parsed, 0, parsed.metablocks.len(), [None; 4]).is_ok()
+}
+
+pub fn BrotliDescribeStream(input: &[u8]) -> Result<StreamDescription, BroCatliResult> {
+  let parsed = match BrotliParseStream(input) {
+    Ok(parsed) => parsed,
+    Err(()) => return Err(BroCatliResult::BrotliFileCorrupt),
+  };
+  Ok(StreamDescription {
+    window_bits: parsed.window_bits,
+    large_window: parsed.large_window,
+    magic_number: has_magic_number(input, &parsed),
+    catable: is_catable(&parsed),
+    appendable: parsed.metablocks.last().map(|mb| mb.kind) == Some(MetablockKind::LastEmpty),
+    uses_dictionary: parsed.uses_dictionary(),
+    output_len: parsed.output.len() as u64,
+  })
+}
+
+// Checks a sequence of streams against the rules BroCatli enforces, returning the window
+// it must be created with, or the index of the first file it would reject and the result it
+// would report for it. window_override mirrors BroCatli::new_with_window_size.
+pub fn BrotliCheckConcatenation(files: &[StreamDescription], window_override: Option<u8>) -> Result<ConcatenationPlan, (usize, BroCatliResult)> {
+  if let Some(window) = window_override {
+    if window < 10 || window > 30 {
+      return Err((0, BroCatliResult::InvalidWindowSize));
+    }
+  }
+  for (index, file) in files.iter().enumerate() {
+    if index + 1 < files.len() && !file.appendable {
+      return Err((index, BroCatliResult::BrotliFileNotCraftedForAppend));
+    }
+    // with an explicit window the first file is treated like any later one
+    if (index != 0 || window_override.is_some()) && !file.catable {
+      return Err((index, BroCatliResult::BrotliFileNotCraftedForConcatenation));
+    }
+  }
+  let first_window = match (window_override, files.first()) {
+    (Some(window), _) => window,
+    (None, Some(file)) => file.window_bits,
+    (None, None) => return Err((0, BroCatliResult::NeedsMoreInput)),
+  };
+  if let Some(index) = files.iter().position(|file| file.window_bits > first_window) {
+    // a larger window up front only works if the first file can also be treated as a later one
+    if window_override.is_some() || !files[0].catable {
+      return Err((index, BroCatliResult::WindowSizeLargerThanPreviousFile));
+    }
+    return Ok(ConcatenationPlan {
+      window_size: files.iter().fold(first_window, |acc, file| core::cmp::max(acc, file.window_bits)),
+      needs_window_override: true,
+    });
+  }
+  Ok(ConcatenationPlan {
+    window_size: first_window,
+    needs_window_override: window_override.is_some(),
+  })
+}
@@ -0,0 +1,171 @@
+// Read and Write adapters that own their buffers and drive BroCatli, so callers need not
+// manage offsets or the timing of new_brotli_file themselves.
+use std::io;
+use std::io::{Read, Write};
+use std::vec::Vec;
+use super::{BroCatli, BroCatliResult};
+
+fn concat_error(result: BroCatliResult) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", result))
+}
+
+// Concatenates a list of catable brotli streams, read one after another, into a single stream.
+pub struct ConcatReader<R: Read> {
+  state: BroCatli,
+  upstreams: Vec<R>,
+  current: usize,
+  input_buffer: Vec<u8>,
+  input_offset: usize,
+  input_len: usize,
+  done: bool,
+}
+
+impl<R: Read> ConcatReader<R> {
+  pub fn new(upstreams: Vec<R>, buffer_size: usize) -> Self {
+    Self::with_state(BroCatli::new(), upstreams, buffer_size)
+  }
+  pub fn new_with_window_size(upstreams: Vec<R>, buffer_size: usize, log_window_size: u8) -> Self {
+    Self::with_state(BroCatli::new_with_window_size(log_window_size), upstreams, buffer_size)
+  }
+  fn with_state(mut state: BroCatli, upstreams: Vec<R>, buffer_size: usize) -> Self {
+    if !upstreams.is_empty() {
+      state.new_brotli_file();
+    }
+    ConcatReader {
+      state: state,
+      upstreams: upstreams,
+      current: 0,
+      input_buffer: vec![0u8; if buffer_size == 0 { 4096 } else { buffer_size }],
+      input_offset: 0,
+      input_len: 0,
+      done: false,
+    }
+  }
+  pub fn get_ref(&self) -> &[R] {
+    &self.upstreams[..]
+  }
+  pub fn into_inner(self) -> Vec<R> {
+    self.upstreams
+  }
+}
+
+impl<R: Read> Read for ConcatReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() || self.done {
+      return Ok(0);
+    }
+    loop {
+      if self.input_offset == self.input_len && self.current < self.upstreams.len() {
+        self.input_offset = 0;
+        self.input_len = self.upstreams[self.current].read(&mut self.input_buffer[..])?;
+        if self.input_len == 0 {
+          self.current += 1;
+          if self.current < self.upstreams.len() {
+            self.state.new_brotli_file();
+          }
+          continue;
+        }
+      }
+      let mut output_offset = 0usize;
+      let result = if self.input_offset != self.input_len {
+        self.state.stream(&self.input_buffer[..self.input_len], &mut self.input_offset,
+                          buf, &mut output_offset)
+      } else {
+        self.state.finish(buf, &mut output_offset)
+      };
+      match result {
+        BroCatliResult::Success => {
+          self.done = true;
+          return Ok(output_offset);
+        },
+        BroCatliResult::NeedsMoreInput | BroCatliResult::NeedsMoreOutput => if output_offset != 0 {
+          return Ok(output_offset);
+        },
+        err => return Err(concat_error(err)),
+      }
+    }
+  }
+}
+
+// Concatenates catable brotli streams written to it: call start_file before the bytes of each
+// stream. The combined stream is completed by finish, or when the writer is dropped.
+pub struct ConcatWriter<W: Write> {
+  state: BroCatli,
+  output: Option<W>,
+  output_buffer: Vec<u8>,
+  file_started: bool,
+}
+
+impl<W: Write> ConcatWriter<W> {
+  pub fn new(w: W, buffer_size: usize) -> Self {
+    Self::with_state(BroCatli::new(), w, buffer_size)
+  }
+  pub fn new_with_window_size(w: W, buffer_size: usize, log_window_size: u8) -> Self {
+    Self::with_state(BroCatli::new_with_window_size(log_window_size), w, buffer_size)
+  }
+  fn with_state(state: BroCatli, w: W, buffer_size: usize) -> Self {
+    ConcatWriter {
+      state: state,
+      output: Some(w),
+      output_buffer: vec![0u8; if buffer_size == 0 { 4096 } else { buffer_size }],
+      file_started: false,
+    }
+  }
+  pub fn start_file(&mut self) {
+    self.state.new_brotli_file();
+    self.file_started = true;
+  }
+  pub fn get_ref(&self) -> &W {
+    self.output.as_ref().unwrap()
+  }
+  fn finish_stream(&mut self) -> io::Result<()> {
+    loop {
+      let mut output_offset = 0usize;
+      let result = self.state.finish(&mut self.output_buffer[..], &mut output_offset);
+      self.output.as_mut().unwrap().write_all(&self.output_buffer[..output_offset])?;
+      match result {
+        BroCatliResult::Success => return Ok(()),
+        BroCatliResult::NeedsMoreOutput => {},
+        err => return Err(concat_error(err)),
+      }
+    }
+  }
+  // Writes the end of the combined stream and returns the underlying writer.
+  pub fn finish(mut self) -> io::Result<W> {
+    self.finish_stream()?;
+    Ok(self.output.take().unwrap())
+  }
+}
+
+impl<W: Write> Write for ConcatWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.file_started {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "start_file must be called before writing"));
+    }
+    let mut input_offset = 0usize;
+    loop {
+      let mut output_offset = 0usize;
+      let result = self.state.stream(buf, &mut input_offset, &mut self.output_buffer[..], &mut output_offset);
+      self.output.as_mut().unwrap().write_all(&self.output_buffer[..output_offset])?;
+      match result {
+        BroCatliResult::NeedsMoreOutput => {},
+        BroCatliResult::NeedsMoreInput => return Ok(input_offset),
+        err => return Err(concat_error(err)),
+      }
+    }
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.as_mut().unwrap().flush()
+  }
+}
+
+impl<W: Write> Drop for ConcatWriter<W> {
+  fn drop(&mut self) {
+    if self.output.is_some() {
+      match self.finish_stream() {
+        Ok(_) => {},
+        Err(_) => {},
+      }
+    }
+  }
+}
@@ -1,4 +1,28 @@
 use core;
+#[cfg(feature="std")]
+mod parse;
+#[cfg(feature="std")]
+mod transcode;
+#[cfg(feature="std")]
+mod split;
+#[cfg(feature="std")]
+mod append;
+#[cfg(feature="std")]
+mod describe;
+#[cfg(feature="std")]
+mod io;
+#[cfg(feature="std")]
+pub use self::parse::{BrotliParseStream, ParsedStream, ParsedCommand, MetablockInfo, MetablockKind, CopyKind};
+#[cfg(feature="std")]
+pub use self::transcode::{BrotliTranscodeForConcatenation, TranscodedFile};
+#[cfg(feature="std")]
+pub use self::split::{BrotliFindSplitPoints, BrotliExtractMetablocks, BrotliSplitStream, SplitPoint};
+#[cfg(feature="std")]
+pub use self::append::BrotliAppendToStream;
+#[cfg(feature="std")]
+pub use self::describe::{BrotliDescribeStream, BrotliCheckConcatenation, StreamDescription, ConcatenationPlan};
+#[cfg(feature="std")]
+pub use self::io::{ConcatReader, ConcatWriter};
 
 #[repr(C)]
 #[derive(Debug,Clone,Copy, PartialEq)]
@@ -7,6 +31,7 @@ pub enum BroCatliResult {
   Success = 0,
   NeedsMoreInput = 1,
   NeedsMoreOutput = 2,
+  BrotliFileCorrupt = 123,
   BrotliFileNotCraftedForAppend = 124,
   InvalidWindowSize = 125,
   WindowSizeLargerThanPreviousFile = 126,
@@ -37,7 +62,7 @@ impl NewStreamData {
     }
 }
 
-fn parse_window_size(bytes_so_far:&[u8]) -> Result<(u8, usize), ()> {  // returns window_size and offset in stream in bits
+pub fn parse_window_size(bytes_so_far:&[u8]) -> Result<(u8, usize), ()> {  // returns window_size and offset in stream in bits
   if bytes_so_far[0] & 1 == 0 {
     return Ok((16, 1));
   }
@@ -267,19 +292,18 @@ impl BroCatli {
       }
       index -= 1; // discard the final two bits
       last_bytes &= (1 << index) - 1; // mask them out
+      if index >= 8 && out_bytes.len() == *out_offset {
+        return BroCatliResult::NeedsMoreOutput; // bail before touching last_bytes so the retry sees the same bits
+      }
       self.last_bytes[0] = last_bytes as u8 & 0xff; // reset the last_bytes pair
       self.last_bytes[1] = (last_bytes >> 8) as u8 & 0xff;
       if index >= 8 { // if both bits and one useful bit were in the second block, then write that
-        if out_bytes.len() > *out_offset {
-          out_bytes[*out_offset] = self.last_bytes[0];
-          self.last_bytes[0] = self.last_bytes[1];
-          *out_offset += 1;
-          self.any_bytes_emitted = true;
-          index -= 8;
-          self.last_bytes_len -= 1;
-        } else {
-          return BroCatliResult::NeedsMoreOutput;
-        }
+        out_bytes[*out_offset] = self.last_bytes[0];
+        self.last_bytes[0] = self.last_bytes[1];
+        *out_offset += 1;
+        self.any_bytes_emitted = true;
+        index -= 8;
+        self.last_bytes_len -= 1;
       }
       self.last_byte_bit_offset = index;
       assert!(index < 8);
@@ -0,0 +1,808 @@
+// Walks a complete brotli stream metablock by metablock, recording where each metablock starts
+// and ends in the bitstream along with the commands it holds, so that tools can rewrite
+// streams while copying most metablocks bit for bit.
+use core;
+use std::vec::Vec;
+use super::parse_window_size;
+use ::dictionary::{kBrotliDictionary, kBrotliDictionaryOffsetsByLength,
+                   kBrotliDictionarySizeBitsByLength,
+                   kBrotliMinDictionaryWordLength, kBrotliMaxDictionaryWordLength};
+use ::transform::{TransformDictionaryWord, kNumTransforms};
+use ::enc::histogram::{Context, ContextType};
+
+const MAX_ALLOWED_DISTANCE: u64 = 0x7ffffffc;
+const WINDOW_GAP: u64 = 16;
+const NUM_LITERAL_SYMBOLS: u32 = 256;
+const NUM_COMMAND_SYMBOLS: u32 = 704;
+const NUM_BLOCK_LEN_SYMBOLS: u32 = 26;
+const MAX_CODE_LENGTH: usize = 15;
+const FAST_BITS: u32 = 8;
+
+static kCodeLengthCodeOrder: [u8; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+static kCodeLengthPrefixLength: [u8; 16] = [2, 2, 2, 3, 2, 2, 2, 4, 2, 2, 2, 3, 2, 2, 2, 4];
+static kCodeLengthPrefixValue: [u8; 16] = [0, 4, 3, 2, 0, 4, 3, 1, 0, 4, 3, 2, 0, 4, 3, 5];
+
+static kBlockLengthPrefix: [(u32, u32); 26] = [
+  (1, 2), (5, 2), (9, 2), (13, 2), (17, 3), (25, 3), (33, 3), (41, 3),
+  (49, 4), (65, 4), (81, 4), (97, 4), (113, 5), (145, 5), (177, 5), (209, 5),
+  (241, 6), (305, 6), (369, 7), (497, 8), (753, 9), (1265, 10), (2289, 11), (4337, 12),
+  (8433, 13), (16625, 24)];
+static kInsertLengthPrefix: [(u32, u32); 24] = [
+  (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 1), (8, 1),
+  (10, 2), (14, 2), (18, 3), (26, 3), (34, 4), (50, 4), (66, 5), (98, 5),
+  (130, 6), (194, 7), (322, 8), (578, 9), (1090, 10), (2114, 12), (6210, 14), (22594, 24)];
+static kCopyLengthPrefix: [(u32, u32); 24] = [
+  (2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0),
+  (10, 1), (12, 1), (14, 2), (18, 2), (22, 3), (30, 3), (38, 4), (54, 4),
+  (70, 5), (102, 5), (134, 6), (198, 7), (326, 8), (582, 9), (1094, 10), (2118, 24)];
+static kInsertRangeLut: [u32; 11] = [0, 0, 0, 0, 8, 8, 0, 16, 8, 16, 16];
+static kCopyRangeLut: [u32; 11] = [0, 8, 0, 8, 0, 8, 16, 0, 16, 8, 16];
+// which entry of the distance cache (0 = last) each short code refers to, and the delta applied
+static kDistanceShortCodeIndex: [usize; 16] = [0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1];
+static kDistanceShortCodeOffset: [i64; 16] = [0, 0, 0, 0, -1, 1, -2, 2, -3, 3, -1, 1, -2, 2, -3, 3];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetablockKind {
+  Compressed,
+  Uncompressed,
+  Metadata,
+  LastEmpty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyKind {
+  NoCopy, // the metablock ended after the inserted literals
+  Backward,
+  Dictionary,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParsedCommand {
+  pub position: u64, // output offset of the first inserted literal
+  pub insert_len: u32,
+  pub copy_len: u32, // bytes produced by the copy (after the transform for dictionary words)
+  pub distance: u64,
+  pub distance_code: u32,
+  pub kind: CopyKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MetablockInfo {
+  pub kind: MetablockKind,
+  pub is_last: bool,
+  pub start_bit: usize,
+  pub header_end_bit: usize, // first bit after ISUNCOMPRESSED (or after MLEN/MSKIPLEN when absent)
+  pub end_bit: usize, // first bit after the data, before any trailing padding
+  pub output_start: u64,
+  pub output_len: u64,
+  pub first_command: usize,
+  pub num_commands: usize,
+  pub uses_dictionary: bool,
+  pub distance_cache: [u64; 4], // decoder distance cache on entry, most recent first
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedStream {
+  pub window_bits: u8,
+  pub large_window: bool,
+  pub header_bits: usize,
+  pub stream_len: usize, // bytes of input occupied by the stream
+  pub metablocks: Vec<MetablockInfo>,
+  pub commands: Vec<ParsedCommand>,
+  pub output: Vec<u8>,
+}
+
+impl ParsedStream {
+  pub fn metablock_commands(&self, index: usize) -> &[ParsedCommand] {
+    let mb = &self.metablocks[index];
+    &self.commands[mb.first_command..mb.first_command + mb.num_commands]
+  }
+  pub fn uses_dictionary(&self) -> bool {
+    self.metablocks.iter().any(|mb| mb.uses_dictionary)
+  }
+}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+  fn peek(&self, n_bits: u32) -> u32 {
+    let byte = self.pos >> 3;
+    let mut buf = [0u8; 8];
+    if byte + 8 <= self.data.len() {
+      buf.copy_from_slice(&self.data[byte..byte + 8]);
+    } else if byte < self.data.len() {
+      let avail = self.data.len() - byte;
+      buf[..avail].clone_from_slice(&self.data[byte..]);
+    }
+    let v = u64::from_le_bytes(buf) >> (self.pos & 7);
+    (v & ((1u64 << n_bits) - 1)) as u32
+  }
+  fn skip(&mut self, n_bits: u32) -> Result<(), ()> {
+    if self.pos + n_bits as usize > self.data.len() << 3 {
+      return Err(());
+    }
+    self.pos += n_bits as usize;
+    Ok(())
+  }
+  fn read(&mut self, n_bits: u32) -> Result<u32, ()> {
+    let ret = self.peek(n_bits);
+    self.skip(n_bits)?;
+    Ok(ret)
+  }
+  // padding up to the next byte boundary must be zero
+  fn align(&mut self) -> Result<(), ()> {
+    let pad = ((8 - (self.pos & 7)) & 7) as u32;
+    if self.read(pad)? != 0 {
+      return Err(());
+    }
+    Ok(())
+  }
+}
+
+struct PrefixCode {
+  single: Option<u32>,
+  fast: Vec<(u16, u8)>,
+  counts: [u16; MAX_CODE_LENGTH + 1],
+  sorted: Vec<u16>,
+}
+
+impl PrefixCode {
+  fn single_symbol(symbol: u32) -> PrefixCode {
+    PrefixCode {
+      single: Some(symbol),
+      fast: Vec::new(),
+      counts: [0; MAX_CODE_LENGTH + 1],
+      sorted: Vec::new(),
+    }
+  }
+  fn from_lengths(lengths: &[u8]) -> PrefixCode {
+    let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+    for &len in lengths.iter() {
+      counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut offsets = [0usize; MAX_CODE_LENGTH + 2];
+    let mut next_code = [0u32; MAX_CODE_LENGTH + 1];
+    let mut code = 0u32;
+    for len in 1..MAX_CODE_LENGTH + 1 {
+      offsets[len + 1] = offsets[len] + counts[len] as usize;
+      code = (code + u32::from(counts[len - 1])) << 1;
+      next_code[len] = code;
+    }
+    let mut sorted = vec![0u16; offsets[MAX_CODE_LENGTH + 1]];
+    let mut fast = vec![(0u16, 0u8); 1 << FAST_BITS];
+    for (symbol, &len) in lengths.iter().enumerate() {
+      if len == 0 {
+        continue;
+      }
+      let len = len as usize;
+      sorted[offsets[len]] = symbol as u16;
+      offsets[len] += 1;
+      let code = next_code[len];
+      next_code[len] += 1;
+      if len as u32 <= FAST_BITS {
+        let mut rev = 0usize;
+        for i in 0..len {
+          rev |= (((code >> (len - 1 - i)) & 1) as usize) << i;
+        }
+        let mut index = rev;
+        while index < fast.len() {
+          fast[index] = (symbol as u16, len as u8);
+          index += 1 << len;
+        }
+      }
+    }
+    PrefixCode {
+      single: None,
+      fast: fast,
+      counts: counts,
+      sorted: sorted,
+    }
+  }
+  fn read_symbol(&self, br: &mut BitReader) -> Result<u32, ()> {
+    if let Some(symbol) = self.single {
+      return Ok(symbol);
+    }
+    let bits = br.peek(MAX_CODE_LENGTH as u32);
+    let (symbol, len) = self.fast[(bits & ((1 << FAST_BITS) - 1)) as usize];
+    if len != 0 {
+      br.skip(u32::from(len))?;
+      return Ok(u32::from(symbol));
+    }
+    // canonical walk for codes longer than the lookup table
+    let mut code = 0i32;
+    let mut first = 0i32;
+    let mut index = 0i32;
+    for len in 1..MAX_CODE_LENGTH + 1 {
+      code |= ((bits >> (len - 1)) & 1) as i32;
+      let count = i32::from(self.counts[len]);
+      if code - first < count {
+        br.skip(len as u32)?;
+        return Ok(u32::from(self.sorted[(index + code - first) as usize]));
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err(())
+  }
+}
+
+fn read_simple_prefix_code(br: &mut BitReader, alphabet_size_max: u32, alphabet_size_limit: u32) -> Result<PrefixCode, ()> {
+  let max_bits = 32 - (alphabet_size_max - 1).leading_zeros();
+  let num_symbols = br.read(2)? as usize + 1;
+  let mut symbols = [0u32; 4];
+  for i in 0..num_symbols {
+    symbols[i] = br.read(max_bits)?;
+    if symbols[i] >= alphabet_size_limit || symbols[..i].contains(&symbols[i]) {
+      return Err(());
+    }
+  }
+  let code_lengths: [u8; 4] = match num_symbols {
+    1 => return Ok(PrefixCode::single_symbol(symbols[0])),
+    2 => [1, 1, 0, 0],
+    3 => [1, 2, 2, 0],
+    _ => if br.read(1)? == 0 { [2, 2, 2, 2] } else { [1, 2, 3, 3] },
+  };
+  // lengths follow the order the symbols were listed in; ties are broken canonically by symbol
+  let mut lengths = vec![0u8; alphabet_size_limit as usize];
+  for i in 0..num_symbols {
+    lengths[symbols[i] as usize] = code_lengths[i];
+  }
+  Ok(PrefixCode::from_lengths(&lengths))
+}
+
+fn read_prefix_code(br: &mut BitReader, alphabet_size_max: u32, alphabet_size_limit: u32) -> Result<PrefixCode, ()> {
+  let hskip = br.read(2)?;
+  if hskip == 1 {
+    return read_simple_prefix_code(br, alphabet_size_max, alphabet_size_limit);
+  }
+  let mut code_length_code_lengths = [0u8; 18];
+  let mut space = 32i32;
+  let mut num_codes = 0;
+  for i in hskip as usize..18 {
+    let bits = br.peek(4) as usize;
+    br.skip(u32::from(kCodeLengthPrefixLength[bits]))?;
+    let v = kCodeLengthPrefixValue[bits];
+    code_length_code_lengths[kCodeLengthCodeOrder[i] as usize] = v;
+    if v != 0 {
+      space -= 32 >> v;
+      num_codes += 1;
+      if space <= 0 {
+        break;
+      }
+    }
+  }
+  if !(num_codes == 1 || space == 0) {
+    return Err(());
+  }
+  let code_length_code = if num_codes == 1 {
+    let symbol = code_length_code_lengths.iter().position(|&l| l != 0).unwrap();
+    PrefixCode::single_symbol(symbol as u32)
+  } else {
+    PrefixCode::from_lengths(&code_length_code_lengths)
+  };
+  let mut lengths = vec![0u8; alphabet_size_limit as usize];
+  let mut symbol = 0usize;
+  let mut prev_code_len = 8u8;
+  let mut repeat = 0usize;
+  let mut repeat_code_len = 0u8;
+  let mut space = 32768i32;
+  let mut num_symbols = 0;
+  while symbol < lengths.len() && space > 0 {
+    let code_len = code_length_code.read_symbol(br)?;
+    if code_len < 16 {
+      repeat = 0;
+      lengths[symbol] = code_len as u8;
+      symbol += 1;
+      if code_len != 0 {
+        prev_code_len = code_len as u8;
+        space -= 32768 >> code_len;
+        num_symbols += 1;
+      }
+    } else {
+      let extra_bits = if code_len == 16 { 2 } else { 3 };
+      let new_len = if code_len == 16 { prev_code_len } else { 0 };
+      if repeat_code_len != new_len {
+        repeat = 0;
+        repeat_code_len = new_len;
+      }
+      let old_repeat = repeat;
+      if repeat > 0 {
+        repeat = (repeat - 2) << extra_bits;
+      }
+      repeat += br.read(extra_bits)? as usize + 3;
+      let delta = repeat - old_repeat;
+      if symbol + delta > lengths.len() {
+        return Err(());
+      }
+      for _ in 0..delta {
+        lengths[symbol] = repeat_code_len;
+        symbol += 1;
+      }
+      if repeat_code_len != 0 {
+        space -= (delta as i32) * (32768 >> repeat_code_len);
+        num_symbols += delta;
+      }
+    }
+  }
+  if space != 0 {
+    return Err(());
+  }
+  if num_symbols == 1 {
+    let symbol = lengths.iter().position(|&l| l != 0).unwrap();
+    return Ok(PrefixCode::single_symbol(symbol as u32));
+  }
+  Ok(PrefixCode::from_lengths(&lengths))
+}
+
+fn read_var_len_u8(br: &mut BitReader) -> Result<u32, ()> {
+  if br.read(1)? == 0 {
+    return Ok(0);
+  }
+  let nbits = br.read(3)?;
+  if nbits == 0 {
+    return Ok(1);
+  }
+  Ok((1 << nbits) + br.read(nbits)?)
+}
+
+fn read_block_length(br: &mut BitReader, code: &PrefixCode) -> Result<u32, ()> {
+  let (base, extra) = kBlockLengthPrefix[code.read_symbol(br)? as usize];
+  Ok(base + br.read(extra)?)
+}
+
+fn read_context_map(br: &mut BitReader, context_map_size: usize, num_trees: u32) -> Result<Vec<u8>, ()> {
+  let mut map = vec![0u8; context_map_size];
+  if num_trees < 2 {
+    return Ok(map);
+  }
+  let max_run_length_prefix = if br.read(1)? == 1 { br.read(4)? + 1 } else { 0 };
+  let alphabet_size = num_trees + max_run_length_prefix;
+  let code = read_prefix_code(br, alphabet_size, alphabet_size)?;
+  let mut i = 0;
+  while i < context_map_size {
+    let symbol = code.read_symbol(br)?;
+    if symbol == 0 {
+      map[i] = 0;
+      i += 1;
+    } else if symbol <= max_run_length_prefix {
+      let reps = (1usize << symbol) + br.read(symbol)? as usize;
+      if i + reps > context_map_size {
+        return Err(());
+      }
+      i += reps; // already zero
+    } else {
+      map[i] = (symbol - max_run_length_prefix) as u8;
+      i += 1;
+    }
+  }
+  if br.read(1)? == 1 {
+    // inverse move-to-front
+    let mut mtf = [0u8; 256];
+    for (index, item) in mtf.iter_mut().enumerate() {
+      *item = index as u8;
+    }
+    for entry in map.iter_mut() {
+      let index = *entry as usize;
+      let value = mtf[index];
+      *entry = value;
+      for j in (1..index + 1).rev() {
+        mtf[j] = mtf[j - 1];
+      }
+      mtf[0] = value;
+    }
+  }
+  if map.iter().any(|&tree| u32::from(tree) >= num_trees) {
+    return Err(());
+  }
+  Ok(map)
+}
+
+struct BlockCategory {
+  num_types: u32,
+  type_code: Option<PrefixCode>,
+  length_code: Option<PrefixCode>,
+  current: u32,
+  previous: u32,
+  remaining: u32,
+}
+
+impl BlockCategory {
+  fn read(br: &mut BitReader) -> Result<BlockCategory, ()> {
+    let num_types = read_var_len_u8(br)? + 1;
+    if num_types < 2 {
+      return Ok(BlockCategory {
+        num_types: 1,
+        type_code: None,
+        length_code: None,
+        current: 0,
+        previous: 1,
+        remaining: u32::max_value(),
+      });
+    }
+    let type_code = read_prefix_code(br, num_types + 2, num_types + 2)?;
+    let length_code = read_prefix_code(br, NUM_BLOCK_LEN_SYMBOLS, NUM_BLOCK_LEN_SYMBOLS)?;
+    let remaining = read_block_length(br, &length_code)?;
+    Ok(BlockCategory {
+      num_types: num_types,
+      type_code: Some(type_code),
+      length_code: Some(length_code),
+      current: 0,
+      previous: 1,
+      remaining: remaining,
+    })
+  }
+  // counts one symbol of this category, switching block type first if the current block ran out
+  fn next(&mut self, br: &mut BitReader) -> Result<u32, ()> {
+    if self.remaining == 0 {
+      let (type_code, length_code) = match (self.type_code.as_ref(), self.length_code.as_ref()) {
+        (Some(t), Some(l)) => (t, l),
+        _ => return Err(()),
+      };
+      let symbol = type_code.read_symbol(br)?;
+      let block_type = match symbol {
+        0 => self.previous,
+        1 => self.current + 1,
+        _ => symbol - 2,
+      } % self.num_types;
+      self.previous = self.current;
+      self.current = block_type;
+      self.remaining = read_block_length(br, length_code)?;
+    }
+    self.remaining = self.remaining.wrapping_sub(1);
+    Ok(self.current)
+  }
+}
+
+fn context_type(mode: u32) -> ContextType {
+  match mode {
+    0 => ContextType::CONTEXT_LSB6,
+    1 => ContextType::CONTEXT_MSB6,
+    2 => ContextType::CONTEXT_UTF8,
+    _ => ContextType::CONTEXT_SIGNED,
+  }
+}
+
+struct Parser<'a> {
+  br: BitReader<'a>,
+  max_backward: u64,
+  large_window: bool,
+  dist_rb: [u64; 4], // most recent first
+  // false once the caller only needs metablock boundaries: commands are dropped and
+  // ret.output only keeps the window
+  retain: bool,
+  output_base: u64, // stream offset of ret.output[0]
+  ret: ParsedStream,
+}
+
+impl<'a> Parser<'a> {
+  fn position(&self) -> u64 {
+    self.output_base + self.ret.output.len() as u64
+  }
+  fn push_command(&mut self, cmd: ParsedCommand) {
+    if self.retain {
+      self.ret.commands.push(cmd);
+    }
+  }
+  fn trim_output(&mut self) {
+    let window = core::cmp::min(self.max_backward, usize::max_value() as u64 / 2) as usize;
+    if !self.retain && self.ret.output.len() > 2 * window {
+      let excess = self.ret.output.len() - window;
+      self.ret.output.drain(..excess);
+      self.output_base += excess as u64;
+    }
+  }
+  fn push_distance(&mut self, distance: u64) {
+    self.dist_rb = [distance, self.dist_rb[0], self.dist_rb[1], self.dist_rb[2]];
+  }
+  fn read_distance(&mut self, distance_code: u32, npostfix: u32, ndirect: u32) -> Result<u64, ()> {
+    if distance_code < 16 {
+      let base = self.dist_rb[kDistanceShortCodeIndex[distance_code as usize]] as i64
+        + kDistanceShortCodeOffset[distance_code as usize];
+      return Ok(if base <= 0 { 0x7fffffff } else { base as u64 });
+    }
+    if distance_code < 16 + ndirect {
+      return Ok(u64::from(distance_code - 15));
+    }
+    let dist_val = distance_code - ndirect - 16;
+    let ndistbits = 1 + (dist_val >> (npostfix + 1));
+    if ndistbits > 31 {
+      return Err(());
+    }
+    let hcode = u64::from(dist_val >> npostfix);
+    let lcode = u64::from(dist_val & ((1 << npostfix) - 1));
+    let offset = ((2 + (hcode & 1)) << ndistbits) - 4;
+    let extra = u64::from(self.br.read(ndistbits)?);
+    Ok(((offset + extra) << npostfix) + lcode + u64::from(ndirect) + 1)
+  }
+  fn parse_compressed(&mut self, mlen: usize, info: &mut MetablockInfo) -> Result<(), ()> {
+    let mut literal_blocks = BlockCategory::read(&mut self.br)?;
+    let mut command_blocks = BlockCategory::read(&mut self.br)?;
+    let mut distance_blocks = BlockCategory::read(&mut self.br)?;
+    let npostfix = self.br.read(2)?;
+    let ndirect = self.br.read(4)? << npostfix;
+    let mut context_modes = Vec::with_capacity(literal_blocks.num_types as usize);
+    for _ in 0..literal_blocks.num_types {
+      context_modes.push(context_type(self.br.read(2)?));
+    }
+    let num_literal_trees = read_var_len_u8(&mut self.br)? + 1;
+    let literal_map = read_context_map(&mut self.br, 64 * literal_blocks.num_types as usize, num_literal_trees)?;
+    let num_distance_trees = read_var_len_u8(&mut self.br)? + 1;
+    let distance_map = read_context_map(&mut self.br, 4 * distance_blocks.num_types as usize, num_distance_trees)?;
+    let distance_alphabet = 16 + ndirect + ((if self.large_window { 62 } else { 24 }) << (npostfix + 1));
+    let mut literal_codes = Vec::with_capacity(num_literal_trees as usize);
+    for _ in 0..num_literal_trees {
+      literal_codes.push(read_prefix_code(&mut self.br, NUM_LITERAL_SYMBOLS, NUM_LITERAL_SYMBOLS)?);
+    }
+    let mut command_codes = Vec::with_capacity(command_blocks.num_types as usize);
+    for _ in 0..command_blocks.num_types {
+      command_codes.push(read_prefix_code(&mut self.br, NUM_COMMAND_SYMBOLS, NUM_COMMAND_SYMBOLS)?);
+    }
+    let mut distance_codes = Vec::with_capacity(num_distance_trees as usize);
+    for _ in 0..num_distance_trees {
+      distance_codes.push(read_prefix_code(&mut self.br, distance_alphabet, distance_alphabet)?);
+    }
+    let mut remaining = mlen;
+    while remaining > 0 {
+      let command_type = command_blocks.next(&mut self.br)?;
+      let symbol = command_codes[command_type as usize].read_symbol(&mut self.br)?;
+      let range = (symbol >> 6) as usize;
+      let (insert_base, insert_extra) = kInsertLengthPrefix[(kInsertRangeLut[range] + ((symbol >> 3) & 7)) as usize];
+      let (copy_base, copy_extra) = kCopyLengthPrefix[(kCopyRangeLut[range] + (symbol & 7)) as usize];
+      let insert_len = insert_base + self.br.read(insert_extra)?;
+      let copy_len = copy_base + self.br.read(copy_extra)?;
+      if insert_len as usize > remaining {
+        return Err(());
+      }
+      let position = self.position();
+      for _ in 0..insert_len {
+        let literal_type = literal_blocks.next(&mut self.br)? as usize;
+        let n = self.ret.output.len();
+        let p1 = if n > 0 { self.ret.output[n - 1] } else { 0 };
+        let p2 = if n > 1 { self.ret.output[n - 2] } else { 0 };
+        let context = Context(p1, p2, context_modes[literal_type]) as usize;
+        let tree = literal_map[literal_type * 64 + context] as usize;
+        let literal = literal_codes[tree].read_symbol(&mut self.br)?;
+        self.ret.output.push(literal as u8);
+      }
+      remaining -= insert_len as usize;
+      if remaining == 0 {
+        self.push_command(ParsedCommand {
+          position: position,
+          insert_len: insert_len,
+          copy_len: 0,
+          distance: 0,
+          distance_code: 0,
+          kind: CopyKind::NoCopy,
+        });
+        break;
+      }
+      let distance_code = if symbol < 128 {
+        0
+      } else {
+        let distance_type = distance_blocks.next(&mut self.br)? as usize;
+        let context = if copy_len > 4 { 3 } else { copy_len as usize - 2 };
+        let tree = distance_map[distance_type * 4 + context] as usize;
+        distance_codes[tree].read_symbol(&mut self.br)?
+      };
+      let distance = self.read_distance(distance_code, npostfix, ndirect)?;
+      let max_distance = core::cmp::min(self.position(), self.max_backward);
+      let kind;
+      let produced;
+      if distance > max_distance {
+        if distance > MAX_ALLOWED_DISTANCE || copy_len < u32::from(kBrotliMinDictionaryWordLength)
+          || copy_len > u32::from(kBrotliMaxDictionaryWordLength) {
+          return Err(());
+        }
+        let word_id = distance - max_distance - 1;
+        let shift = kBrotliDictionarySizeBitsByLength[copy_len as usize];
+        let word_index = (word_id & ((1u64 << shift) - 1)) as usize;
+        let transform = word_id >> shift;
+        if transform >= kNumTransforms as u64 {
+          return Err(());
+        }
+        let offset = kBrotliDictionaryOffsetsByLength[copy_len as usize] as usize + word_index * copy_len as usize;
+        let mut word = [0u8; 256];
+        let len = TransformDictionaryWord(&mut word[..],
+                                          &kBrotliDictionary[offset..offset + copy_len as usize],
+                                          copy_len as i32,
+                                          transform as i32) as usize;
+        if len > remaining {
+          return Err(());
+        }
+        self.ret.output.extend_from_slice(&word[..len]);
+        info.uses_dictionary = true;
+        kind = CopyKind::Dictionary;
+        produced = len;
+      } else {
+        if copy_len as usize > remaining {
+          return Err(());
+        }
+        if distance_code != 0 {
+          self.push_distance(distance);
+        }
+        let start = self.ret.output.len() - distance as usize;
+        for i in 0..copy_len as usize {
+          let byte = self.ret.output[start + i];
+          self.ret.output.push(byte);
+        }
+        kind = CopyKind::Backward;
+        produced = copy_len as usize;
+      }
+      remaining -= produced;
+      self.push_command(ParsedCommand {
+        position: position,
+        insert_len: insert_len,
+        copy_len: produced as u32,
+        distance: distance,
+        distance_code: distance_code,
+        kind: kind,
+      });
+    }
+    Ok(())
+  }
+  fn parse_metablock(&mut self) -> Result<bool, ()> {
+    let mut info = MetablockInfo {
+      kind: MetablockKind::Compressed,
+      is_last: false,
+      start_bit: self.br.pos,
+      header_end_bit: 0,
+      end_bit: 0,
+      output_start: self.position(),
+      output_len: 0,
+      first_command: self.ret.commands.len(),
+      num_commands: 0,
+      uses_dictionary: false,
+      distance_cache: self.dist_rb,
+    };
+    info.is_last = self.br.read(1)? == 1;
+    if info.is_last && self.br.read(1)? == 1 {
+      info.kind = MetablockKind::LastEmpty;
+      info.header_end_bit = self.br.pos;
+      info.end_bit = self.br.pos;
+      self.ret.metablocks.push(info);
+      return Ok(true);
+    }
+    let nibbles_code = self.br.read(2)?;
+    if nibbles_code == 3 {
+      if self.br.read(1)? != 0 {
+        return Err(());
+      }
+      let skip_bytes = self.br.read(2)?;
+      let mut skip_len = 0usize;
+      for i in 0..skip_bytes {
+        let byte = self.br.read(8)?;
+        if byte == 0 && i + 1 == skip_bytes && skip_bytes > 1 {
+          return Err(());
+        }
+        skip_len |= (byte as usize) << (8 * i);
+      }
+      if skip_bytes > 0 {
+        skip_len += 1;
+      }
+      info.kind = MetablockKind::Metadata;
+      info.header_end_bit = self.br.pos;
+      self.br.align()?;
+      self.br.skip((skip_len * 8) as u32)?;
+      info.end_bit = self.br.pos;
+      self.ret.metablocks.push(info);
+      return Ok(info.is_last);
+    }
+    let nibbles = nibbles_code + 4;
+    let mut mlen = 0usize;
+    for i in 0..nibbles {
+      let nibble = self.br.read(4)?;
+      if nibble == 0 && i + 1 == nibbles && nibbles > 4 {
+        return Err(());
+      }
+      mlen |= (nibble as usize) << (4 * i);
+    }
+    mlen += 1;
+    let is_uncompressed = !info.is_last && self.br.read(1)? == 1;
+    info.header_end_bit = self.br.pos;
+    if is_uncompressed {
+      info.kind = MetablockKind::Uncompressed;
+      self.br.align()?;
+      let byte = self.br.pos >> 3;
+      if byte + mlen > self.br.data.len() {
+        return Err(());
+      }
+      let position = self.position();
+      self.push_command(ParsedCommand {
+        position: position,
+        insert_len: mlen as u32,
+        copy_len: 0,
+        distance: 0,
+        distance_code: 0,
+        kind: CopyKind::NoCopy,
+      });
+      self.ret.output.extend_from_slice(&self.br.data[byte..byte + mlen]);
+      self.br.pos += mlen << 3;
+    } else {
+      self.parse_compressed(mlen, &mut info)?;
+    }
+    info.end_bit = self.br.pos;
+    info.output_len = mlen as u64;
+    info.num_commands = self.ret.commands.len() - info.first_command;
+    self.ret.metablocks.push(info);
+    self.trim_output();
+    Ok(info.is_last)
+  }
+}
+
+// Decodes a stream one metablock at a time so callers can stop keeping its output and commands
+// once they have seen enough of it.
+pub struct MetablockParser<'a> {
+  parser: Parser<'a>,
+  done: bool,
+}
+
+impl<'a> MetablockParser<'a> {
+  pub fn new(input: &'a [u8]) -> Result<MetablockParser<'a>, ()> {
+    if input.is_empty() || (input.len() < 2 && (input[0] & 0x7f) == 0x11) {
+      return Err(());
+    }
+    let (window_bits, header_bits) = parse_window_size(input)?;
+    let large_window = header_bits == 14;
+    Ok(MetablockParser {
+      parser: Parser {
+        br: BitReader { data: input, pos: header_bits },
+        max_backward: (1u64 << window_bits) - WINDOW_GAP,
+        large_window: large_window,
+        dist_rb: [4, 11, 15, 16],
+        retain: true,
+        output_base: 0,
+        ret: ParsedStream {
+          window_bits: window_bits,
+          large_window: large_window,
+          header_bits: header_bits,
+          stream_len: 0,
+          metablocks: Vec::new(),
+          commands: Vec::new(),
+          output: Vec::new(),
+        },
+      },
+      done: false,
+    })
+  }
+  // everything parsed so far; output and commands stop growing after discard_output
+  pub fn parsed(&self) -> &ParsedStream {
+    &self.parser.ret
+  }
+  pub fn is_done(&self) -> bool {
+    self.done
+  }
+  // bytes of output the parsed metablocks produce
+  pub fn output_len(&self) -> u64 {
+    self.parser.position()
+  }
+  // Parses the next metablock into parsed().metablocks; returns whether it was the last one.
+  pub fn parse_metablock(&mut self) -> Result<bool, ()> {
+    if !self.done {
+      self.done = self.parser.parse_metablock()?;
+      if self.done {
+        self.parser.br.align()?;
+        self.parser.ret.stream_len = self.parser.br.pos >> 3;
+      }
+    }
+    Ok(self.done)
+  }
+  // Keeps parsing metablock boundaries only: commands are no longer recorded and the output
+  // is limited to the window that later copies can reach.
+  pub fn discard_output(&mut self) {
+    self.parser.retain = false;
+    self.parser.trim_output();
+  }
+}
+
+// Decodes the whole stream, failing on any corruption or on trailing truncation.
+// Streams relying on a custom dictionary are reported as corrupt.
+pub fn BrotliParseStream(input: &[u8]) -> Result<ParsedStream, ()> {
+  let mut parser = MetablockParser::new(input)?;
+  while !parser.parse_metablock()? {}
+  Ok(parser.parser.ret)
+}
@@ -0,0 +1,144 @@
+// Cuts runs of metablocks out of a stream as standalone streams without recompressing them,
+// for sharding catable or multithreaded output.
+use core;
+use std::vec::Vec;
+use super::BroCatliResult;
+use super::parse::{BrotliParseStream, ParsedStream, MetablockKind, CopyKind};
+use super::transcode::{BitWriter, check_distance_cache, copy_metablocks};
+
+// the cache a decoder starts every stream with
+const INITIAL_DISTANCE_CACHE: [Option<u64>; 4] = [Some(4), Some(11), Some(15), Some(16)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplitPoint {
+  pub metablock: usize,
+  pub output_offset: u64, // uncompressed bytes before the metablock
+  pub bit_offset: usize, // position of the metablock header in the stream
+}
+
+// Literals coded right after a cut would see different context bytes in a new stream.
+fn literals_near_start(parsed: &ParsedStream, first: usize, end: usize) -> bool {
+  let start = parsed.metablocks[first].output_start;
+  for index in first..end {
+    let mb = &parsed.metablocks[index];
+    if mb.output_start >= start + 2 {
+      break;
+    }
+    if mb.kind == MetablockKind::Compressed
+      && parsed.metablock_commands(index).iter().any(|cmd| cmd.insert_len != 0 && cmd.position < start + 2) {
+      return true;
+    }
+  }
+  false
+}
+
+// Whether metablocks [first, end) decode to the same bytes when they start a stream of their own.
+fn is_standalone(parsed: &ParsedStream, first: usize, end: usize) -> bool {
+  let start = parsed.metablocks[first].output_start;
+  if start == 0 {
+    return true;
+  }
+  let max_backward = (1u64 << parsed.window_bits) - 16;
+  let end_command = if end < parsed.metablocks.len() {
+    parsed.metablocks[end].first_command
+  } else {
+    parsed.commands.len()
+  };
+  for cmd in parsed.commands[parsed.metablocks[first].first_command..end_command].iter() {
+    let copy_position = cmd.position + u64::from(cmd.insert_len);
+    match cmd.kind {
+      CopyKind::NoCopy => {},
+      CopyKind::Backward => if cmd.distance > copy_position - start {
+        return false;
+      },
+      // dictionary words are numbered from the amount of output so far until the window fills
+      CopyKind::Dictionary => if copy_position - start < max_backward {
+        return false;
+      },
+    }
+  }
+  !literals_near_start(parsed, first, end)
+    && check_distance_cache(parsed, first, end, INITIAL_DISTANCE_CACHE).is_ok()
+}
+
+// Metablocks where the remainder of the stream stops depending on anything before it;
+// the stream can be cut at any of them. The first metablock is always included.
+pub fn BrotliFindSplitPoints(parsed: &ParsedStream) -> Vec<SplitPoint> {
+  let num_metablocks = parsed.metablocks.len();
+  let max_backward = (1u64 << parsed.window_bits) - 16;
+  // latest output offset a cut can sit at without breaking a reference in this or a later metablock
+  let mut latest_cut = vec![u64::max_value(); num_metablocks + 1];
+  for index in (0..num_metablocks).rev() {
+    let mut cut = latest_cut[index + 1];
+    for cmd in parsed.metablock_commands(index) {
+      let copy_position = cmd.position + u64::from(cmd.insert_len);
+      cut = core::cmp::min(cut, match cmd.kind {
+        CopyKind::NoCopy => u64::max_value(),
+        CopyKind::Backward => copy_position - cmd.distance,
+        CopyKind::Dictionary => copy_position.saturating_sub(max_backward),
+      });
+    }
+    latest_cut[index] = cut;
+  }
+  let mut ret = Vec::new();
+  for (index, mb) in parsed.metablocks.iter().enumerate() {
+    if mb.kind == MetablockKind::LastEmpty {
+      break;
+    }
+    let start = mb.output_start;
+    if index == 0 || start == 0
+      || (start <= latest_cut[index] && !literals_near_start(parsed, index, num_metablocks)
+          && check_distance_cache(parsed, index, num_metablocks, INITIAL_DISTANCE_CACHE).is_ok()) {
+      ret.push(SplitPoint {
+        metablock: index,
+        output_offset: start,
+        bit_offset: mb.start_bit,
+      });
+    }
+  }
+  ret
+}
+
+// Writes metablocks [first, end) of a parsed stream as a standalone stream with the original
+// window, ending in an empty last metablock so the result is also appendable.
+pub fn BrotliExtractMetablocks(input: &[u8], parsed: &ParsedStream, first: usize, end: usize) -> Result<Vec<u8>, BroCatliResult> {
+  if first > end || end > parsed.metablocks.len() {
+    return Err(BroCatliResult::BrotliFileNotCraftedForConcatenation);
+  }
+  if first != end && !is_standalone(parsed, first, end) {
+    return Err(BroCatliResult::BrotliFileNotCraftedForConcatenation);
+  }
+  let mut writer = BitWriter::new();
+  writer.copy_bits(input, 0, parsed.header_bits);
+  copy_metablocks(&mut writer, input, &parsed.metablocks[first..end]);
+  writer.write_bits(2, 3);
+  writer.align();
+  Ok(writer.into_bytes())
+}
+
+// Splits a stream into standalone streams of at least target_size uncompressed bytes each
+// (except possibly the last), cutting only at split points.
+pub fn BrotliSplitStream(input: &[u8], target_size: u64) -> Result<Vec<Vec<u8>>, BroCatliResult> {
+  let parsed = match BrotliParseStream(input) {
+    Ok(parsed) => parsed,
+    Err(()) => return Err(BroCatliResult::BrotliFileCorrupt),
+  };
+  let mut num_metablocks = parsed.metablocks.len();
+  if parsed.metablocks[num_metablocks - 1].kind == MetablockKind::LastEmpty {
+    num_metablocks -= 1;
+  }
+  let mut cuts = vec![0];
+  let mut chunk_start = 0u64;
+  for point in BrotliFindSplitPoints(&parsed) {
+    if point.metablock != 0 && point.output_offset >= chunk_start + target_size {
+      cuts.push(point.metablock);
+      chunk_start = point.output_offset;
+    }
+  }
+  cuts.push(num_metablocks);
+  let mut ret = Vec::with_capacity(cuts.len() - 1);
+  for window in cuts.windows(2) {
+    ret.push(BrotliExtractMetablocks(input, &parsed, window[0], window[1])?);
+  }
+  Ok(ret)
+}
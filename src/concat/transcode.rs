@@ -0,0 +1,436 @@
+// Makes an arbitrary brotli stream suitable for BroCatli by re-encoding only the metablocks
+// that depend on the start of the stream and copying the rest verbatim.
+use core;
+use std::vec::Vec;
+use super::BroCatliResult;
+use super::parse::{MetablockParser, ParsedStream, MetablockInfo, MetablockKind, CopyKind};
+use ::enc::StandardAlloc;
+use ::enc::backward_references::BrotliEncoderParams;
+use ::enc::brotli_bit_stream::{BrotliStoreMetaBlockTrivial, BrotliStoreUncompressedMetaBlock, RecoderState};
+use ::enc::command::{Command, InitCommand};
+use ::enc::encode::{BrotliEncoderInitParams, InitInsertCommand};
+use ::enc::metablock::BrotliInitDistanceParams;
+
+const MAX_METABLOCK_LEN: usize = 1 << 24;
+const NUM_LEADING_UNCOMPRESSED_BYTES: u64 = 2;
+
+pub struct TranscodedFile {
+  pub data: Vec<u8>,
+  pub reencoded_bytes: u64, // uncompressed bytes that had to be compressed again
+  pub copied_bytes: u64, // uncompressed bytes whose metablocks were copied unchanged
+}
+
+// Bit-granular output that stays zero past the write position, like the encoder's storage.
+pub struct BitWriter {
+  pub storage: Vec<u8>,
+  pub ix: usize,
+}
+
+impl BitWriter {
+  pub fn new() -> BitWriter {
+    BitWriter {
+      storage: Vec::new(),
+      ix: 0,
+    }
+  }
+  // the encoder writes 8 bytes at a time, so keep slack after the requested space
+  pub fn reserve(&mut self, nbytes: usize) {
+    let needed = (self.ix >> 3) + nbytes + 16;
+    if self.storage.len() < needed {
+      self.storage.resize(needed, 0);
+    }
+  }
+  pub fn write_bits(&mut self, n_bits: usize, bits: u64) {
+    self.reserve(8);
+    let p = self.ix >> 3;
+    let mut v = u64::from(self.storage[p]) | (bits << (self.ix & 7));
+    for i in 0..8 {
+      self.storage[p + i] = v as u8;
+      v >>= 8;
+    }
+    self.ix += n_bits;
+  }
+  pub fn copy_bits(&mut self, src: &[u8], mut start: usize, end: usize) {
+    if (start & 7) == (self.ix & 7) {
+      while start < end && (start & 7) != 0 {
+        self.write_bits(1, u64::from((src[start >> 3] >> (start & 7)) & 1));
+        start += 1;
+      }
+      let nbytes = (end - start) >> 3;
+      if nbytes != 0 {
+        self.reserve(nbytes);
+        let p = self.ix >> 3;
+        self.storage[p..p + nbytes].clone_from_slice(&src[(start >> 3)..(start >> 3) + nbytes]);
+        self.ix += nbytes << 3;
+        start += nbytes << 3;
+      }
+    }
+    while start < end {
+      let n = core::cmp::min(32, end - start);
+      let mut v = 0u64;
+      for i in 0..n {
+        let bit = start + i;
+        v |= u64::from((src[bit >> 3] >> (bit & 7)) & 1) << i;
+      }
+      self.write_bits(n, v);
+      start += n;
+    }
+  }
+  pub fn align(&mut self) {
+    self.ix = (self.ix + 7) & !7;
+  }
+  pub fn into_bytes(mut self) -> Vec<u8> {
+    self.storage.truncate(self.ix >> 3);
+    self.storage
+  }
+}
+
+#[derive(Clone, Copy)]
+enum ReplayOp {
+  Literals(usize),
+  Copy(usize, Option<u64>), // length and explicit distance; None repeats the last distance
+}
+
+struct Replay {
+  ops: Vec<ReplayOp>,
+  cache: [Option<u64>; 4], // distances pushed while replaying, most recent first
+}
+
+impl Replay {
+  fn literals(&mut self, count: usize) {
+    if count == 0 {
+      return;
+    }
+    if let Some(&mut ReplayOp::Literals(ref mut n)) = self.ops.last_mut() {
+      *n += count;
+      return;
+    }
+    self.ops.push(ReplayOp::Literals(count));
+  }
+}
+
+// Commands producing output [skip, end_of_metablock(num_metablocks)) with the static dictionary
+// expanded and every copy expressed relative to what the replay itself has pushed.
+fn build_replay(parsed: &ParsedStream, num_metablocks: usize, skip: u64) -> Replay {
+  let mut replay = Replay {
+    ops: Vec::new(),
+    cache: [None; 4],
+  };
+  for index in 0..num_metablocks {
+    for cmd in parsed.metablock_commands(index) {
+      let insert_end = cmd.position + u64::from(cmd.insert_len);
+      replay.literals(insert_end.saturating_sub(core::cmp::max(cmd.position, skip)) as usize);
+      let trimmed = core::cmp::min(skip.saturating_sub(insert_end), u64::from(cmd.copy_len)) as usize;
+      let rest = cmd.copy_len as usize - trimmed;
+      match cmd.kind {
+        CopyKind::NoCopy => {},
+        CopyKind::Dictionary => replay.literals(rest),
+        CopyKind::Backward => {
+          if rest < 2 {
+            replay.literals(rest);
+          } else if cmd.distance_code == 0 && replay.cache[0] == Some(cmd.distance) {
+            replay.ops.push(ReplayOp::Copy(rest, None));
+          } else {
+            replay.ops.push(ReplayOp::Copy(rest, Some(cmd.distance)));
+            replay.cache = [Some(cmd.distance), replay.cache[0], replay.cache[1], replay.cache[2]];
+          }
+        },
+      }
+    }
+  }
+  replay
+}
+
+// Checks that the copied metablocks only read distance cache entries the replay reproduces.
+// Returns the index of the first metablock that reads one it does not.
+pub fn check_distance_cache(parsed: &ParsedStream, first: usize, end: usize, cache: [Option<u64>; 4]) -> Result<(), usize> {
+  distance_cache_converges(parsed, first, end, cache).map(|_| ())
+}
+
+// Like check_distance_cache, also telling whether the two caches become equal before end,
+// after which no later metablock can tell them apart.
+fn distance_cache_converges(parsed: &ParsedStream, first: usize, end: usize, mut cache: [Option<u64>; 4]) -> Result<bool, usize> {
+  let mut file_cache = parsed.metablocks[first].distance_cache;
+  for index in first..end {
+    if cache.iter().zip(file_cache.iter()).all(|(a, b)| *a == Some(*b)) {
+      return Ok(true);
+    }
+    for cmd in parsed.metablock_commands(index) {
+      if cmd.kind == CopyKind::NoCopy {
+        continue;
+      }
+      if cmd.distance_code < 16 {
+        let entry = if cmd.distance_code < 4 {
+          cmd.distance_code as usize
+        } else if cmd.distance_code < 10 {
+          0
+        } else {
+          1
+        };
+        if cache[entry] != Some(file_cache[entry]) {
+          return Err(index);
+        }
+      }
+      if cmd.kind == CopyKind::Backward && cmd.distance_code != 0 {
+        cache = [Some(cmd.distance), cache[0], cache[1], cache[2]];
+        file_cache = [cmd.distance, file_cache[0], file_cache[1], file_cache[2]];
+      }
+    }
+  }
+  Ok(cache.iter().zip(file_cache.iter()).all(|(a, b)| *a == Some(*b)))
+}
+
+// Index of the first metablock that can be copied verbatim; everything before it is re-encoded.
+// Unless the stream is complete, None means a metablock not parsed yet may still move it.
+fn find_copy_start(parsed: &ParsedStream, num_metablocks: usize, same_window: bool, complete: bool) -> Option<usize> {
+  let max_backward = (1u64 << parsed.window_bits) - 16;
+  let mut first = 1;
+  for index in 0..num_metablocks {
+    if parsed.metablocks[index].kind != MetablockKind::Compressed {
+      continue;
+    }
+    // literals in the first bytes are coded with a context of previous output,
+    // and dictionary references are numbered from the amount of output so far
+    let depends_on_start = parsed.metablock_commands(index).iter().any(|cmd| {
+      (cmd.insert_len != 0 && cmd.position < NUM_LEADING_UNCOMPRESSED_BYTES)
+        || (cmd.kind == CopyKind::Dictionary
+            && (!same_window || cmd.position + u64::from(cmd.insert_len) < max_backward))
+    });
+    if depends_on_start {
+      first = index + 1;
+    }
+  }
+  loop {
+    if first >= num_metablocks {
+      return if complete { Some(num_metablocks) } else { None };
+    }
+    let start = parsed.metablocks[first].output_start;
+    if start == 0 {
+      first += 1;
+      continue;
+    }
+    let skip = core::cmp::min(NUM_LEADING_UNCOMPRESSED_BYTES, start);
+    let replay = build_replay(parsed, first, skip);
+    match distance_cache_converges(parsed, first, num_metablocks, replay.cache) {
+      Ok(converged) => return if converged || complete { Some(first) } else { None },
+      Err(index) => first = index + 1,
+    }
+  }
+}
+
+struct MetablockStore<'a> {
+  writer: &'a mut BitWriter,
+  output: &'a [u8],
+  mask: usize,
+  params: BrotliEncoderParams,
+  alloc: StandardAlloc,
+  recoder_state: RecoderState,
+  commands: Vec<Command>,
+  start: usize,
+  len: usize,
+  pending_literals: usize,
+}
+
+impl<'a> MetablockStore<'a> {
+  fn flush(&mut self) {
+    if self.pending_literals != 0 {
+      let mut cmd = Command::default();
+      InitInsertCommand(&mut cmd, self.pending_literals);
+      self.commands.push(cmd);
+      self.len += self.pending_literals;
+      self.pending_literals = 0;
+    }
+    if self.len == 0 {
+      return;
+    }
+    let dist_cache = [0i32; 4];
+    self.writer.reserve(2 * self.len + 16 * self.commands.len() + 4096);
+    BrotliStoreMetaBlockTrivial(&mut self.alloc,
+                                self.output,
+                                self.start,
+                                self.len,
+                                self.mask,
+                                0,
+                                &self.params,
+                                &dist_cache,
+                                &self.commands[..],
+                                self.commands.len(),
+                                &mut self.recoder_state,
+                                &mut self.writer.ix,
+                                &mut self.writer.storage[..],
+                                &mut |_a, _b, _c, _d| ());
+    self.commands.clear();
+    self.start += self.len;
+    self.len = 0;
+  }
+  fn literals(&mut self, mut count: usize) {
+    while self.len + self.pending_literals + count > MAX_METABLOCK_LEN {
+      let room = MAX_METABLOCK_LEN - self.len - self.pending_literals;
+      self.pending_literals += room;
+      count -= room;
+      self.flush();
+    }
+    self.pending_literals += count;
+  }
+  fn copy(&mut self, mut len: usize, mut distance: Option<u64>) {
+    loop {
+      let room = MAX_METABLOCK_LEN - self.len - self.pending_literals;
+      let part = if len <= room { len } else { core::cmp::min(room, len - 2) };
+      if part < 2 {
+        // not enough room left for a copy: close the metablock with the pending literals
+        self.flush();
+        continue;
+      }
+      let mut cmd = Command::default();
+      let distance_code = match distance {
+        Some(d) => d as usize + 15,
+        None => 0,
+      };
+      InitCommand(&mut cmd, &self.params.dist, self.pending_literals, part, part, distance_code);
+      self.commands.push(cmd);
+      self.len += self.pending_literals + part;
+      self.pending_literals = 0;
+      len -= part;
+      distance = None;
+      if self.len == MAX_METABLOCK_LEN {
+        self.flush();
+      }
+      if len == 0 {
+        return;
+      }
+    }
+  }
+}
+
+// Copies metablocks bit for bit, clearing ISLAST so more metablocks can follow them.
+pub fn copy_metablocks(writer: &mut BitWriter, input: &[u8], metablocks: &[MetablockInfo]) {
+  for mb in metablocks.iter() {
+    if mb.kind == MetablockKind::LastEmpty {
+      continue;
+    }
+    if mb.is_last {
+      // drop ISLASTEMPTY and add the ISUNCOMPRESSED bit a non-final metablock carries
+      writer.write_bits(1, 0);
+      writer.copy_bits(input, mb.start_bit + 2, mb.header_end_bit);
+      if mb.kind == MetablockKind::Compressed {
+        writer.write_bits(1, 0);
+      }
+    } else {
+      writer.copy_bits(input, mb.start_bit, mb.header_end_bit);
+    }
+    if mb.kind == MetablockKind::Compressed {
+      writer.copy_bits(input, mb.header_end_bit, mb.end_bit);
+    } else {
+      writer.align();
+      writer.copy_bits(input, (mb.header_end_bit + 7) & !7, mb.end_bit);
+    }
+  }
+}
+
+// Writes the stream header and re-encodes the output of the metablocks before copy_start,
+// returning how many bytes that output holds.
+fn write_prefix(writer: &mut BitWriter, input: &[u8], parsed: &ParsedStream, num_metablocks: usize, copy_start: usize) -> u64 {
+  writer.copy_bits(input, 0, parsed.header_bits);
+  let prefix_len = if copy_start < num_metablocks {
+    parsed.metablocks[copy_start].output_start
+  } else {
+    parsed.output.len() as u64
+  };
+  let skip = core::cmp::min(NUM_LEADING_UNCOMPRESSED_BYTES, prefix_len);
+  let mut params = BrotliEncoderInitParams();
+  params.lgwin = i32::from(parsed.window_bits);
+  params.large_window = parsed.large_window;
+  BrotliInitDistanceParams(&mut params, 0, 0);
+  let mask = parsed.output.len().next_power_of_two() - 1;
+  let mut alloc = StandardAlloc::default();
+  let mut recoder_state = RecoderState::new();
+  if skip != 0 {
+    writer.reserve(skip as usize + 16);
+    BrotliStoreUncompressedMetaBlock(&mut alloc,
+                                     0,
+                                     &parsed.output[..],
+                                     0,
+                                     mask,
+                                     &params,
+                                     skip as usize,
+                                     &mut recoder_state,
+                                     &mut writer.ix,
+                                     &mut writer.storage[..],
+                                     true,
+                                     &mut |_a, _b, _c, _d| ());
+  }
+  let replay = build_replay(parsed, copy_start, skip);
+  let mut store = MetablockStore {
+    writer: writer,
+    output: &parsed.output[..],
+    mask: mask,
+    params: params,
+    alloc: alloc,
+    recoder_state: recoder_state,
+    commands: Vec::new(),
+    start: skip as usize,
+    len: 0,
+    pending_literals: 0,
+  };
+  for op in replay.ops.iter() {
+    match *op {
+      ReplayOp::Literals(count) => store.literals(count),
+      ReplayOp::Copy(len, distance) => store.copy(len, distance),
+    }
+  }
+  store.flush();
+  prefix_len
+}
+
+fn num_data_metablocks(parsed: &ParsedStream) -> usize {
+  match parsed.metablocks.last() {
+    Some(mb) if mb.kind == MetablockKind::LastEmpty => parsed.metablocks.len() - 1,
+    _ => parsed.metablocks.len(),
+  }
+}
+
+// Rewrites a brotli stream so BroCatli accepts it, both as the first file and as a later one.
+// log_window_size is the window of the concatenated stream, or 0 to keep the file's own window.
+// Only the metablocks that depend on being at the start of the stream are re-encoded. The stream
+// is decoded until no later command can reach before its start; the remaining metablocks are
+// only walked to find their boundaries and copied without keeping their output.
+pub fn BrotliTranscodeForConcatenation(input: &[u8], log_window_size: u8) -> Result<TranscodedFile, BroCatliResult> {
+  let corrupt = |_| BroCatliResult::BrotliFileCorrupt;
+  let mut parser = MetablockParser::new(input).map_err(corrupt)?;
+  let window_bits = parser.parsed().window_bits;
+  if log_window_size != 0 && !(10..=30).contains(&log_window_size) {
+    return Err(BroCatliResult::InvalidWindowSize);
+  }
+  if log_window_size != 0 && window_bits > log_window_size {
+    return Err(BroCatliResult::WindowSizeLargerThanPreviousFile);
+  }
+  // in a different window, dictionary references anywhere in the file would change meaning
+  let same_window = log_window_size == 0 || log_window_size == window_bits;
+  let max_backward = (1u64 << window_bits) - 16;
+  let copy_start = loop {
+    let done = parser.parse_metablock().map_err(corrupt)?;
+    if done || (same_window && parser.output_len() >= max_backward) {
+      if let Some(copy_start) = find_copy_start(parser.parsed(), num_data_metablocks(parser.parsed()), same_window, done) {
+        break copy_start;
+      }
+    }
+  };
+  let mut writer = BitWriter::new();
+  let num_parsed = num_data_metablocks(parser.parsed());
+  let prefix_len = write_prefix(&mut writer, input, parser.parsed(), num_parsed, copy_start);
+  copy_metablocks(&mut writer, input, &parser.parsed().metablocks[copy_start..]);
+  parser.discard_output();
+  while !parser.is_done() {
+    parser.parse_metablock().map_err(corrupt)?;
+    let mb = parser.parsed().metablocks[parser.parsed().metablocks.len() - 1];
+    copy_metablocks(&mut writer, input, &[mb]);
+  }
+  // ISLAST, ISLASTEMPTY so the result can be appended to
+  writer.write_bits(2, 3);
+  writer.align();
+  Ok(TranscodedFile {
+    data: writer.into_bytes(),
+    reencoded_bytes: prefix_len,
+    copied_bytes: parser.output_len() - prefix_len,
+  })
+}
@@ -0,0 +1,198 @@
+#[cfg(feature="std")]
+use std::io::{self, Read, Write};
+#[cfg(feature="std")]
+use std::time::Instant;
+#[cfg(feature="std")]
+use alloc_stdlib::StandardAlloc;
+#[cfg(feature="std")]
+use super::backward_references::BrotliEncoderParams;
+#[cfg(feature="std")]
+use super::encode::{BrotliEncoderCreateInstance, BrotliEncoderDestroyInstance,
+                    BrotliEncoderCompressStream, BrotliEncoderOperation,
+                    BrotliEncoderIsFinished, BrotliEncoderHasMoreOutput,
+                    BrotliEncoderInputBlockSize, BrotliEncoderChangeQuality};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+pub const NUM_QUALITIES: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdaptiveTarget {
+  // sustain at least this many input bytes per second
+  Throughput(u64),
+  // compress expected_size bytes in no more than nanos nanoseconds
+  TimeBudget{nanos: u64, expected_size: u64},
+}
+
+impl AdaptiveTarget {
+  pub fn megabytes_per_second(mb_per_sec: u64) -> AdaptiveTarget {
+    AdaptiveTarget::Throughput(mb_per_sec.saturating_mul(1024 * 1024))
+  }
+}
+
+// Picks the quality of each upcoming metablock from the speed the previous ones were compressed at.
+// The controller walks the quality ladder one rung at a time: the fragment compressors (q0, q1),
+// the greedy hashers (q2-q9) and the zopfli search (q10, q11).
+#[derive(Clone, Debug)]
+pub struct AdaptiveQualityController {
+  target: AdaptiveTarget,
+  min_quality: i32,
+  max_quality: i32,
+  quality: i32,
+  total_bytes: u64,
+  total_nanos: u64,
+  // number of input bytes compressed at each quality
+  pub bytes_per_quality: [u64; NUM_QUALITIES],
+  pub num_quality_changes: u32,
+}
+
+impl AdaptiveQualityController {
+  pub fn new(target: AdaptiveTarget, min_quality: i32, max_quality: i32) -> AdaptiveQualityController {
+    let max_quality = core::cmp::min(core::cmp::max(max_quality, 0), NUM_QUALITIES as i32 - 1);
+    let min_quality = core::cmp::min(core::cmp::max(min_quality, 0), max_quality);
+    AdaptiveQualityController {
+      target: target,
+      min_quality: min_quality,
+      max_quality: max_quality,
+      quality: max_quality,
+      total_bytes: 0,
+      total_nanos: 0,
+      bytes_per_quality: [0; NUM_QUALITIES],
+      num_quality_changes: 0,
+    }
+  }
+  pub fn quality(&self) -> i32 {
+    self.quality
+  }
+  pub fn total_bytes(&self) -> u64 {
+    self.total_bytes
+  }
+  pub fn total_nanos(&self) -> u64 {
+    self.total_nanos
+  }
+  // input bytes per second the remainder of the stream has to be compressed at
+  fn required_rate(&self) -> u64 {
+    match self.target {
+      AdaptiveTarget::Throughput(rate) => rate,
+      AdaptiveTarget::TimeBudget{nanos, expected_size} => {
+        if self.total_nanos >= nanos {
+          return u64::max_value();
+        }
+        let remaining_bytes = expected_size.saturating_sub(self.total_bytes);
+        let remaining_nanos = nanos - self.total_nanos;
+        ((remaining_bytes as f64) * NANOS_PER_SEC as f64 / remaining_nanos as f64) as u64
+      },
+    }
+  }
+  // reports that the last metablock of the given size took nanos to compress
+  // returns the quality to use for the next metablock
+  pub fn record(&mut self, bytes: usize, nanos: u64) -> i32 {
+    self.bytes_per_quality[self.quality as usize] += bytes as u64;
+    self.total_bytes += bytes as u64;
+    self.total_nanos += nanos;
+    if bytes == 0 {
+      return self.quality;
+    }
+    let observed = ((bytes as f64) * NANOS_PER_SEC as f64 / core::cmp::max(nanos, 1) as f64) as u64;
+    let required = self.required_rate();
+    let mut next = self.quality;
+    if observed < required {
+      next -= if observed < required / 4 { 2 } else { 1 };
+    } else if observed / 2 > required {
+      // only speed back up once there is plenty of headroom, to avoid oscillating
+      next += 1;
+    }
+    next = core::cmp::min(core::cmp::max(next, self.min_quality), self.max_quality);
+    if next != self.quality {
+      self.num_quality_changes += 1;
+      self.quality = next;
+    }
+    next
+  }
+}
+
+// compresses r into w, moving the quality between metablocks to meet the controller's target
+// every input block is flushed as its own metablock so that progress can be measured regularly
+#[cfg(feature="std")]
+pub fn BrotliCompressAdaptive<InputType, OutputType>(r: &mut InputType,
+                                                     w: &mut OutputType,
+                                                     params: &BrotliEncoderParams,
+                                                     controller: &mut AdaptiveQualityController)
+                                                     -> Result<usize, io::Error>
+  where InputType: Read,
+        OutputType: Write
+{
+  let mut s = BrotliEncoderCreateInstance(StandardAlloc::default());
+  s.params = params.clone();
+  s.params.adaptive_quality = true;
+  s.params.quality = controller.quality();
+  let block_size = BrotliEncoderInputBlockSize(&mut s);
+  let mut input_buffer = vec![0u8; block_size];
+  let mut output_buffer = vec![0u8; 65536];
+  let mut total_out = Some(0usize);
+  let mut nop_callback = |_data:&mut super::interface::PredictionModeContextMap<super::InputReferenceMut>,
+                          _cmds: &mut [super::interface::StaticCommand],
+                          _mb: super::interface::InputPair, _m: &mut StandardAlloc|();
+  let mut result = Ok(());
+  loop {
+    let mut available_in = 0usize;
+    while available_in < block_size {
+      match r.read(&mut input_buffer[available_in..]) {
+        Ok(0) => break,
+        Ok(size) => available_in += size,
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => {
+          result = Err(e);
+          break;
+        },
+      }
+    }
+    if result.is_err() {
+      break;
+    }
+    let op = if available_in == 0 {
+      BrotliEncoderOperation::BROTLI_OPERATION_FINISH
+    } else {
+      BrotliEncoderOperation::BROTLI_OPERATION_FLUSH
+    };
+    let bytes = available_in;
+    let start = Instant::now();
+    let mut next_in_offset = 0usize;
+    loop {
+      let mut available_out = output_buffer.len();
+      let mut next_out_offset = 0usize;
+      if BrotliEncoderCompressStream(&mut s,
+                                     op,
+                                     &mut available_in,
+                                     &input_buffer[..],
+                                     &mut next_in_offset,
+                                     &mut available_out,
+                                     &mut output_buffer[..],
+                                     &mut next_out_offset,
+                                     &mut total_out,
+                                     &mut nop_callback) <= 0 {
+        result = Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid encoder state"));
+        break;
+      }
+      if let Err(e) = w.write_all(&output_buffer[..next_out_offset]) {
+        result = Err(e);
+        break;
+      }
+      if available_in == 0 && BrotliEncoderHasMoreOutput(&s) == 0 {
+        break;
+      }
+    }
+    if result.is_err() || BrotliEncoderIsFinished(&s) != 0 {
+      break;
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * NANOS_PER_SEC + u64::from(elapsed.subsec_nanos());
+    let quality = controller.record(bytes, nanos);
+    let changed = BrotliEncoderChangeQuality(&mut s, quality);
+    debug_assert!(changed != 0);
+  }
+  BrotliEncoderDestroyInstance(&mut s);
+  match result {
+    Ok(()) => Ok(total_out.unwrap_or(0)),
+    Err(e) => Err(e),
+  }
+}
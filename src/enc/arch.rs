@@ -0,0 +1,828 @@
+// Kernels behind the vector types of compat.rs, a few hot loops of the cost models and the match
+// length and hashing of the hashers. With the
+// simd feature they use core::arch on stable Rust: SSE2 and NEON belong to the x86_64 and aarch64
+// baselines so they are chosen at compile time, and the loops switch to AVX2 when the CPU has it,
+// detected at run time in std builds and taken from the target features otherwise. Every kernel
+// returns the same bits as the scalar code, which other targets and builds without simd use, so the
+// compressed output does not depend on the backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimdBackend {
+  Scalar,
+  Sse2,
+  Avx2,
+  Neon,
+}
+
+// the instruction set the encoder kernels use on this machine
+pub fn BrotliEncoderSimdBackend() -> SimdBackend {
+  backend()
+}
+
+#[cfg(all(feature="simd", target_arch="x86_64"))]
+fn backend() -> SimdBackend {
+  if x86::avx2() {
+    SimdBackend::Avx2
+  } else {
+    SimdBackend::Sse2
+  }
+}
+
+#[cfg(all(feature="simd", target_arch="aarch64"))]
+fn backend() -> SimdBackend {
+  SimdBackend::Neon
+}
+
+#[cfg(not(all(feature="simd", any(target_arch="x86_64", target_arch="aarch64"))))]
+fn backend() -> SimdBackend {
+  SimdBackend::Scalar
+}
+
+#[cfg(all(feature="simd", target_arch="x86_64"))]
+pub use self::x86::{add_i16x16, sub_i16x16, and_i16x16, shr_i16x16, gt_i16x16,
+                    add_i32x8, and_i32x8, gt_i32x8, ge_i32x8,
+                    add_f32x8, sub_f32x8, mul_f32x8, ge_f32x8, min_f32x8,
+                    add_u32, add_cdf_speeds, rescale_cdf_columns,
+                    find_match_length, hash_words32};
+// SSE2 has no 32 bit multiply
+#[cfg(all(feature="simd", target_arch="x86_64"))]
+pub use self::scalar::mul_i32x8;
+#[cfg(all(feature="simd", target_arch="x86_64", not(feature="float64")))]
+pub use self::x86::population_costs;
+#[cfg(all(feature="simd", target_arch="aarch64"))]
+pub use self::neon::{add_i16x16, sub_i16x16, and_i16x16, shr_i16x16, gt_i16x16,
+                     add_i32x8, and_i32x8, mul_i32x8, gt_i32x8, ge_i32x8,
+                     add_f32x8, sub_f32x8, mul_f32x8, ge_f32x8, min_f32x8,
+                     add_u32, add_cdf_speeds, rescale_cdf_columns,
+                     find_match_length, hash_words32};
+#[cfg(not(all(feature="simd", any(target_arch="x86_64", target_arch="aarch64"))))]
+pub use self::scalar::{add_i16x16, sub_i16x16, and_i16x16, shr_i16x16, gt_i16x16,
+                       add_i32x8, and_i32x8, mul_i32x8, gt_i32x8, ge_i32x8,
+                       add_f32x8, sub_f32x8, mul_f32x8, ge_f32x8, min_f32x8,
+                       add_u32, add_cdf_speeds, rescale_cdf_columns,
+                       find_match_length, hash_words32};
+// the table lookups only pay off with an AVX2 gather
+#[cfg(not(all(feature="simd", target_arch="x86_64", not(feature="float64"))))]
+pub use self::scalar::population_costs;
+
+// the reference implementation, also the fallback of every backend
+#[allow(dead_code)]
+mod scalar {
+  use super::super::util::{floatX, FastLog2u16};
+
+  #[inline(always)]
+  fn lanes<T: Copy, R, F: Fn(T, T) -> R>(a: &[T], b: &[T], ret: &mut [R], f: F) {
+    for (r, (x, y)) in ret.iter_mut().zip(a.iter().zip(b.iter())) {
+      *r = f(*x, *y);
+    }
+  }
+  #[inline(always)]
+  pub fn add_i16x16(a: [i16; 16], b: [i16; 16]) -> [i16; 16] {
+    let mut ret = [0i16; 16];
+    lanes(&a, &b, &mut ret, |x, y| x.wrapping_add(y));
+    ret
+  }
+  #[inline(always)]
+  pub fn sub_i16x16(a: [i16; 16], b: [i16; 16]) -> [i16; 16] {
+    let mut ret = [0i16; 16];
+    lanes(&a, &b, &mut ret, |x, y| x.wrapping_sub(y));
+    ret
+  }
+  #[inline(always)]
+  pub fn and_i16x16(a: [i16; 16], b: [i16; 16]) -> [i16; 16] {
+    let mut ret = [0i16; 16];
+    lanes(&a, &b, &mut ret, |x, y| x & y);
+    ret
+  }
+  #[inline(always)]
+  pub fn shr_i16x16(a: [i16; 16], shift: i64) -> [i16; 16] {
+    let mut ret = a;
+    for item in ret.iter_mut() {
+      *item >>= shift;
+    }
+    ret
+  }
+  #[inline(always)]
+  pub fn gt_i16x16(a: [i16; 16], b: [i16; 16]) -> [i16; 16] {
+    let mut ret = [0i16; 16];
+    lanes(&a, &b, &mut ret, |x, y| -((x > y) as i16));
+    ret
+  }
+  #[inline(always)]
+  pub fn add_i32x8(a: [i32; 8], b: [i32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x.wrapping_add(y));
+    ret
+  }
+  #[inline(always)]
+  pub fn and_i32x8(a: [i32; 8], b: [i32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x & y);
+    ret
+  }
+  #[inline(always)]
+  pub fn mul_i32x8(a: [i32; 8], b: [i32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x.wrapping_mul(y));
+    ret
+  }
+  #[inline(always)]
+  pub fn gt_i32x8(a: [i32; 8], b: [i32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| -((x > y) as i32));
+    ret
+  }
+  #[inline(always)]
+  pub fn ge_i32x8(a: [i32; 8], b: [i32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| -((x >= y) as i32));
+    ret
+  }
+  #[inline(always)]
+  pub fn add_f32x8(a: [f32; 8], b: [f32; 8]) -> [f32; 8] {
+    let mut ret = [0.0f32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x + y);
+    ret
+  }
+  #[inline(always)]
+  pub fn sub_f32x8(a: [f32; 8], b: [f32; 8]) -> [f32; 8] {
+    let mut ret = [0.0f32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x - y);
+    ret
+  }
+  #[inline(always)]
+  pub fn mul_f32x8(a: [f32; 8], b: [f32; 8]) -> [f32; 8] {
+    let mut ret = [0.0f32; 8];
+    lanes(&a, &b, &mut ret, |x, y| x * y);
+    ret
+  }
+  #[inline(always)]
+  pub fn ge_f32x8(a: [f32; 8], b: [f32; 8]) -> [i32; 8] {
+    let mut ret = [0i32; 8];
+    lanes(&a, &b, &mut ret, |x, y| -((x >= y) as i32));
+    ret
+  }
+  // b when the lanes are equal or either is NaN, like minps
+  #[inline(always)]
+  pub fn min_f32x8(a: [f32; 8], b: [f32; 8]) -> [f32; 8] {
+    let mut ret = [0.0f32; 8];
+    lanes(&a, &b, &mut ret, |x, y| if x < y { x } else { y });
+    ret
+  }
+  pub fn add_u32(dst: &mut [u32], src: &[u32]) {
+    for (d, s) in dst.iter_mut().zip(src.iter()) {
+      *d = d.wrapping_add(*s);
+    }
+  }
+  // for 8 histogram counts: the cost count * log2p with log2p = log2total - FastLog2u16(count as u16),
+  // and the depth min(round(log2p), 15) of the symbol
+  pub fn population_costs(counts: &[u32; 8], log2total: floatX, bits: &mut [floatX; 8], depth: &mut [u8; 8]) {
+    for i in 0..8 {
+      let log2p = log2total - FastLog2u16(counts[i] as u16);
+      bits[i] = counts[i] as floatX * log2p;
+      depth[i] = core::cmp::min((log2p + 0.5 as floatX) as usize, 15) as u8;
+    }
+  }
+  // the length of the common prefix of s1[..limit] and s2[..limit]
+  pub fn find_match_length(s1: &[u8], s2: &[u8], limit: usize) -> usize {
+    for (index, pair) in s1[..limit].iter().zip(s2[..limit].iter()).enumerate() {
+      if *pair.0 != *pair.1 {
+        return index;
+      }
+    }
+    limit
+  }
+  // out[i] = (little endian u32 at data[i..]) * mul >> shift, the 32 bit hash of the lookahead 4
+  // hashers, for up to 8 consecutive positions; data holds out.len() + 3 bytes
+  pub fn hash_words32(data: &[u8], mul: u32, shift: u32, out: &mut [u32]) {
+    for (i, item) in out.iter_mut().enumerate() {
+      let word = u32::from(data[i])
+        | (u32::from(data[i + 1]) << 8)
+        | (u32::from(data[i + 2]) << 16)
+        | (u32::from(data[i + 3]) << 24);
+      *item = word.wrapping_mul(mul) >> shift;
+    }
+  }
+  // cdfs holds 16 rows of 16 u16, one row per nibble and one column per adaptation speed:
+  // adds speeds to the rows of nibble and above
+  pub fn add_cdf_speeds(cdfs: &mut [u16], nibble: u8, speeds: &[u16; 16]) {
+    for row in cdfs[(nibble as usize & 0xf) * 16..256].chunks_mut(16) {
+      for (item, speed) in row.iter_mut().zip(speeds.iter()) {
+        *item = item.wrapping_add(*speed);
+      }
+    }
+  }
+  // rescales the columns whose last row reached their max
+  pub fn rescale_cdf_columns(cdfs: &mut [u16], maxes: &[u16; 16]) {
+    for column in 0..16 {
+      if cdfs[15 * 16 + column] >= maxes[column] {
+        for nibble in 0..16 {
+          let tmp = &mut cdfs[nibble * 16 + column];
+          let biased = tmp.wrapping_add(nibble as u16 + 1);
+          *tmp = biased.wrapping_sub(biased >> 2);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(all(feature="simd", target_arch="x86_64"))]
+mod x86 {
+  use core::arch::x86_64::*;
+  use core::mem::transmute;
+  use super::scalar;
+  use super::super::util::log64k;
+
+  #[cfg(feature="std")]
+  pub fn avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+  }
+  #[cfg(not(feature="std"))]
+  pub fn avx2() -> bool {
+    cfg!(target_feature="avx2")
+  }
+
+  // the 256 bit types as two SSE2 registers
+  macro_rules! sse2_lanes {
+    ($name: ident, $T: ty, $N: expr, $R: ty, $V: ty, $op: expr) => (
+      #[inline(always)]
+      pub fn $name(a: [$T; $N], b: [$T; $N]) -> [$R; $N] {
+        unsafe {
+          let a: [$V; 2] = transmute(a);
+          let b: [$V; 2] = transmute(b);
+          transmute([$op(a[0], b[0]), $op(a[1], b[1])])
+        }
+      }
+    )
+  }
+  #[inline(always)]
+  unsafe fn ge_epi32(a: __m128i, b: __m128i) -> __m128i {
+    _mm_xor_si128(_mm_cmpgt_epi32(b, a), _mm_set1_epi32(-1))
+  }
+  #[inline(always)]
+  unsafe fn ge_ps(a: __m128, b: __m128) -> __m128i {
+    _mm_castps_si128(_mm_cmpge_ps(a, b))
+  }
+  sse2_lanes!(add_i16x16, i16, 16, i16, __m128i, _mm_add_epi16);
+  sse2_lanes!(sub_i16x16, i16, 16, i16, __m128i, _mm_sub_epi16);
+  sse2_lanes!(and_i16x16, i16, 16, i16, __m128i, _mm_and_si128);
+  sse2_lanes!(gt_i16x16, i16, 16, i16, __m128i, _mm_cmpgt_epi16);
+  sse2_lanes!(add_i32x8, i32, 8, i32, __m128i, _mm_add_epi32);
+  sse2_lanes!(and_i32x8, i32, 8, i32, __m128i, _mm_and_si128);
+  sse2_lanes!(gt_i32x8, i32, 8, i32, __m128i, _mm_cmpgt_epi32);
+  sse2_lanes!(ge_i32x8, i32, 8, i32, __m128i, ge_epi32);
+  sse2_lanes!(add_f32x8, f32, 8, f32, __m128, _mm_add_ps);
+  sse2_lanes!(sub_f32x8, f32, 8, f32, __m128, _mm_sub_ps);
+  sse2_lanes!(mul_f32x8, f32, 8, f32, __m128, _mm_mul_ps);
+  sse2_lanes!(ge_f32x8, f32, 8, i32, __m128, ge_ps);
+  // minps returns its second operand for equal or NaN lanes, as the scalar a < b ? a : b does
+  sse2_lanes!(min_f32x8, f32, 8, f32, __m128, _mm_min_ps);
+
+  #[inline(always)]
+  pub fn shr_i16x16(a: [i16; 16], shift: i64) -> [i16; 16] {
+    unsafe {
+      let a: [__m128i; 2] = transmute(a);
+      let count = _mm_cvtsi64_si128(shift);
+      transmute([_mm_sra_epi16(a[0], count), _mm_sra_epi16(a[1], count)])
+    }
+  }
+
+  pub fn add_u32(dst: &mut [u32], src: &[u32]) {
+    let n = core::cmp::min(dst.len(), src.len());
+    let (dst, src) = (&mut dst[..n], &src[..n]);
+    if avx2() {
+      unsafe { add_u32_avx2(dst, src) }
+    } else {
+      add_u32_sse2(dst, src)
+    }
+  }
+  pub fn add_u32_sse2(dst: &mut [u32], src: &[u32]) {
+    let vec_len = dst.len() & !3;
+    for (d, s) in dst[..vec_len].chunks_mut(4).zip(src[..vec_len].chunks(4)) {
+      unsafe {
+        let sum = _mm_add_epi32(_mm_loadu_si128(d.as_ptr() as *const __m128i),
+                                _mm_loadu_si128(s.as_ptr() as *const __m128i));
+        _mm_storeu_si128(d.as_mut_ptr() as *mut __m128i, sum);
+      }
+    }
+    scalar::add_u32(&mut dst[vec_len..], &src[vec_len..]);
+  }
+  #[target_feature(enable="avx2")]
+  pub unsafe fn add_u32_avx2(dst: &mut [u32], src: &[u32]) {
+    let vec_len = dst.len() & !7;
+    for (d, s) in dst[..vec_len].chunks_mut(8).zip(src[..vec_len].chunks(8)) {
+      let sum = _mm256_add_epi32(_mm256_loadu_si256(d.as_ptr() as *const __m256i),
+                                 _mm256_loadu_si256(s.as_ptr() as *const __m256i));
+      _mm256_storeu_si256(d.as_mut_ptr() as *mut __m256i, sum);
+    }
+    scalar::add_u32(&mut dst[vec_len..], &src[vec_len..]);
+  }
+
+  // most candidates differ within their first 16 bytes, which one inlined compare settles
+  #[inline(always)]
+  pub fn find_match_length(s1: &[u8], s2: &[u8], limit: usize) -> usize {
+    let (s1, s2) = (&s1[..limit], &s2[..limit]);
+    if limit < 16 {
+      return scalar::find_match_length(s1, s2, limit);
+    }
+    let equal = unsafe {
+      _mm_movemask_epi8(_mm_cmpeq_epi8(_mm_loadu_si128(s1.as_ptr() as *const __m128i),
+                                       _mm_loadu_si128(s2.as_ptr() as *const __m128i))) as u32
+    };
+    if equal != 0xffff {
+      return (!equal).trailing_zeros() as usize;
+    }
+    16 + find_long_match_length(&s1[16..], &s2[16..])
+  }
+  #[inline(never)]
+  fn find_long_match_length(s1: &[u8], s2: &[u8]) -> usize {
+    if s1.len() >= 32 && avx2() {
+      unsafe { find_match_length_avx2(s1, s2) }
+    } else {
+      find_match_length_sse2(s1, s2)
+    }
+  }
+  pub fn find_match_length_sse2(s1: &[u8], s2: &[u8]) -> usize {
+    let mut matched = 0;
+    while matched + 16 <= s1.len() {
+      let equal = unsafe {
+        _mm_movemask_epi8(_mm_cmpeq_epi8(_mm_loadu_si128(s1[matched..].as_ptr() as *const __m128i),
+                                         _mm_loadu_si128(s2[matched..].as_ptr() as *const __m128i))) as u32
+      };
+      if equal != 0xffff {
+        return matched + (!equal).trailing_zeros() as usize;
+      }
+      matched += 16;
+    }
+    matched + scalar::find_match_length(&s1[matched..], &s2[matched..], s1.len() - matched)
+  }
+  #[target_feature(enable="avx2")]
+  pub unsafe fn find_match_length_avx2(s1: &[u8], s2: &[u8]) -> usize {
+    let mut matched = 0;
+    while matched + 32 <= s1.len() {
+      let equal = _mm256_movemask_epi8(_mm256_cmpeq_epi8(_mm256_loadu_si256(s1[matched..].as_ptr() as *const __m256i),
+                                                         _mm256_loadu_si256(s2[matched..].as_ptr() as *const __m256i))) as u32;
+      if equal != 0xffff_ffff {
+        return matched + (!equal).trailing_zeros() as usize;
+      }
+      matched += 32;
+    }
+    matched + find_match_length_sse2(&s1[matched..], &s2[matched..])
+  }
+
+  #[inline(always)]
+  pub fn hash_words32(data: &[u8], mul: u32, shift: u32, out: &mut [u32]) {
+    if data.len() < 16 || out.len() > 8 {
+      return scalar::hash_words32(data, mul, shift, out);
+    }
+    let mut hashes = [0u32; 8];
+    // four hashes fit one SSE2 multiply, so only wider batches are worth the AVX2 dispatch
+    if out.len() > 4 && avx2() {
+      unsafe { hash_words32_avx2(data, mul, shift, &mut hashes) }
+    } else {
+      hash_words32_sse2(data, mul, shift, &mut hashes)
+    }
+    let n = out.len();
+    out.clone_from_slice(&hashes[..n]);
+  }
+  // the low 32 bits of the lane products, which SSE2 only has for even lanes
+  #[inline(always)]
+  unsafe fn mullo_epi32(a: __m128i, b: __m128i) -> __m128i {
+    let even = _mm_mul_epu32(a, b);
+    let odd = _mm_mul_epu32(_mm_srli_epi64(a, 32), _mm_srli_epi64(b, 32));
+    _mm_unpacklo_epi32(_mm_shuffle_epi32(even, 0b1000), _mm_shuffle_epi32(odd, 0b1000))
+  }
+  // data holds at least 16 bytes
+  #[inline(always)]
+  pub fn hash_words32_sse2(data: &[u8], mul: u32, shift: u32, out: &mut [u32; 8]) {
+    unsafe {
+      let v = _mm_loadu_si128(data.as_ptr() as *const __m128i);
+      // the lanes of v shifted by k bytes hold the words at k, k + 4, ...
+      let words01 = _mm_unpacklo_epi32(v, _mm_srli_si128(v, 1));
+      let words23 = _mm_unpacklo_epi32(_mm_srli_si128(v, 2), _mm_srli_si128(v, 3));
+      let factor = _mm_set1_epi32(mul as i32);
+      let count = _mm_cvtsi32_si128(shift as i32);
+      let lo = _mm_srl_epi32(mullo_epi32(_mm_unpacklo_epi64(words01, words23), factor), count);
+      let hi = _mm_srl_epi32(mullo_epi32(_mm_unpackhi_epi64(words01, words23), factor), count);
+      _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, lo);
+      _mm_storeu_si128(out[4..].as_mut_ptr() as *mut __m128i, hi);
+    }
+  }
+  #[target_feature(enable="avx2")]
+  pub unsafe fn hash_words32_avx2(data: &[u8], mul: u32, shift: u32, out: &mut [u32; 8]) {
+    let v = _mm256_broadcastsi128_si256(_mm_loadu_si128(data.as_ptr() as *const __m128i));
+    let words = _mm256_shuffle_epi8(v, _mm256_setr_epi8(0, 1, 2, 3, 1, 2, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6,
+                                                        4, 5, 6, 7, 5, 6, 7, 8, 6, 7, 8, 9, 7, 8, 9, 10));
+    let hashes = _mm256_srl_epi32(_mm256_mullo_epi32(words, _mm256_set1_epi32(mul as i32)),
+                                  _mm_cvtsi32_si128(shift as i32));
+    _mm256_storeu_si256(out.as_mut_ptr() as *mut __m256i, hashes);
+  }
+
+  #[cfg(not(feature="float64"))]
+  pub fn population_costs(counts: &[u32; 8], log2total: f32, bits: &mut [f32; 8], depth: &mut [u8; 8]) {
+    if avx2() {
+      unsafe { population_costs_avx2(counts, log2total, bits, depth) }
+    } else {
+      scalar::population_costs(counts, log2total, bits, depth)
+    }
+  }
+  #[cfg(not(feature="float64"))]
+  #[target_feature(enable="avx2")]
+  pub unsafe fn population_costs_avx2(counts: &[u32; 8], log2total: f32, bits: &mut [f32; 8], depth: &mut [u8; 8]) {
+    let c = _mm256_loadu_si256(counts.as_ptr() as *const __m256i);
+    let low = _mm256_and_si256(c, _mm256_set1_epi32(0xffff));
+    let log2p = _mm256_sub_ps(_mm256_set1_ps(log2total), _mm256_i32gather_ps(log64k.as_ptr(), low, 4));
+    // both halves convert exactly, so their sum rounds once, like count as f32
+    let count = _mm256_add_ps(_mm256_mul_ps(_mm256_cvtepi32_ps(_mm256_srli_epi32(c, 16)), _mm256_set1_ps(65536.0)),
+                              _mm256_cvtepi32_ps(low));
+    _mm256_storeu_ps(bits.as_mut_ptr(), _mm256_mul_ps(count, log2p));
+    let rounded = _mm256_cvttps_epi32(_mm256_add_ps(log2p, _mm256_set1_ps(0.5)));
+    let clamped = _mm256_min_epi32(_mm256_max_epi32(rounded, _mm256_setzero_si256()), _mm256_set1_epi32(15));
+    let lanes: [i32; 8] = transmute(clamped);
+    for (d, l) in depth.iter_mut().zip(lanes.iter()) {
+      *d = *l as u8;
+    }
+  }
+
+  pub fn add_cdf_speeds(cdfs: &mut [u16], nibble: u8, speeds: &[u16; 16]) {
+    let rows = &mut cdfs[(nibble as usize & 0xf) * 16..256];
+    unsafe {
+      let s0 = _mm_loadu_si128(speeds.as_ptr() as *const __m128i);
+      let s1 = _mm_loadu_si128(speeds[8..].as_ptr() as *const __m128i);
+      for row in rows.chunks_mut(16) {
+        let r0 = row.as_mut_ptr() as *mut __m128i;
+        let r1 = row[8..].as_mut_ptr() as *mut __m128i;
+        _mm_storeu_si128(r0, _mm_add_epi16(_mm_loadu_si128(r0), s0));
+        _mm_storeu_si128(r1, _mm_add_epi16(_mm_loadu_si128(r1), s1));
+      }
+    }
+  }
+  pub fn rescale_cdf_columns(cdfs: &mut [u16], maxes: &[u16; 16]) {
+    let cdfs = &mut cdfs[..256];
+    unsafe {
+      let zero = _mm_setzero_si128();
+      // last >= max exactly when the saturating max - last is zero
+      let mask0 = _mm_cmpeq_epi16(_mm_subs_epu16(_mm_loadu_si128(maxes.as_ptr() as *const __m128i),
+                                                 _mm_loadu_si128(cdfs[240..].as_ptr() as *const __m128i)), zero);
+      let mask1 = _mm_cmpeq_epi16(_mm_subs_epu16(_mm_loadu_si128(maxes[8..].as_ptr() as *const __m128i),
+                                                 _mm_loadu_si128(cdfs[248..].as_ptr() as *const __m128i)), zero);
+      if _mm_movemask_epi8(_mm_or_si128(mask0, mask1)) == 0 {
+        return;
+      }
+      for (nibble, row) in cdfs.chunks_mut(16).enumerate() {
+        let bias = _mm_set1_epi16(nibble as i16 + 1);
+        for &(offset, mask) in [(0usize, mask0), (8usize, mask1)].iter() {
+          let ptr = row[offset..].as_mut_ptr() as *mut __m128i;
+          let old = _mm_loadu_si128(ptr);
+          let biased = _mm_add_epi16(old, bias);
+          let rescaled = _mm_sub_epi16(biased, _mm_srli_epi16(biased, 2));
+          _mm_storeu_si128(ptr, _mm_or_si128(_mm_and_si128(mask, rescaled), _mm_andnot_si128(mask, old)));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(all(feature="simd", target_arch="aarch64"))]
+mod neon {
+  use core::arch::aarch64::*;
+  use core::mem::transmute;
+  use super::scalar;
+
+  // the 256 bit types as two NEON registers
+  macro_rules! neon_lanes {
+    ($name: ident, $T: ty, $N: expr, $R: ty, $V: ty, $op: expr) => (
+      #[inline(always)]
+      pub fn $name(a: [$T; $N], b: [$T; $N]) -> [$R; $N] {
+        unsafe {
+          let a: [$V; 2] = transmute(a);
+          let b: [$V; 2] = transmute(b);
+          transmute([$op(a[0], b[0]), $op(a[1], b[1])])
+        }
+      }
+    )
+  }
+  #[inline(always)]
+  unsafe fn gt_s16(a: int16x8_t, b: int16x8_t) -> int16x8_t {
+    vreinterpretq_s16_u16(vcgtq_s16(a, b))
+  }
+  #[inline(always)]
+  unsafe fn gt_s32(a: int32x4_t, b: int32x4_t) -> int32x4_t {
+    vreinterpretq_s32_u32(vcgtq_s32(a, b))
+  }
+  #[inline(always)]
+  unsafe fn ge_s32(a: int32x4_t, b: int32x4_t) -> int32x4_t {
+    vreinterpretq_s32_u32(vcgeq_s32(a, b))
+  }
+  #[inline(always)]
+  unsafe fn ge_f32(a: float32x4_t, b: float32x4_t) -> int32x4_t {
+    vreinterpretq_s32_u32(vcgeq_f32(a, b))
+  }
+  // vminq_f32 orders -0 below +0 and propagates NaN, so select on a < b like the scalar code
+  #[inline(always)]
+  unsafe fn min_f32(a: float32x4_t, b: float32x4_t) -> float32x4_t {
+    vbslq_f32(vcltq_f32(a, b), a, b)
+  }
+  neon_lanes!(add_i16x16, i16, 16, i16, int16x8_t, vaddq_s16);
+  neon_lanes!(sub_i16x16, i16, 16, i16, int16x8_t, vsubq_s16);
+  neon_lanes!(and_i16x16, i16, 16, i16, int16x8_t, vandq_s16);
+  neon_lanes!(gt_i16x16, i16, 16, i16, int16x8_t, gt_s16);
+  neon_lanes!(add_i32x8, i32, 8, i32, int32x4_t, vaddq_s32);
+  neon_lanes!(and_i32x8, i32, 8, i32, int32x4_t, vandq_s32);
+  neon_lanes!(mul_i32x8, i32, 8, i32, int32x4_t, vmulq_s32);
+  neon_lanes!(gt_i32x8, i32, 8, i32, int32x4_t, gt_s32);
+  neon_lanes!(ge_i32x8, i32, 8, i32, int32x4_t, ge_s32);
+  neon_lanes!(add_f32x8, f32, 8, f32, float32x4_t, vaddq_f32);
+  neon_lanes!(sub_f32x8, f32, 8, f32, float32x4_t, vsubq_f32);
+  neon_lanes!(mul_f32x8, f32, 8, f32, float32x4_t, vmulq_f32);
+  neon_lanes!(ge_f32x8, f32, 8, i32, float32x4_t, ge_f32);
+  neon_lanes!(min_f32x8, f32, 8, f32, float32x4_t, min_f32);
+
+  #[inline(always)]
+  pub fn shr_i16x16(a: [i16; 16], shift: i64) -> [i16; 16] {
+    unsafe {
+      let a: [int16x8_t; 2] = transmute(a);
+      let count = vdupq_n_s16(-(shift as i16));
+      transmute([vshlq_s16(a[0], count), vshlq_s16(a[1], count)])
+    }
+  }
+
+  pub fn add_u32(dst: &mut [u32], src: &[u32]) {
+    let n = core::cmp::min(dst.len(), src.len());
+    let vec_len = n & !3;
+    for (d, s) in dst[..vec_len].chunks_mut(4).zip(src[..vec_len].chunks(4)) {
+      unsafe {
+        vst1q_u32(d.as_mut_ptr(), vaddq_u32(vld1q_u32(d.as_ptr()), vld1q_u32(s.as_ptr())));
+      }
+    }
+    scalar::add_u32(&mut dst[vec_len..n], &src[vec_len..n]);
+  }
+
+  pub fn find_match_length(s1: &[u8], s2: &[u8], limit: usize) -> usize {
+    let (s1, s2) = (&s1[..limit], &s2[..limit]);
+    let mut matched = 0;
+    while matched + 16 <= limit {
+      // narrowing the byte compare gives 4 bits per byte
+      let equal = unsafe {
+        let eq = vceqq_u8(vld1q_u8(s1[matched..].as_ptr()), vld1q_u8(s2[matched..].as_ptr()));
+        vget_lane_u64(vreinterpret_u64_u8(vshrn_n_u16::<4>(vreinterpretq_u16_u8(eq))), 0)
+      };
+      if equal != !0u64 {
+        return matched + ((!equal).trailing_zeros() >> 2) as usize;
+      }
+      matched += 16;
+    }
+    matched + scalar::find_match_length(&s1[matched..], &s2[matched..], limit - matched)
+  }
+
+  pub fn hash_words32(data: &[u8], mul: u32, shift: u32, out: &mut [u32]) {
+    if data.len() < 16 || out.len() > 8 {
+      return scalar::hash_words32(data, mul, shift, out);
+    }
+    const WORDS: [u8; 32] = [0, 1, 2, 3, 1, 2, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6,
+                             4, 5, 6, 7, 5, 6, 7, 8, 6, 7, 8, 9, 7, 8, 9, 10];
+    let mut hashes = [0u32; 8];
+    unsafe {
+      let v = vld1q_u8(data.as_ptr());
+      let factor = vdupq_n_u32(mul);
+      let count = vdupq_n_s32(-(shift as i32));
+      for half in 0..2 {
+        let words = vreinterpretq_u32_u8(vqtbl1q_u8(v, vld1q_u8(WORDS[half * 16..].as_ptr())));
+        vst1q_u32(hashes[half * 4..].as_mut_ptr(), vshlq_u32(vmulq_u32(words, factor), count));
+      }
+    }
+    let n = out.len();
+    out.clone_from_slice(&hashes[..n]);
+  }
+
+  pub fn add_cdf_speeds(cdfs: &mut [u16], nibble: u8, speeds: &[u16; 16]) {
+    let rows = &mut cdfs[(nibble as usize & 0xf) * 16..256];
+    unsafe {
+      let s0 = vld1q_u16(speeds.as_ptr());
+      let s1 = vld1q_u16(speeds[8..].as_ptr());
+      for row in rows.chunks_mut(16) {
+        let r0 = row.as_mut_ptr();
+        let r1 = row[8..].as_mut_ptr();
+        vst1q_u16(r0, vaddq_u16(vld1q_u16(r0), s0));
+        vst1q_u16(r1, vaddq_u16(vld1q_u16(r1), s1));
+      }
+    }
+  }
+  pub fn rescale_cdf_columns(cdfs: &mut [u16], maxes: &[u16; 16]) {
+    let cdfs = &mut cdfs[..256];
+    unsafe {
+      let mask0 = vcgeq_u16(vld1q_u16(cdfs[240..].as_ptr()), vld1q_u16(maxes.as_ptr()));
+      let mask1 = vcgeq_u16(vld1q_u16(cdfs[248..].as_ptr()), vld1q_u16(maxes[8..].as_ptr()));
+      if vmaxvq_u16(vorrq_u16(mask0, mask1)) == 0 {
+        return;
+      }
+      for (nibble, row) in cdfs.chunks_mut(16).enumerate() {
+        let bias = vdupq_n_u16(nibble as u16 + 1);
+        for &(offset, mask) in [(0usize, mask0), (8usize, mask1)].iter() {
+          let ptr = row[offset..].as_mut_ptr();
+          let old = vld1q_u16(ptr);
+          let biased = vaddq_u16(old, bias);
+          let rescaled = vsubq_u16(biased, vshrq_n_u16::<2>(biased));
+          vst1q_u16(ptr, vbslq_u16(mask, rescaled, old));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::scalar;
+  use super::super::util::floatX;
+
+  // deterministic pseudo random lanes that hit the wrapping, sign and equality corner cases
+  struct Lcg(u64);
+  impl Lcg {
+    fn next(&mut self) -> u32 {
+      self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      let r = (self.0 >> 33) as u32;
+      match r & 7 {
+        0 => 0,
+        1 => 0xffff_ffff,
+        2 => 0x8000 | (r & 3),
+        _ => r,
+      }
+    }
+    fn i16s(&mut self) -> [i16; 16] {
+      let mut ret = [0i16; 16];
+      for item in ret.iter_mut() {
+        *item = self.next() as i16;
+      }
+      ret
+    }
+    fn i32s(&mut self) -> [i32; 8] {
+      let mut ret = [0i32; 8];
+      for item in ret.iter_mut() {
+        *item = self.next() as i32;
+      }
+      ret
+    }
+    fn f32s(&mut self) -> [f32; 8] {
+      let mut ret = [0.0f32; 8];
+      for item in ret.iter_mut() {
+        *item = match self.next() & 15 {
+          0 => 0.0,
+          1 => -0.0,
+          2 => core::f32::NAN,
+          3 => 1.5,
+          r => (self.next() as i32) as f32 / (r as f32),
+        };
+      }
+      ret
+    }
+  }
+
+  fn same_f32(a: [f32; 8], b: [f32; 8]) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| x.to_bits() == y.to_bits())
+  }
+
+  #[test]
+  fn vector_lanes_match_scalar() {
+    let mut rng = Lcg(1);
+    for _ in 0..4096 {
+      let (a, b) = (rng.i16s(), rng.i16s());
+      assert_eq!(super::add_i16x16(a, b), scalar::add_i16x16(a, b));
+      assert_eq!(super::sub_i16x16(a, b), scalar::sub_i16x16(a, b));
+      assert_eq!(super::and_i16x16(a, b), scalar::and_i16x16(a, b));
+      assert_eq!(super::gt_i16x16(a, b), scalar::gt_i16x16(a, b));
+      for shift in 0..16 {
+        assert_eq!(super::shr_i16x16(a, shift), scalar::shr_i16x16(a, shift));
+      }
+      let (a, b) = (rng.i32s(), rng.i32s());
+      assert_eq!(super::add_i32x8(a, b), scalar::add_i32x8(a, b));
+      assert_eq!(super::and_i32x8(a, b), scalar::and_i32x8(a, b));
+      assert_eq!(super::mul_i32x8(a, b), scalar::mul_i32x8(a, b));
+      assert_eq!(super::gt_i32x8(a, b), scalar::gt_i32x8(a, b));
+      assert_eq!(super::ge_i32x8(a, b), scalar::ge_i32x8(a, b));
+      let (a, b) = (rng.f32s(), rng.f32s());
+      assert!(same_f32(super::add_f32x8(a, b), scalar::add_f32x8(a, b)));
+      assert!(same_f32(super::sub_f32x8(a, b), scalar::sub_f32x8(a, b)));
+      assert!(same_f32(super::mul_f32x8(a, b), scalar::mul_f32x8(a, b)));
+      assert!(same_f32(super::min_f32x8(a, b), scalar::min_f32x8(a, b)));
+      assert_eq!(super::ge_f32x8(a, b), scalar::ge_f32x8(a, b));
+    }
+  }
+
+  #[test]
+  fn loop_kernels_match_scalar() {
+    let mut rng = Lcg(2);
+    let (mut src, mut dst) = ([0u32; 40], [0u32; 43]);
+    for len in 0..40 {
+      for item in src.iter_mut().chain(dst.iter_mut()) {
+        *item = rng.next();
+      }
+      let mut expected = dst;
+      scalar::add_u32(&mut expected[..len + 3], &src[..len]);
+      super::add_u32(&mut dst[..len + 3], &src[..len]);
+      assert_eq!(&dst[..], &expected[..]);
+    }
+    for total in [1u32, 7, 255, 256, 65535, 65536, 1 << 20, 0x7fff_ffff].iter() {
+      let log2total = super::super::util::FastLog2(u64::from(*total));
+      for _ in 0..256 {
+        let mut counts = [0u32; 8];
+        for item in counts.iter_mut() {
+          *item = rng.next() % (total + 1);
+        }
+        let (mut bits, mut depth) = ([0.0 as floatX; 8], [0u8; 8]);
+        let (mut expected_bits, mut expected_depth) = ([0.0 as floatX; 8], [0u8; 8]);
+        super::population_costs(&counts, log2total, &mut bits, &mut depth);
+        scalar::population_costs(&counts, log2total, &mut expected_bits, &mut expected_depth);
+        assert_eq!(depth, expected_depth);
+        for (x, y) in bits.iter().zip(expected_bits.iter()) {
+          assert_eq!(x.to_bits(), y.to_bits());
+        }
+      }
+    }
+    let mut speeds = [0u16; 16];
+    let mut maxes = [0u16; 16];
+    for i in 0..16 {
+      speeds[i] = (rng.next() & 0x3ff) as u16;
+      maxes[i] = (rng.next() & 0x7fff) as u16 | 16;
+    }
+    let mut cdfs = [0u16; 256];
+    for (i, item) in cdfs.iter_mut().enumerate() {
+      *item = ((i >> 4) as u16 + 1) * 4;
+    }
+    let mut expected = cdfs;
+    for _ in 0..2048 {
+      let nibble = (rng.next() & 0xf) as u8;
+      super::add_cdf_speeds(&mut cdfs, nibble, &speeds);
+      scalar::add_cdf_speeds(&mut expected, nibble, &speeds);
+      assert_eq!(&cdfs[..], &expected[..]);
+      super::rescale_cdf_columns(&mut cdfs, &maxes);
+      scalar::rescale_cdf_columns(&mut expected, &maxes);
+      assert_eq!(&cdfs[..], &expected[..]);
+    }
+  }
+
+  #[test]
+  fn match_kernels_match_scalar() {
+    let mut rng = Lcg(4);
+    let mut a = [0u8; 300];
+    for item in a.iter_mut() {
+      *item = rng.next() as u8;
+    }
+    for mismatch in 0..a.len() + 1 {
+      let mut b = a;
+      if mismatch < b.len() {
+        b[mismatch] ^= 1 << (mismatch & 7);
+      }
+      for &limit in [0usize, 1, 15, 16, 17, 31, 32, 33, 64, 100, 299, 300].iter() {
+        let expected = scalar::find_match_length(&a, &b, limit);
+        assert_eq!(super::find_match_length(&a, &b, limit), expected);
+        assert_eq!(expected, core::cmp::min(mismatch, limit));
+      }
+    }
+    for start in 0..64 {
+      for &n in [1usize, 4, 8].iter() {
+        let shift = (rng.next() % 32) as u32;
+        let mul = if start & 1 == 0 { 0x1e35a7bd } else { rng.next() };
+        let (mut hashes, mut expected) = ([0u32; 8], [0u32; 8]);
+        super::hash_words32(&a[start..], mul, shift, &mut hashes[..n]);
+        scalar::hash_words32(&a[start..], mul, shift, &mut expected[..n]);
+        assert_eq!(hashes, expected);
+        // too short for a vector load
+        super::hash_words32(&a[start..start + n + 3], mul, shift, &mut hashes[..n]);
+        assert_eq!(hashes, expected);
+      }
+    }
+  }
+
+  #[cfg(all(feature="simd", target_arch="x86_64"))]
+  #[test]
+  fn sse2_and_avx2_loops_agree() {
+    let mut rng = Lcg(3);
+    if !super::x86::avx2() {
+      return;
+    }
+    let (mut src, mut dst) = ([0u32; 40], [0u32; 40]);
+    for len in 0..40 {
+      for item in src.iter_mut().chain(dst.iter_mut()) {
+        *item = rng.next();
+      }
+      let mut expected = dst;
+      super::x86::add_u32_sse2(&mut expected[..len], &src[..len]);
+      unsafe { super::x86::add_u32_avx2(&mut dst[..len], &src[..len]) };
+      assert_eq!(&dst[..], &expected[..]);
+    }
+    let mut a = [0u8; 100];
+    for item in a.iter_mut() {
+      *item = rng.next() as u8 & 3;
+    }
+    for start in 0..84 {
+      let b = &a[start..];
+      for len in 0..b.len() + 1 {
+        let expected = super::x86::find_match_length_sse2(&a[..len], &b[..len]);
+        assert_eq!(unsafe { super::x86::find_match_length_avx2(&a[..len], &b[..len]) }, expected);
+      }
+      let (mut hashes, mut expected) = ([0u32; 8], [0u32; 8]);
+      super::x86::hash_words32_sse2(b, 0x1e35a7bd, 18, &mut expected);
+      unsafe { super::x86::hash_words32_avx2(b, 0x1e35a7bd, 18, &mut hashes) };
+      assert_eq!(hashes, expected);
+    }
+  }
+}
@@ -10,6 +10,7 @@ use ::alloc;
 use alloc::{SliceWrapper, SliceWrapperMut, Allocator};
 use enc::util::{Log2FloorNonZero, brotli_max_size_t,FastLog2, FastLog2f64, floatX};
 use super::hash_to_binary_tree::{InitBackwardMatch, BackwardMatch, BackwardMatchMut, StoreAndFindMatchesH10, Allocable, H10Params, H10, ZopfliNode, Union1, kInfinity};
+use super::ldm::LdmHasher;
 use core;
 use enc::encode;
 
@@ -1168,6 +1169,28 @@ fn ComputeShortestPathFromNodes(
 }
 
 const MAX_NUM_MATCHES_H10:usize = 128;
+
+// adds the long distance match covering pos after the matches found there when it is longer than
+// all of them, keeping the matches sorted by length
+fn AppendLdmMatch<AllocU32:Allocator<u32>>(
+    ldm : &mut LdmHasher<AllocU32>,
+    ringbuffer : & [u8],
+    ringbuffer_mask : usize,
+    pos : usize,
+    max_length : usize,
+    matches : &mut [u64],
+    num_matches : usize,
+) -> usize {
+    if let Some((len, distance)) = ldm.find_match(ringbuffer, ringbuffer_mask, pos) {
+        let len = brotli_min_size_t(len, max_length);
+        if num_matches < matches.len() &&
+           (num_matches == 0 || BackwardMatchLength(&BackwardMatch(matches[num_matches - 1])) < len) {
+            InitBackwardMatch(&mut BackwardMatchMut(&mut matches[num_matches]), distance, len);
+            return num_matches + 1;
+        }
+    }
+    num_matches
+}
 pub fn BrotliZopfliComputeShortestPath<AllocU32:Allocator<u32>,
                                        Buckets: Allocable<u32, AllocU32>+SliceWrapperMut<u32>+SliceWrapper<u32>,
                                        Params:H10Params,
@@ -1182,6 +1205,7 @@ pub fn BrotliZopfliComputeShortestPath<AllocU32:Allocator<u32>,
     max_backward_limit : usize,
     dist_cache : & [i32],
     handle : &mut H10<AllocU32, Buckets, Params>,
+    ldm : &mut LdmHasher<AllocU32>,
     nodes : &mut [ZopfliNode
 ]) -> usize where Buckets:PartialEq<Buckets> {
     let max_zopfli_len : usize = MaxZopfliLen(params);
@@ -1245,6 +1269,8 @@ pub fn BrotliZopfliComputeShortestPath<AllocU32:Allocator<u32>,
                                 lz_matches_offset as (usize)
                             ).. ]
                   );
+            num_matches = AppendLdmMatch(ldm, ringbuffer, ringbuffer_mask, pos, num_bytes.wrapping_sub(i),
+                                         &mut matches[lz_matches_offset..], num_matches);
             if num_matches > 0usize && (BackwardMatchLength(
                                                      &BackwardMatch(matches[(
                                                                num_matches.wrapping_sub(
@@ -1341,6 +1367,7 @@ pub fn BrotliCreateZopfliBackwardReferences<Alloc:Allocator<u32> + Allocator<flo
     ringbuffer_mask : usize,
     params : &BrotliEncoderParams,
     hasher : &mut H10<Alloc, Buckets, Params>,
+    ldm : &mut LdmHasher<Alloc>,
     dist_cache : &mut [i32],
     last_insert_len : &mut usize,
     commands : &mut [Command],
@@ -1378,6 +1405,7 @@ pub fn BrotliCreateZopfliBackwardReferences<Alloc:Allocator<u32> + Allocator<flo
                             max_backward_limit,
                             dist_cache ,
                             hasher,
+                            ldm,
                             nodes.slice_mut()
                         )
                     );
@@ -1691,6 +1719,7 @@ pub fn BrotliCreateHqZopfliBackwardReferences<Alloc:Allocator<u32> + Allocator<u
     ringbuffer_mask : usize,
     params : &BrotliEncoderParams,
     hasher : &mut H10<Alloc, Buckets, Params>,
+    ldm : &mut LdmHasher<Alloc>,
     dist_cache : &mut [i32],
     last_insert_len : &mut usize,
     commands : &mut [Command],
@@ -1750,7 +1779,7 @@ pub fn BrotliCreateHqZopfliBackwardReferences<Alloc:Allocator<u32> + Allocator<u
                 : usize
                 = brotli_min_size_t(pos,max_backward_limit);
             let max_length : usize = num_bytes.wrapping_sub(i);
-            let num_found_matches : usize;
+            let mut num_found_matches : usize;
             let cur_match_end : usize;
             let mut j : usize;
             {
@@ -1813,6 +1842,15 @@ pub fn BrotliCreateHqZopfliBackwardReferences<Alloc:Allocator<u32> + Allocator<u
                                               cur_match_pos.wrapping_add(shadow_matches) as (usize)
                                           )..]
                                 );
+            num_found_matches = AppendLdmMatch(
+                                    ldm,
+                                    ringbuffer,
+                                    ringbuffer_mask,
+                                    pos,
+                                    max_length,
+                                    &mut matches.slice_mut()[cur_match_pos.wrapping_add(shadow_matches)..
+                                                             cur_match_pos.wrapping_add(shadow_matches).wrapping_add(MAX_NUM_MATCHES_H10)],
+                                    num_found_matches);
             cur_match_end = cur_match_pos.wrapping_add(num_found_matches);
             j = cur_match_pos;
             while j.wrapping_add(1usize) < cur_match_end {
@@ -0,0 +1,257 @@
+// Long distance matching for large windows. The regular hashers keep a few candidates per bucket,
+// so a repeat that is megabytes back (a file stored twice in a tarball, a disk image with duplicate
+// blocks) has been evicted long before it comes round again. This matcher samples about one
+// position in 64 instead: a rolling gear hash of the LDM_MIN_MATCH bytes before each position picks
+// the positions by content alone, so both copies of a repeat are sampled at the same places, and
+// a table sized to the window remembers them. The backward reference search scans each block ahead
+// of its parse and is offered the verified matches, at least LDM_MIN_MATCH bytes long, in order.
+use alloc;
+use alloc::{SliceWrapper, SliceWrapperMut};
+use enc::static_dict::FindMatchLengthWithLimit;
+use core;
+
+// the number of bytes the rolling hash covers and the shortest match offered
+pub const LDM_MIN_MATCH: usize = 64;
+// one position in 1 << LDM_HASH_RATE_BITS is sampled
+const LDM_HASH_RATE_BITS: u32 = 6;
+const LDM_BUCKET_SIZE: usize = 4;
+const LDM_MIN_BUCKET_BITS: u32 = 8;
+const LDM_MAX_BUCKET_BITS: u32 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LdmMatch {
+  start: usize,
+  len: usize,
+  distance: usize,
+}
+
+pub struct LdmHasher<AllocU32: alloc::Allocator<u32>> {
+  // 1 << bucket_bits buckets of LDM_BUCKET_SIZE (position, check) pairs, newest first;
+  // no position below LDM_MIN_MATCH is sampled, so position 0 marks an empty slot
+  pub table: AllocU32::AllocatedMemory,
+  pub bucket_bits: u32,
+  // the scan of the current block
+  hash: u64,
+  scan_pos: usize,
+  scan_end: usize,
+  block_start: usize,
+  // the first position with LDM_MIN_MATCH bytes hashed before it
+  valid_from: usize,
+  // positions sampled inside the last match found are stored but not searched
+  match_end: usize,
+  max_distance: usize,
+  pending: Option<LdmMatch>,
+}
+
+impl<AllocU32: alloc::Allocator<u32>> Default for LdmHasher<AllocU32> {
+  fn default() -> Self {
+    LdmHasher {
+      table: AllocU32::AllocatedMemory::default(),
+      bucket_bits: 0,
+      hash: 0,
+      scan_pos: 0,
+      scan_end: 0,
+      block_start: 0,
+      valid_from: 0,
+      match_end: 0,
+      max_distance: 0,
+      pending: None,
+    }
+  }
+}
+
+impl<AllocU32: alloc::Allocator<u32>> LdmHasher<AllocU32> {
+  // a table with about as many slots as a window of 1 << lgwin bytes has sampled positions
+  pub fn new(m: &mut AllocU32, lgwin: i32) -> Self {
+    let bucket_bits = (lgwin as u32).saturating_sub(8).clamp(LDM_MIN_BUCKET_BITS, LDM_MAX_BUCKET_BITS);
+    let mut table = m.alloc_cell((LDM_BUCKET_SIZE * 2) << bucket_bits);
+    for item in table.slice_mut().iter_mut() {
+      *item = 0;
+    }
+    LdmHasher {
+      table: table,
+      bucket_bits: bucket_bits,
+      ..LdmHasher::default()
+    }
+  }
+  pub fn is_enabled(&self) -> bool {
+    !self.table.slice().is_empty()
+  }
+  pub fn free(&mut self, m: &mut AllocU32) {
+    m.free_cell(core::mem::replace(&mut self.table, AllocU32::AllocatedMemory::default()));
+    *self = LdmHasher::default();
+  }
+  // starts the scan of data[position..position + num_bytes], whose matches may reach max_distance back
+  pub fn begin(&mut self, data: &[u8], mask: usize, position: usize, num_bytes: usize, max_distance: usize) {
+    self.pending = None;
+    self.block_start = position;
+    self.match_end = position;
+    self.scan_end = position + num_bytes;
+    self.max_distance = max_distance;
+    if !self.is_enabled() {
+      self.scan_pos = self.scan_end;
+      return;
+    }
+    // the hash only depends on the last 64 bytes, so the bytes just before the block prime it
+    let warm_start = position - core::cmp::min(position, LDM_MIN_MATCH);
+    self.hash = 0;
+    for index in warm_start..position {
+      self.hash = (self.hash << 1).wrapping_add(kLdmGearTable[data[index & mask] as usize]);
+    }
+    self.valid_from = warm_start + LDM_MIN_MATCH;
+    self.scan_pos = position;
+  }
+  // remembers the sampled positions of data[start..end] without searching them, e.g. a custom dictionary
+  pub fn store_range(&mut self, data: &[u8], mask: usize, start: usize, end: usize) {
+    self.begin(data, mask, start, end - start, 0);
+    let found = self.scan(data, mask, false);
+    debug_assert!(found.is_none());
+  }
+  // the (length, distance) of a long distance match that covers position, trimmed to start there;
+  // position must not decrease between calls for the same block
+  pub fn find_match(&mut self, data: &[u8], mask: usize, position: usize) -> Option<(usize, usize)> {
+    loop {
+      if let Some(m) = self.pending {
+        if m.start + m.len >= position + LDM_MIN_MATCH {
+          if m.start > position {
+            return None;
+          }
+          return Some((m.start + m.len - position, m.distance));
+        }
+        self.pending = None;
+      }
+      if self.scan_pos >= self.scan_end {
+        return None;
+      }
+      self.pending = self.scan(data, mask, true);
+    }
+  }
+  fn scan(&mut self, data: &[u8], mask: usize, search: bool) -> Option<LdmMatch> {
+    let bucket_mask = (1usize << self.bucket_bits) - 1;
+    let bucket_shift = 64 - LDM_HASH_RATE_BITS - self.bucket_bits;
+    while self.scan_pos < self.scan_end {
+      self.hash = (self.hash << 1).wrapping_add(kLdmGearTable[data[self.scan_pos & mask] as usize]);
+      self.scan_pos += 1;
+      if (self.hash >> (64 - LDM_HASH_RATE_BITS)) != 0 || self.scan_pos < self.valid_from {
+        continue;
+      }
+      let pos = self.scan_pos;
+      let check = self.hash as u32;
+      let bucket = ((self.hash >> bucket_shift) as usize & bucket_mask) * LDM_BUCKET_SIZE * 2;
+      let found = if search && pos >= self.match_end {
+        self.longest_match(data, mask, pos, check, bucket)
+      } else {
+        None
+      };
+      let slots = &mut self.table.slice_mut()[bucket..bucket + LDM_BUCKET_SIZE * 2];
+      for index in (2..LDM_BUCKET_SIZE * 2).rev() {
+        slots[index] = slots[index - 2];
+      }
+      slots[0] = pos as u32;
+      slots[1] = check;
+      if let Some(m) = found {
+        self.match_end = m.start + m.len;
+        return found;
+      }
+    }
+    None
+  }
+  // extends each candidate stored for pos forwards to the end of the block and backwards to the
+  // block start or the end of the previous match
+  fn longest_match(&self, data: &[u8], mask: usize, pos: usize, check: u32, bucket: usize) -> Option<LdmMatch> {
+    let lowest = core::cmp::max(self.block_start, self.match_end);
+    let cur_ix_masked = pos & mask;
+    let mut best: Option<LdmMatch> = None;
+    for slot in self.table.slice()[bucket..bucket + LDM_BUCKET_SIZE * 2].chunks(2) {
+      let candidate = slot[0] as usize;
+      if candidate == 0 || slot[1] != check || candidate >= pos || pos - candidate > self.max_distance {
+        continue;
+      }
+      let forward = FindMatchLengthWithLimit(&data[(candidate & mask)..],
+                                             &data[cur_ix_masked..],
+                                             self.scan_end - pos);
+      let mut backward = 0usize;
+      while backward < pos - lowest && backward < candidate &&
+            data[(pos - backward - 1) & mask] == data[(candidate - backward - 1) & mask] {
+        backward += 1;
+      }
+      let len = backward + forward;
+      // the newest candidate wins ties, being the closest
+      if len > best.map_or(LDM_MIN_MATCH - 1, |b| b.len) {
+        best = Some(LdmMatch {
+          start: pos - backward,
+          len: len,
+          distance: pos - candidate,
+        });
+      }
+    }
+    best
+  }
+}
+
+static kLdmGearTable: [u64; 256] = [
+  0x2443073b167046d0, 0x0d857c2999b7ef66, 0xfe9c4d340ae4b003, 0xe53a6d04f358d352,
+  0x2f9348edcee55956, 0x34f6aff460ce1c1a, 0x556c31968b1528c2, 0x84076a8280431787,
+  0xd4cb246b95eb1705, 0x7e3e807b06767186, 0xb8b94d4f19279713, 0x2f8f6a0bff40a338,
+  0x13940c6ffebc7520, 0x22be5383bc5df9ca, 0x1c9dd489835436c2, 0x1ca4ed3e9419b377,
+  0x01514443eb781ee4, 0xe72be82478888d4a, 0x456a0be39abc837f, 0x28828beb28e6e656,
+  0x6f4b94791c9d2221, 0x576d7c1ffff8e222, 0x7c7bdd8c14b99ee8, 0x73117e3993d84598,
+  0xdc536218ecee2096, 0xfbfef053cf249d4d, 0xac30c08aecc6b012, 0xf13bf73fdf1caf4a,
+  0x694ee0f42b35d2ca, 0x6ff4c753326b2660, 0xd247d7649f7be759, 0xc20401d971cb3c70,
+  0xd5febb0e8c6754ed, 0x21e9db6a5266310e, 0xf69133b9326cabc4, 0x2831b6b76ebeec48,
+  0x2910b897c7dab635, 0xeae1fafdb44bfbb8, 0x07fa3e44a92b1273, 0xf55f3a62acb799ae,
+  0xfe830b847eef017b, 0xa7a52a0a8e5f508a, 0x9dbce1afb4193746, 0xb41f59479a84c01a,
+  0x36ec48c20c10a426, 0xce50345c7413685c, 0x1ea11a10405e6c4d, 0x6412d5b4bd867922,
+  0xa61dfc2ee79d6851, 0x7a9bc51f0de8f0ca, 0x5ae924795716272e, 0x1947611373f6e283,
+  0x6b868cb806875e8c, 0xc49ba310ec6454cc, 0x7133727bfc5c790a, 0xc38f8a7fb2c7b52a,
+  0x87cdbd8eec0bb702, 0xda6fa01f534ccb9f, 0x0f1c97cc1dbd6353, 0xeee4be9896d682eb,
+  0x1f145bdfbce5aeb7, 0x382ceffcf1494d56, 0xfeae908787b8732c, 0x7d4b39e76fedad22,
+  0x7f33ba13fe11cf3a, 0x6b7b796bf561e295, 0x2dbe44b78e69fd12, 0xe7cce662b6d870ec,
+  0x9537d82021aa3b0f, 0xba7f01ea31737ab3, 0x1c3695b5f9c39ca6, 0x0627bfa25d80a979,
+  0x95adb97845b0898a, 0xd20482d56937ef52, 0x6646fa1742476172, 0xf6a2dedab784c5a9,
+  0x6d3d275e3a012153, 0xaad61c364b3fdde1, 0x59dd35a41a47d091, 0x30b79ea337c2f831,
+  0x02f2e196e1ade84a, 0xd6225c455ba08f20, 0xae8e7837bd22eac6, 0xc5dfa64e7a55b63d,
+  0xb7fc80ca034ebfa3, 0x5fc8e5dbe57460d2, 0x3276c564131ea796, 0xe589099dabad6283,
+  0x4530fe8d53422737, 0xd1a81f8d398d0143, 0xdd418d432ea48285, 0x62f588bc74ba10e5,
+  0x69773917fc37b499, 0xd47b72f8e60bb1a2, 0x39680ab28269bde8, 0x7ff72a9cbe9cbaf7,
+  0xb056df26c927d01f, 0xda1d0f94b36177da, 0x59239ab90db54821, 0x093a00145f74939a,
+  0x2a40f0ef33b6d684, 0xfe7610f70c7a1ff1, 0x69e21368115cea4a, 0x6841f83c48fea8f7,
+  0x1cff2dfa09360053, 0xf7f914ca6f088185, 0x80d799ae7021e5b9, 0xa5018db11b56e21e,
+  0x1c3486a58256a0a7, 0x46dba79b0b0eb56a, 0x1a2021f7f35aed82, 0x51037add0818835c,
+  0xf2b86ade152ba0e1, 0xe4cc57af2a8d4467, 0x895d4e1b259026df, 0x13008692d31f5ffe,
+  0xacdaa1ba88c74606, 0x4bf7f835ff7f295b, 0xa68c98dad114987d, 0xf4c65e3cf0779ac5,
+  0xe8d3ba2be25c976d, 0x2275c1a9722b67d0, 0x47dfd4cb3a76d9ce, 0x257205cd17e5db4c,
+  0xb1885e85f7f91465, 0xcd7c98d4d619b903, 0x0a8db7e1cbdae53d, 0x0758b32a0e3523e8,
+  0xadafece4cc52c518, 0x6b573b338a224d59, 0x9e8e5da2b26ee418, 0x22947e72bac0683e,
+  0x9e7dfa7c77046362, 0x012fb4d1bacc4930, 0x5dca47fee57e3e8a, 0x9fd779b71cf78bbb,
+  0xccf97d3b64b1b576, 0xcc8fe9245c01ff59, 0x8ab42240ad6d3c8f, 0xc2816f8a8dcee8ef,
+  0xb97990ae4b38c48d, 0xbae3bbf134d6b678, 0x2a31ef6a74aa1c3b, 0x38bfe70c8b2f35a4,
+  0x3986ace6e6795dc7, 0xe836dca25a9b96f2, 0x29b5e972e86429f6, 0x4051859a96927e5d,
+  0xfcb3fcac04977858, 0x299167b887fb13ff, 0xd73d3e05d3ded197, 0xbed8546b3f17449e,
+  0x97b6bd5e6b69215b, 0x1b84d7edda5c01ef, 0xd4893acbaafc8892, 0x1f312638d0124689,
+  0x16eeda852641175c, 0x6dc806293c08d1c0, 0x34b929370d2ab745, 0x34386286dc571b64,
+  0x13ab7eb138cb9e3c, 0xbcd9d0cb7606818f, 0x98ab4da556a5e3d3, 0x58d90db3f153714d,
+  0xeb695cd7bbd79d50, 0x7e597b4105437352, 0x6c348d9ad56a8cb9, 0x8770b63135adc883,
+  0xbdd452286dc16c32, 0xe1b679fa302e3181, 0xf4a728a756b6349a, 0x344d6e58ff07add3,
+  0x7e2c0395bfe7ddc2, 0xb329b4f63983200c, 0x9980d80eda68cb14, 0xc2643c70179be895,
+  0x6dad9d421e459c2e, 0xe74ab46f6b5dc4bc, 0x93fe75910db87b71, 0x6b8bcb780f448dab,
+  0xb7d92482ba550404, 0xeaccdef793e2d361, 0x51e0466e77384d9c, 0x7912da9283461eed,
+  0x13dbaf7611a73cf3, 0x4f8b208e5b2e0280, 0x5afde0751521a195, 0x5c5fe271ddd61a44,
+  0x55337607220a02e9, 0xb28546f35431c357, 0x2af0b1621512a88b, 0x64c9ad073aa2c7d0,
+  0x5a423ca6ac660deb, 0x5249eaf8581ea249, 0xd6d6ae706d56b1be, 0xe75e3fada3d5c496,
+  0xca5070ef9f519e83, 0x3a98097c106f2e43, 0xb0cb3f2c637fc351, 0x19bb1dbcabb20f59,
+  0x0c6058c44de63acb, 0x9edf555aa6262434, 0x5aa9a5b3d8ff00fe, 0xed007b4719c2008d,
+  0x61aa6f7faa297fca, 0x339a5dd4e591894f, 0xd76cfe7c87c7422f, 0xba2a0fc29070fbfa,
+  0x782a86d9919d638a, 0x02f399f62dc9283a, 0xddc1868ea26ca9d5, 0x47efc64139282172,
+  0x48202e2a4e48e133, 0x410396983a231e62, 0x0c497ec837214b49, 0x35dbbd419551fa8e,
+  0x603c035cc3583cf0, 0x425ba1b5266ac2e9, 0x0349ce5e99245ee8, 0xd241c8b70a7b5375,
+  0xa40391c07f13f0f9, 0x1ab8588b75600f43, 0x17b4fefc1a931207, 0x1ec0effbe8f3e8fd,
+  0xd78c563a77f7a603, 0x1643b64317272e13, 0x51dffcf3df25bdab, 0x6959b89553ebcccb,
+  0x08aa74ea7d173d03, 0x5dae72700d8c3c8d, 0x0f2c7be00f06f26e, 0x3d125531b2885d77,
+  0x1337aaf50cc5535e, 0x27eb5cdd240d07b4, 0x8e1f790de446626d, 0xfdc636738e6e2668,
+  0xe3f1643330d8106d, 0x51a51d93eff1711c, 0x242303a40279ce8b, 0x2ccfa2dbfb3eaa79,
+  0xb66c6ce20c53074b, 0x1ebd19e1015ce69a, 0x6ba1d16311c45c8c, 0x7c7d42971a33af82,
+  0x848661b50e09fc62, 0x61447ea82c1189c0, 0xa37676597d4b64f8, 0xe3cfcf5e33acff9e,
+  0x8a886f5c396b0613, 0x7ddb35354d40019e, 0xbb4462f0a85e2ea6, 0x685f5aa8e5070091,
+  0xe0fb72f2b8ff93dc, 0x1bcd2f32fbcd4042, 0x5d6ee1d76c198613, 0x6d0e2b466086774c,
+];
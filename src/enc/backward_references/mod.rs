@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 pub mod hq;
 pub mod hash_to_binary_tree;
+pub mod profile;
+pub mod ldm;
 mod test;
 mod benchmark;
 use super::command::{Command, ComputeDistanceCode, InitCommand, BrotliDistanceParams};
@@ -12,6 +14,9 @@ use super::super::alloc;
 use super::super::alloc::{SliceWrapper, SliceWrapperMut, Allocator};
 use super::util::{Log2FloorNonZero, brotli_max_size_t, floatX};
 use core;
+pub use self::profile::{BrotliEncoderParseProfile, BrotliEncoderPrintProfile, ParamsProfile, ProfileError,
+                        ProfileErrorKind, PROFILE_VERSION};
+pub use self::ldm::{LdmHasher, LDM_MIN_MATCH};
 static kBrotliMinWindowBits: i32 = 10i32;
 
 static kBrotliMaxWindowBits: i32 = 24i32;
@@ -41,6 +46,12 @@ pub enum BrotliEncoderMode {
   BROTLI_FORCE_SIGNED_PRIOR = 6,
 }
 
+impl Default for BrotliEncoderMode {
+  fn default() -> BrotliEncoderMode {
+    BrotliEncoderMode::BROTLI_MODE_GENERIC
+  }
+}
+
 #[derive(Clone,Copy, Debug, PartialEq)]
 pub struct BrotliHasherParams {
   // type of hasher to use (default: type 6, but others have tradeoffs of speed/memory)
@@ -102,6 +113,26 @@ pub struct BrotliEncoderParams {
   // prefer to compute the map of previously seen strings
   // just once for all the threads at the beginning, since they overlap significantly
   pub favor_cpu_efficiency: bool,
+  // for the multithreaded compressors: chunks are at least min_chunk_size bytes (fewer chunks than
+  // threads on small inputs) and at most max_chunk_size bytes (more chunks than threads, run in rounds); 0 is no limit
+  pub min_chunk_size: usize,
+  pub max_chunk_size: usize,
+  // move each chunk boundary back to just after this byte (e.g. b'\n') when one is near
+  pub chunk_delimiter: Option<u8>,
+  // or to a position where this returns true; it is given the whole input and the candidate chunk start
+  pub chunk_split_hint: Option<fn(&[u8], usize) -> bool>,
+  // chunk the input by max_chunk_size (DETERMINISTIC_CHUNK_SIZE when 0) whatever the number of threads,
+  // so the multithreaded compressors write the same bytes for any thread count
+  pub deterministic: bool,
+  // allow the quality to be changed between metablocks (disables the separate q0/q1 stream path)
+  pub adaptive_quality: bool,
+  // classify each metablock (text, tables, fonts, images, compressed data) to pick its literal prior (quality 2 and up)
+  pub auto_detect_content: bool,
+  // store blocks that sample as random without searching them for matches (quality 2 and up);
+  // high_entropy_detection_quality above 2 sets the shortest block it judges
+  pub skip_incompressible: bool,
+  // also search for repeats far back in large windows with a sampled rolling hash (quality 2 and up)
+  pub long_distance_matching: bool,
 }
 
 impl Default for BrotliEncoderParams {
@@ -942,21 +973,17 @@ impl<Specialization: AdvHashSpecialization + Clone, Alloc: alloc::Allocator<u16>
       assert_eq!(num.len(), self.specialization.bucket_size() as usize);
       assert_eq!(buckets.len(), self.specialization.bucket_size() as usize * self.specialization.block_size() as usize);
       let shift = self.specialization.hash_shift();
+      // the lookahead 4 specializations hash with a 32 bit multiply, see hash_words32
+      let hash_mul = self.specialization.get_k_hash_mul() as u32;
+      let mut hashes = [0u32; 4];
       let chunk_count = (ix_end - ix_start) / 4;
       for chunk_id in 0..chunk_count {
         let i = (ix_start + chunk_id * 4) & mask;
-        let ffffffff = 0xffffffff;
-        let word = u64::from(data[i])
-          | (u64::from(data[i + 1]) << 8)
-          | (u64::from(data[i + 2]) << 16)
-          | (u64::from(data[i + 3]) << 24)
-          | (u64::from(data[i + 4]) << 32)
-          | (u64::from(data[i + 5]) << 40)
-          | (u64::from(data[i + 6]) << 48);
-          let mixed0 = ((((word & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed1 = (((((word >> 8) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed2 = (((((word >> 16) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed3 = (((((word >> 24) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
+          super::arch::hash_words32(&data[i..], hash_mul, shift as u32, &mut hashes);
+          let mixed0 = hashes[0] as usize;
+          let mixed1 = hashes[1] as usize;
+          let mixed2 = hashes[2] as usize;
+          let mixed3 = hashes[3] as usize;
           let mut num_ref0 = u32::from(num[mixed0]);
           num[mixed0] = num_ref0.wrapping_add(1) as u16;
           num_ref0 &= (*self).specialization.block_mask();
@@ -996,23 +1023,20 @@ impl<Specialization: AdvHashSpecialization + Clone, Alloc: alloc::Allocator<u16>
       assert_eq!(num.len(), self.specialization.bucket_size() as usize);
       assert_eq!(buckets.len(), self.specialization.bucket_size() as usize * self.specialization.block_size() as usize);
       let shift = self.specialization.hash_shift();
+      let hash_mul = self.specialization.get_k_hash_mul() as u32;
+      let mut hashes = [0u32; REG_SIZE];
       for chunk_id in 0..del {
         let ix_offset = ix_start + chunk_id * REG_SIZE;
         data64[..REG_SIZE + lookahead4 - 1].clone_from_slice(data.split_at(ix_offset).1.split_at(REG_SIZE + lookahead4 - 1).0);
+        for (index, batch) in hashes.chunks_mut(8).enumerate() {
+          super::arch::hash_words32(&data64[index * 8..], hash_mul, shift as u32, batch);
+        }
         for quad_index in 0..(REG_SIZE>>2) {
           let i = quad_index<<2;
-          let ffffffff = 0xffffffff;
-          let word = u64::from(data64[i])
-            | (u64::from(data64[i + 1]) << 8)
-            | (u64::from(data64[i + 2]) << 16)
-            | (u64::from(data64[i + 3]) << 24)
-            | (u64::from(data64[i + 4]) << 32)
-            | (u64::from(data64[i + 5]) << 40)
-            | (u64::from(data64[i + 6]) << 48);
-          let mixed0 = ((((word & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed1 = (((((word >> 8) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed2 = (((((word >> 16) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
-          let mixed3 = (((((word >> 24) & ffffffff) * self.specialization.get_k_hash_mul()) & self.specialization.get_hash_mask()) >> shift) as usize;
+          let mixed0 = hashes[i] as usize;
+          let mixed1 = hashes[i + 1] as usize;
+          let mixed2 = hashes[i + 2] as usize;
+          let mixed3 = hashes[i + 3] as usize;
           let mut num_ref0 = u32::from(num[mixed0]);
           num[mixed0] = num_ref0.wrapping_add(1) as u16;
           num_ref0 &= (*self).specialization.block_mask();
@@ -1965,7 +1989,7 @@ impl<Alloc: alloc::Allocator<u16> + alloc::Allocator<u32>> Default
           },
           })
           */
-fn CreateBackwardReferences<AH: AnyHasher>(dictionary: Option<&BrotliDictionary>,
+fn CreateBackwardReferences<AH: AnyHasher, AllocU32: alloc::Allocator<u32>>(dictionary: Option<&BrotliDictionary>,
                                            dictionary_hash: &[u16],
                                            num_bytes: usize,
                                            mut position: usize,
@@ -1973,6 +1997,7 @@ fn CreateBackwardReferences<AH: AnyHasher>(dictionary: Option<&BrotliDictionary>
                                            ringbuffer_mask: usize,
                                            params: &BrotliEncoderParams,
                                            hasher: &mut AH,
+                                           ldm: &mut LdmHasher<AllocU32>,
                                            dist_cache: &mut [i32],
                                            last_insert_len: &mut usize,
                                            mut commands: &mut [Command],
@@ -2007,17 +2032,28 @@ fn CreateBackwardReferences<AH: AnyHasher>(dictionary: Option<&BrotliDictionary>
     sr.len_x_code = 0usize;
     sr.distance = 0usize;
     sr.score = kMinScore;
-    if hasher.FindLongestMatch(dictionary,
-                               dictionary_hash,
-                               ringbuffer,
-                               ringbuffer_mask,
-                               dist_cache,
-                               position,
-                               max_length,
-                               max_distance,
-                               gap,
-                               params.dist.max_distance,
-                               &mut sr) {
+    let mut is_match_found = hasher.FindLongestMatch(dictionary,
+                                                     dictionary_hash,
+                                                     ringbuffer,
+                                                     ringbuffer_mask,
+                                                     dist_cache,
+                                                     position,
+                                                     max_length,
+                                                     max_distance,
+                                                     gap,
+                                                     params.dist.max_distance,
+                                                     &mut sr);
+    if let Some((len, distance)) = ldm.find_match(ringbuffer, ringbuffer_mask, position) {
+      let score = BackwardReferenceScore(len, distance, hasher.Opts());
+      if score > sr.score {
+        sr.len = len;
+        sr.len_x_code = 0;
+        sr.distance = distance;
+        sr.score = score;
+        is_match_found = true;
+      }
+    }
+    if is_match_found {
       let mut delayed_backward_references_in_row: i32 = 0i32;
       max_length = max_length.wrapping_sub(1 as (usize));
       'break6: loop {
@@ -2136,11 +2172,16 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
    ringbuffer_mask: usize,
    params: &BrotliEncoderParams,
    hasher_union: &mut UnionHasher<Alloc>,
+   ldm: &mut LdmHasher<Alloc>,
    dist_cache: &mut [i32],
    last_insert_len: &mut usize,
    commands: &mut [Command],
    num_commands: &mut usize,
    num_literals: &mut usize) {
+  // scans nothing unless long distance matching is enabled
+  let max_backward_limit = (1usize << params.lgwin).wrapping_sub(16usize);
+  ldm.begin(ringbuffer, ringbuffer_mask, position, num_bytes,
+            brotli_min_size_t(max_backward_limit, params.dist.max_distance));
   match (hasher_union) {
     &mut UnionHasher::Uninit => panic!("working with uninitialized hash map"),
       &mut UnionHasher::H10(ref mut hasher) => {
@@ -2153,6 +2194,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                   ringbuffer_mask,
                   params,
                   hasher,
+                  ldm,
                   dist_cache,
                   last_insert_len,
                   commands,
@@ -2168,6 +2210,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                   ringbuffer_mask,
                   params,
                   hasher,
+                  ldm,
                   dist_cache,
                   last_insert_len,
                   commands,
@@ -2184,6 +2227,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2199,6 +2243,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2214,6 +2259,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2229,6 +2275,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2244,6 +2291,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2259,6 +2307,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2274,6 +2323,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2289,6 +2339,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
@@ -2304,6 +2355,7 @@ pub fn BrotliCreateBackwardReferences<Alloc: alloc::Allocator<u16> + alloc::Allo
                                ringbuffer_mask,
                                params,
                                hasher,
+                               ldm,
                                dist_cache,
                                last_insert_len,
                                commands,
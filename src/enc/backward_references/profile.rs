@@ -0,0 +1,250 @@
+// A stable text form of BrotliEncoderParams: one key=value per line, # comments, and a
+// version=1 line first so that later formats can be told apart. Keys missing from a profile keep
+// the value already in the params; the per-call fields (log_meta_block, chunk_split_hint) and the
+// derived distance params are not part of it.
+use core;
+use core::fmt;
+use super::{BrotliEncoderMode, BrotliEncoderParams};
+
+pub const PROFILE_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileErrorKind {
+  // the first key of a profile must be version
+  MissingVersion,
+  UnsupportedVersion,
+  // a line that is neither blank, a comment nor key=value
+  Syntax,
+  UnknownKey,
+  InvalidValue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfileError {
+  // 1 based line number of the offending line
+  pub line: usize,
+  pub kind: ProfileErrorKind,
+}
+
+impl fmt::Display for ProfileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let what = match self.kind {
+      ProfileErrorKind::MissingVersion => "expected version=1 before any other key",
+      ProfileErrorKind::UnsupportedVersion => "unsupported profile version",
+      ProfileErrorKind::Syntax => "expected key=value",
+      ProfileErrorKind::UnknownKey => "unknown key",
+      ProfileErrorKind::InvalidValue => "invalid value",
+    };
+    write!(f, "line {}: {}", self.line, what)
+  }
+}
+
+#[cfg(feature="std")]
+impl ::std::error::Error for ProfileError {}
+
+static MODE_NAMES: [(&'static str, BrotliEncoderMode); 7] = [
+  ("generic", BrotliEncoderMode::BROTLI_MODE_GENERIC),
+  ("text", BrotliEncoderMode::BROTLI_MODE_TEXT),
+  ("font", BrotliEncoderMode::BROTLI_MODE_FONT),
+  ("force_lsb_prior", BrotliEncoderMode::BROTLI_FORCE_LSB_PRIOR),
+  ("force_msb_prior", BrotliEncoderMode::BROTLI_FORCE_MSB_PRIOR),
+  ("force_utf8_prior", BrotliEncoderMode::BROTLI_FORCE_UTF8_PRIOR),
+  ("force_signed_prior", BrotliEncoderMode::BROTLI_FORCE_SIGNED_PRIOR),
+];
+
+fn mode_name(mode: BrotliEncoderMode) -> &'static str {
+  for &(name, m) in MODE_NAMES.iter() {
+    if m == mode {
+      return name;
+    }
+  }
+  "generic"
+}
+
+// writes every profile key of params, in a fixed order, one per line
+pub fn BrotliEncoderPrintProfile<W: fmt::Write>(params: &BrotliEncoderParams, w: &mut W) -> fmt::Result {
+  writeln!(w, "version={}", PROFILE_VERSION)?;
+  writeln!(w, "mode={}", mode_name(params.mode))?;
+  writeln!(w, "quality={}", params.quality)?;
+  writeln!(w, "q9_5={}", params.q9_5)?;
+  writeln!(w, "lgwin={}", params.lgwin)?;
+  writeln!(w, "large_window={}", params.large_window)?;
+  writeln!(w, "lgblock={}", params.lgblock)?;
+  writeln!(w, "size_hint={}", params.size_hint)?;
+  writeln!(w, "disable_literal_context_modeling={}", params.disable_literal_context_modeling)?;
+  writeln!(w, "hasher.type={}", params.hasher.type_)?;
+  writeln!(w, "hasher.bucket_bits={}", params.hasher.bucket_bits)?;
+  writeln!(w, "hasher.block_bits={}", params.hasher.block_bits)?;
+  writeln!(w, "hasher.hash_len={}", params.hasher.hash_len)?;
+  writeln!(w, "hasher.num_last_distances_to_check={}", params.hasher.num_last_distances_to_check)?;
+  writeln!(w, "hasher.literal_byte_score={}", params.hasher.literal_byte_score)?;
+  writeln!(w, "stride_detection_quality={}", params.stride_detection_quality)?;
+  writeln!(w, "high_entropy_detection_quality={}", params.high_entropy_detection_quality)?;
+  writeln!(w, "cdf_adaptation_detection={}", params.cdf_adaptation_detection)?;
+  writeln!(w, "prior_bitmask_detection={}", params.prior_bitmask_detection)?;
+  let s = &params.literal_adaptation;
+  writeln!(w, "literal_adaptation={},{},{},{},{},{},{},{}",
+           s[0].0, s[0].1, s[1].0, s[1].1, s[2].0, s[2].1, s[3].0, s[3].1)?;
+  writeln!(w, "avoid_distance_prefix_search={}", params.avoid_distance_prefix_search)?;
+  writeln!(w, "catable={}", params.catable)?;
+  // the effective value: catable files never refer to the static dictionary
+  writeln!(w, "use_dictionary={}", params.use_dictionary && !params.catable)?;
+  writeln!(w, "appendable={}", params.appendable)?;
+  writeln!(w, "magic_number={}", params.magic_number)?;
+  writeln!(w, "favor_cpu_efficiency={}", params.favor_cpu_efficiency)?;
+  writeln!(w, "min_chunk_size={}", params.min_chunk_size)?;
+  writeln!(w, "max_chunk_size={}", params.max_chunk_size)?;
+  match params.chunk_delimiter {
+    Some(delimiter) => writeln!(w, "chunk_delimiter={}", delimiter)?,
+    None => writeln!(w, "chunk_delimiter=none")?,
+  }
+  writeln!(w, "deterministic={}", params.deterministic)?;
+  writeln!(w, "adaptive_quality={}", params.adaptive_quality)?;
+  writeln!(w, "auto_detect_content={}", params.auto_detect_content)?;
+  writeln!(w, "skip_incompressible={}", params.skip_incompressible)?;
+  writeln!(w, "long_distance_matching={}", params.long_distance_matching)
+}
+
+// formats params as a profile, e.g. format!("{}", ParamsProfile(&params))
+pub struct ParamsProfile<'a>(pub &'a BrotliEncoderParams);
+
+impl<'a> fmt::Display for ParamsProfile<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    BrotliEncoderPrintProfile(self.0, f)
+  }
+}
+
+fn parse_number<T: core::str::FromStr>(value: &str) -> Option<T> {
+  value.parse::<T>().ok()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+  match value {
+    "true" | "1" => Some(true),
+    "false" | "0" => Some(false),
+    _ => None,
+  }
+}
+
+fn parse_delimiter(value: &str) -> Option<Option<u8>> {
+  if value == "none" {
+    return Some(None);
+  }
+  parse_number::<u8>(value).map(Some)
+}
+
+fn parse_speeds(value: &str) -> Option<[(u16, u16); 4]> {
+  let mut ret = [(0u16, 0u16); 4];
+  let mut count = 0usize;
+  for item in value.split(',') {
+    if count == 8 {
+      return None;
+    }
+    let speed = parse_number::<u16>(item.trim())?;
+    if count & 1 == 0 {
+      ret[count / 2].0 = speed;
+    } else {
+      ret[count / 2].1 = speed;
+    }
+    count += 1;
+  }
+  if count != 8 {
+    return None;
+  }
+  Some(ret)
+}
+
+// returns None for an unknown key and Some(false) for a value that does not parse
+fn set_key(params: &mut BrotliEncoderParams, key: &str, value: &str) -> Option<bool> {
+  macro_rules! set {
+    ($field:expr, $parsed:expr) => {
+      match $parsed {
+        Some(v) => { $field = v; Some(true) },
+        None => Some(false),
+      }
+    }
+  }
+  match key {
+    "mode" => set!(params.mode, MODE_NAMES.iter().find(|m| m.0 == value).map(|m| m.1)),
+    "quality" => set!(params.quality, parse_number::<i32>(value)),
+    "q9_5" => set!(params.q9_5, parse_bool(value)),
+    "lgwin" => set!(params.lgwin, parse_number::<i32>(value)),
+    "large_window" => set!(params.large_window, parse_bool(value)),
+    "lgblock" => set!(params.lgblock, parse_number::<i32>(value)),
+    "size_hint" => set!(params.size_hint, parse_number::<usize>(value)),
+    "disable_literal_context_modeling" => set!(params.disable_literal_context_modeling, parse_number::<i32>(value)),
+    "hasher.type" => set!(params.hasher.type_, parse_number::<i32>(value)),
+    "hasher.bucket_bits" => set!(params.hasher.bucket_bits, parse_number::<i32>(value)),
+    "hasher.block_bits" => set!(params.hasher.block_bits, parse_number::<i32>(value)),
+    "hasher.hash_len" => set!(params.hasher.hash_len, parse_number::<i32>(value)),
+    "hasher.num_last_distances_to_check" => set!(params.hasher.num_last_distances_to_check, parse_number::<i32>(value)),
+    "hasher.literal_byte_score" => set!(params.hasher.literal_byte_score, parse_number::<i32>(value)),
+    "stride_detection_quality" => set!(params.stride_detection_quality, parse_number::<u8>(value)),
+    "high_entropy_detection_quality" => set!(params.high_entropy_detection_quality, parse_number::<u8>(value)),
+    "cdf_adaptation_detection" => set!(params.cdf_adaptation_detection, parse_number::<u8>(value)),
+    "prior_bitmask_detection" => set!(params.prior_bitmask_detection, parse_number::<u8>(value)),
+    "literal_adaptation" => set!(params.literal_adaptation, parse_speeds(value)),
+    "avoid_distance_prefix_search" => set!(params.avoid_distance_prefix_search, parse_bool(value)),
+    // same side effects as BROTLI_PARAM_CATABLE, so a profile with only catable=true concatenates
+    "catable" => match parse_bool(value) {
+      Some(catable) => {
+        params.catable = catable;
+        params.appendable |= catable;
+        params.use_dictionary &= !catable;
+        Some(true)
+      },
+      None => Some(false),
+    },
+    // catable files cannot refer to the static dictionary, whichever key comes first
+    "use_dictionary" => set!(params.use_dictionary, parse_bool(value).map(|use_dictionary| use_dictionary && !params.catable)),
+    "appendable" => set!(params.appendable, parse_bool(value)),
+    "magic_number" => set!(params.magic_number, parse_bool(value)),
+    "favor_cpu_efficiency" => set!(params.favor_cpu_efficiency, parse_bool(value)),
+    "min_chunk_size" => set!(params.min_chunk_size, parse_number::<usize>(value)),
+    "max_chunk_size" => set!(params.max_chunk_size, parse_number::<usize>(value)),
+    "chunk_delimiter" => set!(params.chunk_delimiter, parse_delimiter(value)),
+    "deterministic" => set!(params.deterministic, parse_bool(value)),
+    "adaptive_quality" => set!(params.adaptive_quality, parse_bool(value)),
+    "auto_detect_content" => set!(params.auto_detect_content, parse_bool(value)),
+    "skip_incompressible" => set!(params.skip_incompressible, parse_bool(value)),
+    "long_distance_matching" => set!(params.long_distance_matching, parse_bool(value)),
+    _ => None,
+  }
+}
+
+// applies the keys of profile to params; on error params is left untouched
+pub fn BrotliEncoderParseProfile(profile: &str, params: &mut BrotliEncoderParams) -> Result<(), ProfileError> {
+  let mut parsed = params.clone();
+  let mut seen_version = false;
+  for (index, line) in profile.lines().enumerate() {
+    let error = |kind| Err(ProfileError { line: index + 1, kind: kind });
+    let line = line.trim();
+    if line.len() == 0 || line.starts_with('#') {
+      continue;
+    }
+    let (key, value) = match line.find('=') {
+      Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+      None => return error(ProfileErrorKind::Syntax),
+    };
+    if !seen_version {
+      if key != "version" {
+        return error(ProfileErrorKind::MissingVersion);
+      }
+      if parse_number::<u32>(value) != Some(PROFILE_VERSION) {
+        return error(ProfileErrorKind::UnsupportedVersion);
+      }
+      seen_version = true;
+      continue;
+    }
+    match set_key(&mut parsed, key, value) {
+      Some(true) => {},
+      Some(false) => return error(ProfileErrorKind::InvalidValue),
+      None => return error(ProfileErrorKind::UnknownKey),
+    }
+  }
+  if !seen_version {
+    return Err(ProfileError { line: 0, kind: ProfileErrorKind::MissingVersion });
+  }
+  *params = parsed;
+  Ok(())
+}
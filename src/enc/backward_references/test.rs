@@ -225,3 +225,90 @@ fn test_bulk_store_range_pow2() {
   assert!(hasher_d == hasher_c);
   assert!(hasher_a == hasher_e);
 }
+
+#[test]
+fn test_profile_round_trip() {
+  use std::string::ToString;
+  use super::{BrotliEncoderMode, BrotliEncoderParams, BrotliEncoderParseProfile, ParamsProfile};
+  let mut params = BrotliEncoderParams::default();
+  params.mode = BrotliEncoderMode::BROTLI_FORCE_UTF8_PRIOR;
+  params.quality = 11;
+  params.q9_5 = true;
+  params.lgwin = 26;
+  params.large_window = true;
+  params.hasher.literal_byte_score = 700;
+  params.literal_adaptation = [(1, 2), (3, 4), (5, 6), (7, 16384)];
+  params.catable = true;
+  params.skip_incompressible = true;
+  params.long_distance_matching = true;
+  params.max_chunk_size = 1 << 20;
+  params.chunk_delimiter = Some(b'\n');
+  let profile = ParamsProfile(&params).to_string();
+  assert!(profile.starts_with("version=1\nmode=force_utf8_prior\nquality=11\nq9_5=true\n"));
+  let mut parsed = BrotliEncoderParams::default();
+  BrotliEncoderParseProfile(&profile, &mut parsed).unwrap();
+  assert_eq!(ParamsProfile(&parsed).to_string(), profile);
+  assert_eq!(parsed.hasher, params.hasher);
+  assert_eq!(parsed.literal_adaptation, params.literal_adaptation);
+  assert_eq!(parsed.chunk_delimiter, Some(b'\n'));
+}
+
+#[test]
+fn test_profile_errors() {
+  use super::{BrotliEncoderParams, BrotliEncoderParseProfile, ProfileErrorKind};
+  let mut params = BrotliEncoderParams::default();
+  params.quality = 3;
+  let parse = |text: &str, params: &mut BrotliEncoderParams| BrotliEncoderParseProfile(text, params).map_err(|e| (e.line, e.kind));
+  assert_eq!(parse("# comment\n\n version = 1 \nlgwin = 18\n", &mut params), Ok(()));
+  assert_eq!((params.quality, params.lgwin), (3, 18));
+  assert_eq!(parse("quality=5\n", &mut params), Err((1, ProfileErrorKind::MissingVersion)));
+  assert_eq!(parse("", &mut params), Err((0, ProfileErrorKind::MissingVersion)));
+  assert_eq!(parse("version=2\n", &mut params), Err((1, ProfileErrorKind::UnsupportedVersion)));
+  assert_eq!(parse("version=1\nquality=5\nlgwin\n", &mut params), Err((3, ProfileErrorKind::Syntax)));
+  assert_eq!(parse("version=1\nqualty=5\n", &mut params), Err((2, ProfileErrorKind::UnknownKey)));
+  assert_eq!(parse("version=1\nquality=5\ncatable=yes\n", &mut params), Err((3, ProfileErrorKind::InvalidValue)));
+  assert_eq!(parse("version=1\nliteral_adaptation=1,2,3\n", &mut params), Err((2, ProfileErrorKind::InvalidValue)));
+  // a failed parse leaves the params untouched
+  assert_eq!(params.quality, 3);
+  // catable implies what BROTLI_PARAM_CATABLE implies
+  assert_eq!(parse("version=1\ncatable=true\n", &mut params), Ok(()));
+  assert!(params.catable && params.appendable && !params.use_dictionary);
+  assert_eq!(parse("version=1\ncatable=true\nuse_dictionary=true\n", &mut params), Ok(()));
+  assert!(!params.use_dictionary);
+  assert_eq!(parse("version=1\nuse_dictionary=false\ncatable=false\n", &mut params), Ok(()));
+  assert!(!params.catable && !params.use_dictionary);
+}
+
+#[test]
+fn test_ldm_finds_distant_repeat() {
+  use super::LdmHasher;
+  let mut state = 0x2545f4914f6cdd1du64;
+  let mut data = vec![0u8; 3 << 19];
+  for item in data.iter_mut() {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    *item = (state >> 24) as u8;
+  }
+  let (first, second, len) = (1000usize, 1usize << 20, 65536usize);
+  let copy = data[first..first + len].to_vec();
+  data[second..second + len].clone_from_slice(&copy[..]);
+  let mut alloc = StandardAlloc::default();
+  let mut ldm = LdmHasher::<StandardAlloc>::new(&mut alloc, 24);
+  ldm.begin(&data[..], !0usize, 0, data.len(), 1 << 24);
+  let found = (0..data.len()).filter_map(|position| ldm.find_match(&data[..], !0usize, position).map(|m| (position, m))).next();
+  let (position, (match_len, distance)) = found.unwrap();
+  assert_eq!((position, distance), (second, second - first));
+  assert!(match_len >= len);
+  // the first half as earlier input, e.g. a custom dictionary, and the second half as the next block
+  let mut ldm = LdmHasher::<StandardAlloc>::new(&mut alloc, 24);
+  ldm.store_range(&data[..], !0usize, 0, second - 4096);
+  ldm.begin(&data[..], !0usize, second - 4096, data.len() - second + 4096, 1 << 24);
+  assert_eq!(ldm.find_match(&data[..], !0usize, second - 4096), None);
+  assert_eq!(ldm.find_match(&data[..], !0usize, second + 100).map(|m| m.1), Some(second - first));
+  // a match further back than max_distance is not offered
+  ldm.begin(&data[..], !0usize, second - 4096, data.len() - second + 4096, second - first - 1);
+  assert!((second - 4096..data.len()).all(|position| ldm.find_match(&data[..], !0usize, position).is_none()));
+  ldm.free(&mut alloc);
+  assert!(!ldm.is_enabled());
+}
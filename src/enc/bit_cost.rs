@@ -4,8 +4,6 @@ use super::histogram::CostAccessors;
 use super::super::alloc::SliceWrapper;
 
 use super::util::{brotli_max_uint32_t, FastLog2, floatX, FastLog2u16};
-#[cfg(feature="simd")]
-use packed_simd::IntoBits;
 use super::vectorization::{v256,v256i, Mem256i, sum8, cast_f32_to_i32, cast_i32_to_f32, log2i};
 
 static kCopyBase: [u32; 24] = [2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 14, 18, 22, 30, 38, 54, 70,
@@ -361,29 +359,35 @@ pub fn BrotliPopulationCost<HistogramType:SliceWrapper<u32>+CostAccessors>(
                                       0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32];
     let log2total: super::util::floatX = FastLog2((*histogram).total_count() as u64); // 64 bit here
     let mut reps : u32 = 0;
-    for histo in histogram.slice()[..data_size].iter() {
-        if *histo != 0 {
-            if reps != 0 {
-                if reps < 3 {
-                    depth_histo[0] += reps;
-                } else {
-                    reps -= 2;
-                    while reps > 0u32 {
-                        depth_histo[17] += 1;
-                        bits = bits + 3 as super::util::floatX;
-                        reps = reps >> 3;
+    for block in histogram.slice()[..data_size].chunks(8) {
+        // the costs and depths of 8 symbols at a time, added up in order below
+        let mut counts = [0u32; 8];
+        let mut block_bits = [0.0 as super::util::floatX; 8];
+        let mut block_depth = [0u8; 8];
+        counts[..block.len()].clone_from_slice(block);
+        super::arch::population_costs(&counts, log2total, &mut block_bits, &mut block_depth);
+        for (index, histo) in block.iter().enumerate() {
+            if *histo != 0 {
+                if reps != 0 {
+                    if reps < 3 {
+                        depth_histo[0] += reps;
+                    } else {
+                        reps -= 2;
+                        while reps > 0u32 {
+                            depth_histo[17] += 1;
+                            bits = bits + 3 as super::util::floatX;
+                            reps = reps >> 3;
+                        }
                     }
+                    reps = 0;
                 }
-                reps = 0;
+                let depth = block_depth[index] as usize;
+                bits = bits + block_bits[index];
+                max_depth = core::cmp::max(depth, max_depth);
+                depth_histo[depth] += 1;
+            } else {
+                reps += 1;
             }
-            let log2p: super::util::floatX = log2total - FastLog2u16(*histo as (u16));
-            let mut depth: usize = (log2p + 0.5 as super::util::floatX) as (usize);
-            bits = bits + *histo as super::util::floatX * log2p;
-            depth = core::cmp::min(depth, 15);
-            max_depth = core::cmp::max(depth, max_depth);
-            depth_histo[depth] += 1;
-        } else {
-            reps += 1;
         }
     }
     bits = bits + (18usize).wrapping_add((2usize).wrapping_mul(max_depth)) as super::util::floatX;
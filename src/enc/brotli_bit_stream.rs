@@ -69,7 +69,8 @@ fn prediction_mode_str(prediction_mode_nibble:interface::LiteralPredictionModeNi
    }
 }
 
-fn is_long_enough_to_be_random(len: usize, high_entropy_detection_quality:u8) -> bool{
+// whether len literals are enough to call them random at this detection quality
+pub fn is_long_enough_to_be_random(len: usize, high_entropy_detection_quality:u8) -> bool{
     return match high_entropy_detection_quality {
         0 => false,
         1 => false,
@@ -0,0 +1,223 @@
+use super::super::alloc::{Allocator, SliceWrapper};
+use super::backward_references::BrotliEncoderMode;
+use super::bit_cost::ShannonEntropy;
+use super::find_stride::{EntropyTally, NUM_STRIDES};
+use super::histogram::ContextType;
+use super::utf8_util::BrotliIsMostlyUTF8;
+use super::util::floatX;
+
+static kMinUTF8Ratio: floatX = 0.75 as floatX;
+// large inputs are only sampled, in evenly spaced windows
+const SAMPLE_WINDOW_SIZE: usize = 4096;
+const MAX_SAMPLE_WINDOWS: usize = 15;
+pub const MAX_DETECTED_STRIDE: usize = NUM_STRIDES;
+pub const NUM_CONTENT_TYPES: usize = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContentType {
+  // nothing conclusive: keep the default behaviour of the encoder
+  Generic = 0,
+  Text = 1,
+  // fixed size records such as arrays of integers or floats
+  BinaryTable = 2,
+  Font = 3,
+  // uncompressed pixel data
+  Image = 4,
+  // entropy coded data that brotli will barely shrink
+  Compressed = 5,
+}
+
+impl Default for ContentType {
+  fn default() -> ContentType {
+    ContentType::Generic
+  }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ContentAnalysis {
+  pub content_type: ContentType,
+  // the mode a caller would have passed had they known the content type
+  pub mode: BrotliEncoderMode,
+  // literal prior used for the metablock (None leaves the choice to the encoder)
+  pub literal_context_mode: Option<ContextType>,
+  // distance to the byte that best predicts the current one, up to MAX_DETECTED_STRIDE
+  pub stride: u8,
+  // order-0 entropy of the sample
+  pub bits_per_byte: floatX,
+  // cost of each byte once the one stride bytes before it is known, as find_stride counts
+  // it (table overhead included)
+  pub stride_bits_per_byte: floatX,
+  pub num_sampled: usize,
+}
+
+// summary of every analysis made while compressing a stream
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ContentReport {
+  pub last: ContentAnalysis,
+  pub num_metablocks: u32,
+  // number of input bytes attributed to each ContentType
+  pub bytes_per_type: [u64; NUM_CONTENT_TYPES],
+  // number of input bytes attributed to each stride, indexed by stride - 1
+  pub bytes_per_stride: [u64; MAX_DETECTED_STRIDE],
+}
+
+impl ContentReport {
+  pub fn record(&mut self, analysis: &ContentAnalysis, length: usize) {
+    self.last = *analysis;
+    self.num_metablocks += 1;
+    self.bytes_per_type[analysis.content_type as usize] += length as u64;
+    self.bytes_per_stride[analysis.stride as usize - 1] += length as u64;
+  }
+}
+
+fn starts_with_any(header: &[u8], magics: &[&[u8]]) -> bool {
+  magics.iter().any(|magic| header.len() >= magic.len() && header[..magic.len()] == **magic)
+}
+
+// Plain TrueType/OpenType signatures are also ordinary text or integers, so they only count
+// when followed by the binary search fields of a table directory.
+fn is_sfnt_header(header: &[u8]) -> bool {
+  if header.len() < 12 || !starts_with_any(header, &[b"OTTO", b"true", b"\x00\x01\x00\x00"]) {
+    return false;
+  }
+  let field = |offset: usize| (u16::from(header[offset]) << 8) | u16::from(header[offset + 1]);
+  let num_tables = field(4);
+  if num_tables == 0 || num_tables > 256 {
+    return false;
+  }
+  let entry_selector = 15 - num_tables.leading_zeros() as u16;
+  let search_range = 16 << entry_selector;
+  field(6) == search_range && field(8) == entry_selector && field(10) == num_tables * 16 - search_range
+}
+
+fn classify_magic(header: &[u8]) -> Option<ContentType> {
+  if starts_with_any(header, &[b"wOFF", b"wOF2", b"ttcf"]) || is_sfnt_header(header) {
+    return Some(ContentType::Font);
+  }
+  if starts_with_any(header, &[b"BM", b"P5\n", b"P6\n", b"II*\x00", b"MM\x00*"]) {
+    return Some(ContentType::Image);
+  }
+  // png, jpeg, gif, webp, zip, gzip, bzip2, xz, zstd, 7z
+  if starts_with_any(header, &[b"\x89PNG", b"\xff\xd8\xff", b"GIF8", b"PK\x03\x04", b"\x1f\x8b",
+                              b"BZh", b"\xfd7zXZ", b"\x28\xb5\x2f\xfd", b"7z\xbc\xaf"]) ||
+     (header.len() >= 12 && &header[..4] == b"RIFF" && &header[8..12] == b"WEBP") {
+    return Some(ContentType::Compressed);
+  }
+  None
+}
+
+fn choices_for(content_type: ContentType) -> (BrotliEncoderMode, Option<ContextType>) {
+  match content_type {
+    ContentType::Generic => (BrotliEncoderMode::BROTLI_MODE_GENERIC, None),
+    ContentType::Text => (BrotliEncoderMode::BROTLI_MODE_TEXT, Some(ContextType::CONTEXT_UTF8)),
+    ContentType::BinaryTable => (BrotliEncoderMode::BROTLI_MODE_GENERIC, Some(ContextType::CONTEXT_SIGNED)),
+    ContentType::Font => (BrotliEncoderMode::BROTLI_MODE_FONT, Some(ContextType::CONTEXT_SIGNED)),
+    ContentType::Image => (BrotliEncoderMode::BROTLI_MODE_GENERIC, Some(ContextType::CONTEXT_MSB6)),
+    ContentType::Compressed => (BrotliEncoderMode::BROTLI_MODE_GENERIC, Some(ContextType::CONTEXT_LSB6)),
+  }
+}
+
+fn bits_per_symbol(histogram: &[u32; 256]) -> floatX {
+  let mut total = 0usize;
+  let bits = ShannonEntropy(&histogram[..], 256, &mut total);
+  if total == 0 {
+    return 0.0 as floatX;
+  }
+  bits / total as floatX
+}
+
+// Classifies length bytes of the ringbuffer starting at pos by sampling a few windows of it.
+// check_magic should only be set when pos is the start of the stream, since file signatures
+// are meaningless in the middle of one.
+pub fn BrotliAnalyzeContent<AllocU32: Allocator<u32>>(m32: &mut AllocU32,
+                                                      data: &[u8],
+                                                      pos: usize,
+                                                      mask: usize,
+                                                      length: usize,
+                                                      check_magic: bool)
+                                                      -> ContentAnalysis {
+  let mut ret = ContentAnalysis {
+    stride: 1,
+    ..ContentAnalysis::default()
+  };
+  if length == 0 {
+    return ret;
+  }
+  let mut magic_type = None;
+  if check_magic {
+    let mut header = [0u8; 12];
+    let header_len = core::cmp::min(header.len(), length);
+    for (index, item) in header[..header_len].iter_mut().enumerate() {
+      *item = data[pos.wrapping_add(index) & mask];
+    }
+    magic_type = classify_magic(&header[..header_len]);
+  }
+  let num_windows = core::cmp::min(MAX_SAMPLE_WINDOWS,
+                                   (length + SAMPLE_WINDOW_SIZE - 1) / SAMPLE_WINDOW_SIZE);
+  let window_size = core::cmp::min(SAMPLE_WINDOW_SIZE, length);
+  let window_spacing = if num_windows > 1 {
+    (length - window_size) / (num_windows - 1)
+  } else {
+    0
+  };
+  let mut tally = EntropyTally::<AllocU32>::new(m32, None);
+  let mut num_utf8_windows = 0usize;
+  for window in 0..num_windows {
+    let start = pos.wrapping_add(window * window_spacing);
+    if BrotliIsMostlyUTF8(data, start, mask, window_size, kMinUTF8Ratio) != 0 {
+      num_utf8_windows += 1;
+    }
+    // the window may wrap around the end of the ringbuffer
+    let masked_start = start & mask;
+    let first_len = core::cmp::min(window_size - 1, mask - masked_start) + 1;
+    tally.tally_input_stream(&data[masked_start..masked_start + first_len],
+                             &data[..window_size - first_len]);
+  }
+  ret.num_sampled = num_windows * window_size;
+  let mut histogram = [0u32; 256];
+  for (index, count) in tally.peek().bucket_populations.slice().iter().enumerate() {
+    histogram[index & 0xff] += *count;
+  }
+  let num_zeros = histogram[0] as usize;
+  ret.bits_per_byte = bits_per_symbol(&histogram);
+  let costs = tally.stride_costs();
+  tally.free(m32);
+  let mut best_cost = costs[0];
+  for (stride_index, cost) in costs.iter().enumerate().skip(1) {
+    // a longer stride has to be clearly better to count as structure
+    if *cost < best_cost * 0.9 {
+      best_cost = *cost;
+      ret.stride = stride_index as u8 + 1;
+    }
+  }
+  // the populations pair each byte with its prior, so their cost includes the order-0 one
+  ret.stride_bits_per_byte = (best_cost / ret.num_sampled as f64) as floatX - ret.bits_per_byte;
+  let structure_gain = ret.bits_per_byte - ret.stride_bits_per_byte;
+  ret.content_type = if let Some(content_type) = magic_type {
+    content_type
+  } else if num_utf8_windows * 4 >= num_windows * 3 {
+    ContentType::Text
+  } else if ret.bits_per_byte >= 7.5 as floatX && structure_gain < 1.0 as floatX {
+    ContentType::Compressed
+  } else if (ret.stride == 3 || ret.stride == 4) && structure_gain >= 0.5 as floatX &&
+            num_zeros * 10 < ret.num_sampled {
+    // pixels rarely contain zero bytes while tables of small integers are padded with them
+    ContentType::Image
+  } else if ret.stride > 1 && structure_gain >= 0.25 as floatX {
+    ContentType::BinaryTable
+  } else {
+    ContentType::Generic
+  };
+  let (mode, literal_context_mode) = choices_for(ret.content_type);
+  ret.mode = mode;
+  ret.literal_context_mode = literal_context_mode;
+  ret
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IncompressibleStats {
+  pub num_blocks_sampled: u32,
+  pub num_blocks_skipped: u32,
+  // input bytes that were stored without running the backward reference search
+  pub bytes_skipped: u64,
+}
@@ -5,6 +5,7 @@ use super::super::alloc::{SliceWrapper, SliceWrapperMut, Allocator};
 use super::interface;
 use super::input_pair::{InputPair, InputReference, InputReferenceMut};
 use super::util::{floatX, FastLog2u16};
+use super::arch;
 use super::find_stride;
 use super::weights::{Weights, BLEND_FIXED_POINT_PRECISION};
 
@@ -151,14 +152,8 @@ fn compute_cost(singleton_cost: &mut [floatX;NUM_SPEEDS_TO_TRY],
 fn update_cdf(cdfs: &mut [u16],
               nibble_u8: u8) {
     assert_eq!(cdfs.len(), 16 * NUM_SPEEDS_TO_TRY);
-    let mut overall_index = nibble_u8 as usize * NUM_SPEEDS_TO_TRY;
-    for _nibble in (nibble_u8 as usize & 0xf) .. 16 {
-        for speed_index in 0..NUM_SPEEDS_TO_TRY {
-            cdfs[overall_index + speed_index] += SPEEDS_TO_SEARCH[speed_index];
-        }
-        overall_index += NUM_SPEEDS_TO_TRY;
-    }
-    overall_index = 0;
+    arch::add_cdf_speeds(cdfs, nibble_u8, &SPEEDS_TO_SEARCH);
+    let mut overall_index = 0;
     for nibble in 0 .. 16 {
         for speed_index in 0..NUM_SPEEDS_TO_TRY {
             if nibble == 0 {
@@ -169,15 +164,7 @@ fn update_cdf(cdfs: &mut [u16],
         }
         overall_index += NUM_SPEEDS_TO_TRY;
     }
-    for max_index in 0..NUM_SPEEDS_TO_TRY {
-        if cdfs[15 * NUM_SPEEDS_TO_TRY + max_index] >= MAXES_TO_SEARCH[max_index] {
-            const CDF_BIAS:[u16;16] = [1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16];
-            for nibble_index in 0..16  {
-                let tmp = &mut cdfs[nibble_index * NUM_SPEEDS_TO_TRY + max_index];
-                *tmp = (tmp.wrapping_add(CDF_BIAS[nibble_index])).wrapping_sub(tmp.wrapping_add(CDF_BIAS[nibble_index]) >> 2);
-            }
-        }
-    }
+    arch::rescale_cdf_columns(cdfs, &MAXES_TO_SEARCH);
     overall_index = 0;
     for nibble in 0 .. 16 {
         for speed_index in 0..NUM_SPEEDS_TO_TRY {
@@ -6,7 +6,8 @@ use super::backward_references::{BrotliCreateBackwardReferences, Struct1, UnionH
                                  BrotliEncoderParams, BrotliEncoderMode, BrotliHasherParams, H2Sub,
                                  H3Sub, H4Sub, H5Sub, H6Sub, H54Sub, HQ5Sub, HQ7Sub, AdvHasher, BasicHasher, H9Sub,
                                  H9_BUCKET_BITS, H9_BLOCK_BITS, H9_NUM_LAST_DISTANCES_TO_CHECK,
-                                 AnyHasher, HowPrepared, StoreLookaheadThenStore, AdvHashSpecialization};
+                                 AnyHasher, HowPrepared, StoreLookaheadThenStore, AdvHashSpecialization,
+                                 LdmHasher};
 use alloc::Allocator;
 pub use super::parameters::BrotliEncoderParameter;
 use super::combined_alloc::BrotliAlloc;
@@ -19,7 +20,8 @@ use super::brotli_bit_stream::{BrotliBuildAndStoreHuffmanTreeFast, BrotliStoreHu
                                BrotliStoreMetaBlock, BrotliStoreMetaBlockFast,
                                BrotliStoreMetaBlockTrivial, BrotliStoreUncompressedMetaBlock,
                                BrotliWriteEmptyLastMetaBlock, BrotliWriteMetadataMetaBlock,
-                               MetaBlockSplit, RecoderState, JumpToByteBoundary};
+                               MetaBlockSplit, RecoderState, JumpToByteBoundary,
+                               is_long_enough_to_be_random};
                                
 use enc::input_pair::InputReferenceMut;
 use super::command::{Command, GetLengthCode, BrotliDistanceParams};
@@ -33,6 +35,8 @@ use super::histogram::{ContextType, HistogramLiteral, HistogramCommand, Histogra
 use super::super::alloc;
 use super::super::alloc::{SliceWrapper, SliceWrapperMut};
 use super::utf8_util::BrotliIsMostlyUTF8;
+use super::content_detect::{BrotliAnalyzeContent, ContentReport, ContentType,
+                            IncompressibleStats};
 use super::util::{brotli_min_size_t, Log2FloorNonZero};
 use core;
 //fn BrotliCreateHqZopfliBackwardReferences(m: &mut [MemoryManager],
@@ -159,6 +163,8 @@ pub struct BrotliEncoderStateStruct<Alloc: BrotliAlloc>
   pub params: BrotliEncoderParams,
   pub m8: Alloc,
   pub hasher_: UnionHasher<Alloc>,
+  // the long distance matcher, allocated on first use when params.long_distance_matching is set
+  pub ldm_: LdmHasher<Alloc>,
   pub input_pos_: u64,
   pub ringbuffer_: RingBuffer<Alloc>,
   pub cmd_alloc_size_: usize,
@@ -199,6 +205,10 @@ pub struct BrotliEncoderStateStruct<Alloc: BrotliAlloc>
   pub distance_scratch_space: <HistogramDistance as CostAccessors>::i32vec,
   pub recoder_state: RecoderState,
   custom_dictionary: bool,
+  // what auto_detect_content decided for the metablocks written so far
+  pub content_report: ContentReport,
+  // how many blocks skip_incompressible stored without compressing them
+  pub incompressible_stats: IncompressibleStats,
 }
 
 pub fn set_parameter(params: &mut BrotliEncoderParams,
@@ -340,6 +350,26 @@ value: u32) -> i32 {
     params.favor_cpu_efficiency = value != 0;
     return 1i32;
   }
+  if p as (i32) == BrotliEncoderParameter::BROTLI_PARAM_ADAPTIVE_QUALITY as (i32) {
+    params.adaptive_quality = value != 0;
+    return 1i32;
+  }
+  if p as (i32) == BrotliEncoderParameter::BROTLI_PARAM_AUTO_DETECT_CONTENT as (i32) {
+    params.auto_detect_content = value != 0;
+    return 1i32;
+  }
+  if p as (i32) == BrotliEncoderParameter::BROTLI_PARAM_SKIP_INCOMPRESSIBLE as (i32) {
+    params.skip_incompressible = value != 0;
+    return 1i32;
+  }
+  if p as (i32) == BrotliEncoderParameter::BROTLI_PARAM_DETERMINISTIC as (i32) {
+    params.deterministic = value != 0;
+    return 1i32;
+  }
+  if p as (i32) == BrotliEncoderParameter::BROTLI_PARAM_LONG_DISTANCE_MATCHING as (i32) {
+    params.long_distance_matching = value != 0;
+    return 1i32;
+  }
   0i32  
 }
 
@@ -353,6 +383,60 @@ pub fn BrotliEncoderSetParameter<Alloc: BrotliAlloc>
   }
   set_parameter(&mut state.params, p, value)
 }
+
+// returns true if every byte handed to the encoder has been written out as part of a metablock
+pub fn BrotliEncoderIsAtMetablockBoundary<Alloc: BrotliAlloc>(s: &BrotliEncoderStateStruct<Alloc>) -> bool {
+  s.num_commands_ == 0 && s.last_insert_len_ == 0 && s.last_processed_pos_ == s.input_pos_ &&
+    (s.last_flush_pos_ == s.input_pos_ || s.params.quality == 0 || s.params.quality == 1)
+}
+
+// switches the quality of a running stream that was started with adaptive_quality set
+// the switch only succeeds between metablocks; returns 0 if the stream is mid-metablock
+pub fn BrotliEncoderChangeQuality<Alloc: BrotliAlloc>(s: &mut BrotliEncoderStateStruct<Alloc>,
+                                                      quality: i32) -> i32 {
+  let quality = brotli_min_int(11i32, brotli_max_int(0i32, quality));
+  if !s.is_initialized_ {
+    s.params.quality = quality;
+    return 1i32;
+  }
+  if !s.params.adaptive_quality || !BrotliEncoderIsAtMetablockBoundary(s) {
+    return 0i32;
+  }
+  if quality == s.params.quality {
+    return 1i32;
+  }
+  let was_fragment = s.params.quality == 0 || s.params.quality == 1;
+  if was_fragment && quality > 1 {
+    // the fragment compressors neither move last_flush_pos_ nor track the distance cache:
+    // resynchronize the position and make sure no stale distance cache entry is ever referenced
+    s.last_flush_pos_ = s.input_pos_;
+    let mask = s.ringbuffer_.mask_;
+    let data = &s.ringbuffer_.data_mo.slice()[s.ringbuffer_.buffer_index as usize..];
+    if s.last_flush_pos_ > 0 {
+      s.prev_byte_ = data[((s.last_flush_pos_ as u32).wrapping_sub(1) & mask) as usize];
+    }
+    if s.last_flush_pos_ > 1 {
+      s.prev_byte2_ = data[((s.last_flush_pos_ as u32).wrapping_sub(2) & mask) as usize];
+    }
+    for item in s.dist_cache_.iter_mut() {
+      *item = 0x7ffffff0;
+    }
+    for item in s.saved_dist_cache_.iter_mut() {
+      *item = 0x7ffffff0;
+    }
+  }
+  // the hasher type depends on the quality, so a fresh one gets set up for the next metablock
+  DestroyHasher(&mut s.m8, &mut s.hasher_);
+  s.params.quality = quality;
+  ChooseDistanceParams(&mut s.params);
+  if quality == 0 {
+    InitCommandPrefixCodes(&mut s.cmd_depths_[..],
+                           &mut s.cmd_bits_[..],
+                           &mut s.cmd_code_[..],
+                           &mut s.cmd_code_numbits_);
+  }
+  1i32
+}
 /* "Large Window Brotli" */
 pub const BROTLI_LARGE_MAX_DISTANCE_BITS: u32 = 62;
 pub const BROTLI_LARGE_MIN_WBITS: u32 = 10;
@@ -402,6 +486,15 @@ pub fn BrotliEncoderInitParams() -> BrotliEncoderParams {
            appendable: false,
            magic_number: false,
            favor_cpu_efficiency:false,
+           min_chunk_size:0,
+           max_chunk_size:0,
+           chunk_delimiter:None,
+           chunk_split_hint:None,
+           deterministic:false,
+           adaptive_quality:false,
+           auto_detect_content:false,
+           skip_incompressible:false,
+           long_distance_matching:false,
            hasher: BrotliHasherParams {
              type_: 6,
              block_bits: 9 - 1,
@@ -483,6 +576,7 @@ pub fn BrotliEncoderCreateInstance<Alloc: BrotliAlloc>
     storage_size_: 0usize,
     storage_: <Alloc as Allocator<u8>>::AllocatedMemory::default(),
     hasher_: UnionHasher::<Alloc>::default(),
+    ldm_: LdmHasher::default(),
     large_table_: <Alloc as Allocator<i32>>::AllocatedMemory::default(),
 //    large_table_size_: 0usize,
     cmd_code_numbits_: 0usize,
@@ -514,6 +608,8 @@ pub fn BrotliEncoderCreateInstance<Alloc: BrotliAlloc>
     distance_scratch_space: HistogramDistance::make_nnz_storage(),
     recoder_state: RecoderState::new(),
     custom_dictionary: false,
+    content_report: ContentReport::default(),
+    incompressible_stats: IncompressibleStats::default(),
   }
 }
 
@@ -571,6 +667,7 @@ fn BrotliEncoderCleanupState<Alloc:BrotliAlloc>
   }
   RingBufferFree(&mut s.m8, &mut (*s).ringbuffer_);
   DestroyHasher(&mut s.m8, &mut (*s).hasher_);
+  s.ldm_.free(&mut s.m8);
   {
     <Alloc as Allocator<i32>>::free_cell(&mut s.m8, core::mem::replace(&mut (*s).large_table_,
                                        <Alloc as Allocator<i32>>::AllocatedMemory::default()));
@@ -1252,6 +1349,17 @@ pub fn BrotliEncoderSetCustomDictionaryWithOptionalPrecomputedHasher<Alloc: Brot
       DestroyHasher(m16, &mut orig_hasher);
     }
   }
+  if LdmSetup(s) {
+    s.ldm_.store_range(dict, !0usize, 0, dict_size);
+  }
+}
+
+// allocates the long distance matcher if the params ask for one; returns whether it is enabled
+fn LdmSetup<Alloc: BrotliAlloc>(s: &mut BrotliEncoderStateStruct<Alloc>) -> bool {
+  if s.params.long_distance_matching && !s.ldm_.is_enabled() {
+    s.ldm_ = LdmHasher::new(&mut s.m8, s.params.lgwin);
+  }
+  s.ldm_.is_enabled()
 }
 pub fn BrotliEncoderMaxCompressedSizeMulti(input_size: usize, num_threads: usize) -> usize {
   BrotliEncoderMaxCompressedSize(input_size) + num_threads * 8
@@ -1306,6 +1414,52 @@ pub fn InitInsertCommand(xself: &mut Command, insertlen: usize) {
 
 
 
+// true if a sample of every sample_rate-th byte is too close to 8 bits of entropy per byte to
+// be worth compressing; small samples never qualify since they cannot reach the threshold
+fn LiteralsLookRandom(data: &[u8], mask: usize, last_flush_pos: u64, bytes: usize, sample_rate: u32) -> bool {
+  let mut literal_histo: [u32; 256] =
+    [0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
+     0u32];
+  static kMinEntropy: super::util::floatX = 7.92 as super::util::floatX;
+  let bit_cost_threshold: super::util::floatX = bytes as (super::util::floatX) * kMinEntropy / sample_rate as (super::util::floatX);
+  let t: usize = bytes.wrapping_add(sample_rate as (usize))
+    .wrapping_sub(1usize)
+    .wrapping_div(sample_rate as (usize));
+  let mut pos: u32 = last_flush_pos as (u32);
+  let mut i: usize;
+  i = 0usize;
+  while i < t {
+    {
+      {
+        let _rhs = 1;
+        let _lhs = &mut literal_histo[data[((pos as (usize) & mask) as (usize))] as (usize)];
+        *_lhs = (*_lhs).wrapping_add(_rhs as (u32));
+      }
+      pos = pos.wrapping_add(sample_rate);
+    }
+    i = i.wrapping_add(1 as (usize));
+  }
+  BitsEntropy(&literal_histo[..], 256usize) > bit_cost_threshold
+}
+
+static kMinEntropySampleRate: u32 = 13u32;
+
 fn ShouldCompress(data: &[u8],
                   mask: usize,
                   last_flush_pos: u64,
@@ -1315,46 +1469,7 @@ fn ShouldCompress(data: &[u8],
                   -> i32 {
   if num_commands < (bytes >> 8i32).wrapping_add(2usize) {
     if num_literals as (super::util::floatX) > 0.99 as super::util::floatX * bytes as (super::util::floatX) {
-      let mut literal_histo: [u32; 256] =
-        [0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32,
-         0u32];
-      static kSampleRate: u32 = 13u32;
-      static kMinEntropy: super::util::floatX = 7.92 as super::util::floatX;
-      let bit_cost_threshold: super::util::floatX = bytes as (super::util::floatX) * kMinEntropy / kSampleRate as (super::util::floatX);
-      let t: usize = bytes.wrapping_add(kSampleRate as (usize))
-        .wrapping_sub(1usize)
-        .wrapping_div(kSampleRate as (usize));
-      let mut pos: u32 = last_flush_pos as (u32);
-      let mut i: usize;
-      i = 0usize;
-      while i < t {
-        {
-          {
-            let _rhs = 1;
-            let _lhs = &mut literal_histo[data[((pos as (usize) & mask) as (usize))] as (usize)];
-            *_lhs = (*_lhs).wrapping_add(_rhs as (u32));
-          }
-          pos = pos.wrapping_add(kSampleRate);
-        }
-        i = i.wrapping_add(1 as (usize));
-      }
-      if BitsEntropy(&literal_histo[..], 256usize) > bit_cost_threshold {
+      if LiteralsLookRandom(data, mask, last_flush_pos, bytes, kMinEntropySampleRate) {
         return 0i32;
       }
     }
@@ -1362,6 +1477,24 @@ fn ShouldCompress(data: &[u8],
   1i32
 }
 
+// true if length bytes of the ringbuffer at pos look like random or entropy coded data:
+// the same test ShouldCompress applies after the backward reference search, done up front
+// on blocks that high_entropy_detection_quality deems long enough to be random.
+// Blocks are often too short for a sparse sample to reach the threshold, so they are sampled
+// more densely, keeping at least 16K samples.
+pub fn BrotliIsIncompressible(data: &[u8],
+                              pos: usize,
+                              mask: usize,
+                              length: usize,
+                              high_entropy_detection_quality: u8)
+                              -> bool {
+  if !is_long_enough_to_be_random(length, high_entropy_detection_quality) {
+    return false;
+  }
+  let sample_rate = core::cmp::max(1, core::cmp::min(kMinEntropySampleRate as usize, length >> 14));
+  LiteralsLookRandom(data, mask, pos as u64, length, sample_rate as u32)
+}
+
 /* Chooses the literal context mode for a metablock */
 fn ChooseContextMode(params: &BrotliEncoderParams,
     data: &[u8], pos: usize, mask: usize,
@@ -2371,6 +2504,47 @@ fn EncodeData<Alloc: BrotliAlloc,
       (*s).commands_ = new_commands;
     }
   }
+  if s.params.skip_incompressible && bytes != 0 && s.num_commands_ == 0 && s.last_insert_len_ == 0 &&
+     s.last_flush_pos_ == s.last_processed_pos_ {
+    // nothing is pending, so a random looking block can become its own uncompressed metablock
+    // before the hasher and block splitter spend any time on it
+    s.incompressible_stats.num_blocks_sampled += 1;
+    let data = &(*s).ringbuffer_.data_mo.slice()[((*s).ringbuffer_.buffer_index as usize)..];
+    // quality 0 and 1 turn high entropy detection off, which skip_incompressible turns on
+    let detection_quality = core::cmp::max(s.params.high_entropy_detection_quality, 2);
+    if BrotliIsIncompressible(data, wrapped_last_processed_pos as usize, mask as usize, bytes as usize,
+                              detection_quality) {
+      BrotliStoreUncompressedMetaBlock(&mut s.m8,
+                                       is_last,
+                                       data,
+                                       wrapped_last_processed_pos as usize,
+                                       mask as usize,
+                                       &s.params,
+                                       bytes as usize,
+                                       &mut (*s).recoder_state,
+                                       &mut storage_ix,
+                                       (*s).storage_.slice_mut(),
+                                       false,
+                                       callback);
+      s.incompressible_stats.num_blocks_skipped += 1;
+      s.incompressible_stats.bytes_skipped += u64::from(bytes);
+      (*s).last_bytes_ = (*s).storage_.slice()[((storage_ix >> 3i32) as (usize))] as u16 | (
+        ((*s).storage_.slice()[1 + ((storage_ix >> 3i32) as (usize))] as u16)<<8);
+      (*s).last_bytes_bits_ = (storage_ix & 7u32 as (usize)) as (u8);
+      (*s).last_flush_pos_ = (*s).input_pos_;
+      if UpdateLastProcessedPos(s) != 0 {
+        HasherReset(&mut (*s).hasher_);
+      }
+      let data = &(*s).ringbuffer_.data_mo.slice()[((*s).ringbuffer_.buffer_index as usize)..];
+      (*s).prev_byte_ = data[((((*s).last_flush_pos_ as (u32)).wrapping_sub(1u32) & mask) as (usize))];
+      if (*s).last_flush_pos_ > 1 {
+        (*s).prev_byte2_ = data[(((*s).last_flush_pos_.wrapping_sub(2) as (u32) & mask) as (usize))];
+      }
+      (*s).next_out_ = NextOut::DynamicStorage(0);
+      *out_size = storage_ix >> 3;
+      return 1i32;
+    }
+  }
   InitOrStitchToPreviousBlock(&mut (*s).m8,
                               &mut (*s).hasher_,
                               &mut (*s).ringbuffer_.data_mo.slice_mut()[((*s).ringbuffer_.buffer_index as (usize))..],
@@ -2379,12 +2553,25 @@ fn EncodeData<Alloc: BrotliAlloc,
                               wrapped_last_processed_pos as (usize),
                               bytes as (usize),
                               is_last);
-  let literal_context_mode = ChooseContextMode(
+  if s.params.auto_detect_content && s.input_pos_ == u64::from(bytes) && s.num_commands_ == 0 &&
+     s.params.mode == BrotliEncoderMode::BROTLI_MODE_GENERIC && !s.custom_dictionary {
+    // the distance parameters can only change before the first command is made,
+    // so fonts are recognized from the first input block alone
+    let analysis = BrotliAnalyzeContent(&mut s.m8,
+                                        &s.ringbuffer_.data_mo.slice()[s.ringbuffer_.buffer_index as usize..],
+                                        0, mask as usize, bytes as usize, true);
+    if analysis.content_type == ContentType::Font {
+      s.params.mode = BrotliEncoderMode::BROTLI_MODE_FONT;
+      ChooseDistanceParams(&mut s.params);
+    }
+  }
+  let mut literal_context_mode = ChooseContextMode(
       &s.params, (*s).ringbuffer_.data_mo.slice(), WrapPosition(s.last_flush_pos_) as usize,
       mask as usize, (s.input_pos_.wrapping_sub(s.last_flush_pos_)) as usize);
   if s.num_commands_ != 0 && s.last_insert_len_ == 0 {
       ExtendLastCommand(s, &mut bytes, &mut wrapped_last_processed_pos);
   }
+  LdmSetup(s);
   if false { // we are remapping 10 as quality=9.5 since Zopfli doesn't seem to offer much benefits here
     panic!(r####"
     BrotliCreateZopfliBackwardReferences(m,
@@ -2422,6 +2609,7 @@ fn EncodeData<Alloc: BrotliAlloc,
                                    mask as (usize),
                                    &mut (*s).params,
                                    &mut (*s).hasher_,
+                                   &mut (*s).ldm_,
                                    &mut (*s).dist_cache_,
                                    &mut (*s).last_insert_len_,
                                    &mut (*s).commands_.slice_mut()[((*s).num_commands_ as (usize))..],
@@ -2475,7 +2663,33 @@ fn EncodeData<Alloc: BrotliAlloc,
     //let mut storage_ix: usize = (*s).last_bytes_bits_ as (usize);
     //(*s).storage_.slice_mut()[(0usize)] = (*s).last_bytes_ as u8;
     //(*s).storage_.slice_mut()[(1usize)] = ((*s).last_bytes_ >> 8) as u8;
-
+    // the caller's stride detection, restored once the metablock is written
+    let stride_detection_quality = s.params.stride_detection_quality;
+    if s.params.auto_detect_content && metablock_size != 0 {
+      let analysis = BrotliAnalyzeContent(&mut s.m8,
+                                          &s.ringbuffer_.data_mo.slice()[s.ringbuffer_.buffer_index as usize..],
+                                          WrapPosition(s.last_flush_pos_) as usize,
+                                          mask as usize,
+                                          metablock_size as usize,
+                                          s.last_flush_pos_ == 0 && !s.custom_dictionary);
+      match s.params.mode {
+        BrotliEncoderMode::BROTLI_MODE_GENERIC | BrotliEncoderMode::BROTLI_MODE_TEXT | BrotliEncoderMode::BROTLI_MODE_FONT => {
+          // the distance parameters can no longer change, so a font stays a font
+          if s.params.mode != BrotliEncoderMode::BROTLI_MODE_FONT {
+            s.params.mode = analysis.mode;
+          }
+          if let Some(context_mode) = analysis.literal_context_mode {
+            literal_context_mode = context_mode;
+          }
+        },
+        _ => {}, // a prior forced by the caller takes precedence
+      }
+      // let the prediction modes of the metablock use the structure that was found
+      if analysis.stride > 1 && stride_detection_quality == 0 {
+        s.params.stride_detection_quality = 1;
+      }
+      s.content_report.record(&analysis, metablock_size as usize);
+    }
     WriteMetaBlockInternal(&mut (*s).m8,
                            &mut (*s).ringbuffer_.data_mo.slice_mut()[((*s).ringbuffer_.buffer_index as usize)..],
                            mask as (usize),
@@ -2498,6 +2712,7 @@ fn EncodeData<Alloc: BrotliAlloc,
                            &mut storage_ix,
                            (*s).storage_.slice_mut(),
                            callback);
+    s.params.stride_detection_quality = stride_detection_quality;
 
     (*s).last_bytes_ = (*s).storage_.slice()[((storage_ix >> 3i32) as (usize))] as u16 | (
           ((*s).storage_.slice()[1 + ((storage_ix >> 3i32) as (usize))] as u16)<<8);
@@ -2880,7 +3095,7 @@ pub fn BrotliEncoderCompressStream<Alloc: BrotliAlloc,
      BrotliEncoderStreamState::BROTLI_STREAM_PROCESSING as (i32) && (*available_in != 0usize) {
     return 0i32;
   }
-  if ((*s).params.quality == 0i32 || (*s).params.quality == 1i32) && !s.params.catable { // this part of the code does not support concatability
+  if ((*s).params.quality == 0i32 || (*s).params.quality == 1i32) && !s.params.catable && !s.params.adaptive_quality { // this part of the code does not support concatability or quality changes
     return BrotliEncoderCompressStreamFast(s,
                                            op,
                                            available_in,
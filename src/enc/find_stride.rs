@@ -122,7 +122,7 @@ impl<AllocU32:alloc::Allocator<u32>> EntropyBucketPopulation<AllocU32> {
    }
 }
 
-const NUM_STRIDES:usize = 8;
+pub const NUM_STRIDES:usize = 8;
 #[derive(Copy,Clone)]
 pub struct BucketPopIndex {
     pub val: u8,
@@ -538,6 +538,12 @@ impl<AllocU32:alloc::Allocator<u32> > EntropyTally<AllocU32> {
         Self::new(m32, Some(0))
     }
     fn observe_input_stream(&mut self, input0:&[u8], input1:&[u8]) {
+        self.tally_input_stream(input0, input1);
+        self.stride_costs();
+    }
+    // counts every byte under each of the NUM_STRIDES bytes before it without costing the
+    // populations, so several disjoint samples can be tallied before calling stride_costs
+    pub fn tally_input_stream(&mut self, input0:&[u8], input1:&[u8]) {
         let mut priors = [0u8;NUM_STRIDES];
         for val in input0.iter().chain(input1.iter()) {
             for stride in 0..NUM_STRIDES {
@@ -550,9 +556,15 @@ impl<AllocU32:alloc::Allocator<u32> > EntropyTally<AllocU32> {
                 priors[0] = *val;
             }
         }
-        for stride in 0..NUM_STRIDES {
-            self.pop[stride].cached_bit_entropy = HuffmanCost(self.pop[stride].bucket_populations.slice());
+    }
+    // the cost in bits of everything tallied so far, indexed by stride - 1
+    pub fn stride_costs(&mut self) -> [floatY; NUM_STRIDES] {
+        let mut ret = [0.0 as floatY; NUM_STRIDES];
+        for (cost, pop) in ret.iter_mut().zip(self.pop.iter_mut()) {
+            pop.cached_bit_entropy = HuffmanCost(pop.bucket_populations.slice());
+            *cost = pop.cached_bit_entropy;
         }
+        ret
     }
     fn identify_best_population_and_update_cache(&mut self) -> u8 {
         let mut old_bit_entropy : [floatY; NUM_STRIDES] = [0.0; NUM_STRIDES];
@@ -320,7 +320,7 @@ impl CostAccessors for HistogramDistance {
 
 
 
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
 pub enum ContextType {
   CONTEXT_LSB6 = 0,
   CONTEXT_MSB6 = 1,
@@ -434,14 +434,7 @@ pub fn HistogramAddHistogram<HistogramType:SliceWrapperMut<u32> + SliceWrapper<u
 ){
   let old_total_count = (*xself).total_count();
   (*xself).set_total_count(old_total_count + (*v).total_count());
-  let h0 = xself.slice_mut();
-  let h1 = v.slice();
-  let n = min(h0.len(), h1.len());
-  for i in 0..n {
-    let mut h0val = &mut h0[i];
-    let val = h0val.wrapping_add(h1[i]);
-    *h0val = val;
-  }
+  super::arch::add_u32(xself.slice_mut(), v.slice());
 }
 pub fn HistogramSelfAddHistogram<HistogramType:SliceWrapperMut<u32> + SliceWrapper<u32> + CostAccessors>(
     xself : &mut [HistogramType], i0 : usize, i1 : usize
@@ -14,6 +14,7 @@ pub mod static_dict_lut;
 pub mod dictionary_hash;
 pub mod util;
 pub mod utf8_util;
+pub mod content_detect;
 pub mod bit_cost;
 pub mod cluster;
 pub mod literal_cost;
@@ -37,25 +38,18 @@ pub mod context_map_entropy;
 pub mod pdf;
 pub use self::combined_alloc::{CombiningAllocator, BrotliAlloc};
 mod compat;
+mod arch;
 pub mod threading;
 pub mod singlethreading;
 pub mod multithreading;
 pub mod fixed_queue;
 pub mod worker_pool;
-#[cfg(feature="simd")]
-use packed_simd::{i16x16, f32x8, i32x8};
-#[cfg(feature="simd")]
-pub type s16 = i16x16;
-#[cfg(feature="simd")]
-pub type v8 = f32x8;
-#[cfg(feature="simd")]
-pub type s8 = i32x8;
-#[cfg(not(feature="simd"))]
+pub mod adaptive;
+pub mod tune;
 pub type s16 = compat::Compat16x16;
-#[cfg(not(feature="simd"))]
 pub type v8 = compat::CompatF8;
-#[cfg(not(feature="simd"))]
 pub type s8 = compat::Compat32x8;
+pub use self::arch::{SimdBackend, BrotliEncoderSimdBackend};
 
 mod test;
 mod weights;
@@ -64,12 +58,23 @@ pub use self::util::floatX;
 pub use self::pdf::PDF;
 pub use self::hash_to_binary_tree::ZopfliNode;
 pub use self::backward_references::{BrotliEncoderParams, UnionHasher};
+pub use self::backward_references::{BrotliEncoderParseProfile, BrotliEncoderPrintProfile, ParamsProfile, ProfileError,
+                                    ProfileErrorKind};
 pub use self::encode::{
     BrotliEncoderInitParams,
     BrotliEncoderSetParameter,
     BrotliEncoderMaxCompressedSizeMulti,
     BrotliEncoderMaxCompressedSize,
+    BrotliEncoderChangeQuality,
+    BrotliIsIncompressible,
 };
+pub use self::adaptive::{AdaptiveQualityController, AdaptiveTarget};
+pub use self::content_detect::{BrotliAnalyzeContent, ContentAnalysis, ContentReport, ContentType,
+                               IncompressibleStats};
+#[cfg(feature="std")]
+pub use self::adaptive::BrotliCompressAdaptive;
+#[cfg(feature="std")]
+pub use self::tune::{BrotliTuneParams, TuneCandidate, TuneKnob, TuneOptions, TuneResult, TuneStrategy};
 use self::encode::{BrotliEncoderCreateInstance, BrotliEncoderDestroyInstance,
                    BrotliEncoderOperation,
                    BrotliEncoderSetCustomDictionary,
@@ -100,12 +105,14 @@ pub use self::threading::{SendAlloc,
 #[cfg(feature="std")]
 pub use self::worker_pool::{
   compress_worker_pool,
+  compress_worker_pool_with_dictionary,
   new_work_pool,
   WorkerPool,
 };
 #[cfg(not(feature="std"))]
 pub use self::singlethreading::{
   compress_worker_pool,
+  compress_worker_pool_with_dictionary,
   new_work_pool,
   WorkerPool,
 };
@@ -127,12 +134,35 @@ pub fn compress_multi<Alloc:BrotliAlloc+Send+'static,
   compress_worker_pool(params, owned_input,output, alloc_per_thread,&mut work_pool)
 }
 
+#[cfg(feature="std")]
+pub fn compress_multi_with_dictionary<Alloc:BrotliAlloc+Send+'static,
+                      SliceW: SliceWrapper<u8>+Send+'static+Sync> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>,
+                                   backward_references::UnionHasher<Alloc>,
+                                   Alloc,
+                                   <WorkerPool<CompressionThreadResult<Alloc>, backward_references::UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)> as BatchSpawnableLite<CompressionThreadResult<Alloc>, backward_references::UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)>>::JoinHandle>],
+) -> Result<usize, BrotliEncoderThreadError>
+  where <Alloc as Allocator<u8>>::AllocatedMemory: Send,
+        <Alloc as Allocator<u16>>::AllocatedMemory: Send+Sync,
+        <Alloc as Allocator<u32>>::AllocatedMemory: Send+Sync {
+  let mut work_pool = self::worker_pool::new_work_pool(alloc_per_thread.len() - 1);
+  compress_worker_pool_with_dictionary(params, owned_input, dictionary, output, alloc_per_thread,&mut work_pool)
+}
+
 #[cfg(not(feature="std"))]
-pub use self::singlethreading::compress_multi;
+pub use self::singlethreading::{compress_multi, compress_multi_with_dictionary};
 #[cfg(not(feature="std"))]
 pub use self::singlethreading::compress_multi as compress_multi_no_threadpool;
+#[cfg(not(feature="std"))]
+pub use self::singlethreading::compress_multi_with_dictionary as compress_multi_no_threadpool_with_dictionary;
 #[cfg(feature="std")]
 pub use self::multithreading::compress_multi as compress_multi_no_threadpool;
+#[cfg(feature="std")]
+pub use self::multithreading::compress_multi_with_dictionary as compress_multi_no_threadpool_with_dictionary;
 
 
 #[cfg(feature="std")]
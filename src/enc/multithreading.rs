@@ -11,6 +11,7 @@ use enc::BrotliEncoderParams;
 use enc::backward_references::UnionHasher;
 use enc::threading::{
   CompressMulti,
+  CompressMultiWithDictionary,
   SendAlloc,
   InternalSendAlloc,
   BatchSpawnable,
@@ -146,3 +147,20 @@ pub fn compress_multi<Alloc:BrotliAlloc+Send+'static,
   CompressMulti(params, owned_input, output, alloc_per_thread, &mut MultiThreadedSpawner::default())
 }
 
+pub fn compress_multi_with_dictionary<Alloc:BrotliAlloc+Send+'static,
+                      SliceW: SliceWrapper<u8>+Send+'static+Sync> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>,
+                                   UnionHasher<Alloc>,
+                                   Alloc,
+                                   <MultiThreadedSpawner as BatchSpawnable<CompressionThreadResult<Alloc>,UnionHasher<Alloc>, Alloc, SliceW>>::JoinHandle>],
+) -> Result<usize, BrotliEncoderThreadError>
+  where <Alloc as Allocator<u8>>::AllocatedMemory: Send,
+        <Alloc as Allocator<u16>>::AllocatedMemory: Send+Sync,
+        <Alloc as Allocator<u32>>::AllocatedMemory: Send+Sync {
+  CompressMultiWithDictionary(params, owned_input, dictionary, output, alloc_per_thread, &mut MultiThreadedSpawner::default())
+}
+
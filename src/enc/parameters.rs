@@ -30,6 +30,11 @@ pub enum BrotliEncoderParameter {
   BROTLI_PARAM_MAGIC_NUMBER = 169,
   BROTLI_PARAM_NO_DICTIONARY = 170,
   BROTLI_PARAM_FAVOR_EFFICIENCY = 171,
+  BROTLI_PARAM_ADAPTIVE_QUALITY = 172,
+  BROTLI_PARAM_AUTO_DETECT_CONTENT = 173,
+  BROTLI_PARAM_SKIP_INCOMPRESSIBLE = 174,
+  BROTLI_PARAM_DETERMINISTIC = 175,
+  BROTLI_PARAM_LONG_DISTANCE_MATCHING = 176,
   UNUSED7=7,
   UNUSED8=8,
   UNUSED9=9,
@@ -173,11 +178,6 @@ pub enum BrotliEncoderParameter {
   UNUSED147=147,
   UNUSED148=148,
   UNUSED149=149,
-  UNUSED172=172,
-  UNUSED173=173,
-  UNUSED174=174,
-  UNUSED175=175,
-  UNUSED176=176,
   UNUSED177=177,
   UNUSED178=178,
   UNUSED179=179,
@@ -8,8 +8,6 @@ use super::ir_interpret::{IRInterpreter, push_base};
 use super::util::{floatX, FastLog2u16};
 use super::find_stride;
 use super::{s16, v8};
-#[cfg(feature="simd")]
-use packed_simd::IntoBits;
 // the high nibble, followed by the low nibbles
 pub const CONTEXT_MAP_PRIOR_SIZE: usize = 256 * 17;
 pub const STRIDE_PRIOR_SIZE: usize = 256 * 256 * 2;
@@ -8,6 +8,7 @@ use core::marker::PhantomData;
 use super::backward_references::{UnionHasher};
 use enc::threading::{
   CompressMulti,
+  CompressMultiWithDictionary,
   SendAlloc,
   InternalSendAlloc,
   BatchSpawnable,
@@ -134,6 +135,20 @@ pub fn compress_multi<Alloc:BrotliAlloc+Send+'static,
   CompressMulti(params, owned_input, output, alloc_per_thread, &mut SingleThreadedSpawner::default())
 }
 
+pub fn compress_multi_with_dictionary<Alloc:BrotliAlloc+Send+'static,
+                      SliceW: SliceWrapper<u8>+Send+'static+Sync> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>,
+                                   UnionHasher<Alloc>,
+                                   Alloc,
+                                   <SingleThreadedSpawner as BatchSpawnable<CompressionThreadResult<Alloc>,UnionHasher<Alloc>, Alloc, SliceW>>::JoinHandle>],
+) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send {
+  CompressMultiWithDictionary(params, owned_input, dictionary, output, alloc_per_thread, &mut SingleThreadedSpawner::default())
+}
+
 pub struct WorkerPool<A,B,C, D> {
   a: PhantomData<A>,
   b: PhantomData<B>,
@@ -165,3 +180,21 @@ pub fn compress_worker_pool<Alloc:BrotliAlloc+Send+'static,
 ) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send {
   compress_multi(params, owned_input, output, alloc_per_thread)
 }
+
+pub fn compress_worker_pool_with_dictionary<Alloc:BrotliAlloc+Send+'static,
+                      SliceW: SliceWrapper<u8>+Send+'static+Sync> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>,
+                                   UnionHasher<Alloc>,
+                                   Alloc,
+                                   <SingleThreadedSpawner as BatchSpawnable<CompressionThreadResult<Alloc>,
+                                                                            UnionHasher<Alloc>,
+                                                                            Alloc,
+                                                                            SliceW>>::JoinHandle>],
+  _worker_pool:&mut WorkerPool<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)>,
+) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send {
+  compress_multi_with_dictionary(params, owned_input, dictionary, output, alloc_per_thread)
+}
@@ -121,12 +121,7 @@ pub fn SlowerFindMatchLengthWithLimit(s1: &[u8], s2: &[u8], limit: usize) -> usi
 // factor of 5 slower (example takes 90 seconds)
 #[allow(unused)]
 pub fn FindMatchLengthWithLimit(s1: &[u8], s2: &[u8], limit: usize) -> usize {
-  for (index, pair) in s1[..limit].iter().zip(s2[..limit].iter()).enumerate() {
-    if *pair.0 != *pair.1 {
-      return index;
-    }
-  }
-  return limit;
+  super::arch::find_match_length(s1, s2, limit)
 }
 #[allow(unused)]
 pub fn FindMatchLengthWithLimitMin4(s1: &[u8], s2: &[u8], limit: usize) -> usize {
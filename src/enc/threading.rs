@@ -279,32 +279,177 @@ pub fn CompressMultiSlice<Alloc:BrotliAlloc+Send+'static,
   ret
 }
 
-fn get_range(thread_index: usize, num_threads: usize, file_size: usize) -> Range<usize> {
-    ((thread_index * file_size) / num_threads)..(((thread_index + 1) * file_size) / num_threads)
+// the chunk size of deterministic mode when max_chunk_size is not set
+pub const DETERMINISTIC_CHUNK_SIZE: usize = 1 << 22;
+
+fn get_max_chunk_size(params: &BrotliEncoderParams) -> usize {
+  if params.deterministic && params.max_chunk_size == 0 {
+    DETERMINISTIC_CHUNK_SIZE
+  } else {
+    params.max_chunk_size
+  }
+}
+
+// one chunk per thread unless min_chunk_size or max_chunk_size ask for fewer or more
+fn get_num_chunks(params: &BrotliEncoderParams, num_threads: usize, data_size: usize) -> usize {
+  let mut num_chunks = if params.deterministic {1} else {num_threads};
+  let max_chunk_size = get_max_chunk_size(params);
+  if let Some(whole_chunks) = data_size.checked_div(max_chunk_size) {
+    let needed = whole_chunks + if whole_chunks * max_chunk_size != data_size {1} else {0};
+    num_chunks = core::cmp::max(num_chunks, needed);
+  }
+  if let Some(fitting_chunks) = data_size.checked_div(params.min_chunk_size) {
+    num_chunks = core::cmp::min(num_chunks, fitting_chunks);
+  }
+  core::cmp::max(num_chunks, 1)
+}
+
+// the number of chunks CompressMulti splits input_size bytes into,
+// e.g. to size the output with BrotliEncoderMaxCompressedSizeMulti
+pub fn BrotliEncoderNumChunks(params: &BrotliEncoderParams, input_size: usize, num_threads: usize) -> usize {
+  get_num_chunks(params, num_threads, input_size)
+}
+
+fn get_nominal_boundary(chunk_index: usize, num_chunks: usize, file_size: usize) -> usize {
+  (chunk_index * file_size) / num_chunks
+}
+
+fn is_split_point(params: &BrotliEncoderParams, input: &[u8], pos: usize) -> bool {
+  if let Some(delimiter) = params.chunk_delimiter {
+    if input[pos - 1] == delimiter {
+      return true;
+    }
+  }
+  if let Some(hint) = params.chunk_split_hint {
+    return hint(input, pos);
+  }
+  false
+}
+
+// the start of a chunk is its equal-split boundary, moved back to the nearest split point that keeps
+// both chunks around it within min_chunk_size and max_chunk_size, so every chunk is placed independently
+fn get_boundary(params: &BrotliEncoderParams, input: &[u8], chunk_index: usize, num_chunks: usize) -> usize {
+  if chunk_index == 0 {
+    return 0;
+  }
+  if chunk_index >= num_chunks {
+    return input.len();
+  }
+  let nominal = get_nominal_boundary(chunk_index, num_chunks, input.len());
+  if params.chunk_delimiter.is_none() && params.chunk_split_hint.is_none() {
+    return nominal;
+  }
+  let mut lowest = get_nominal_boundary(chunk_index - 1, num_chunks, input.len())
+    + core::cmp::max(params.min_chunk_size, 1);
+  let max_chunk_size = get_max_chunk_size(params);
+  if max_chunk_size != 0 {
+    let next = get_nominal_boundary(chunk_index + 1, num_chunks, input.len());
+    lowest = core::cmp::max(lowest, next.saturating_sub(max_chunk_size));
+  }
+  let mut pos = nominal;
+  while pos >= lowest {
+    if is_split_point(params, input, pos) {
+      return pos;
+    }
+    pos -= 1;
+  }
+  nominal
+}
+
+fn get_range(params: &BrotliEncoderParams, input: &[u8], chunk_index: usize, num_threads: usize) -> Range<usize> {
+  let num_chunks = get_num_chunks(params, num_threads, input.len());
+  get_boundary(params, input, chunk_index, num_chunks)..get_boundary(params, input, chunk_index + 1, num_chunks)
+}
+
+// what the threads share: the input, the params for it and the custom dictionary the decoder
+// will be given before the output
+pub trait InputAndParams {
+  fn input(&self) -> &[u8];
+  fn params(&self) -> &BrotliEncoderParams;
+  fn dictionary(&self) -> &[u8];
+}
+
+impl<SliceW:SliceWrapper<u8>> InputAndParams for (SliceW, BrotliEncoderParams) {
+  fn input(&self) -> &[u8] {
+    self.0.slice()
+  }
+  fn params(&self) -> &BrotliEncoderParams {
+    &self.1
+  }
+  fn dictionary(&self) -> &[u8] {
+    &[]
+  }
+}
+
+impl<SliceW:SliceWrapper<u8>> InputAndParams for (SliceW, BrotliEncoderParams, SliceW) {
+  fn input(&self) -> &[u8] {
+    self.0.slice()
+  }
+  fn params(&self) -> &BrotliEncoderParams {
+    &self.1
+  }
+  fn dictionary(&self) -> &[u8] {
+    self.2.slice()
+  }
 }
 
 fn compress_part<Alloc: BrotliAlloc+Send+'static,
-                 SliceW:SliceWrapper<u8>>(
-  hasher: UnionHasher<Alloc>,
-  thread_index: usize,
+                 U:InputAndParams>(
+  mut hasher: UnionHasher<Alloc>,
+  chunk_index: usize,
   num_threads: usize,
-  input_and_params:&(SliceW, BrotliEncoderParams),
+  input_and_params:&U,
   mut alloc: Alloc,
 ) -> CompressionThreadResult<Alloc> where <Alloc as Allocator<u8>>::AllocatedMemory:Send+'static {
-  let mut range = get_range(thread_index, num_threads, input_and_params.0.len());
+  let input = input_and_params.input();
+  let dictionary = input_and_params.dictionary();
+  let mut range = get_range(input_and_params.params(), input, chunk_index, num_threads);
   let mut mem = <Alloc as Allocator<u8>>::alloc_cell(&mut alloc,
                                                      BrotliEncoderMaxCompressedSize(range.end - range.start));
   let mut state = BrotliEncoderCreateInstance(alloc);
-  state.params = input_and_params.1.clone();
-  if thread_index != 0 {
+  state.params = input_and_params.params().clone();
+  if chunk_index != 0 {
     state.params.catable = true; // make sure we can concatenate this to the other work results
     state.params.magic_number = false; // no reason to pepper this around
   }
   state.params.appendable = true; // make sure we are at least appendable, so that future items can be catted in
-  if thread_index != 0 {
+  // the dictionary and the input before the chunk (up to the window) prime the ring buffer and hasher, so matches
+  // may reach into them: after BroCatli joins the streams the decoder has those bytes in its window at the same
+  // distances. catable only drops the distance cache, which depends on how the previous chunk ended
+  let max_dict_size = (1usize << state.params.lgwin).wrapping_sub(16usize);
+  let dictionary_used = if range.start < max_dict_size {
+    core::cmp::min(dictionary.len(), max_dict_size - range.start)
+  } else {
+    0
+  };
+  if dictionary_used != dictionary.len() {
+    // a shared hasher holds the whole dictionary, but the window cuts it here
+    hasher.free(&mut state.m8);
+    hasher = UnionHasher::Uninit;
+  }
+  if dictionary_used == 0 {
+    if range.start != 0 {
+      BrotliEncoderSetCustomDictionaryWithOptionalPrecomputedHasher(
+        &mut state, range.start, &input[..range.start], hasher,
+      );
+    }
+  } else {
+    let mut prime = <Alloc as Allocator<u8>>::alloc_cell(&mut state.m8, dictionary_used + range.start);
+    prime.slice_mut()[..dictionary_used].clone_from_slice(&dictionary[dictionary.len() - dictionary_used..]);
+    prime.slice_mut()[dictionary_used..].clone_from_slice(&input[..range.start]);
+    if let UnionHasher::Uninit = hasher {
+    } else {
+      // the shared hasher holds the dictionary: add the input before the chunk
+      let overlap = hasher.StoreLookahead().wrapping_sub(1usize);
+      if prime.len() > overlap {
+        hasher.BulkStoreRange(prime.slice(), !(0usize),
+                              if dictionary_used > overlap {dictionary_used - overlap} else {0}, prime.len() - overlap);
+      }
+    }
     BrotliEncoderSetCustomDictionaryWithOptionalPrecomputedHasher(
-      &mut state, range.start, &input_and_params.0.slice()[..range.start], hasher,
+      &mut state, prime.len(), prime.slice(), hasher,
     );
+    <Alloc as Allocator<u8>>::free_cell(&mut state.m8, prime);
   }
   let mut out_offset = 0usize;
   let compression_result;
@@ -315,7 +460,7 @@ fn compress_part<Alloc: BrotliAlloc+Send+'static,
     let result = BrotliEncoderCompressStream(&mut state,
                                              BrotliEncoderOperation::BROTLI_OPERATION_FINISH,
                                              &mut available_in,
-                                             &input_and_params.0.slice()[range.clone()],
+                                             &input[range.clone()],
                                              &mut next_in_offset,  
                                              &mut available_out,
                                              mem.slice_mut(),
@@ -362,80 +507,139 @@ pub fn CompressMulti<Alloc:BrotliAlloc+Send+'static,
   alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, Spawner::JoinHandle>],
   thread_spawner: &mut Spawner,
 ) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send{
-  let num_threads = alloc_per_thread.len();
   let actually_owned_mem = mem::replace(owned_input, Owned(InternalOwned::Borrowed));
   let mut owned_input_pair = Owned::new((actually_owned_mem.unwrap(), params.clone()));
-  // start thread spawner
-  let mut spawner_and_input = thread_spawner.make_spawner(&mut owned_input_pair);
-  if num_threads > 1 {
-    // spawn first thread without "custom dictionary" while we compute the custom dictionary for other work items
-      thread_spawner.spawn(&mut spawner_and_input, &mut alloc_per_thread[0], 0, num_threads, compress_part);
-    }
-    // populate all hashers at once, cloning them one by one
-    let mut compression_last_thread_result;
-    if num_threads > 1 && params.favor_cpu_efficiency {
-      let mut local_params = params.clone();
-      SanitizeParams(&mut local_params);
-      let mut hasher = UnionHasher::Uninit;
-      HasherSetup(alloc_per_thread[num_threads -1].0.unwrap_input().0,
-                  &mut hasher,
-                  &mut local_params,
-                  &[],
-                  0,
-                  0,
-                  0);
-      for thread_index in 1..num_threads {
-        let res = spawner_and_input.view(|input_and_params:&(SliceW, BrotliEncoderParams)| -> () {
-          let range = get_range(thread_index - 1, num_threads, input_and_params.0.len());
-          let overlap = hasher.StoreLookahead().wrapping_sub(1usize);
-          if range.end - range.start > overlap {
-            hasher.BulkStoreRange(input_and_params.0.slice(),
-                                  !(0usize),
-                                  if range.start > overlap {range.start - overlap} else {0}, range.end - overlap);
+  let compression_result = compress_multi_shared(params, &mut owned_input_pair, output, alloc_per_thread, thread_spawner);
+  if let InternalOwned::Item(input_and_params) = owned_input_pair.0 {
+    *owned_input = Owned::new(input_and_params.0); // return the input to its rightful owner before returning
+  }
+  compression_result
+}
+
+// like CompressMulti, with a custom dictionary the decoder will be given before the output.
+// every chunk is primed with it, so the threads share an owned copy of it with the input
+pub fn CompressMultiWithDictionary<Alloc:BrotliAlloc+Send+'static,
+                                   SliceW: SliceWrapper<u8>+Send+'static+Sync,
+                                   Spawner:BatchSpawnableLite<CompressionThreadResult<Alloc>,
+                                                              UnionHasher<Alloc>,
+                                                              Alloc,
+                                                              (SliceW, BrotliEncoderParams, SliceW)>> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, Spawner::JoinHandle>],
+  thread_spawner: &mut Spawner,
+) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send{
+  let actually_owned_mem = mem::replace(owned_input, Owned(InternalOwned::Borrowed));
+  let mut owned_input_triple = Owned::new((actually_owned_mem.unwrap(), params.clone(), dictionary));
+  let compression_result = compress_multi_shared(params, &mut owned_input_triple, output, alloc_per_thread, thread_spawner);
+  if let InternalOwned::Item(input_and_params) = owned_input_triple.0 {
+    *owned_input = Owned::new(input_and_params.0); // return the input to its rightful owner before returning
+  }
+  compression_result
+}
+
+fn compress_multi_shared<Alloc:BrotliAlloc+Send+'static,
+                         U:InputAndParams+Send+'static+Sync,
+                         Spawner:BatchSpawnableLite<CompressionThreadResult<Alloc>,
+                                                    UnionHasher<Alloc>,
+                                                    Alloc,
+                                                    U>> (
+  params:&BrotliEncoderParams,
+  owned_input_pair: &mut Owned<U>,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, Spawner::JoinHandle>],
+  thread_spawner: &mut Spawner,
+) -> Result<usize, BrotliEncoderThreadError> where <Alloc as Allocator<u8>>::AllocatedMemory: Send, <Alloc as Allocator<u16>>::AllocatedMemory: Send, <Alloc as Allocator<u32>>::AllocatedMemory: Send{
+  let num_threads = alloc_per_thread.len();
+  let num_chunks = BrotliEncoderNumChunks(params, owned_input_pair.view().input().len(), num_threads);
+  let has_dictionary = owned_input_pair.view().dictionary().len() != 0;
+  let share_hasher = num_chunks > 1 && params.favor_cpu_efficiency;
+  // the shared hasher is allocated from the last thread of the first round and cloned for every other chunk
+  let hasher_owner = core::cmp::min(num_threads, num_chunks) - 1;
+  let mut hasher = UnionHasher::Uninit;
+  let mut hasher_ready = false;
+  let mut stored_until = 0usize;
+  let mut compression_result = Err(BrotliEncoderThreadError::InsufficientOutputSpace);
+  let mut out_file_size = 0usize;
+  let mut bro_cat_li = BroCatli::new();
+  // with max_chunk_size there can be more chunks than threads: each round compresses one chunk per thread
+  let mut first_chunk = 0usize;
+  while first_chunk < num_chunks {
+    let num_round_chunks = core::cmp::min(num_threads, num_chunks - first_chunk);
+    let round = &mut alloc_per_thread[..num_round_chunks];
+    let mut spawner_and_input = thread_spawner.make_spawner(owned_input_pair);
+    let local_index = num_round_chunks - 1;
+    let mut compression_local_thread_result;
+    for chunk_index in first_chunk..first_chunk + num_round_chunks {
+      let thread_index = chunk_index - first_chunk;
+      if share_hasher && (chunk_index != 0 || has_dictionary) {
+        if !hasher_ready {
+          let mut local_params = params.clone();
+          SanitizeParams(&mut local_params);
+          HasherSetup(round[hasher_owner].0.unwrap_input().0,
+                      &mut hasher,
+                      &mut local_params,
+                      &[],
+                      0,
+                      0,
+                      0);
+          hasher_ready = true;
+          // the dictionary comes before every chunk, so it is hashed once here: each chunk adds the input before it
+          let res = spawner_and_input.view(|input_and_params:&U| -> () {
+            let dictionary = input_and_params.dictionary();
+            let overlap = hasher.StoreLookahead().wrapping_sub(1usize);
+            if dictionary.len() > overlap {
+              hasher.BulkStoreRange(dictionary, !(0usize), 0, dictionary.len() - overlap);
+            }
+          });
+          if let Err(_e) = res {
+            return Err(BrotliEncoderThreadError::OtherThreadPanic);
           }
-        });
-        if let Err(_e) = res {
-          return Err(BrotliEncoderThreadError::OtherThreadPanic);
         }
-        if thread_index + 1 != num_threads {
-          {
-            let (alloc, out_hasher) = alloc_per_thread[thread_index].unwrap_view_mut();
-            *out_hasher = hasher.clone_with_alloc(alloc);
+        if !has_dictionary {
+          // everything before the start of a chunk is its dictionary: extend the hasher up to there
+          let res = spawner_and_input.view(|input_and_params:&U| -> () {
+            let range = get_range(input_and_params.params(), input_and_params.input(), chunk_index, num_threads);
+            let overlap = hasher.StoreLookahead().wrapping_sub(1usize);
+            if range.start > stored_until + overlap {
+              hasher.BulkStoreRange(input_and_params.input(),
+                                    !(0usize),
+                                    stored_until, range.start - overlap);
+              stored_until = range.start - overlap;
+            }
+          });
+          if let Err(_e) = res {
+            return Err(BrotliEncoderThreadError::OtherThreadPanic);
           }
-          thread_spawner.spawn(&mut spawner_and_input, &mut alloc_per_thread[thread_index], thread_index, num_threads, compress_part);
         }
-      }
-      let (alloc, _extra) = alloc_per_thread[num_threads -1].replace_with_default();
-      compression_last_thread_result = spawner_and_input.view(move |input_and_params:&(SliceW, BrotliEncoderParams)| -> CompressionThreadResult<Alloc> {
-        compress_part(hasher,
-                      num_threads - 1,
-                      num_threads,
-                      input_and_params,
-                      alloc,
-        )
-      });
-    } else {
-      if num_threads > 1 {
-        for thread_index in 1..num_threads-1 {
-          thread_spawner.spawn(&mut spawner_and_input, &mut alloc_per_thread[thread_index], thread_index, num_threads, compress_part);
+        let (alloc, out_hasher) = round[thread_index].unwrap_view_mut();
+        if chunk_index + 1 == num_chunks && thread_index == hasher_owner {
+          *out_hasher = mem::replace(&mut hasher, UnionHasher::Uninit);
+        } else {
+          *out_hasher = hasher.clone_with_alloc(alloc);
         }
       }
-      let (alloc, _extra) = alloc_per_thread[num_threads - 1].replace_with_default();
-      compression_last_thread_result = spawner_and_input.view(move |input_and_params:&(SliceW, BrotliEncoderParams)| -> CompressionThreadResult<Alloc> {
-        compress_part(UnionHasher::Uninit,
-                      num_threads - 1,
+      if thread_index != local_index {
+        thread_spawner.spawn(&mut spawner_and_input, &mut round[thread_index], chunk_index, num_threads, compress_part);
+      }
+    }
+    {
+      let chunk_index = first_chunk + local_index;
+      let (alloc, chunk_hasher) = round[local_index].replace_with_default();
+      compression_local_thread_result = spawner_and_input.view(move |input_and_params:&U| -> CompressionThreadResult<Alloc> {
+        compress_part(chunk_hasher,
+                      chunk_index,
                       num_threads,
                       input_and_params,
                       alloc,
         )
       });
     }
-    let mut compression_result = Err(BrotliEncoderThreadError::InsufficientOutputSpace);
-    let mut out_file_size = 0usize;
-    let mut bro_cat_li = BroCatli::new();
-    for (index, thread) in alloc_per_thread.iter_mut().enumerate() {
-      let mut cur_result = if index + 1 == num_threads {
-        match mem::replace(&mut compression_last_thread_result, Err(())){
+    for (index, thread) in round.iter_mut().enumerate() {
+      let mut cur_result = if index == local_index {
+        match mem::replace(&mut compression_local_thread_result, Err(())){
           Ok(result) => result,
           Err(_err) => return Err(BrotliEncoderThreadError::OtherThreadPanic),
         }
@@ -477,20 +681,20 @@ pub fn CompressMulti<Alloc:BrotliAlloc+Send+'static,
       }
       thread.0 = InternalSendAlloc::A(cur_result.alloc, UnionHasher::Uninit);
     }
+    match spawner_and_input.unwrap() {
+      Ok(retrieved_owned_input) => *owned_input_pair = Owned::new(retrieved_owned_input),
+      Err(_) => return Err(BrotliEncoderThreadError::OtherThreadPanic),
+    }
     if let Err(e) = compression_result {
       return Err(e);
     }
-    match bro_cat_li.finish(output, &mut out_file_size) {
-      BroCatliResult::Success => compression_result = Ok(out_file_size),
-      err => compression_result = Err(BrotliEncoderThreadError::ConcatenationFinalizationError(err)),
-    }
-    if let Ok(retrieved_owned_input) = spawner_and_input.unwrap() {
-      *owned_input = Owned::new(retrieved_owned_input.0); // return the input to its rightful owner before returning
-    } else {
-      if let Ok(_) = compression_result {
-        compression_result = Err(BrotliEncoderThreadError::OtherThreadPanic);
-      }
-    }
-    compression_result
+    first_chunk += num_round_chunks;
+  }
+  // a no-op unless the last chunk ran on another thread than the one the hasher was allocated from
+  hasher.free(alloc_per_thread[hasher_owner].0.unwrap_input().0);
+  match bro_cat_li.finish(output, &mut out_file_size) {
+    BroCatliResult::Success => compression_result = Ok(out_file_size),
+    err => compression_result = Err(BrotliEncoderThreadError::ConcatenationFinalizationError(err)),
+  }
+  compression_result
 }
-
@@ -0,0 +1,290 @@
+#![cfg(feature="std")]
+// Searches the expert knobs of BrotliEncoderParams on a sample corpus. Every evaluated
+// configuration records its total compressed size and compression time; the result holds the
+// smallest configuration and the Pareto frontier of size versus time.
+use std::io;
+use std::string::String;
+use std::time::Instant;
+use std::vec::Vec;
+
+use super::backward_references::BrotliEncoderParams;
+use super::BrotliCompress;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TuneKnob {
+  // hasher.literal_byte_score, 0 keeps the hasher default of 540
+  LiteralByteScore,
+  StrideDetectionQuality,
+  PriorBitmaskDetection,
+  // literal_adaptation, applied to all four (speed, max) slots like -speed=
+  LiteralAdaptation,
+  CdfAdaptationDetection,
+  AvoidDistancePrefixSearch,
+}
+
+pub const ALL_TUNE_KNOBS: [TuneKnob; 6] = [TuneKnob::LiteralByteScore,
+                                           TuneKnob::StrideDetectionQuality,
+                                           TuneKnob::PriorBitmaskDetection,
+                                           TuneKnob::LiteralAdaptation,
+                                           TuneKnob::CdfAdaptationDetection,
+                                           TuneKnob::AvoidDistancePrefixSearch];
+
+const LITERAL_BYTE_SCORES: [i32; 8] = [0, 100, 200, 300, 400, 700, 1000, 1400];
+// (0, 0) keeps the default speed of the prior evaluation
+const LITERAL_ADAPTATION_SPEEDS: [(u16, u16); 6] = [(0, 0), (1, 16384), (4, 16384), (16, 8192), (32, 4096), (128, 2048)];
+
+impl TuneKnob {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      TuneKnob::LiteralByteScore => "bytescore",
+      TuneKnob::StrideDetectionQuality => "stride",
+      TuneKnob::PriorBitmaskDetection => "prior",
+      TuneKnob::LiteralAdaptation => "speed",
+      TuneKnob::CdfAdaptationDetection => "cdf",
+      TuneKnob::AvoidDistancePrefixSearch => "avoiddistance",
+    }
+  }
+  pub fn from_name(name: &str) -> Option<TuneKnob> {
+    ALL_TUNE_KNOBS.iter().find(|k| k.name() == name).cloned()
+  }
+  pub fn num_values(&self) -> usize {
+    match *self {
+      TuneKnob::LiteralByteScore => LITERAL_BYTE_SCORES.len(),
+      TuneKnob::StrideDetectionQuality => 4,
+      TuneKnob::LiteralAdaptation => LITERAL_ADAPTATION_SPEEDS.len(),
+      TuneKnob::PriorBitmaskDetection | TuneKnob::CdfAdaptationDetection |
+      TuneKnob::AvoidDistancePrefixSearch => 2,
+    }
+  }
+  // sets the knob to its index-th candidate value
+  pub fn apply(&self, params: &mut BrotliEncoderParams, index: usize) {
+    match *self {
+      TuneKnob::LiteralByteScore => params.hasher.literal_byte_score = LITERAL_BYTE_SCORES[index],
+      TuneKnob::StrideDetectionQuality => params.stride_detection_quality = index as u8,
+      TuneKnob::PriorBitmaskDetection => params.prior_bitmask_detection = index as u8,
+      TuneKnob::LiteralAdaptation => params.literal_adaptation = [LITERAL_ADAPTATION_SPEEDS[index]; 4],
+      TuneKnob::CdfAdaptationDetection => params.cdf_adaptation_detection = index as u8,
+      TuneKnob::AvoidDistancePrefixSearch => params.avoid_distance_prefix_search = index != 0,
+    }
+  }
+  fn same_value(&self, a: &BrotliEncoderParams, b: &BrotliEncoderParams) -> bool {
+    match *self {
+      TuneKnob::LiteralByteScore => a.hasher.literal_byte_score == b.hasher.literal_byte_score,
+      TuneKnob::StrideDetectionQuality => a.stride_detection_quality == b.stride_detection_quality,
+      TuneKnob::PriorBitmaskDetection => a.prior_bitmask_detection == b.prior_bitmask_detection,
+      TuneKnob::LiteralAdaptation => a.literal_adaptation == b.literal_adaptation,
+      TuneKnob::CdfAdaptationDetection => a.cdf_adaptation_detection == b.cdf_adaptation_detection,
+      TuneKnob::AvoidDistancePrefixSearch => a.avoid_distance_prefix_search == b.avoid_distance_prefix_search,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TuneStrategy {
+  // every combination of the candidate values
+  Grid,
+  // tries each knob in turn with the others fixed, keeping the smallest, until a pass changes nothing
+  CoordinateDescent{max_passes: usize},
+}
+
+#[derive(Clone, Debug)]
+pub struct TuneOptions {
+  pub knobs: Vec<TuneKnob>,
+  pub strategy: TuneStrategy,
+  // timed compressions per configuration, the fastest is kept
+  pub repeat: usize,
+}
+
+impl Default for TuneOptions {
+  fn default() -> Self {
+    TuneOptions {
+      knobs: ALL_TUNE_KNOBS.to_vec(),
+      strategy: TuneStrategy::CoordinateDescent{max_passes: 3},
+      repeat: 1,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct TuneCandidate {
+  pub params: BrotliEncoderParams,
+  pub compressed_size: usize,
+  pub nanos: u64,
+}
+
+impl TuneCandidate {
+  // the brotli command line flags that reproduce this configuration
+  pub fn cli_arguments(&self) -> String {
+    let p = &self.params;
+    let mut ret = if p.q9_5 {
+      match p.quality {
+        10 => String::from("-q9.5"),
+        11 => String::from("-q9.5x"),
+        _ => String::from("-q9.5y"),
+      }
+    } else {
+      format!("-q{}", p.quality)
+    };
+    ret.push_str(&format!(" -w{}", p.lgwin));
+    if p.hasher.literal_byte_score != 0 {
+      ret.push_str(&format!(" -bytescore={}", p.hasher.literal_byte_score));
+    }
+    match p.stride_detection_quality {
+      0 => {},
+      1 => ret.push_str(" -basicstride"),
+      2 => ret.push_str(" -stride"),
+      _ => ret.push_str(" -advstride"),
+    }
+    if p.prior_bitmask_detection != 0 {
+      ret.push_str(" -findprior");
+    }
+    if p.literal_adaptation != [(0, 0); 4] {
+      let speeds: Vec<String> = p.literal_adaptation.iter().map(|s| format!("{},{}", s.0, s.1)).collect();
+      ret.push_str(&format!(" -speed={}", speeds.join(",")));
+    }
+    if p.cdf_adaptation_detection != 0 {
+      ret.push_str(&format!(" -findspeed={}", p.cdf_adaptation_detection));
+    }
+    if p.avoid_distance_prefix_search {
+      ret.push_str(" -avoiddistanceprefixsearch");
+    }
+    ret
+  }
+}
+
+pub struct TuneResult {
+  // every configuration in the order it was evaluated, the base parameters first
+  pub evaluated: Vec<TuneCandidate>,
+  // index of the smallest configuration, the fastest among equally small ones
+  pub best: usize,
+  // indices of the configurations no other one beats on both size and time, smallest first
+  pub frontier: Vec<usize>,
+}
+
+impl TuneResult {
+  pub fn best(&self) -> &TuneCandidate {
+    &self.evaluated[self.best]
+  }
+}
+
+struct Tuner<'a> {
+  corpus: &'a [&'a [u8]],
+  knobs: &'a [TuneKnob],
+  repeat: usize,
+  evaluated: Vec<TuneCandidate>,
+}
+
+impl<'a> Tuner<'a> {
+  fn find(&self, params: &BrotliEncoderParams) -> Option<usize> {
+    self.evaluated.iter().position(|c| self.knobs.iter().all(|k| k.same_value(&c.params, params)))
+  }
+  fn evaluate(&mut self, params: &BrotliEncoderParams) -> Result<usize, io::Error> {
+    if let Some(index) = self.find(params) {
+      return Ok(index);
+    }
+    let mut compressed_size = 0usize;
+    let mut nanos = 0u64;
+    let mut output = Vec::new();
+    for data in self.corpus.iter() {
+      let mut fastest = u64::max_value();
+      for _ in 0..core::cmp::max(self.repeat, 1) {
+        output.clear();
+        let start = Instant::now();
+        BrotliCompress(&mut &data[..], &mut output, params)?;
+        let elapsed = start.elapsed();
+        fastest = core::cmp::min(fastest, elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos()));
+      }
+      compressed_size += output.len();
+      nanos += fastest;
+    }
+    self.evaluated.push(TuneCandidate {
+      params: params.clone(),
+      compressed_size: compressed_size,
+      nanos: nanos,
+    });
+    Ok(self.evaluated.len() - 1)
+  }
+  fn better(&self, a: usize, b: usize) -> bool {
+    let (a, b) = (&self.evaluated[a], &self.evaluated[b]);
+    a.compressed_size < b.compressed_size || (a.compressed_size == b.compressed_size && a.nanos < b.nanos)
+  }
+  fn grid(&mut self, params: &mut BrotliEncoderParams, knob: usize, best: &mut usize) -> Result<(), io::Error> {
+    if knob == self.knobs.len() {
+      let index = self.evaluate(params)?;
+      if self.better(index, *best) {
+        *best = index;
+      }
+      return Ok(());
+    }
+    let k = self.knobs[knob];
+    for value in 0..k.num_values() {
+      k.apply(params, value);
+      self.grid(params, knob + 1, best)?;
+    }
+    Ok(())
+  }
+  fn coordinate_descent(&mut self, max_passes: usize, best: &mut usize) -> Result<(), io::Error> {
+    for _ in 0..max_passes {
+      let mut changed = false;
+      for knob in 0..self.knobs.len() {
+        let k = self.knobs[knob];
+        let current = *best;
+        for value in 0..k.num_values() {
+          let mut params = self.evaluated[current].params.clone();
+          k.apply(&mut params, value);
+          let index = self.evaluate(&params)?;
+          if self.better(index, *best) {
+            *best = index;
+          }
+        }
+        changed |= *best != current;
+      }
+      if !changed {
+        break;
+      }
+    }
+    Ok(())
+  }
+  fn frontier(&self) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..self.evaluated.len()).collect();
+    order.sort_by(|&a, &b| {
+      let (a, b) = (&self.evaluated[a], &self.evaluated[b]);
+      (a.compressed_size, a.nanos).cmp(&(b.compressed_size, b.nanos))
+    });
+    let mut ret = Vec::new();
+    let mut fastest = u64::max_value();
+    for index in order {
+      if self.evaluated[index].nanos < fastest {
+        fastest = self.evaluated[index].nanos;
+        ret.push(index);
+      }
+    }
+    ret
+  }
+}
+
+// compresses every corpus sample under the configurations chosen by the strategy, starting from base
+pub fn BrotliTuneParams(base: &BrotliEncoderParams,
+                        corpus: &[&[u8]],
+                        options: &TuneOptions) -> Result<TuneResult, io::Error> {
+  let mut tuner = Tuner {
+    corpus: corpus,
+    knobs: &options.knobs[..],
+    repeat: options.repeat,
+    evaluated: Vec::new(),
+  };
+  let mut best = tuner.evaluate(base)?;
+  match options.strategy {
+    TuneStrategy::Grid => {
+      let mut params = base.clone();
+      tuner.grid(&mut params, 0, &mut best)?;
+    },
+    TuneStrategy::CoordinateDescent{max_passes} => tuner.coordinate_descent(max_passes, &mut best)?,
+  }
+  let frontier = tuner.frontier();
+  Ok(TuneResult {
+    evaluated: tuner.evaluated,
+    best: best,
+    frontier: frontier,
+  })
+}
@@ -291,37 +291,43 @@ pub fn FastLog2u16(v: u16) -> floatX {
     log64k[v as usize]
 }
 
-#[cfg(feature="std")]
-#[inline(always)]
-pub fn FastLog2(v: u64) -> floatX {
-    if v < 256 {
-        return kLog2Table[v as usize] as floatX;
-    }
-    (v as f32).log2() as floatX
-}
+// 1/(2k+1) for the series ln(m) = 2 * sum(s^(2k+1) / (2k+1)) with s = (m - 1) / (m + 1)
+const LN_SERIES: [f64; 12] = [1.0, 1.0 / 3.0, 1.0 / 5.0, 1.0 / 7.0, 1.0 / 9.0, 1.0 / 11.0,
+                              1.0 / 13.0, 1.0 / 15.0, 1.0 / 17.0, 1.0 / 19.0, 1.0 / 21.0, 1.0 / 23.0];
 
-#[cfg(not(feature="std"))]
-#[inline(always)]
-pub fn FastLog2(v: u64) -> floatX {
-  if v < 256 {
-    return kLog2Table[v as usize] as floatX;
+// log2 of a positive normal number using only IEEE arithmetic rather than the platform libm, so the
+// cost models round the same way on every target, with or without std
+pub fn PortableLog2(x: f64) -> f64 {
+  let bits = x.to_bits();
+  let mut exponent = ((bits >> 52) & 0x7ff) as i32 - 1023;
+  let mut mantissa = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | 0x3ff0_0000_0000_0000);
+  if mantissa > core::f64::consts::SQRT_2 {
+    mantissa *= 0.5;
+    exponent += 1;
+  }
+  let s = (mantissa - 1.0) / (mantissa + 1.0);
+  let s2 = s * s;
+  let mut series = 0.0f64;
+  for coefficient in LN_SERIES.iter().rev() {
+    series = series * s2 + coefficient;
   }
-  FastLog2u64(v)
+  exponent as f64 + 2.0 * s * series * core::f64::consts::LOG2_E
 }
 
-#[cfg(feature="std")]
 #[inline(always)]
-pub fn FastLog2f64(v: u64) -> floatX {
+pub fn FastLog2(v: u64) -> floatX {
     if v < 256 {
         return kLog2Table[v as usize] as floatX;
     }
-    (v as floatX).log2()
+    PortableLog2(v as f32 as f64) as f32 as floatX
 }
 
-#[cfg(not(feature="std"))]
 #[inline(always)]
 pub fn FastLog2f64(v: u64) -> floatX {
-    FastLog2(v) as floatX
+    if v < 256 {
+        return kLog2Table[v as usize] as floatX;
+    }
+    PortableLog2(v as floatX as f64) as floatX
 }
 
 #[inline]
@@ -345,14 +351,6 @@ pub fn xFastLog2u16(v: u16) -> floatX {
   offset as floatX + kLog2Table[(v >> offset) as u8 as usize] as (floatX)
 }
 
-#[cfg(feature="std")]
-#[inline(always)]
-pub fn FastPow2(v: super::util::floatX) -> super::util::floatX {
-  return (2 as super::util::floatX).powf(v);
-}
-
-
-#[cfg(not(feature="std"))]
 #[inline(always)]
 pub fn FastPow2(v: super::util::floatX) -> super::util::floatX {
    assert!(v >= 0 as super::util::floatX);
@@ -422,6 +420,17 @@ pub fn approx_eq(a : f64, b: f64, tol: f64) {
        assert_eq!(a, b);
     }
 }
+  #[test]
+  fn portable_log2_matches_libm(){
+    let mut v = 256u64;
+    while v < (1u64 << 62) {
+      for &x in [v, v + 1, v + v / 2 + 7].iter() {
+        let y = x as f32 as f64;
+        assert_eq!(super::PortableLog2(y) as f32, y.log2() as f32);
+      }
+      v += v / 4;
+    }
+  }
   #[test]
   fn fast_log2_works(){
     let examples = [4u64, 254u64, 256u64,
@@ -443,7 +452,7 @@ pub fn approx_eq(a : f64, b: f64, tol: f64) {
 }
 
 
-static log64k : [floatX; 65536] = [
+pub static log64k : [floatX; 65536] = [
     0.0,0.0,1.0,1.58496250072,2.0,2.32192809489,2.58496250072,2.80735492206,3.0,3.16992500144,3.32192809489,3.45943161864,3.58496250072,3.70043971814,3.80735492206,3.90689059561,
 4.0,4.08746284125,4.16992500144,4.24792751344,4.32192809489,4.39231742278,4.45943161864,4.52356195606,4.58496250072,4.64385618977,4.70043971814,4.75488750216,4.80735492206,4.85798099513,4.90689059561,4.95419631039,
 5.0,5.04439411936,5.08746284125,5.12928301694,5.16992500144,5.20945336563,5.24792751344,5.28540221886,5.32192809489,5.35755200462,5.39231742278,5.4262647547,5.45943161864,5.49185309633,5.52356195606,5.55458885168,
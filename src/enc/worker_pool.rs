@@ -13,6 +13,7 @@ use enc::BrotliEncoderParams;
 use enc::backward_references::UnionHasher;
 use enc::threading::{
   CompressMulti,
+  CompressMultiWithDictionary,
   SendAlloc,
   InternalSendAlloc,
   BatchSpawnableLite,
@@ -213,12 +214,13 @@ impl <ReturnValue:Send+'static,
 }
 
 
-pub fn new_work_pool<Alloc:BrotliAlloc+Send+'static, SliceW: SliceWrapper<u8>+Send+'static+Sync>(
+// U is (input, params) for compress_worker_pool and (input, params, dictionary) for compress_worker_pool_with_dictionary
+pub fn new_work_pool<Alloc:BrotliAlloc+Send+'static, U:Send+'static+Sync>(
   num_threads:usize,
 ) -> WorkerPool<CompressionThreadResult<Alloc>,
                 UnionHasher<Alloc>,
                 Alloc,
-                (SliceW, BrotliEncoderParams)>
+                U>
   where <Alloc as Allocator<u8>>::AllocatedMemory:Send+'static,
         <Alloc as Allocator<u16>>::AllocatedMemory: Send+Sync,
         <Alloc as Allocator<u32>>::AllocatedMemory: Send+Sync {
@@ -318,4 +320,22 @@ pub fn compress_worker_pool<Alloc:BrotliAlloc+Send+'static,
   CompressMulti(params, owned_input, output, alloc_per_thread, work_pool)
 }
 
+pub fn compress_worker_pool_with_dictionary<Alloc:BrotliAlloc+Send+'static,
+                      SliceW: SliceWrapper<u8>+Send+'static+Sync> (
+  params:&BrotliEncoderParams,
+  owned_input: &mut Owned<SliceW>,
+  dictionary: SliceW,
+  output: &mut [u8],
+  alloc_per_thread:&mut [SendAlloc<CompressionThreadResult<Alloc>,
+                                   UnionHasher<Alloc>,
+                                   Alloc,
+                                   <WorkerPool<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)> as BatchSpawnableLite<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)>>::JoinHandle>],
+  work_pool: &mut WorkerPool<CompressionThreadResult<Alloc>, UnionHasher<Alloc>, Alloc, (SliceW, BrotliEncoderParams, SliceW)>,
+) -> Result<usize, BrotliEncoderThreadError>
+  where <Alloc as Allocator<u8>>::AllocatedMemory: Send,
+        <Alloc as Allocator<u16>>::AllocatedMemory: Send+Sync,
+        <Alloc as Allocator<u32>>::AllocatedMemory: Send+Sync {
+  CompressMultiWithDictionary(params, owned_input, dictionary, output, alloc_per_thread, work_pool)
+}
+
 // out of place thread create
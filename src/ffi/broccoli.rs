@@ -11,16 +11,19 @@ pub use concat::BroCatliResult;
 pub type BroccoliResult = BroCatliResult;
 // a tool to concatenate brotli files together
 
+// matches the data array of BroccoliState in c/brotli/broccoli.h
+pub const BROCCOLI_STATE_DATA_SIZE: usize = 248;
+
 #[repr(C)]
 #[no_mangle]
 pub struct BroccoliState {
     more_data: *mut c_void,
-    current_data: [u8;120],
+    current_data: [u8;BROCCOLI_STATE_DATA_SIZE],
 }
 
 impl Clone for BroccoliState {
   fn clone(&self) -> BroccoliState {
-    let mut cd = [0u8; 120];
+    let mut cd = [0u8; BROCCOLI_STATE_DATA_SIZE];
     cd.clone_from_slice(&self.current_data[..]);
     BroccoliState{
       more_data:self.more_data,
@@ -38,7 +41,7 @@ impl Default for BroccoliState {
 }
 impl From<BroCatli> for BroccoliState {
     fn from(data: BroCatli) -> BroccoliState {
-        let mut buffer = [0u8; 120];
+        let mut buffer = [0u8; BROCCOLI_STATE_DATA_SIZE];
         data.serialize_to_buffer(&mut buffer[..]).unwrap();
         BroccoliState{
             more_data: core::ptr::null_mut(),
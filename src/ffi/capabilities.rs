@@ -0,0 +1,118 @@
+// Lets dynamic loaders check that the library they found was built with the ABI and the
+// optional features they were compiled against; mirrored in c/brotli/capabilities.h.
+use core;
+use super::broccoli::BroccoliState;
+use super::compressor::BrotliEncoderState;
+use super::decompressor::BrotliDecoderState;
+use super::multicompress::BrotliEncoderWorkPool;
+
+// bumped whenever a struct shared with C or an exported signature changes
+pub const BROTLI_FFI_ABI_VERSION: u32 = 1;
+
+pub const BROTLI_FFI_CAPABILITY_MULTITHREADING: u64 = 1; // work pools and multithreaded compression spawn threads
+pub const BROTLI_FFI_CAPABILITY_SIMD: u64 = 2;
+pub const BROTLI_FFI_CAPABILITY_BROCCOLI: u64 = 4; // Broccoli* concatenation functions
+pub const BROTLI_FFI_CAPABILITY_IR_CALLBACK: u64 = 8; // metablock IR can be observed from C
+pub const BROTLI_FFI_CAPABILITY_LARGE_WINDOW: u64 = 16;
+pub const BROTLI_FFI_CAPABILITY_DEFAULT_ALLOCATOR: u64 = 32; // null alloc_func and free_func are accepted
+
+#[cfg(feature="std")]
+const STD_CAPABILITIES: u64 = BROTLI_FFI_CAPABILITY_MULTITHREADING | BROTLI_FFI_CAPABILITY_DEFAULT_ALLOCATOR;
+#[cfg(not(feature="std"))]
+const STD_CAPABILITIES: u64 = 0;
+
+#[cfg(feature="simd")]
+const SIMD_CAPABILITIES: u64 = BROTLI_FFI_CAPABILITY_SIMD;
+#[cfg(not(feature="simd"))]
+const SIMD_CAPABILITIES: u64 = 0;
+
+#[no_mangle]
+pub extern fn BrotliFFIAbiVersion() -> u32 {
+  BROTLI_FFI_ABI_VERSION
+}
+
+#[no_mangle]
+pub extern fn BrotliFFICapabilities() -> u64 {
+  BROTLI_FFI_CAPABILITY_BROCCOLI | BROTLI_FFI_CAPABILITY_IR_CALLBACK | BROTLI_FFI_CAPABILITY_LARGE_WINDOW
+    | STD_CAPABILITIES | SIMD_CAPABILITIES
+}
+
+#[no_mangle]
+pub extern fn BroccoliStateSize() -> usize {
+  core::mem::size_of::<BroccoliState>()
+}
+
+// opaque to C, but custom allocators are asked for exactly this many bytes
+#[no_mangle]
+pub extern fn BrotliEncoderStateSize() -> usize {
+  core::mem::size_of::<BrotliEncoderState>()
+}
+
+#[no_mangle]
+pub extern fn CBrotliDecoderStateSize() -> usize {
+  core::mem::size_of::<BrotliDecoderState>()
+}
+
+#[no_mangle]
+pub extern fn BrotliEncoderWorkPoolSize() -> usize {
+  core::mem::size_of::<BrotliEncoderWorkPool>()
+}
+
+// Returns 1 if a caller built against the given ABI version, sizeof(BroccoliState) and
+// encoder state size can use this library, 0 otherwise. An encoder_state_size of 0 is
+// for callers whose allocators do not depend on BrotliEncoderStateSize.
+#[no_mangle]
+pub extern fn BrotliFFICheckAbi(abi_version: u32, broccoli_state_size: usize, encoder_state_size: usize) -> i32 {
+  (abi_version == BROTLI_FFI_ABI_VERSION && broccoli_state_size == BroccoliStateSize()
+   && (encoder_state_size == 0 || encoder_state_size == BrotliEncoderStateSize())) as i32
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::broccoli::BROCCOLI_STATE_DATA_SIZE;
+  static CAPABILITIES_H: &'static str = include_str!("../../c/brotli/capabilities.h");
+  static BROCCOLI_H: &'static str = include_str!("../../c/brotli/broccoli.h");
+
+  fn define_value(header: &str, name: &str) -> u64 {
+    for line in header.lines() {
+      let mut words = line.split_whitespace();
+      if words.next() == Some("#define") && words.next() == Some(name) {
+        let value = words.next().unwrap().trim_end_matches(|c| c == 'u' || c == 'U' || c == 'L');
+        return value.parse().unwrap();
+      }
+    }
+    panic!("{} is not defined", name);
+  }
+
+  #[test]
+  fn test_header_matches_abi() {
+    assert_eq!(define_value(CAPABILITIES_H, "BROTLI_FFI_ABI_VERSION"), u64::from(BROTLI_FFI_ABI_VERSION));
+    for &(name, value) in [("BROTLI_FFI_CAPABILITY_MULTITHREADING", BROTLI_FFI_CAPABILITY_MULTITHREADING),
+                           ("BROTLI_FFI_CAPABILITY_SIMD", BROTLI_FFI_CAPABILITY_SIMD),
+                           ("BROTLI_FFI_CAPABILITY_BROCCOLI", BROTLI_FFI_CAPABILITY_BROCCOLI),
+                           ("BROTLI_FFI_CAPABILITY_IR_CALLBACK", BROTLI_FFI_CAPABILITY_IR_CALLBACK),
+                           ("BROTLI_FFI_CAPABILITY_LARGE_WINDOW", BROTLI_FFI_CAPABILITY_LARGE_WINDOW),
+                           ("BROTLI_FFI_CAPABILITY_DEFAULT_ALLOCATOR", BROTLI_FFI_CAPABILITY_DEFAULT_ALLOCATOR)].iter() {
+      assert_eq!(define_value(CAPABILITIES_H, name), value);
+    }
+    let data_decl = BROCCOLI_H.split("unsigned char data[").nth(1).unwrap();
+    assert_eq!(data_decl.split("];").next().unwrap().parse::<usize>().unwrap(), BROCCOLI_STATE_DATA_SIZE);
+    assert_eq!(BroccoliStateSize(), core::mem::size_of::<*mut u8>() + BROCCOLI_STATE_DATA_SIZE);
+  }
+
+  #[test]
+  fn test_check_abi() {
+    let encoder_size = BrotliEncoderStateSize();
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, BroccoliStateSize(), encoder_size), 1);
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, BroccoliStateSize(), 0), 1);
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION + 1, BroccoliStateSize(), encoder_size), 0);
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, BroccoliStateSize() - 8, encoder_size), 0);
+    // an allocator sized for a build with different features
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, BroccoliStateSize(), encoder_size + 8), 0);
+    assert_eq!(BrotliFFICheckAbi(BROTLI_FFI_ABI_VERSION, BroccoliStateSize(), encoder_size - 8), 0);
+    let capabilities = BrotliFFICapabilities();
+    assert!(capabilities & BROTLI_FFI_CAPABILITY_BROCCOLI != 0);
+    assert!(capabilities & BROTLI_FFI_CAPABILITY_IR_CALLBACK != 0);
+  }
+}
@@ -20,6 +20,8 @@ use brotli_decompressor::ffi::{
   slice_from_raw_parts_or_nil_mut,
 };
 use ::enc::encode::BrotliEncoderStateStruct;
+use ::enc::interface;
+use alloc::{Allocator, SliceWrapper, SliceWrapperMut};
 use super::alloc_util::BrotliSubclassableAllocator;
 
 #[repr(C)]
@@ -43,11 +45,147 @@ pub enum BrotliEncoderMode {
   BROTLI_MODE_FORCE_SIGNED_PRIOR = 6,
 }
 
+#[repr(C)]
+#[no_mangle]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrotliEncoderIRCommandType {
+  BROTLI_IR_LITERAL = 0,
+  BROTLI_IR_COPY = 1,
+  BROTLI_IR_DICT = 2,
+  BROTLI_IR_BLOCK_SWITCH_COMMAND = 3,
+  BROTLI_IR_BLOCK_SWITCH_LITERAL = 4,
+  BROTLI_IR_BLOCK_SWITCH_DISTANCE = 5,
+}
+
+// One command of a metablock; which fields are meaningful depends on command_type.
+#[repr(C)]
+#[no_mangle]
+#[derive(Clone, Copy)]
+pub struct BrotliEncoderIRCommand {
+  pub command_type: BrotliEncoderIRCommandType,
+  pub num_bytes: u32, // literal length, copy length or transformed dictionary word length
+  pub distance: u32, // copy
+  pub word_id: u32, // dict
+  pub word_size: u8, // dict
+  pub transform: u8, // dict
+  pub block_type: u8, // block switches
+  pub stride: u8, // literal block switch
+  pub high_entropy: u8, // literal
+  pub data: *const u8, // literal bytes, valid only during the callback
+}
+
+impl Default for BrotliEncoderIRCommand {
+  fn default() -> Self {
+    BrotliEncoderIRCommand {
+      command_type: BrotliEncoderIRCommandType::BROTLI_IR_LITERAL,
+      num_bytes: 0,
+      distance: 0,
+      word_id: 0,
+      word_size: 0,
+      transform: 0,
+      block_type: 0,
+      stride: 0,
+      high_entropy: 0,
+      data: core::ptr::null(),
+    }
+  }
+}
+
+// The context maps a metablock was coded with; the arrays are valid only during the callback.
+#[repr(C)]
+#[no_mangle]
+pub struct BrotliEncoderIRPredictionMode {
+  pub literal_prediction_mode: u8,
+  pub literal_context_map: *const u8,
+  pub literal_context_map_size: usize,
+  pub distance_context_map: *const u8,
+  pub distance_context_map_size: usize,
+  pub predmode_speed_and_distance_context_map: *const u8,
+  pub predmode_speed_and_distance_context_map_size: usize,
+}
+
+pub type brotli_encoder_metablock_callback = Option<extern "C" fn(opaque: *mut c_void,
+                                                                   prediction_mode: *const BrotliEncoderIRPredictionMode,
+                                                                   commands: *const BrotliEncoderIRCommand,
+                                                                   num_commands: usize)>;
+
 #[repr(C)]
 #[no_mangle]
 pub struct BrotliEncoderState {
   pub custom_allocator: CAllocator,
   pub compressor: BrotliEncoderStateStruct<BrotliSubclassableAllocator>,
+  pub metablock_callback: brotli_encoder_metablock_callback,
+  pub metablock_callback_opaque: *mut c_void,
+}
+
+// prediction modes are passed to the callback on their own, so they have no command
+fn ir_command(cmd: &interface::Command<interface::InputReference>) -> Option<BrotliEncoderIRCommand> {
+  let mut ret = BrotliEncoderIRCommand::default();
+  match *cmd {
+    interface::Command::Literal(ref lit) => {
+      ret.num_bytes = lit.data.slice().len() as u32;
+      ret.high_entropy = lit.high_entropy as u8;
+      ret.data = lit.data.slice().as_ptr();
+    },
+    interface::Command::Copy(ref copy) => {
+      ret.command_type = BrotliEncoderIRCommandType::BROTLI_IR_COPY;
+      ret.num_bytes = copy.num_bytes;
+      ret.distance = copy.distance;
+    },
+    interface::Command::Dict(ref dict) => {
+      ret.command_type = BrotliEncoderIRCommandType::BROTLI_IR_DICT;
+      ret.num_bytes = u32::from(dict.final_size);
+      ret.word_id = dict.word_id;
+      ret.word_size = dict.word_size;
+      ret.transform = dict.transform;
+    },
+    interface::Command::BlockSwitchCommand(ref bs) => {
+      ret.command_type = BrotliEncoderIRCommandType::BROTLI_IR_BLOCK_SWITCH_COMMAND;
+      ret.block_type = bs.block_type();
+    },
+    interface::Command::BlockSwitchLiteral(ref bs) => {
+      ret.command_type = BrotliEncoderIRCommandType::BROTLI_IR_BLOCK_SWITCH_LITERAL;
+      ret.block_type = bs.block_type();
+      ret.stride = bs.stride();
+    },
+    interface::Command::BlockSwitchDistance(ref bs) => {
+      ret.command_type = BrotliEncoderIRCommandType::BROTLI_IR_BLOCK_SWITCH_DISTANCE;
+      ret.block_type = bs.block_type();
+    },
+    interface::Command::PredictionMode(_) => return None,
+  }
+  Some(ret)
+}
+
+// Converts a metablock to the C representation, in memory from the encoder's allocator.
+fn deliver_metablock(callback: extern "C" fn(*mut c_void,
+                                             *const BrotliEncoderIRPredictionMode,
+                                             *const BrotliEncoderIRCommand,
+                                             usize),
+                     opaque: *mut c_void,
+                     pm: &interface::PredictionModeContextMap<interface::InputReferenceMut>,
+                     cmds: &[interface::StaticCommand],
+                     mb: interface::InputPair,
+                     alloc: &mut BrotliSubclassableAllocator) {
+  let prediction_mode = BrotliEncoderIRPredictionMode {
+    literal_prediction_mode: pm.literal_prediction_mode().prediction_mode(),
+    literal_context_map: pm.literal_context_map.slice().as_ptr(),
+    literal_context_map_size: pm.literal_context_map.slice().len(),
+    distance_context_map: pm.distance_context_map().as_ptr(),
+    distance_context_map_size: pm.distance_context_map().len(),
+    predmode_speed_and_distance_context_map: pm.predmode_speed_and_distance_context_map.slice().as_ptr(),
+    predmode_speed_and_distance_context_map_size: pm.predmode_speed_and_distance_context_map.slice().len(),
+  };
+  let mut ir = <BrotliSubclassableAllocator as Allocator<BrotliEncoderIRCommand>>::alloc_cell(alloc, cmds.len());
+  let mut num_commands = 0usize;
+  for cmd in cmds.iter() {
+    if let Some(ir_cmd) = ir_command(&interface::thaw_pair(cmd, &mb)) {
+      ir.slice_mut()[num_commands] = ir_cmd;
+      num_commands += 1;
+    }
+  }
+  callback(opaque, &prediction_mode, ir.slice().as_ptr(), num_commands);
+  <BrotliSubclassableAllocator as Allocator<BrotliEncoderIRCommand>>::free_cell(alloc, ir);
 }
 
 #[cfg(not(feature="std"))]
@@ -91,6 +229,8 @@ pub unsafe extern fn BrotliEncoderCreateInstance(
         BrotliSubclassableAllocator::new(
           SubclassableAllocator::new(allocators.clone())),
       ),
+      metablock_callback: None,
+      metablock_callback_opaque: core::ptr::null_mut(),
     };
     if let Some(alloc) = alloc_func {
       if free_func.is_none() {
@@ -121,6 +261,17 @@ pub unsafe extern fn BrotliEncoderSetParameter(
   ::enc::encode::BrotliEncoderSetParameter(&mut (*state_ptr).compressor, param, value)
 }
 
+// Calls callback with the commands of each metablock as it is written, or stops if it is null.
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderSetMetablockCallback(
+  state_ptr: *mut BrotliEncoderState,
+  callback: brotli_encoder_metablock_callback,
+  opaque: *mut c_void,
+) {
+  (*state_ptr).metablock_callback = callback;
+  (*state_ptr).metablock_callback_opaque = opaque;
+}
+
 #[no_mangle]
 pub unsafe extern fn BrotliEncoderDestroyInstance(state_ptr: *mut BrotliEncoderState) {
   ::enc::encode::BrotliEncoderDestroyInstance(&mut (*state_ptr).compressor);
@@ -163,6 +314,34 @@ pub unsafe extern fn BrotliEncoderSetCustomDictionary(
   }
 }
 
+// Applies a key=value profile (see enc::backward_references::profile) to the parameters.
+// Returns 0 and leaves them untouched if the profile is invalid or the stream has started.
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderSetProfile(
+  state_ptr: *mut BrotliEncoderState,
+  size: usize,
+  profile: *const u8,
+) -> i32 {
+  match catch_panic(|| {
+    if (*state_ptr).compressor.is_initialized_ {
+      return 0;
+    }
+    match core::str::from_utf8(slice_from_raw_parts_or_nil(profile, size)) {
+      Ok(text) => match ::enc::BrotliEncoderParseProfile(text, &mut (*state_ptr).compressor.params) {
+        Ok(()) => 1,
+        Err(_) => 0,
+      },
+      Err(_) => 0,
+    }
+  }) {
+    Ok(ret) => ret,
+    Err(panic_err) => {
+      error_print(panic_err);
+      0
+    },
+  }
+}
+
 #[no_mangle]
 pub unsafe extern fn BrotliEncoderTakeOutput(
   state_ptr: *mut BrotliEncoderState,
@@ -284,6 +463,11 @@ pub unsafe extern fn BrotliEncoderCompressStream(
       let input_buf = slice_from_raw_parts_or_nil(*input_buf_ptr, *available_in);
       let output_buf = slice_from_raw_parts_or_nil_mut(*output_buf_ptr, *available_out);
       let mut to = Some(0usize);
+      let callback = (*state_ptr).metablock_callback;
+      let opaque = (*state_ptr).metablock_callback_opaque;
+      // a callback needs the metablocks logged, without overriding BROTLI_METABLOCK_CALLBACK
+      let log_meta_block = (*state_ptr).compressor.params.log_meta_block;
+      (*state_ptr).compressor.params.log_meta_block = log_meta_block || callback.is_some();
       result = ::enc::encode::BrotliEncoderCompressStream(
         &mut (*state_ptr).compressor,
         translated_op,
@@ -294,8 +478,11 @@ pub unsafe extern fn BrotliEncoderCompressStream(
         output_buf,
         &mut output_offset,
         &mut to,
-        &mut |_a,_b,_c,_d|(),
+        &mut |pm, cmds, mb, alloc| if let Some(cb) = callback {
+          deliver_metablock(cb, opaque, pm, cmds, mb, alloc);
+        },
       );
+      (*state_ptr).compressor.params.log_meta_block = log_meta_block;
       if !total_out.is_null() {
         *total_out = to.unwrap_or(0);
       }
@@ -380,3 +567,99 @@ fn catch_panic_cstate<F:FnOnce()->*mut BrotliEncoderState>(f: F) -> Result<*mut
 #[cfg(any(not(feature="std"), feature="pass-through-ffi-panics"))]
 fn error_print<Err>(_err: Err) {
 }
+
+#[cfg(all(test, feature="std"))]
+mod test {
+  use super::*;
+  use std::vec::Vec;
+  use ::enc::encode::BrotliEncoderParameter;
+
+  struct Replay {
+    input: &'static [u8],
+    output: Vec<u8>,
+    metablocks: usize,
+  }
+
+  extern "C" fn replay_metablock(opaque: *mut c_void,
+                                 prediction_mode: *const BrotliEncoderIRPredictionMode,
+                                 commands: *const BrotliEncoderIRCommand,
+                                 num_commands: usize) {
+    let replay = unsafe { &mut *(opaque as *mut Replay) };
+    let prediction_mode = unsafe { &*prediction_mode };
+    assert!(prediction_mode.literal_prediction_mode < 4);
+    assert!(prediction_mode.literal_context_map_size % 64 == 0);
+    let commands = unsafe { slice_from_raw_parts_or_nil(commands, num_commands) };
+    for cmd in commands.iter() {
+      let position = replay.output.len();
+      match cmd.command_type {
+        BrotliEncoderIRCommandType::BROTLI_IR_LITERAL => {
+          let data = unsafe { slice_from_raw_parts_or_nil(cmd.data, cmd.num_bytes as usize) };
+          replay.output.extend(data);
+        },
+        BrotliEncoderIRCommandType::BROTLI_IR_COPY => for _ in 0..cmd.num_bytes {
+          let byte = replay.output[replay.output.len() - cmd.distance as usize];
+          replay.output.push(byte);
+        },
+        BrotliEncoderIRCommandType::BROTLI_IR_DICT => {
+          let word = &replay.input[position..position + cmd.num_bytes as usize];
+          replay.output.extend(word);
+        },
+        _ => continue,
+      }
+      assert_eq!(&replay.output[position..], &replay.input[position..replay.output.len()]);
+    }
+    replay.metablocks += 1;
+  }
+
+  #[test]
+  fn test_metablock_callback() {
+    static INPUT: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+    let mut replay = Replay { input: INPUT, output: Vec::new(), metablocks: 0 };
+    let mut compressed = vec![0u8; BrotliEncoderMaxCompressedSize(INPUT.len())];
+    unsafe {
+      let state = BrotliEncoderCreateInstance(None, None, core::ptr::null_mut());
+      BrotliEncoderSetParameter(state, BrotliEncoderParameter::BROTLI_PARAM_QUALITY, 9);
+      BrotliEncoderSetMetablockCallback(state, Some(replay_metablock), &mut replay as *mut Replay as *mut c_void);
+      let mut available_in = INPUT.len();
+      let mut next_in = INPUT.as_ptr();
+      let mut available_out = compressed.len();
+      let mut next_out = compressed.as_mut_ptr();
+      assert_eq!(BrotliEncoderCompressStream(state, BrotliEncoderOperation::BROTLI_OPERATION_FINISH,
+                                             &mut available_in, &mut next_in,
+                                             &mut available_out, &mut next_out,
+                                             core::ptr::null_mut()), 1);
+      assert_eq!(BrotliEncoderIsFinished(state), 1);
+      BrotliEncoderDestroyInstance(state);
+    }
+    assert!(replay.metablocks != 0);
+    assert_eq!(replay.output.len(), INPUT.len());
+  }
+
+  #[test]
+  fn test_metablock_callback_keeps_log_meta_block() {
+    unsafe {
+      let state = BrotliEncoderCreateInstance(None, None, core::ptr::null_mut());
+      BrotliEncoderSetParameter(state, BrotliEncoderParameter::BROTLI_METABLOCK_CALLBACK, 1);
+      BrotliEncoderSetMetablockCallback(state, None, core::ptr::null_mut());
+      assert!((*state).compressor.params.log_meta_block);
+      BrotliEncoderDestroyInstance(state);
+    }
+  }
+
+  #[test]
+  fn test_set_profile() {
+    let profile = b"version=1\nquality=4\nlgwin=18\ncatable=true\n";
+    unsafe {
+      let state = BrotliEncoderCreateInstance(None, None, core::ptr::null_mut());
+      assert_eq!(BrotliEncoderSetProfile(state, profile.len(), profile.as_ptr()), 1);
+      assert_eq!((*state).compressor.params.quality, 4);
+      assert_eq!((*state).compressor.params.lgwin, 18);
+      assert!((*state).compressor.params.catable);
+      let bad = b"version=1\nquality=4\nlgwin=x\n";
+      (*state).compressor.params.quality = 7;
+      assert_eq!(BrotliEncoderSetProfile(state, bad.len(), bad.as_ptr()), 0);
+      assert_eq!((*state).compressor.params.quality, 7);
+      BrotliEncoderDestroyInstance(state);
+    }
+  }
+}
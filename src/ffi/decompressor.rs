@@ -1,25 +1,179 @@
+#[cfg(feature="std")]
+use std::{panic, thread, io};
+#[cfg(feature="std")]
+use std::io::Write;
 pub use brotli_decompressor::ffi;
 pub use brotli_decompressor::{BrotliDecoderReturnInfo, HuffmanCode};
+use brotli_decompressor::BrotliState;
+#[cfg(feature="std")]
+use brotli_decompressor::ffi::alloc_util;
+use brotli_decompressor::ffi::alloc_util::SubclassableAllocator;
+use brotli_decompressor::ffi::interface::CAllocator;
+use alloc::Allocator;
+use ::limits::{DecompressionLimits, DecompressionLimitExceeded, DecompressionLimitTracker, BrotliDecompressStreamWithLimits};
+use ::limits::{BrotliInspectHeader, BrotliHeaderError};
+use core;
 pub use brotli_decompressor::ffi::interface::{
   brotli_alloc_func,
   brotli_free_func,
   c_void,
 };
 
+// The decoder of brotli_decompressor plus the limits this crate enforces on top of it,
+// allocated in one piece so that each decoder owns its tracker.
+#[repr(C)]
+pub struct BrotliDecoderState {
+  pub decoder: ffi::BrotliDecoderState,
+  pub limits: Option<DecompressionLimitTracker>,
+}
+
+#[cfg(not(feature="std"))]
+fn brotli_new_decompressor_without_custom_alloc(_to_box: BrotliDecoderState) -> *mut BrotliDecoderState {
+  // without std there is no allocator to fall back on
+  core::ptr::null_mut()
+}
+
+#[cfg(feature="std")]
+fn brotli_new_decompressor_without_custom_alloc(to_box: BrotliDecoderState) -> *mut BrotliDecoderState {
+  alloc_util::Box::<BrotliDecoderState>::into_raw(
+    alloc_util::Box::<BrotliDecoderState>::new(to_box))
+}
+
+#[cfg(feature="std")]
+unsafe fn free_decompressor_no_custom_alloc(state_ptr: *mut BrotliDecoderState) {
+  let _state = alloc_util::Box::from_raw(state_ptr);
+}
+
+#[cfg(not(feature="std"))]
+unsafe fn free_decompressor_no_custom_alloc(_state_ptr: *mut BrotliDecoderState) {
+  unreachable!();
+}
+
+// returns null if only one of alloc_func and free_func is given, or neither without std
 pub unsafe extern fn CBrotliDecoderCreateInstance(
     alloc_func: brotli_alloc_func,
     free_func: brotli_free_func,
     opaque: *mut c_void,
-) -> *mut ffi::BrotliDecoderState {
-   ffi::BrotliDecoderCreateInstance(alloc_func, free_func, opaque)
+) -> *mut BrotliDecoderState {
+  if alloc_func.is_some() != free_func.is_some() {
+    return core::ptr::null_mut();
+  }
+  let allocators = CAllocator {
+    alloc_func: alloc_func,
+    free_func: free_func,
+    opaque: opaque,
+  };
+  let to_box = BrotliDecoderState {
+    decoder: ffi::BrotliDecoderState {
+      custom_allocator: allocators.clone(),
+      decompressor: BrotliState::new_with_custom_dictionary(
+        SubclassableAllocator::new(allocators.clone()),
+        SubclassableAllocator::new(allocators.clone()),
+        SubclassableAllocator::new(allocators.clone()),
+        <SubclassableAllocator as Allocator<u8>>::AllocatedMemory::default(),
+      ),
+    },
+    limits: None,
+  };
+  if let Some(alloc) = alloc_func {
+    let ptr = alloc(allocators.opaque, core::mem::size_of::<BrotliDecoderState>());
+    if ptr.is_null() {
+      return core::ptr::null_mut();
+    }
+    let state_ptr = core::mem::transmute::<*mut c_void, *mut BrotliDecoderState>(ptr);
+    core::ptr::write(state_ptr, to_box);
+    state_ptr
+  } else {
+    brotli_new_decompressor_without_custom_alloc(to_box)
+  }
+}
+
+// BrotliDecoderParameter plus the limits this crate enforces on top of the decoder
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CBrotliDecoderParameter {
+    BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION = 0,
+    BROTLI_DECODER_PARAM_LARGE_WINDOW = 1,
+    // low 32 bits of the maximum output size in bytes (0 is unlimited)
+    BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE = 2,
+    // high 32 bits of the maximum output size
+    BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE_HIGH = 3,
+    // maximum decompressed bytes per compressed byte (0 is unlimited)
+    BROTLI_DECODER_PARAM_MAX_RATIO = 4,
+    // maximum log2 of the window declared by the stream (0 is unlimited)
+    BROTLI_DECODER_PARAM_MAX_WINDOW_BITS = 5,
+}
+
+impl From<ffi::interface::BrotliDecoderParameter> for CBrotliDecoderParameter {
+    fn from(p: ffi::interface::BrotliDecoderParameter) -> Self {
+        match p {
+            ffi::interface::BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION =>
+                CBrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION,
+            ffi::interface::BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW =>
+                CBrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW,
+        }
+    }
 }
 
-pub unsafe extern fn CBrotliDecoderSetParameter(state_ptr: *mut ffi::BrotliDecoderState,
-                                       selector: ffi::interface::BrotliDecoderParameter,
+fn set_limit(state: &mut BrotliDecoderState, selector: CBrotliDecoderParameter, value: u32) {
+    let limits = state.limits.get_or_insert_with(|| DecompressionLimitTracker::new(DecompressionLimits::default())).limits_mut();
+    match selector {
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE =>
+            limits.max_output_size = (limits.max_output_size & !0xffffffff) | u64::from(value),
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_OUTPUT_SIZE_HIGH =>
+            limits.max_output_size = (limits.max_output_size & 0xffffffff) | (u64::from(value) << 32),
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_RATIO => limits.max_ratio = u64::from(value),
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_MAX_WINDOW_BITS => limits.max_window_bits = value as u8,
+        _ => {},
+    }
+}
+
+pub unsafe extern fn CBrotliDecoderSetParameter(state_ptr: *mut BrotliDecoderState,
+                                       selector: CBrotliDecoderParameter,
                                        value: u32) {
-    ffi::BrotliDecoderSetParameter(state_ptr,  selector, value)
-} 
+    match selector {
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION =>
+            ffi::BrotliDecoderSetParameter(&mut (*state_ptr).decoder, ffi::interface::BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION, value),
+        CBrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW =>
+            ffi::BrotliDecoderSetParameter(&mut (*state_ptr).decoder, ffi::interface::BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW, value),
+        _ => set_limit(&mut *state_ptr, selector, value),
+    }
+}
+
+// returns which DecompressionLimitExceeded stopped the decoder, or 0 if no limit was hit
+pub unsafe extern fn CBrotliDecoderGetLimitExceeded(state_ptr: *mut BrotliDecoderState) -> u32 {
+    match (*state_ptr).limits.and_then(|tracker| tracker.exceeded()) {
+        Some(reason) => reason as u32,
+        None => 0,
+    }
+}
      
+// Reads the window of a stream without decoding it: returns 1 and fills window_bits and large_window,
+// 0 if a second byte is needed, or -1 if the header is corrupt.
+pub unsafe extern fn CBrotliDecoderInspectHeader(data: *const u8,
+                                                 size: usize,
+                                                 window_bits: *mut u32,
+                                                 large_window: *mut i32) -> i32 {
+    let header = if size == 0 || data.is_null() {
+        &[][..]
+    } else {
+        core::slice::from_raw_parts(data, core::cmp::min(size, 2))
+    };
+    match BrotliInspectHeader(header) {
+        Ok(stream_header) => {
+            if !window_bits.is_null() {
+                *window_bits = u32::from(stream_header.window_bits);
+            }
+            if !large_window.is_null() {
+                *large_window = stream_header.large_window as i32;
+            }
+            1
+        },
+        Err(BrotliHeaderError::NeedsMoreInput) => 0,
+        Err(BrotliHeaderError::Corrupt) => -1,
+    }
+}
+
 #[cfg(feature="std")] // this requires a default allocator
 pub unsafe extern fn CBrotliDecoderDecompress(
   encoded_size: usize,
@@ -29,36 +183,126 @@ pub unsafe extern fn CBrotliDecoderDecompress(
     ffi::BrotliDecoderDecompress(encoded_size, encoded_buffer, decoded_size, decoded_buffer)
 }
 
-pub unsafe extern fn CBrotliDecoderDecompressStream(
+// CBrotliDecoderDecompress that fails instead of breaking the limits, each given as for the
+// BROTLI_DECODER_PARAM_MAX_* parameters (0 is unlimited). The other one-shot functions take no limits:
+// their output is bounded only by the buffer the caller passes.
+#[cfg(feature="std")] // this requires a default allocator
+pub unsafe extern fn CBrotliDecoderDecompressWithLimits(
+  encoded_size: usize,
+  encoded_buffer: *const u8,
+  decoded_size: *mut usize,
+  decoded_buffer: *mut u8,
+  max_output_size: u64,
+  max_ratio: u32,
+  max_window_bits: u32) -> ffi::interface::BrotliDecoderResult {
+    let state_ptr = CBrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    if state_ptr.is_null() {
+        return ffi::interface::BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR;
+    }
+    (*state_ptr).limits = Some(DecompressionLimitTracker::new(DecompressionLimits {
+        max_output_size: max_output_size,
+        max_ratio: u64::from(max_ratio),
+        max_window_bits: max_window_bits as u8,
+    }));
+    let mut available_in = encoded_size;
+    let mut next_in = encoded_buffer;
+    let mut available_out = *decoded_size;
+    let mut next_out = decoded_buffer;
+    let mut total_out = 0usize;
+    let result = CBrotliDecoderDecompressStream(state_ptr, &mut available_in, &mut next_in,
+                                                &mut available_out, &mut next_out, &mut total_out);
+    *decoded_size = total_out;
+    CBrotliDecoderDestroyInstance(state_ptr);
+    match result {
+        ffi::interface::BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS => result,
+        _ => ffi::interface::BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR,
+    }
+}
+
+unsafe fn decompress_stream_with_limits(
     state_ptr: *mut ffi::BrotliDecoderState,
+    tracker: &mut DecompressionLimitTracker,
+    available_in: *mut usize,
+    input_buf_ptr: *mut*const u8,
+    available_out: *mut usize,
+    output_buf_ptr: *mut*mut u8,
+    mut total_out: *mut usize,
+) -> ffi::interface::BrotliDecoderResult {
+    let mut input_offset = 0usize;
+    let mut output_offset = 0usize;
+    let mut fallback_total_out = 0usize;
+    if total_out.is_null() {
+        total_out = &mut fallback_total_out;
+    }
+    let result = {
+        let input_buf = ffi::slice_from_raw_parts_or_nil(*input_buf_ptr, *available_in);
+        let output_buf = ffi::slice_from_raw_parts_or_nil_mut(*output_buf_ptr, *available_out);
+        BrotliDecompressStreamWithLimits(&mut *available_in,
+                                         &mut input_offset,
+                                         input_buf,
+                                         &mut *available_out,
+                                         &mut output_offset,
+                                         output_buf,
+                                         &mut *total_out,
+                                         &mut (*state_ptr).decompressor,
+                                         tracker)
+    };
+    *input_buf_ptr = (*input_buf_ptr).offset(input_offset as isize);
+    *output_buf_ptr = (*output_buf_ptr).offset(output_offset as isize);
+    result.into()
+}
+
+pub unsafe extern fn CBrotliDecoderDecompressStream(
+    state_ptr: *mut BrotliDecoderState,
     available_in: *mut usize,
     input_buf_ptr: *mut*const u8,
     available_out: *mut usize,
     output_buf_ptr: *mut*mut u8,
     total_out: *mut usize,
 ) -> ffi::interface::BrotliDecoderResult {
-ffi::BrotliDecoderDecompressStream(
-  state_ptr,
-  available_in,
-  input_buf_ptr,
-  available_out,
-  output_buf_ptr,
-  total_out)
+  let state = &mut *state_ptr;
+  if let Some(ref mut tracker) = state.limits {
+    let decoder: *mut ffi::BrotliDecoderState = &mut state.decoder;
+    let tracker: *mut DecompressionLimitTracker = tracker;
+    // the same panic handling as ffi::BrotliDecoderDecompressStream
+    return match catch_panic(move || decompress_stream_with_limits(decoder,
+                                                                   &mut *tracker,
+                                                                   available_in,
+                                                                   input_buf_ptr,
+                                                                   available_out,
+                                                                   output_buf_ptr,
+                                                                   total_out)) {
+      Ok(result) => result,
+      Err(err) => {
+        error_print(err);
+        (*decoder).decompressor.error_code = ffi::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_UNREACHABLE;
+        ffi::interface::BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR
+      },
+    };
+  }
+  ffi::BrotliDecoderDecompressStream(
+    &mut state.decoder,
+    available_in,
+    input_buf_ptr,
+    available_out,
+    output_buf_ptr,
+    total_out)
 }
 
 pub unsafe extern fn CBrotliDecoderDecompressStreaming(
-    state_ptr: *mut ffi::BrotliDecoderState,
+    state_ptr: *mut BrotliDecoderState,
     available_in: *mut usize,
-    input_buf_ptr: *const u8,
+    mut input_buf_ptr: *const u8,
     available_out: *mut usize,
-    output_buf_ptr: *mut u8,
+    mut output_buf_ptr: *mut u8,
 ) -> ffi::interface::BrotliDecoderResult {
-ffi::BrotliDecoderDecompressStreaming(
+CBrotliDecoderDecompressStream(
   state_ptr,
   available_in,
-  input_buf_ptr,
+  &mut input_buf_ptr,
   available_out,
-  output_buf_ptr)
+  &mut output_buf_ptr,
+  core::ptr::null_mut())
 }
 
 pub unsafe extern fn CBrotliDecoderDecompressWithReturnInfo(
@@ -99,24 +343,32 @@ ffi::BrotliDecoderDecompressPrealloc(
   hc_ptr)
 }
 
-pub unsafe extern fn CBrotliDecoderMallocU8(state_ptr: *mut ffi::BrotliDecoderState, size: usize) -> *mut u8 {
-  ffi::BrotliDecoderMallocU8(state_ptr, size)
+pub unsafe extern fn CBrotliDecoderMallocU8(state_ptr: *mut BrotliDecoderState, size: usize) -> *mut u8 {
+  ffi::BrotliDecoderMallocU8(&mut (*state_ptr).decoder, size)
 }
 
-pub unsafe extern fn CBrotliDecoderFreeU8(state_ptr: *mut ffi::BrotliDecoderState, data: *mut u8, size: usize) {
-  ffi::BrotliDecoderFreeU8(state_ptr, data, size)
+pub unsafe extern fn CBrotliDecoderFreeU8(state_ptr: *mut BrotliDecoderState, data: *mut u8, size: usize) {
+  ffi::BrotliDecoderFreeU8(&mut (*state_ptr).decoder, data, size)
 }
 
-pub unsafe extern fn CBrotliDecoderMallocUsize(state_ptr: *mut ffi::BrotliDecoderState, size: usize) -> *mut usize {
-  ffi::BrotliDecoderMallocUsize(state_ptr, size)
+pub unsafe extern fn CBrotliDecoderMallocUsize(state_ptr: *mut BrotliDecoderState, size: usize) -> *mut usize {
+  ffi::BrotliDecoderMallocUsize(&mut (*state_ptr).decoder, size)
 }
 
-pub unsafe extern fn CBrotliDecoderFreeUsize(state_ptr: *mut ffi::BrotliDecoderState, data: *mut usize, size: usize) {
-  ffi::BrotliDecoderFreeUsize(state_ptr, data, size)
+pub unsafe extern fn CBrotliDecoderFreeUsize(state_ptr: *mut BrotliDecoderState, data: *mut usize, size: usize) {
+  ffi::BrotliDecoderFreeUsize(&mut (*state_ptr).decoder, data, size)
 }
 
-pub unsafe extern fn CBrotliDecoderDestroyInstance(state_ptr: *mut ffi::BrotliDecoderState) {
-  ffi::BrotliDecoderDestroyInstance(state_ptr)
+pub unsafe extern fn CBrotliDecoderDestroyInstance(state_ptr: *mut BrotliDecoderState) {
+  if let Some(_) = (*state_ptr).decoder.custom_allocator.alloc_func {
+    if let Some(free_fn) = (*state_ptr).decoder.custom_allocator.free_func {
+      let _to_free = core::ptr::read(state_ptr);
+      let ptr = core::mem::transmute::<*mut BrotliDecoderState, *mut c_void>(state_ptr);
+      free_fn((*state_ptr).decoder.custom_allocator.opaque, ptr);
+    }
+  } else {
+    free_decompressor_no_custom_alloc(state_ptr);
+  }
 }
 
 pub extern fn CBrotliDecoderVersion() -> u32 {
@@ -128,31 +380,72 @@ pub extern fn CBrotliDecoderErrorString(c: ffi::BrotliDecoderErrorCode) -> *cons
   ffi::BrotliDecoderErrorString(c)
 }
 
+// output past a limit is never handed out, so there is none once one was exceeded
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderHasMoreOutput(state_ptr: *const ffi::BrotliDecoderState) -> i32 {
-  ffi::BrotliDecoderHasMoreOutput(state_ptr)
+pub unsafe extern fn CBrotliDecoderHasMoreOutput(state_ptr: *const BrotliDecoderState) -> i32 {
+  if (*state_ptr).limits.as_ref().and_then(|tracker| tracker.exceeded()).is_some() {
+    return 0;
+  }
+  ffi::BrotliDecoderHasMoreOutput(&(*state_ptr).decoder)
 }
 
+// output taken from the ring buffer never passes through DecompressStream's buffer, so it is counted here
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderTakeOutput(state_ptr: *mut ffi::BrotliDecoderState, size: *mut usize) -> *const u8 {
-  ffi::BrotliDecoderTakeOutput(state_ptr, size)
+pub unsafe extern fn CBrotliDecoderTakeOutput(state_ptr: *mut BrotliDecoderState, size: *mut usize) -> *const u8 {
+  let state = &mut *state_ptr;
+  if let Some(ref mut tracker) = state.limits {
+    let (allowance, reason) = tracker.output_allowance(0);
+    if tracker.exceeded().is_some() || (allowance == 0 && ffi::BrotliDecoderHasMoreOutput(&state.decoder) != 0) {
+      if tracker.exceeded().is_none() {
+        let _ = tracker.fail(reason.unwrap_or(DecompressionLimitExceeded::OutputSize));
+      }
+      *size = 0;
+      return core::ptr::null();
+    }
+    let requested = if *size == 0 {usize::max_value()} else {*size};
+    *size = core::cmp::min(requested as u64, allowance) as usize;
+    let output = ffi::BrotliDecoderTakeOutput(&mut state.decoder, size);
+    let _ = tracker.observe_output(*size, 0);
+    return output;
+  }
+  ffi::BrotliDecoderTakeOutput(&mut state.decoder, size)
 }
 
 
 
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderIsUsed(state_ptr: *const ffi::BrotliDecoderState) -> i32 {
-  ffi::BrotliDecoderIsUsed(state_ptr)
+pub unsafe extern fn CBrotliDecoderIsUsed(state_ptr: *const BrotliDecoderState) -> i32 {
+  ffi::BrotliDecoderIsUsed(&(*state_ptr).decoder)
 }
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderIsFinished(state_ptr: *const ffi::BrotliDecoderState) -> i32 {
-  ffi::BrotliDecoderIsFinished(state_ptr)
+pub unsafe extern fn CBrotliDecoderIsFinished(state_ptr: *const BrotliDecoderState) -> i32 {
+  ffi::BrotliDecoderIsFinished(&(*state_ptr).decoder)
 }
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderGetErrorCode(state_ptr: *const ffi::BrotliDecoderState) -> ffi::BrotliDecoderErrorCode {
-  ffi::BrotliDecoderGetErrorCode(state_ptr)
+pub unsafe extern fn CBrotliDecoderGetErrorCode(state_ptr: *const BrotliDecoderState) -> ffi::BrotliDecoderErrorCode {
+  ffi::BrotliDecoderGetErrorCode(&(*state_ptr).decoder)
 }
 #[no_mangle]
-pub unsafe extern fn CBrotliDecoderGetErrorString(state_ptr: *const ffi::BrotliDecoderState) -> *const u8 {
-  ffi::BrotliDecoderGetErrorString(state_ptr)
+pub unsafe extern fn CBrotliDecoderGetErrorString(state_ptr: *const BrotliDecoderState) -> *const u8 {
+  ffi::BrotliDecoderGetErrorString(&(*state_ptr).decoder)
+}
+
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+fn catch_panic<F:FnOnce()->ffi::interface::BrotliDecoderResult+panic::UnwindSafe>(f: F) -> thread::Result<ffi::interface::BrotliDecoderResult> {
+    panic::catch_unwind(f)
+}
+
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+fn error_print<Err:core::fmt::Debug>(err: Err) {
+    let _ign = writeln!(&mut io::stderr(), "Internal Error {:?}", err);
+}
+
+// can't catch panics in a reliable way without std:: configure with panic=abort. These shouldn't happen
+#[cfg(any(not(feature="std"), feature="pass-through-ffi-panics"))]
+fn catch_panic<F:FnOnce()->ffi::interface::BrotliDecoderResult>(f: F) -> Result<ffi::interface::BrotliDecoderResult, ()> {
+    Ok(f())
+}
+
+#[cfg(any(not(feature="std"), feature="pass-through-ffi-panics"))]
+fn error_print<Err>(_err: Err) {
 }
@@ -3,3 +3,4 @@ pub mod decompressor;
 pub mod compressor;
 pub mod broccoli;
 pub mod multicompress;
+pub mod capabilities;
@@ -4,6 +4,8 @@ use std::{panic,thread, io};
 #[cfg(feature="std")]
 use std::io::Write;
 mod test;
+mod stream;
+pub use self::stream::*;
 #[no_mangle]
 use core;
 #[allow(unused_imports)]
@@ -15,6 +17,7 @@ use ::enc::encode::{BrotliEncoderCreateInstance,
                     BrotliEncoderCompressStream,
                     BrotliEncoderIsFinished,
                     BrotliEncoderDestroyInstance,
+                    BrotliEncoderSetCustomDictionary,
 };
 use brotli_decompressor::ffi::alloc_util::SubclassableAllocator;
 use brotli_decompressor::ffi::interface::{
@@ -32,7 +35,7 @@ use super::alloc_util::BrotliSubclassableAllocator;
 use ::enc;
 use ::enc::backward_references::{BrotliEncoderParams, UnionHasher};
 use ::enc::encode::{BrotliEncoderParameter, set_parameter};
-use ::enc::threading::{SendAlloc,Owned};
+use ::enc::threading::{SendAlloc,Owned,BrotliEncoderThreadError};
 use alloc::SliceWrapper;
 pub const MAX_THREADS: usize = 16;
 
@@ -60,6 +63,7 @@ pub extern fn BrotliEncoderMaxCompressedSizeMulti(input_size: usize, num_threads
 fn help_brotli_encoder_compress_single(
   param_keys: &[BrotliEncoderParameter],
   param_values: &[u32],
+  dictionary: &[u8],
   input: &[u8],
   output: &mut[u8],
   encoded_size: &mut usize,
@@ -69,6 +73,9 @@ fn help_brotli_encoder_compress_single(
   for (p, v) in param_keys.iter().zip(param_values.iter()) {
     BrotliEncoderSetParameter(&mut encoder, *p, *v);
   }
+  if dictionary.len() != 0 {
+    BrotliEncoderSetCustomDictionary(&mut encoder, dictionary.len(), dictionary);
+  }
   let mut result;
   let mut available_in = input.len();
   let mut next_in_offset = 0usize;
@@ -93,6 +100,79 @@ fn help_brotli_encoder_compress_single(
   result
 }
 
+// compresses input, which follows the given dictionary, on the work pool
+// if one is given and on freshly spawned threads otherwise
+unsafe fn compress_with_dictionary(
+  params: &BrotliEncoderParams,
+  input: &[u8],
+  dictionary: &[u8],
+  output: &mut [u8],
+  num_threads: usize,
+  alloc_func: brotli_alloc_func,
+  free_func: brotli_free_func,
+  alloc_opaque: &[*mut c_void],
+  work_pool: Option<&mut BrotliEncoderWorkPool>,
+) -> Result<usize, BrotliEncoderThreadError> {
+  let num_opaques = alloc_opaque.len();
+  // the threads are all joined before returning, so the input and dictionary outlive them
+  let owned_input = &mut Owned::new(SliceRef(slice_from_raw_parts_or_nil(input.as_ptr(), input.len())));
+  let dictionary = SliceRef(slice_from_raw_parts_or_nil(dictionary.as_ptr(), dictionary.len()));
+  if let Some(work_pool) = work_pool {
+    let mut alloc_array:[_;MAX_THREADS] = [
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[0]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[1%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[2%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[3%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[4%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[5%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[6%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[7%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[8%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[9%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[10%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[11%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[12%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[13%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[14%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[15%num_opaques]),
+    ];
+    enc::compress_worker_pool_with_dictionary(
+      params,
+      owned_input,
+      dictionary,
+      output,
+      &mut alloc_array[..num_threads],
+      &mut work_pool.work_pool,
+    )
+  } else {
+    let mut alloc_array:[_;MAX_THREADS] = [
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[0]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[1%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[2%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[3%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[4%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[5%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[6%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[7%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[8%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[9%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[10%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[11%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[12%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[13%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[14%num_opaques]),
+      make_send_alloc!(alloc_func, free_func, alloc_opaque[15%num_opaques]),
+    ];
+    enc::compress_multi_no_threadpool_with_dictionary(
+      params,
+      owned_input,
+      dictionary,
+      output,
+      &mut alloc_array[..num_threads],
+    )
+  }
+}
+
 #[no_mangle]
 pub unsafe extern fn BrotliEncoderCompressMulti(
   num_params: usize,
@@ -106,6 +186,38 @@ pub unsafe extern fn BrotliEncoderCompressMulti(
   alloc_func: brotli_alloc_func,
   free_func: brotli_free_func,
   alloc_opaque_per_thread: *mut*mut c_void,
+) -> i32 {
+  BrotliEncoderCompressMultiWithDictionary(
+    num_params,
+    param_keys,
+    param_values,
+    0,
+    core::ptr::null(),
+    input_size,
+    input,
+    encoded_size,
+    encoded,
+    desired_num_threads,
+    alloc_func,
+    free_func,
+    alloc_opaque_per_thread)
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderCompressMultiWithDictionary(
+  num_params: usize,
+  param_keys: *const BrotliEncoderParameter,
+  param_values: *const u32,
+  dictionary_size: usize,
+  dictionary: *const u8,
+  input_size: usize,
+  input: *const u8,
+  encoded_size: *mut usize,
+  encoded: *mut u8,
+  desired_num_threads: usize,
+  alloc_func: brotli_alloc_func,
+  free_func: brotli_free_func,
+  alloc_opaque_per_thread: *mut*mut c_void,
 ) -> i32 {
   if desired_num_threads == 0 {
     return 0;
@@ -114,6 +226,7 @@ pub unsafe extern fn BrotliEncoderCompressMulti(
   match compressor::catch_panic(|| {
     let param_keys_slice = slice_from_raw_parts_or_nil(param_keys, num_params);
     let param_values_slice = slice_from_raw_parts_or_nil(param_values, num_params);
+    let dictionary_slice = slice_from_raw_parts_or_nil(dictionary, dictionary_size);
     let input_slice = slice_from_raw_parts_or_nil(input, input_size);
     let output_slice = slice_from_raw_parts_or_nil_mut(encoded, *encoded_size);
     if num_threads == 1 {
@@ -127,6 +240,7 @@ pub unsafe extern fn BrotliEncoderCompressMulti(
       return help_brotli_encoder_compress_single(
         param_keys_slice,
         param_values_slice,
+        dictionary_slice,
         input_slice,
         output_slice,
         &mut *encoded_size,
@@ -145,31 +259,16 @@ pub unsafe extern fn BrotliEncoderCompressMulti(
         return 0;
       }
     }
-    let mut alloc_array:[_;MAX_THREADS] = [
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[0]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[1%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[2%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[3%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[4%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[5%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[6%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[7%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[8%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[9%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[10%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[11%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[12%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[13%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[14%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[15%desired_num_threads]),
-    ];
-
-    let owned_input = &mut Owned::new(SliceRef(input_slice));
-    let res = enc::compress_multi_no_threadpool(
+    let res = compress_with_dictionary(
       &params,
-      owned_input,
+      input_slice,
+      dictionary_slice,
       output_slice,
-      &mut alloc_array[..num_threads],
+      num_threads,
+      alloc_func,
+      free_func,
+      alloc_opaque,
+      None,
     );
     match res {
       Ok(size) => {
@@ -196,7 +295,7 @@ pub struct BrotliEncoderWorkPool {
   work_pool: enc::WorkerPool<enc::CompressionThreadResult<BrotliSubclassableAllocator>,
                              UnionHasher<BrotliSubclassableAllocator>,
                              BrotliSubclassableAllocator,
-                             (SliceRef<'static>, BrotliEncoderParams)>,
+                             (SliceRef<'static>, BrotliEncoderParams, SliceRef<'static>)>,
 }
 
 #[cfg(not(feature="std"))]
@@ -291,15 +390,51 @@ pub unsafe extern fn BrotliEncoderCompressWorkPool(
   alloc_func: brotli_alloc_func,
   free_func: brotli_free_func,
   alloc_opaque_per_thread: *mut*mut c_void,
+) -> i32 {
+  BrotliEncoderCompressWorkPoolWithDictionary(
+    work_pool,
+    num_params,
+    param_keys,
+    param_values,
+    0,
+    core::ptr::null(),
+    input_size,
+    input,
+    encoded_size,
+    encoded,
+    desired_num_threads,
+    alloc_func,
+    free_func,
+    alloc_opaque_per_thread)
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderCompressWorkPoolWithDictionary(
+  work_pool: *mut BrotliEncoderWorkPool,
+  num_params: usize,
+  param_keys: *const BrotliEncoderParameter,
+  param_values: *const u32,
+  dictionary_size: usize,
+  dictionary: *const u8,
+  input_size: usize,
+  input: *const u8,
+  encoded_size: *mut usize,
+  encoded: *mut u8,
+  desired_num_threads: usize,
+  alloc_func: brotli_alloc_func,
+  free_func: brotli_free_func,
+  alloc_opaque_per_thread: *mut*mut c_void,
 ) -> i32 {
   if desired_num_threads == 0 {
     return 0;
   }
   if work_pool.is_null() {
-    match compressor::catch_panic(|| BrotliEncoderCompressMulti(
+    match compressor::catch_panic(|| BrotliEncoderCompressMultiWithDictionary(
       num_params,
       param_keys,
       param_values,
+      dictionary_size,
+      dictionary,
       input_size,
       input,
       encoded_size,
@@ -332,30 +467,18 @@ pub unsafe extern fn BrotliEncoderCompressWorkPool(
       }
     }
     let num_threads = core::cmp::min(desired_num_threads, MAX_THREADS);
-    let mut alloc_array:[_;MAX_THREADS] = [
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[0]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[1%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[2%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[3%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[4%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[5%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[6%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[7%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[8%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[9%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[10%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[11%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[12%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[13%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[14%desired_num_threads]),
-      make_send_alloc!(alloc_func, free_func, alloc_opaque[15%desired_num_threads]),
-    ];
-    let res = enc::compress_worker_pool(
+    let dictionary_slice = slice_from_raw_parts_or_nil(dictionary, dictionary_size);
+    let input_slice = slice_from_raw_parts_or_nil(input, input_size);
+    let res = compress_with_dictionary(
       &params,
-      &mut Owned::new(SliceRef(slice_from_raw_parts_or_nil(input, input_size))),
+      input_slice,
+      dictionary_slice,
       slice_from_raw_parts_or_nil_mut(encoded, *encoded_size),
-      &mut alloc_array[..num_threads],
-      &mut (*work_pool_wrapper.0).work_pool,
+      num_threads,
+      alloc_func,
+      free_func,
+      alloc_opaque,
+      Some(&mut *work_pool_wrapper.0),
     );
     match res {
       Ok(size) => {
@@ -0,0 +1,390 @@
+// A streaming front end for the multithreaded compressor: input is gathered into batches of
+// num_threads chunks, each batch is compressed on the work pool with everything still in the
+// window as its dictionary, and the per-batch streams are joined with BroCatli as they are read.
+#[cfg(feature="std")]
+use std::{panic,thread};
+use core;
+use super::{
+  BrotliEncoderWorkPool,
+  compress_with_dictionary,
+  error_print,
+  MAX_THREADS,
+};
+use super::compressor;
+use super::compressor::BrotliEncoderOperation;
+use super::super::alloc_util::BrotliSubclassableAllocator;
+use brotli_decompressor::ffi::alloc_util::SubclassableAllocator;
+use brotli_decompressor::ffi::interface::{
+  brotli_alloc_func,
+  brotli_free_func,
+  CAllocator,
+  c_void,
+};
+use brotli_decompressor::ffi::{
+  slice_from_raw_parts_or_nil,
+  slice_from_raw_parts_or_nil_mut,
+};
+use ::enc::backward_references::BrotliEncoderParams;
+use ::enc::encode::{BrotliEncoderParameter, BrotliEncoderMaxCompressedSizeMulti, SanitizeParams, set_parameter};
+use ::concat::{BroCatli, BroCatliResult};
+use alloc::{Allocator, SliceWrapper, SliceWrapperMut};
+
+// the size of each thread's share of a batch when the caller passes 0
+pub const DEFAULT_MULTI_STREAM_CHUNK_SIZE: usize = 1 << 20;
+const TAKE_OUTPUT_SIZE: usize = 1 << 16;
+
+type MemoryBlock = <BrotliSubclassableAllocator as Allocator<u8>>::AllocatedMemory;
+
+pub struct BrotliEncoderMultiStream {
+  custom_allocator: CAllocator,
+  m8: BrotliSubclassableAllocator,
+  work_pool: *mut BrotliEncoderWorkPool,
+  alloc_opaque: [*mut c_void; MAX_THREADS],
+  num_threads: usize,
+  params: BrotliEncoderParams,
+  max_dictionary_len: usize,
+  batch_size: usize,
+  // the retained window followed by the input gathered for the next batch
+  input: MemoryBlock,
+  dictionary_len: usize,
+  pending_len: usize,
+  // the compressed batch that is being fed through BroCatli
+  compressed: MemoryBlock,
+  compressed_len: usize,
+  compressed_offset: usize,
+  take_output: MemoryBlock,
+  concat: BroCatli,
+  num_batches: usize,
+  total_out: usize,
+  finishing: bool,
+  finished: bool,
+  failed: bool,
+}
+
+#[cfg(not(feature="std"))]
+fn brotli_new_multi_stream_without_custom_alloc(_to_box: BrotliEncoderMultiStream) -> *mut BrotliEncoderMultiStream{
+    panic!("Must supply allocators if calling divans when compiled without features=std");
+}
+
+#[cfg(feature="std")]
+fn brotli_new_multi_stream_without_custom_alloc(to_box: BrotliEncoderMultiStream) -> *mut BrotliEncoderMultiStream{
+    brotli_decompressor::ffi::alloc_util::Box::<BrotliEncoderMultiStream>::into_raw(
+        brotli_decompressor::ffi::alloc_util::Box::<BrotliEncoderMultiStream>::new(to_box))
+}
+
+#[cfg(feature="std")]
+unsafe fn free_multi_stream_no_custom_alloc(stream: *mut BrotliEncoderMultiStream) {
+    let _state = brotli_decompressor::ffi::alloc_util::Box::from_raw(stream);
+}
+
+#[cfg(not(feature="std"))]
+unsafe fn free_multi_stream_no_custom_alloc(_stream: *mut BrotliEncoderMultiStream) {
+    unreachable!();
+}
+
+struct UnsafeUnwindStream(*mut BrotliEncoderMultiStream);
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+impl panic::RefUnwindSafe for UnsafeUnwindStream{}
+
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+fn catch_panic_mstate<F:FnOnce()->*mut BrotliEncoderMultiStream+panic::UnwindSafe>(f: F) -> thread::Result<*mut BrotliEncoderMultiStream> {
+    panic::catch_unwind(f)
+}
+
+#[cfg(any(not(feature="std"), feature="pass-through-ffi-panics"))]
+fn catch_panic_mstate<F:FnOnce()->*mut BrotliEncoderMultiStream>(f: F) -> Result<*mut BrotliEncoderMultiStream, ()> {
+    Ok(f())
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderCreateMultiStream(
+  work_pool: *mut BrotliEncoderWorkPool,
+  num_params: usize,
+  param_keys: *const BrotliEncoderParameter,
+  param_values: *const u32,
+  desired_num_threads: usize,
+  chunk_size: usize,
+  alloc_func: brotli_alloc_func,
+  free_func: brotli_free_func,
+  alloc_opaque_per_thread: *mut*mut c_void,
+) -> *mut BrotliEncoderMultiStream {
+  if desired_num_threads == 0 {
+    return core::ptr::null_mut();
+  }
+  let work_pool_wrapper = super::UnsafeUnwindBox(work_pool);
+  match catch_panic_mstate(|| {
+    let num_threads = core::cmp::min(desired_num_threads, MAX_THREADS);
+    let mut alloc_opaque = [core::ptr::null_mut::<c_void>(); MAX_THREADS];
+    if !alloc_opaque_per_thread.is_null() {
+      let opaques = slice_from_raw_parts_or_nil(alloc_opaque_per_thread, desired_num_threads);
+      for (index, item) in alloc_opaque.iter_mut().enumerate() {
+        *item = opaques[index % opaques.len()];
+      }
+    }
+    let mut params = BrotliEncoderParams::default();
+    for (k, v) in slice_from_raw_parts_or_nil(param_keys, num_params).iter().zip(
+      slice_from_raw_parts_or_nil(param_values, num_params).iter()) {
+      if set_parameter(&mut params, *k, *v) == 0 {
+        return core::ptr::null_mut();
+      }
+    }
+    let mut sanitized_params = params.clone();
+    SanitizeParams(&mut sanitized_params);
+    let max_dictionary_len = (1usize << sanitized_params.lgwin) - 16;
+    let batch_size = num_threads * if chunk_size == 0 { DEFAULT_MULTI_STREAM_CHUNK_SIZE } else { chunk_size };
+    let allocators = CAllocator {
+      alloc_func:alloc_func,
+      free_func:free_func,
+      opaque:alloc_opaque[0],
+    };
+    let mut m8 = BrotliSubclassableAllocator::new(SubclassableAllocator::new(allocators.clone()));
+    let input = <BrotliSubclassableAllocator as Allocator<u8>>::alloc_cell(&mut m8, max_dictionary_len + batch_size);
+    let compressed = <BrotliSubclassableAllocator as Allocator<u8>>::alloc_cell(
+      &mut m8, BrotliEncoderMaxCompressedSizeMulti(batch_size, num_threads));
+    let take_output = <BrotliSubclassableAllocator as Allocator<u8>>::alloc_cell(&mut m8, TAKE_OUTPUT_SIZE);
+    let to_box = BrotliEncoderMultiStream {
+      custom_allocator: allocators.clone(),
+      m8: m8,
+      work_pool: work_pool_wrapper.0,
+      alloc_opaque: alloc_opaque,
+      num_threads: num_threads,
+      params: params,
+      max_dictionary_len: max_dictionary_len,
+      batch_size: batch_size,
+      input: input,
+      dictionary_len: 0,
+      pending_len: 0,
+      compressed: compressed,
+      compressed_len: 0,
+      compressed_offset: 0,
+      take_output: take_output,
+      concat: BroCatli::new(),
+      num_batches: 0,
+      total_out: 0,
+      finishing: false,
+      finished: false,
+      failed: false,
+    };
+    if let Some(alloc) = alloc_func {
+      if free_func.is_none() {
+        panic!("either both alloc and free must exist or neither");
+      }
+      let ptr = alloc(allocators.opaque, core::mem::size_of::<BrotliEncoderMultiStream>());
+      let stream_ptr = core::mem::transmute::<*mut c_void, *mut BrotliEncoderMultiStream>(ptr);
+      core::ptr::write(stream_ptr, to_box);
+      stream_ptr
+    } else {
+      brotli_new_multi_stream_without_custom_alloc(to_box)
+    }
+  }) {
+    Ok(ret) => ret,
+    Err(err) => {
+      error_print(err);
+      core::ptr::null_mut()
+    }
+  }
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderDestroyMultiStream(stream_ptr: *mut BrotliEncoderMultiStream) {
+  let stream = &mut *stream_ptr;
+  let input = core::mem::replace(&mut stream.input, MemoryBlock::default());
+  let compressed = core::mem::replace(&mut stream.compressed, MemoryBlock::default());
+  let take_output = core::mem::replace(&mut stream.take_output, MemoryBlock::default());
+  <BrotliSubclassableAllocator as Allocator<u8>>::free_cell(&mut stream.m8, input);
+  <BrotliSubclassableAllocator as Allocator<u8>>::free_cell(&mut stream.m8, compressed);
+  <BrotliSubclassableAllocator as Allocator<u8>>::free_cell(&mut stream.m8, take_output);
+  if let Some(_) = stream.custom_allocator.alloc_func {
+    if let Some(free_fn) = stream.custom_allocator.free_func {
+      let _to_free = core::ptr::read(stream_ptr);
+      let ptr = core::mem::transmute::<*mut BrotliEncoderMultiStream, *mut c_void>(stream_ptr);
+      free_fn((*stream_ptr).custom_allocator.opaque, ptr);
+    }
+  } else {
+    free_multi_stream_no_custom_alloc(stream_ptr);
+  }
+}
+
+// compresses the gathered input as one more catable piece of the output stream
+unsafe fn compress_batch(stream: &mut BrotliEncoderMultiStream) -> bool {
+  let mut params = stream.params.clone();
+  if stream.num_batches != 0 {
+    params.catable = true;
+    params.magic_number = false;
+  }
+  let input_len = stream.dictionary_len + stream.pending_len;
+  let work_pool = if stream.work_pool.is_null() {
+    None
+  } else {
+    Some(&mut *stream.work_pool)
+  };
+  let (dictionary, input) = stream.input.slice()[..input_len].split_at(stream.dictionary_len);
+  let res = compress_with_dictionary(
+    &params,
+    input,
+    dictionary,
+    stream.compressed.slice_mut(),
+    stream.num_threads,
+    stream.custom_allocator.alloc_func,
+    stream.custom_allocator.free_func,
+    &stream.alloc_opaque[..],
+    work_pool,
+  );
+  match res {
+    Ok(size) => {
+      stream.compressed_len = size;
+      stream.compressed_offset = 0;
+    },
+    Err(_err) => return false,
+  }
+  stream.concat.new_brotli_file();
+  stream.num_batches += 1;
+  // keep the end of the window at the front of the buffer as the next batch's dictionary
+  let keep = core::cmp::min(input_len, stream.max_dictionary_len);
+  let input = stream.input.slice_mut();
+  for index in 0..keep {
+    input[index] = input[input_len - keep + index];
+  }
+  stream.dictionary_len = keep;
+  stream.pending_len = 0;
+  true
+}
+
+// moves compressed bytes through BroCatli into output, ending the stream once everything is written
+fn pump_output(stream: &mut BrotliEncoderMultiStream, output: &mut [u8], output_offset: &mut usize) -> bool {
+  if stream.compressed_offset != stream.compressed_len {
+    match stream.concat.stream(&stream.compressed.slice()[..stream.compressed_len],
+                               &mut stream.compressed_offset,
+                               output,
+                               output_offset) {
+      BroCatliResult::Success | BroCatliResult::NeedsMoreInput | BroCatliResult::NeedsMoreOutput => {},
+      _ => return false,
+    }
+  }
+  if stream.compressed_offset == stream.compressed_len && stream.finishing && !stream.finished {
+    match stream.concat.finish(output, output_offset) {
+      BroCatliResult::Success => stream.finished = true,
+      BroCatliResult::NeedsMoreOutput => {},
+      _ => return false,
+    }
+  }
+  true
+}
+
+fn has_more_output(stream: &BrotliEncoderMultiStream) -> bool {
+  stream.compressed_offset != stream.compressed_len || (stream.finishing && !stream.finished)
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderMultiStreamCompress(
+  stream_ptr: *mut BrotliEncoderMultiStream,
+  op: BrotliEncoderOperation,
+  available_in: *mut usize,
+  input_buf_ptr: *mut*const u8,
+  available_out: *mut usize,
+  output_buf_ptr: *mut*mut u8,
+  total_out: *mut usize,
+) -> i32 {
+  let stream_wrapper = UnsafeUnwindStream(stream_ptr);
+  match compressor::catch_panic(|| {
+    let stream = &mut *stream_wrapper.0;
+    if stream.failed || (stream.finishing && *available_in != 0) {
+      return 0;
+    }
+    let input = slice_from_raw_parts_or_nil(*input_buf_ptr, *available_in);
+    let output = slice_from_raw_parts_or_nil_mut(*output_buf_ptr, *available_out);
+    let mut input_offset = 0usize;
+    let mut output_offset = 0usize;
+    let mut result = 1;
+    loop {
+      if !pump_output(stream, output, &mut output_offset) {
+        result = 0;
+        break;
+      }
+      if stream.compressed_offset != stream.compressed_len {
+        break; // the caller must make room or take the output before another batch is compressed
+      }
+      if input_offset != input.len() {
+        let start = stream.dictionary_len + stream.pending_len;
+        let to_copy = core::cmp::min(input.len() - input_offset, stream.batch_size - stream.pending_len);
+        stream.input.slice_mut()[start..start + to_copy].clone_from_slice(
+          &input[input_offset..input_offset + to_copy]);
+        input_offset += to_copy;
+        stream.pending_len += to_copy;
+        if stream.pending_len == stream.batch_size && !compress_batch(stream) {
+          result = 0;
+          break;
+        }
+        continue;
+      }
+      match op {
+        BrotliEncoderOperation::BROTLI_OPERATION_FINISH if !stream.finishing => {
+          if (stream.pending_len != 0 || stream.num_batches == 0) && !compress_batch(stream) {
+            result = 0;
+            break;
+          }
+          stream.finishing = true;
+          continue;
+        },
+        BrotliEncoderOperation::BROTLI_OPERATION_FLUSH if stream.pending_len != 0 => {
+          if !compress_batch(stream) {
+            result = 0;
+            break;
+          }
+          continue;
+        },
+        _ => {},
+      }
+      break;
+    }
+    if result == 0 {
+      stream.failed = true;
+    }
+    *input_buf_ptr = input[input_offset..].as_ptr();
+    *available_in -= input_offset;
+    *output_buf_ptr = output[output_offset..].as_mut_ptr();
+    *available_out -= output_offset;
+    stream.total_out += output_offset;
+    if !total_out.is_null() {
+      *total_out = stream.total_out;
+    }
+    result
+  }) {
+    Ok(ret) => ret,
+    Err(panic_err) => {
+      error_print(panic_err);
+      0
+    },
+  }
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderMultiStreamTakeOutput(
+  stream_ptr: *mut BrotliEncoderMultiStream,
+  size: *mut usize,
+) -> *const u8 {
+  let stream = &mut *stream_ptr;
+  let mut take_output = core::mem::replace(&mut stream.take_output, MemoryBlock::default());
+  let mut output_offset = 0usize;
+  if !stream.failed && !pump_output(stream, take_output.slice_mut(), &mut output_offset) {
+    stream.failed = true;
+  }
+  stream.take_output = take_output;
+  stream.total_out += output_offset;
+  *size = output_offset;
+  stream.take_output.slice().as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderMultiStreamHasMoreOutput(
+  stream_ptr: *mut BrotliEncoderMultiStream,
+) -> i32 {
+  has_more_output(&*stream_ptr) as i32
+}
+
+#[no_mangle]
+pub unsafe extern fn BrotliEncoderMultiStreamIsFinished(
+  stream_ptr: *mut BrotliEncoderMultiStream,
+) -> i32 {
+  (*stream_ptr).finished as i32
+}
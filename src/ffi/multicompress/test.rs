@@ -248,3 +248,159 @@ fn test_compress_empty_multi_catable() {
   assert_eq!(&rt_buffer[..rt_size], &input[..]);
 }
 
+
+#[test]
+fn test_compress_workpool_with_dictionary() {
+  let dictionary = b"from ctypes import *\n\nclass EnumType(type(c_uint)):\n    def __new__(metacls):\n        pass\n";
+  let input = b"class EnumType(type(c_uint)):\n    def __new__(metacls):\n        pass\nfrom ctypes import *\n";
+  let params = [BrotliEncoderParameter::BROTLI_PARAM_QUALITY, BrotliEncoderParameter::BROTLI_PARAM_LGWIN, BrotliEncoderParameter::BROTLI_PARAM_FAVOR_EFFICIENCY];
+  for favor_efficiency in 0..2 {
+    for num_threads in 1..5 {
+      // a null work pool goes through BrotliEncoderCompressMultiWithDictionary
+      let use_work_pool = num_threads % 2 == 0;
+      let values = [9u32, 16u32, favor_efficiency];
+      let mut encoded_size = BrotliEncoderMaxCompressedSizeMulti(input.len(), num_threads);
+      let mut encoded_backing = [0u8;145];
+      let encoded = &mut encoded_backing[..encoded_size];
+      let ret = unsafe {
+        let wp = if use_work_pool {
+          BrotliEncoderCreateWorkPool(8, None, None, core::ptr::null_mut())
+        } else {
+          core::ptr::null_mut()
+        };
+        let inner_ret = BrotliEncoderCompressWorkPoolWithDictionary(
+          wp,
+          params.len(),
+          params[..].as_ptr(),
+          values[..].as_ptr(),
+          dictionary.len(),
+          dictionary[..].as_ptr(),
+          input.len(),
+          input[..].as_ptr(),
+          &mut encoded_size,
+          encoded.as_mut_ptr(),
+          num_threads,
+          None,
+          None,
+          core::ptr::null_mut());
+        if use_work_pool {
+          BrotliEncoderDestroyWorkPool(wp);
+        }
+        inner_ret
+      };
+      assert_eq!(ret, 1);
+      let mut rt = std::vec::Vec::<u8>::new();
+      match brotli_decompressor::BrotliDecompressCustomDict(&mut &encoded[..encoded_size],
+                                                            &mut rt,
+                                                            &mut [0u8;4096][..],
+                                                            &mut [0u8;4096][..],
+                                                            dictionary.to_vec()) {
+        Ok(_) => {},
+        Err(e) => panic!("Error {:?}", e),
+      }
+      assert_eq!(&rt[..], &input[..]);
+    }
+  }
+}
+
+fn multi_stream_round_trip(input: &[u8], num_threads: usize, chunk_size: usize, use_work_pool: bool, take_output: bool) -> usize {
+  let params = [BrotliEncoderParameter::BROTLI_PARAM_QUALITY, BrotliEncoderParameter::BROTLI_PARAM_LGWIN];
+  let values = [5u32, 16u32];
+  let mut compressed = std::vec::Vec::<u8>::new();
+  unsafe {
+    let wp = if use_work_pool {
+      BrotliEncoderCreateWorkPool(num_threads, None, None, core::ptr::null_mut())
+    } else {
+      core::ptr::null_mut()
+    };
+    let stream = BrotliEncoderCreateMultiStream(
+      wp,
+      params.len(),
+      params[..].as_ptr(),
+      values[..].as_ptr(),
+      num_threads,
+      chunk_size,
+      None,
+      None,
+      core::ptr::null_mut());
+    assert!(!stream.is_null());
+    let mut out_buffer = [0u8; 777];
+    // feed uneven pieces, flushing once part way through
+    for (index, piece) in input.chunks(1000).enumerate() {
+      let mut available_in = piece.len();
+      let mut next_in = piece.as_ptr();
+      while available_in != 0 || BrotliEncoderMultiStreamHasMoreOutput(stream) != 0 {
+        let op = if index == 5 {
+          compressor::BrotliEncoderOperation::BROTLI_OPERATION_FLUSH
+        } else {
+          compressor::BrotliEncoderOperation::BROTLI_OPERATION_PROCESS
+        };
+        let mut available_out = if take_output {0} else {out_buffer.len()};
+        let mut next_out = out_buffer.as_mut_ptr();
+        assert_eq!(BrotliEncoderMultiStreamCompress(stream, op, &mut available_in, &mut next_in,
+                                                    &mut available_out, &mut next_out, core::ptr::null_mut()), 1);
+        if take_output {
+          let mut size = 0usize;
+          let data = BrotliEncoderMultiStreamTakeOutput(stream, &mut size);
+          compressed.extend_from_slice(slice_from_raw_parts_or_nil(data, size));
+        } else {
+          compressed.extend_from_slice(&out_buffer[..out_buffer.len() - available_out]);
+        }
+      }
+    }
+    while BrotliEncoderMultiStreamIsFinished(stream) == 0 {
+      let mut available_in = 0usize;
+      let mut next_in = input.as_ptr();
+      let mut available_out = out_buffer.len();
+      let mut next_out = out_buffer.as_mut_ptr();
+      let mut total_out = 0usize;
+      assert_eq!(BrotliEncoderMultiStreamCompress(stream, compressor::BrotliEncoderOperation::BROTLI_OPERATION_FINISH,
+                                                  &mut available_in, &mut next_in,
+                                                  &mut available_out, &mut next_out, &mut total_out), 1);
+      compressed.extend_from_slice(&out_buffer[..out_buffer.len() - available_out]);
+      assert_eq!(total_out, compressed.len());
+    }
+    BrotliEncoderDestroyMultiStream(stream);
+    if use_work_pool {
+      BrotliEncoderDestroyWorkPool(wp);
+    }
+  }
+  let mut rt = std::vec::Vec::<u8>::new();
+  match brotli_decompressor::BrotliDecompress(&mut &compressed[..], &mut rt) {
+    Ok(_) => {},
+    Err(e) => panic!("Error {:?}", e),
+  }
+  assert_eq!(&rt[..], input);
+  compressed.len()
+}
+
+static ALICE: &'static [u8] = include_bytes!("../../../testdata/alice29.txt");
+
+#[test]
+fn test_multi_stream_work_pool() {
+  multi_stream_round_trip(ALICE, 4, 8192, true, false);
+}
+
+#[test]
+fn test_multi_stream_take_output() {
+  multi_stream_round_trip(ALICE, 3, 10000, true, true);
+}
+
+#[test]
+fn test_multi_stream_no_work_pool() {
+  multi_stream_round_trip(&ALICE[..20000], 2, 0, false, false);
+}
+
+#[test]
+fn test_multi_stream_empty() {
+  multi_stream_round_trip(&[], 4, 4096, true, true);
+}
+
+#[test]
+fn test_multi_stream_uses_window() {
+  // later batches reference earlier ones, so repeated input costs almost nothing
+  let mut input = ALICE[..16384].to_vec();
+  input.extend_from_slice(&ALICE[..16384]);
+  let size = multi_stream_round_trip(&input, 2, 4096, true, false);
+  assert!(size < multi_stream_round_trip(&ALICE[..16384], 2, 4096, true, false) + 512);
+}
@@ -10,8 +10,6 @@
 // <-- for debugging, remove xprintln from bit_reader and replace with println
 #[cfg(feature="std")]
 extern crate std;
-#[cfg(feature="simd")]
-extern crate packed_simd;
 #[cfg(feature="std")]
 extern crate alloc_stdlib;
 #[allow(unused_imports)]
@@ -25,6 +23,11 @@ pub use alloc_stdlib::HeapAlloc;
 pub mod enc;
 pub use self::enc::combined_alloc::CombiningAllocator;
 pub mod concat;
+pub mod limits;
+pub use limits::{DecompressionLimits, DecompressionLimitExceeded, BrotliDecompressStreamWithLimits};
+pub use limits::{BrotliStreamHeader, BrotliHeaderError, BrotliInspectHeader, BrotliDecoderWindowMemory};
+#[cfg(feature="std")]
+pub use limits::{LimitedDecompressor, LimitedDecompressorWriter, BrotliDecompressWithLimits, BrotliReadHeader};
 pub use brotli_decompressor::transform;
 pub use brotli_decompressor::dictionary;
 pub use brotli_decompressor::reader;
@@ -0,0 +1,421 @@
+use core;
+use alloc;
+use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState, HuffmanCode};
+use super::concat::parse_window_size;
+#[cfg(feature="std")]
+use std::io::{self, Read, Write};
+#[cfg(feature="std")]
+use std::error::Error;
+#[cfg(feature="std")]
+use std::fmt;
+#[cfg(feature="std")]
+use brotli_decompressor::{Decompressor, DecompressorWriter};
+
+// Limits to apply while decoding untrusted input. Zero disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecompressionLimits {
+  // maximum number of decompressed bytes
+  pub max_output_size: u64,
+  // maximum number of decompressed bytes per compressed byte seen so far
+  pub max_ratio: u64,
+  // largest log2 of the window the stream may declare (10-30; 24 is the limit of regular brotli)
+  pub max_window_bits: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressionLimitExceeded {
+  OutputSize = 1,
+  Ratio = 2,
+  WindowSize = 3,
+}
+
+#[cfg(feature="std")]
+impl fmt::Display for DecompressionLimitExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      DecompressionLimitExceeded::OutputSize => write!(f, "brotli stream exceeds the maximum output size"),
+      DecompressionLimitExceeded::Ratio => write!(f, "brotli stream exceeds the maximum expansion ratio"),
+      DecompressionLimitExceeded::WindowSize => write!(f, "brotli stream window is larger than allowed"),
+    }
+  }
+}
+
+#[cfg(feature="std")]
+impl Error for DecompressionLimitExceeded {}
+
+#[cfg(feature="std")]
+impl From<DecompressionLimitExceeded> for io::Error {
+  fn from(e: DecompressionLimitExceeded) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+  }
+}
+
+// Window declared in the first bytes of a brotli stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrotliStreamHeader {
+  // log2 of the window size
+  pub window_bits: u8,
+  // the stream needs a decoder with BROTLI_DECODER_PARAM_LARGE_WINDOW set
+  pub large_window: bool,
+}
+
+impl BrotliStreamHeader {
+  // bytes of ring buffer a decoder allocates for this window
+  pub fn window_memory(&self) -> u64 {
+    BrotliDecoderWindowMemory(self.window_bits)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrotliHeaderError {
+  // a large window stream needs its second byte to declare the window
+  NeedsMoreInput,
+  Corrupt,
+}
+
+// the decoder keeps this many bytes past the end of its ring buffer
+const RING_BUFFER_WRITE_AHEAD_SLACK: u64 = 542;
+
+// Upper bound on the ring buffer a decoder allocates for a stream with the given window.
+pub fn BrotliDecoderWindowMemory(window_bits: u8) -> u64 {
+  (1u64 << window_bits) + RING_BUFFER_WRITE_AHEAD_SLACK
+}
+
+// Reads the window of a stream from its first one or two bytes without decoding anything.
+pub fn BrotliInspectHeader(data: &[u8]) -> Result<BrotliStreamHeader, BrotliHeaderError> {
+  if data.is_empty() || (data.len() == 1 && data[0] & 0x7f == 0x11) {
+    return Err(BrotliHeaderError::NeedsMoreInput);
+  }
+  match parse_window_size(data) {
+    Ok((window_bits, offset)) => Ok(BrotliStreamHeader {
+      window_bits: window_bits,
+      // only the large window encoding puts the window bits in the second byte
+      large_window: offset == 14,
+    }),
+    Err(_) => Err(BrotliHeaderError::Corrupt),
+  }
+}
+
+// Reads just enough of r to inspect the stream header. The bytes read are consumed.
+#[cfg(feature="std")]
+pub fn BrotliReadHeader<R: Read>(r: &mut R) -> io::Result<BrotliStreamHeader> {
+  let mut header = [0u8; 2];
+  let mut header_len = 0;
+  loop {
+    match BrotliInspectHeader(&header[..header_len]) {
+      Ok(ret) => return Ok(ret),
+      Err(BrotliHeaderError::Corrupt) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                                   "Invalid brotli stream header")),
+      Err(BrotliHeaderError::NeedsMoreInput) => {},
+    }
+    match r.read(&mut header[header_len..header_len + 1]) {
+      Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated brotli stream header")),
+      Ok(size) => header_len += size,
+      Err(e) => if e.kind() != io::ErrorKind::Interrupted {
+        return Err(e);
+      },
+    }
+  }
+}
+
+// Counts the bytes going in and out of a decoder and checks them against DecompressionLimits.
+#[derive(Debug, Clone, Copy)]
+pub struct DecompressionLimitTracker {
+  limits: DecompressionLimits,
+  header: [u8; 2],
+  header_len: usize,
+  window_bits: Option<u8>,
+  input_size: u64,
+  output_size: u64,
+  exceeded: Option<DecompressionLimitExceeded>,
+}
+
+impl DecompressionLimitTracker {
+  pub fn new(limits: DecompressionLimits) -> DecompressionLimitTracker {
+    DecompressionLimitTracker {
+      limits: limits,
+      header: [0; 2],
+      header_len: 0,
+      window_bits: None,
+      input_size: 0,
+      output_size: 0,
+      exceeded: None,
+    }
+  }
+  pub fn limits(&self) -> &DecompressionLimits {
+    &self.limits
+  }
+  pub fn limits_mut(&mut self) -> &mut DecompressionLimits {
+    &mut self.limits
+  }
+  // log2 of the window declared by the stream, once enough of it was seen
+  pub fn window_bits(&self) -> Option<u8> {
+    self.window_bits
+  }
+  pub fn input_size(&self) -> u64 {
+    self.input_size
+  }
+  pub fn output_size(&self) -> u64 {
+    self.output_size
+  }
+  pub fn exceeded(&self) -> Option<DecompressionLimitExceeded> {
+    self.exceeded
+  }
+  // records that a limit was exceeded outside observe_output, e.g. by a decoder holding more output than allowed
+  pub fn fail(&mut self, reason: DecompressionLimitExceeded) -> Result<(), DecompressionLimitExceeded> {
+    self.exceeded = Some(reason);
+    Err(reason)
+  }
+  // checks the window bits in the stream header; data is the compressed input not yet passed to observe_input
+  pub fn check_header(&mut self, data: &[u8]) -> Result<(), DecompressionLimitExceeded> {
+    if let Some(reason) = self.exceeded {
+      return Err(reason);
+    }
+    if self.window_bits.is_some() {
+      return Ok(());
+    }
+    let mut header = self.header;
+    let mut header_len = self.header_len;
+    for byte in data.iter().take(header.len() - header_len) {
+      header[header_len] = *byte;
+      header_len += 1;
+    }
+    match BrotliInspectHeader(&header[..header_len]) {
+      Ok(stream_header) => {
+        self.window_bits = Some(stream_header.window_bits);
+        if self.limits.max_window_bits != 0 && stream_header.window_bits > self.limits.max_window_bits {
+          return self.fail(DecompressionLimitExceeded::WindowSize);
+        }
+      },
+      Err(_) => {}, // too short to tell, or corrupt and the decoder will report it
+    }
+    Ok(())
+  }
+  // ring buffer the decoder will allocate, known as soon as the header was seen
+  pub fn window_memory(&self) -> Option<u64> {
+    self.window_bits.map(BrotliDecoderWindowMemory)
+  }
+  // how many bytes may still be produced, if pending_input more compressed bytes were also available
+  pub fn output_allowance(&self, pending_input: usize) -> (u64, Option<DecompressionLimitExceeded>) {
+    let mut allowance = u64::max_value();
+    let mut reason = None;
+    if self.limits.max_output_size != 0 {
+      allowance = self.limits.max_output_size.saturating_sub(self.output_size);
+      reason = Some(DecompressionLimitExceeded::OutputSize);
+    }
+    if self.limits.max_ratio != 0 {
+      let ratio_allowance = self.limits.max_ratio
+        .saturating_mul(self.input_size.saturating_add(pending_input as u64))
+        .saturating_sub(self.output_size);
+      if ratio_allowance < allowance {
+        allowance = ratio_allowance;
+        reason = Some(DecompressionLimitExceeded::Ratio);
+      }
+    }
+    (allowance, reason)
+  }
+  pub fn observe_input(&mut self, data: &[u8]) -> Result<(), DecompressionLimitExceeded> {
+    self.check_header(data)?;
+    let header_bytes = core::cmp::min(data.len(), self.header.len() - self.header_len);
+    self.header[self.header_len..self.header_len + header_bytes].clone_from_slice(&data[..header_bytes]);
+    self.header_len += header_bytes;
+    self.input_size += data.len() as u64;
+    Ok(())
+  }
+  // pending_input counts compressed bytes already handed to the decoder but not consumed yet
+  pub fn observe_output(&mut self, len: usize, pending_input: usize) -> Result<(), DecompressionLimitExceeded> {
+    if let Some(reason) = self.exceeded {
+      return Err(reason);
+    }
+    let (allowance, reason) = self.output_allowance(pending_input);
+    self.output_size += len as u64;
+    if len as u64 > allowance {
+      return self.fail(reason.unwrap_or(DecompressionLimitExceeded::OutputSize));
+    }
+    Ok(())
+  }
+}
+
+// BrotliDecompressStream that fails instead of producing more output than the tracker allows.
+// Output is never written beyond the limit, and tracker.exceeded() tells which limit was hit.
+pub fn BrotliDecompressStreamWithLimits<AllocU8: alloc::Allocator<u8>,
+                                        AllocU32: alloc::Allocator<u32>,
+                                        AllocHC: alloc::Allocator<HuffmanCode>>
+  (available_in: &mut usize,
+   input_offset: &mut usize,
+   input: &[u8],
+   available_out: &mut usize,
+   output_offset: &mut usize,
+   output: &mut [u8],
+   total_out: &mut usize,
+   s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+   tracker: &mut DecompressionLimitTracker)
+   -> BrotliResult {
+  if tracker.check_header(&input[*input_offset..*input_offset + *available_in]).is_err() {
+    return BrotliResult::ResultFailure;
+  }
+  let (allowance, reason) = tracker.output_allowance(*available_in);
+  let caller_available_out = *available_out;
+  let limited_out = core::cmp::min(*available_out as u64, allowance) as usize;
+  let mut remaining_limited_out = limited_out;
+  let input_start = *input_offset;
+  let output_start = *output_offset;
+  let result = BrotliDecompressStream(available_in,
+                                      input_offset,
+                                      input,
+                                      &mut remaining_limited_out,
+                                      output_offset,
+                                      output,
+                                      total_out,
+                                      s);
+  *available_out -= limited_out - remaining_limited_out;
+  let observed = tracker.observe_input(&input[input_start..*input_offset])
+    .and_then(|_| tracker.observe_output(*output_offset - output_start, *available_in));
+  if observed.is_err() {
+    return BrotliResult::ResultFailure;
+  }
+  match result {
+    BrotliResult::NeedsMoreOutput if limited_out < caller_available_out => {
+      // the caller had room for more: the limit is what stopped the decoder
+      let _ = tracker.fail(reason.unwrap_or(DecompressionLimitExceeded::OutputSize));
+      BrotliResult::ResultFailure
+    },
+    _ => result,
+  }
+}
+
+#[cfg(feature="std")]
+struct LimitedReader<R: Read> {
+  inner: R,
+  tracker: DecompressionLimitTracker,
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for LimitedReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let size = self.inner.read(buf)?;
+    self.tracker.observe_input(&buf[..size])?;
+    Ok(size)
+  }
+}
+
+// A Decompressor that fails with an io::Error wrapping DecompressionLimitExceeded
+// once the stream breaks one of the limits.
+#[cfg(feature="std")]
+pub struct LimitedDecompressor<R: Read>(Decompressor<LimitedReader<R>>);
+
+#[cfg(feature="std")]
+impl<R: Read> LimitedDecompressor<R> {
+  pub fn new(r: R, buffer_size: usize, limits: DecompressionLimits) -> Self {
+    LimitedDecompressor(Decompressor::new(LimitedReader {
+      inner: r,
+      tracker: DecompressionLimitTracker::new(limits),
+    }, buffer_size))
+  }
+  pub fn tracker(&self) -> &DecompressionLimitTracker {
+    &self.0.get_ref().tracker
+  }
+  pub fn get_ref(&self) -> &R {
+    &self.0.get_ref().inner
+  }
+  pub fn get_mut(&mut self) -> &mut R {
+    &mut self.0.get_mut().inner
+  }
+  pub fn into_inner(self) -> R {
+    self.0.into_inner().inner
+  }
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for LimitedDecompressor<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if let Some(reason) = self.tracker().exceeded() {
+      return Err(reason.into());
+    }
+    let (allowance, _) = self.tracker().output_allowance(0);
+    // ask for one byte past the limit to find out whether the stream goes on
+    let len = core::cmp::min(buf.len() as u64, allowance.saturating_add(1)) as usize;
+    let size = self.0.read(&mut buf[..len])?;
+    self.0.get_mut().tracker.observe_output(size, 0)?;
+    Ok(size)
+  }
+}
+
+#[cfg(feature="std")]
+struct LimitedWriter<W: Write> {
+  inner: W,
+  tracker: DecompressionLimitTracker,
+}
+
+#[cfg(feature="std")]
+impl<W: Write> Write for LimitedWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // refuse the whole buffer rather than writing anything past the limit
+    let (allowance, reason) = self.tracker.output_allowance(0);
+    if buf.len() as u64 > allowance {
+      self.tracker.observe_output(buf.len(), 0)?;
+      return Err(reason.unwrap_or(DecompressionLimitExceeded::OutputSize).into());
+    }
+    let size = self.inner.write(buf)?;
+    self.tracker.observe_output(size, 0)?;
+    Ok(size)
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+// A DecompressorWriter that refuses to write output past the limits.
+#[cfg(feature="std")]
+pub struct LimitedDecompressorWriter<W: Write>(DecompressorWriter<LimitedWriter<W>>);
+
+#[cfg(feature="std")]
+impl<W: Write> LimitedDecompressorWriter<W> {
+  pub fn new(w: W, buffer_size: usize, limits: DecompressionLimits) -> Self {
+    LimitedDecompressorWriter(DecompressorWriter::new(LimitedWriter {
+      inner: w,
+      tracker: DecompressionLimitTracker::new(limits),
+    }, buffer_size))
+  }
+  pub fn tracker(&self) -> &DecompressionLimitTracker {
+    &self.0.get_ref().tracker
+  }
+  pub fn get_ref(&self) -> &W {
+    &self.0.get_ref().inner
+  }
+  pub fn get_mut(&mut self) -> &mut W {
+    &mut self.0.get_mut().inner
+  }
+  pub fn close(&mut self) -> io::Result<()> {
+    self.0.close()
+  }
+  pub fn into_inner(self) -> Result<W, W> {
+    match self.0.into_inner() {
+      Ok(w) => Ok(w.inner),
+      Err(w) => Err(w.inner),
+    }
+  }
+}
+
+#[cfg(feature="std")]
+impl<W: Write> Write for LimitedDecompressorWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // the input is counted before decoding so the ratio limit applies to the output it produces
+    self.0.get_mut().tracker.observe_input(buf)?;
+    self.0.write(buf)
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
+
+// Decompresses r into w, like BrotliDecompress, while enforcing the limits.
+#[cfg(feature="std")]
+pub fn BrotliDecompressWithLimits<InputType: Read, OutputType: Write>(r: &mut InputType,
+                                                                      w: &mut OutputType,
+                                                                      limits: DecompressionLimits)
+                                                                      -> io::Result<u64> {
+  let mut decompressor = LimitedDecompressor::new(r, 4096, limits);
+  io::copy(&mut decompressor, w)
+}